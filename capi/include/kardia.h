@@ -0,0 +1,81 @@
+#ifndef KARDIA_H
+#define KARDIA_H
+
+/* Generated by cbindgen from capi/src/lib.rs; do not edit. */
+
+#include <stddef.h>
+#include <stdint.h>
+
+// Status of a successful call. Failed conversions return their
+// [`Failure::exit_code`], one of the `KARDIA_ERR_*` statuses.
+#define KARDIA_OK 0
+
+// Any other failure, such as a null pointer ([`Failure::Other`]).
+#define KARDIA_ERR_OTHER 1
+
+// The input can't be read ([`Failure::InputNotFound`]).
+#define KARDIA_ERR_INPUT_NOT_FOUND 2
+
+// No ECG waveform was found in the PDF ([`Failure::NoWaveform`]).
+#define KARDIA_ERR_NO_WAVEFORM 3
+
+// The quality score is below the threshold ([`Failure::QcFailed`]).
+#define KARDIA_ERR_QC_FAILED 4
+
+// An output couldn't be written ([`Failure::WriteFailed`]).
+#define KARDIA_ERR_WRITE_FAILED 5
+
+// The input isn't a readable PDF ([`Failure::InvalidPdf`]).
+#define KARDIA_ERR_INVALID_PDF 6
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Convert a Kardia ECG report PDF of `pdf_len` bytes at `pdf` into an
+// EDF+ file in memory, with the default options, as [`convert_bytes`]
+// does.
+//
+// On success, sets `*edf` and `*edf_len` to the EDF+ file's bytes, to be
+// released with [`kardia_free`], and returns [`KARDIA_OK`]. On failure,
+// sets `*edf` to NULL and `*edf_len` to 0 and returns the failure class's
+// status, `KARDIA_ERR_*`, as the command-line tool's exit status; the
+// message is then available from [`kardia_last_error`].
+//
+// ```c
+// uint8_t *edf;
+// size_t edf_len;
+// int status = kardia_convert_pdf_to_edf(pdf, pdf_len, &edf, &edf_len);
+// if (status == KARDIA_OK) {
+//     fwrite(edf, 1, edf_len, out);
+//     kardia_free(edf, edf_len);
+// } else {
+//     fprintf(stderr, "%s\n", kardia_last_error());
+// }
+// ```
+//
+// # Safety
+//
+// `pdf` must point to `pdf_len` readable bytes, and `edf` and `edf_len`
+// to writable locations.
+int kardia_convert_pdf_to_edf(const uint8_t *pdf, size_t pdf_len, uint8_t **edf, size_t *edf_len);
+
+// Release an EDF+ file returned by [`kardia_convert_pdf_to_edf`]. NULL is
+// ignored.
+//
+// # Safety
+//
+// `edf` and `edf_len` must be as returned by
+// [`kardia_convert_pdf_to_edf`], and not released already.
+void kardia_free(uint8_t *edf, size_t edf_len);
+
+// Message of the last failed call on this thread, as a NUL-terminated
+// UTF-8 string, or NULL if none has failed. It stays valid until the next
+// failed call on the thread.
+const char *kardia_last_error(void);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* KARDIA_H */
@@ -0,0 +1,135 @@
+use std::cell::RefCell;
+use std::ffi::{c_char, c_int, CString};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+use kardiamobile_1l_ecg_convert_pdf_to_edf::failure::Failure;
+use kardiamobile_1l_ecg_convert_pdf_to_edf::{convert_bytes, ConvertOptions};
+
+/// Status of a successful call. Failed conversions return their
+/// [`Failure::exit_code`], one of the `KARDIA_ERR_*` statuses.
+pub const KARDIA_OK: c_int = 0;
+/// Any other failure, such as a null pointer ([`Failure::Other`]).
+pub const KARDIA_ERR_OTHER: c_int = 1;
+/// The input can't be read ([`Failure::InputNotFound`]).
+pub const KARDIA_ERR_INPUT_NOT_FOUND: c_int = 2;
+/// No ECG waveform was found in the PDF ([`Failure::NoWaveform`]).
+pub const KARDIA_ERR_NO_WAVEFORM: c_int = 3;
+/// The quality score is below the threshold ([`Failure::QcFailed`]).
+pub const KARDIA_ERR_QC_FAILED: c_int = 4;
+/// An output couldn't be written ([`Failure::WriteFailed`]).
+pub const KARDIA_ERR_WRITE_FAILED: c_int = 5;
+/// The input isn't a readable PDF ([`Failure::InvalidPdf`]).
+pub const KARDIA_ERR_INVALID_PDF: c_int = 6;
+
+/// cbindgen:ignore
+// cbindgen needs the statuses as literals; keep them the exit codes
+const _: () = {
+    let statuses = [
+        (KARDIA_ERR_OTHER, Failure::Other),
+        (KARDIA_ERR_INPUT_NOT_FOUND, Failure::InputNotFound),
+        (KARDIA_ERR_NO_WAVEFORM, Failure::NoWaveform),
+        (KARDIA_ERR_QC_FAILED, Failure::QcFailed),
+        (KARDIA_ERR_WRITE_FAILED, Failure::WriteFailed),
+        (KARDIA_ERR_INVALID_PDF, Failure::InvalidPdf),
+    ];
+    let mut i = 0;
+    while i < statuses.len() {
+        assert!(statuses[i].0 == statuses[i].1.exit_code() as c_int);
+        i += 1;
+    }
+};
+
+thread_local! {
+    /// Message of the last failed call on this thread.
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: String) {
+    // Messages come from Rust strings, so only a stray NUL can fail
+    let message = CString::new(message.replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+/// Convert a Kardia ECG report PDF of `pdf_len` bytes at `pdf` into an
+/// EDF+ file in memory, with the default options, as [`convert_bytes`]
+/// does.
+///
+/// On success, sets `*edf` and `*edf_len` to the EDF+ file's bytes, to be
+/// released with [`kardia_free`], and returns [`KARDIA_OK`]. On failure,
+/// sets `*edf` to NULL and `*edf_len` to 0 and returns the failure class's
+/// status, `KARDIA_ERR_*`, as the command-line tool's exit status; the
+/// message is then available from [`kardia_last_error`].
+///
+/// ```c
+/// uint8_t *edf;
+/// size_t edf_len;
+/// int status = kardia_convert_pdf_to_edf(pdf, pdf_len, &edf, &edf_len);
+/// if (status == KARDIA_OK) {
+///     fwrite(edf, 1, edf_len, out);
+///     kardia_free(edf, edf_len);
+/// } else {
+///     fprintf(stderr, "%s\n", kardia_last_error());
+/// }
+/// ```
+///
+/// # Safety
+///
+/// `pdf` must point to `pdf_len` readable bytes, and `edf` and `edf_len`
+/// to writable locations.
+#[no_mangle]
+pub unsafe extern "C" fn kardia_convert_pdf_to_edf(
+    pdf: *const u8,
+    pdf_len: usize,
+    edf: *mut *mut u8,
+    edf_len: *mut usize,
+) -> c_int {
+    if pdf.is_null() || edf.is_null() || edf_len.is_null() {
+        set_last_error("Null pointer passed to kardia_convert_pdf_to_edf".into());
+        return Failure::Other.exit_code().into();
+    }
+    *edf = ptr::null_mut();
+    *edf_len = 0;
+    let pdf = std::slice::from_raw_parts(pdf, pdf_len);
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        convert_bytes(pdf, &ConvertOptions::new())
+    }));
+    match result {
+        Ok(Ok(bytes)) => {
+            let bytes = bytes.into_boxed_slice();
+            *edf_len = bytes.len();
+            *edf = Box::into_raw(bytes).cast();
+            KARDIA_OK
+        }
+        Ok(Err(e)) => {
+            set_last_error(format!("{:#}", e));
+            Failure::of(&e).exit_code().into()
+        }
+        Err(_) => {
+            set_last_error("Conversion failed: internal error".into());
+            Failure::Other.exit_code().into()
+        }
+    }
+}
+
+/// Release an EDF+ file returned by [`kardia_convert_pdf_to_edf`]. NULL is
+/// ignored.
+///
+/// # Safety
+///
+/// `edf` and `edf_len` must be as returned by
+/// [`kardia_convert_pdf_to_edf`], and not released already.
+#[no_mangle]
+pub unsafe extern "C" fn kardia_free(edf: *mut u8, edf_len: usize) {
+    if !edf.is_null() {
+        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(edf, edf_len)));
+    }
+}
+
+/// Message of the last failed call on this thread, as a NUL-terminated
+/// UTF-8 string, or NULL if none has failed. It stays valid until the next
+/// failed call on the thread.
+#[no_mangle]
+pub extern "C" fn kardia_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(ptr::null(), |e| e.as_ptr()))
+}
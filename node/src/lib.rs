@@ -0,0 +1,30 @@
+use kardiamobile_1l_ecg_convert_pdf_to_edf::{convert_bytes, ConvertOptions};
+use napi::bindgen_prelude::*;
+use napi_derive::napi;
+
+/// A conversion run on the libuv thread pool, off the JavaScript thread.
+pub struct Convert {
+    pdf: Vec<u8>,
+}
+
+impl Task for Convert {
+    type Output = Vec<u8>;
+    type JsValue = Buffer;
+
+    fn compute(&mut self) -> Result<Self::Output> {
+        convert_bytes(&self.pdf, &ConvertOptions::new())
+            .map_err(|e| Error::from_reason(format!("{:#}", e)))
+    }
+
+    fn resolve(&mut self, _env: Env, edf: Self::Output) -> Result<Self::JsValue> {
+        Ok(edf.into())
+    }
+}
+
+/// Convert a Kardia ECG report PDF into an EDF+ file, in memory and off
+/// the JavaScript thread, as `convert(pdf: Buffer): Promise<Buffer>`. The
+/// promise is rejected with an `Error` for a failed conversion.
+#[napi]
+pub fn convert(pdf: Buffer) -> AsyncTask<Convert> {
+    AsyncTask::new(Convert { pdf: pdf.to_vec() })
+}
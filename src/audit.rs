@@ -0,0 +1,255 @@
+use anyhow::Result;
+use std::cell::RefCell;
+use std::fmt::Write as _;
+use std::path::Path;
+
+use crate::convert::ConvertOptions;
+use crate::provenance::{self, sha256_hex, TOOL_NAME};
+
+thread_local! {
+    /// The audit trail of the conversion running on this thread, if one is
+    /// being kept. Conversions run one per thread, so trails don't mix.
+    static TRAIL: RefCell<Option<Vec<Entry>>> = const { RefCell::new(None) };
+}
+
+/// One line of an audit trail.
+///
+/// Each entry is also a `tracing` event, at the info, warning, or debug
+/// level, so the library prints nothing unless the application installs a
+/// subscriber, as the command-line tool does.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Entry {
+    /// A progress message.
+    Progress(String),
+    /// A warning or error message.
+    Warning(String),
+    /// A detail, such as the thresholds used, logged at the debug level.
+    Detail(String),
+}
+
+/// Log a progress line as an info event and record it in the audit trail.
+macro_rules! outln {
+    ($($arg:tt)*) => {{
+        let line = format!($($arg)*);
+        ::tracing::info!("{}", line);
+        $crate::audit::record($crate::audit::Entry::Progress(line));
+    }};
+}
+
+/// Log a warning or error line as a warning event and record it in the
+/// audit trail.
+macro_rules! errln {
+    ($($arg:tt)*) => {{
+        let line = format!($($arg)*);
+        ::tracing::warn!("{}", line);
+        $crate::audit::record($crate::audit::Entry::Warning(line));
+    }};
+}
+
+pub(crate) use {errln, outln};
+
+/// Start keeping an audit trail on this thread, discarding any earlier one.
+pub fn start() {
+    TRAIL.with(|trail| *trail.borrow_mut() = Some(Vec::new()));
+}
+
+/// Stop keeping the audit trail, returning it.
+pub fn finish() -> Vec<Entry> {
+    TRAIL
+        .with(|trail| trail.borrow_mut().take())
+        .unwrap_or_default()
+}
+
+/// Whether an audit trail is being kept or details are logged, so costly
+/// details can be skipped otherwise.
+pub fn is_recording() -> bool {
+    TRAIL.with(|trail| trail.borrow().is_some()) || tracing::enabled!(tracing::Level::DEBUG)
+}
+
+/// Add an entry to the audit trail, if one is being kept.
+pub fn record(entry: Entry) {
+    TRAIL.with(|trail| {
+        if let Some(entries) = trail.borrow_mut().as_mut() {
+            entries.push(entry);
+        }
+    });
+}
+
+/// Log a detail at the debug level and record it in the audit trail.
+pub fn detail(text: impl Into<String>) {
+    let text = text.into();
+    tracing::debug!("{}", text);
+    record(Entry::Detail(text));
+}
+
+/// Path of the audit log written for an output: the output path with its
+/// extension replaced by ".log".
+pub fn log_path(output: &str) -> String {
+    Path::new(output)
+        .with_extension("log")
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Write the audit log of converting `input` to `output`: the settings,
+/// the decision trail in order, the warnings again in one place, and the
+/// outcome.
+pub fn write_log(
+    path: &str,
+    input: &str,
+    output: &str,
+    options: &ConvertOptions,
+    trail: &[Entry],
+    outcome: &Result<()>,
+) -> Result<()> {
+    let now = provenance::conversion_time(options.deterministic).format("%Y-%m-%dT%H:%M:%SZ");
+    let mut log = String::new();
+    writeln!(
+        log,
+        "{} {} conversion log",
+        TOOL_NAME,
+        env!("CARGO_PKG_VERSION")
+    )?;
+    writeln!(log, "Written: {}", now)?;
+    let input_hash = std::fs::read(input).map(|bytes| sha256_hex(&bytes));
+    match input_hash {
+        Ok(hash) => writeln!(log, "Input: {} (SHA-256 {})", input, hash)?,
+        Err(_) => writeln!(log, "Input: {} (not readable)", input)?,
+    }
+    writeln!(log, "Output: {}", output)?;
+
+    writeln!(log, "\nSettings")?;
+    for (name, value) in settings(options) {
+        writeln!(log, "  {}: {}", name, value)?;
+    }
+
+    writeln!(log, "\nDecision trail")?;
+    for entry in trail {
+        let (mark, text) = match entry {
+            Entry::Progress(text) => (' ', text),
+            Entry::Warning(text) => ('!', text),
+            Entry::Detail(text) => ('-', text),
+        };
+        for (i, line) in text.trim_start_matches('\n').lines().enumerate() {
+            let mark = if i == 0 { mark } else { ' ' };
+            writeln!(log, "{} {}", mark, line)?;
+        }
+    }
+
+    let warnings: Vec<&String> = trail
+        .iter()
+        .filter_map(|entry| match entry {
+            Entry::Warning(text) => Some(text),
+            _ => None,
+        })
+        .collect();
+    writeln!(log, "\nWarnings: {}", warnings.len())?;
+    for warning in warnings {
+        writeln!(log, "  {}", warning.trim())?;
+    }
+
+    match outcome {
+        Ok(()) => writeln!(log, "\nOutcome: converted")?,
+        Err(e) => writeln!(log, "\nOutcome: failed: {:#}", e)?,
+    }
+    std::fs::write(path, log)?;
+    outln!("Audit log written: {}", path);
+    Ok(())
+}
+
+/// The settings that steer a conversion, by name, for the log.
+fn settings(options: &ConvertOptions) -> Vec<(&'static str, String)> {
+    let or_default = |value: Option<String>, default: &str| value.unwrap_or(default.to_string());
+    vec![
+        (
+            "Extractor",
+            or_default(options.extractor.clone(), "detected from the PDF"),
+        ),
+        ("Layout profile", options.profile.name.clone()),
+        ("Trace", format!("{:?}", options.trace)),
+        ("Extraction overrides", format!("{:?}", options.extraction)),
+        (
+            "Calibration",
+            or_default(
+                options
+                    .cal_pt_per_mv
+                    .map(|c| format!("{} pt/mV (override)", c)),
+                "from the report",
+            ),
+        ),
+        ("Detrend", format!("{:?}", options.detrend)),
+        ("Continuous rows", options.continuous_rows.to_string()),
+        (
+            "Max interpolated gap",
+            or_default(
+                options.max_interpolated_gap.map(|s| format!("{} s", s)),
+                "default",
+            ),
+        ),
+        ("Allow partial", options.allow_partial.to_string()),
+        (
+            "Patient override",
+            if options.header.patient.is_some() {
+                "set"
+            } else {
+                "none"
+            }
+            .to_string(),
+        ),
+        (
+            "Recording ID override",
+            or_default(options.header.recording_id.clone(), "none"),
+        ),
+        (
+            "Lead label, transducer, prefiltering",
+            format!(
+                "{:?}, {:?}, {:?}",
+                options.header.label, options.header.transducer, options.header.prefilter
+            ),
+        ),
+        (
+            "Time shift mapping",
+            or_default(options.time_shift.clone(), "none"),
+        ),
+        ("Anonymize", options.anonymize.to_string()),
+        ("Fix inversion", options.fix_inversion.to_string()),
+        ("Delineate", options.delineate.to_string()),
+        ("Detect pacing", options.detect_pacing.to_string()),
+        ("Despike", options.despike.to_string()),
+        ("Filters", format!("{:?}", options.filters())),
+        ("Format", format!("{:?}", options.format)),
+        ("Unit", options.unit.phys_dim().to_string()),
+        ("Deterministic", options.deterministic.to_string()),
+        (
+            "Segment",
+            or_default(options.segment.map(|s| format!("{} s", s)), "none"),
+        ),
+        (
+            "Window",
+            match options.window_duration {
+                Some(d) => format!("{} s from {} s", d, options.window_start),
+                None => format!("from {} s", options.window_start),
+            },
+        ),
+        ("Text policy", format!("{:?}", options.text_policy)),
+        (
+            "Record duration",
+            or_default(
+                options.record_duration.map(|d| format!("{} s", d)),
+                "default",
+            ),
+        ),
+        ("Heart-rate channel", options.heart_rate_channel.to_string()),
+        ("Physical ranges", format!("{:?}", options.phys_ranges)),
+        ("Quantization", format!("{:?}", options.quantization)),
+        ("Append", options.append.to_string()),
+        ("Verify", options.verify.to_string()),
+        ("Checksum", options.checksum.to_string()),
+        ("Conversion report", options.report_json.to_string()),
+        (
+            "Fail below quality score",
+            or_default(options.qc_fail_below.map(|s| s.to_string()), "none"),
+        ),
+        ("Pre-write hooks", options.hooks.len().to_string()),
+    ]
+}
@@ -0,0 +1,127 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::audit::errln;
+use crate::convert::ConvertOptions;
+use crate::provenance::sha256_hex;
+
+/// Name of the state file kept in the output directory.
+pub const CACHE_FILE: &str = ".kardia2edf-cache.json";
+
+/// The PDFs already converted into an output directory, keyed by input
+/// path with the SHA-256 of the content and settings converted, so re-runs
+/// over a growing archive skip the unchanged ones.
+#[derive(Debug, Default)]
+pub struct ConversionCache {
+    path: PathBuf,
+    state: CacheState,
+}
+
+/// On-disk form of a [`ConversionCache`].
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CacheState {
+    /// Each input PDF's last conversion; a state file from before settings
+    /// were recorded reads as empty, so everything converts once more.
+    #[serde(default)]
+    conversions: BTreeMap<String, Conversion>,
+}
+
+/// What an input PDF was last converted from.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Conversion {
+    /// SHA-256 of the PDF's content.
+    pdf_sha256: String,
+    /// SHA-256 of the settings, from [`ConvertOptions::settings_sha256`].
+    settings_sha256: String,
+}
+
+impl Conversion {
+    fn new(sha256: &str, options: &ConvertOptions) -> Self {
+        Self {
+            pdf_sha256: sha256.to_string(),
+            settings_sha256: options.settings_sha256(),
+        }
+    }
+}
+
+impl ConversionCache {
+    /// Load the cache of an output directory; empty if it has none yet, or
+    /// if its state file can't be read, with a warning.
+    pub fn load(out_dir: &Path) -> Self {
+        let path = out_dir.join(CACHE_FILE);
+        let state = match std::fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_else(|e| {
+                errln!(
+                    "Warning: ignoring unreadable cache {}: {}",
+                    path.display(),
+                    e
+                );
+                CacheState::default()
+            }),
+            Err(_) => CacheState::default(),
+        };
+        Self { path, state }
+    }
+
+    /// Whether `input` was converted with this content, by its SHA-256, and
+    /// these options.
+    pub fn is_converted(&self, input: &Path, sha256: &str, options: &ConvertOptions) -> bool {
+        self.state.conversions.get(&key(input)) == Some(&Conversion::new(sha256, options))
+    }
+
+    /// Record that `input` was converted with this content and these
+    /// options, and save the state file, replacing it whole so a crash
+    /// can't leave half of one.
+    pub fn record(&mut self, input: &Path, sha256: &str, options: &ConvertOptions) -> Result<()> {
+        self.state
+            .conversions
+            .insert(key(input), Conversion::new(sha256, options));
+        let temp = self.path.with_extension("json.tmp");
+        std::fs::write(&temp, serde_json::to_vec_pretty(&self.state)?)
+            .and_then(|()| std::fs::rename(&temp, &self.path))
+            .with_context(|| format!("Failed to save cache {}", self.path.display()))
+    }
+}
+
+/// SHA-256 of a file's content, as lowercase hex.
+pub fn file_sha256(path: &Path) -> Result<String> {
+    Ok(sha256_hex(&std::fs::read(path)?))
+}
+
+/// Cache key of an input: its absolute path where it can be found, so the
+/// same file reached by another relative path still matches.
+fn key(input: &Path) -> String {
+    std::fs::canonicalize(input)
+        .unwrap_or_else(|_| input.to_path_buf())
+        .to_string_lossy()
+        .into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_again_when_the_content_or_settings_change() {
+        let (pdf, options) = (Path::new("ecg.pdf"), ConvertOptions::new());
+        let mut cache = ConversionCache::default();
+        cache
+            .state
+            .conversions
+            .insert(key(pdf), Conversion::new("abc", &options));
+
+        assert!(cache.is_converted(pdf, "abc", &options));
+        assert!(!cache.is_converted(pdf, "abd", &options));
+        assert!(!cache.is_converted(pdf, "abc", &options.clone().notch(50.0)));
+        assert!(!cache.is_converted(Path::new("other.pdf"), "abc", &options));
+    }
+
+    #[test]
+    fn reads_a_state_file_without_settings_as_empty() {
+        let state: CacheState =
+            serde_json::from_str(r#"{"files": {"/in/ecg.pdf": "abc"}}"#).unwrap();
+        assert!(state.conversions.is_empty());
+    }
+}
@@ -0,0 +1,213 @@
+use serde::Serialize;
+
+use crate::convert::ConvertOptions;
+use crate::pdf_extract::DrawingPath;
+
+/// PDF points per millimeter of paper.
+pub const PT_PER_MM: f64 = 72.0 / 25.4;
+
+/// Where a calibration came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CalibrationSource {
+    /// Set in the options, e.g. with `--mm-per-mv`.
+    Override,
+    /// Printed on the report, e.g. "Scale: 25mm/s, 10mm/mV".
+    FooterText,
+    /// Height of a 1 mV calibration pulse drawn on a row.
+    CalibrationPulse,
+    /// Spacing of the ECG paper grid, at 10 mm/mV.
+    GridMeasurement,
+    /// The layout profile's value.
+    Default,
+}
+
+impl CalibrationSource {
+    /// Identifier for messages and provenance, e.g. "footer_text".
+    pub fn name(self) -> &'static str {
+        match self {
+            CalibrationSource::Override => "override",
+            CalibrationSource::FooterText => "footer_text",
+            CalibrationSource::CalibrationPulse => "calibration_pulse",
+            CalibrationSource::GridMeasurement => "grid_measurement",
+            CalibrationSource::Default => "default",
+        }
+    }
+}
+
+/// The scale of the printed trace, and where it came from.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Calibration {
+    pub source: CalibrationSource,
+    /// PDF points per millivolt.
+    pub points_per_mv: f64,
+}
+
+/// What calibration strategies look at.
+pub struct CalibrationInput<'a> {
+    pub options: &'a ConvertOptions,
+    /// Text lines of the report, in reading order.
+    pub lines: &'a [String],
+    /// Drawing paths of the strip page.
+    pub paths: &'a [DrawingPath],
+    /// Baseline y-coordinate of each row.
+    pub baselines: &'a [f64],
+}
+
+/// One way of finding the calibration; [`STRATEGIES`] are tried in order.
+pub trait CalibrationStrategy: Sync {
+    fn source(&self) -> CalibrationSource;
+
+    /// PDF points per millivolt, if this strategy finds it.
+    fn points_per_mv(&self, input: &CalibrationInput) -> Option<f64>;
+}
+
+/// Calibration strategies in order of precedence; the last always applies.
+pub static STRATEGIES: &[&dyn CalibrationStrategy] = &[
+    &Override,
+    &FooterText,
+    &CalibrationPulse,
+    &GridMeasurement,
+    &ProfileDefault,
+];
+
+/// The calibration from the first strategy that finds one.
+pub fn calibrate(input: &CalibrationInput) -> Calibration {
+    STRATEGIES
+        .iter()
+        .find_map(|strategy| {
+            strategy
+                .points_per_mv(input)
+                .filter(|&v| v.is_finite() && v > 0.0)
+                .map(|points_per_mv| Calibration {
+                    source: strategy.source(),
+                    points_per_mv,
+                })
+        })
+        .unwrap_or(Calibration {
+            source: CalibrationSource::Default,
+            points_per_mv: input.options.profile.cal_pt_per_mv,
+        })
+}
+
+/// The calibration set in the options.
+pub struct Override;
+
+impl CalibrationStrategy for Override {
+    fn source(&self) -> CalibrationSource {
+        CalibrationSource::Override
+    }
+
+    fn points_per_mv(&self, input: &CalibrationInput) -> Option<f64> {
+        input.options.cal_pt_per_mv
+    }
+}
+
+/// The scale printed on the report, as "10mm/mV" or "10 mm/mV".
+pub struct FooterText;
+
+impl CalibrationStrategy for FooterText {
+    fn source(&self) -> CalibrationSource {
+        CalibrationSource::FooterText
+    }
+
+    fn points_per_mv(&self, input: &CalibrationInput) -> Option<f64> {
+        input.lines.iter().find_map(|line| {
+            let before = line[..line.find("mm/mV")?].trim_end();
+            let start = before
+                .rfind(|c: char| !c.is_ascii_digit() && c != '.')
+                .map_or(0, |i| i + 1);
+            let mm: f64 = before[start..].parse().ok()?;
+            Some(mm * PT_PER_MM)
+        })
+    }
+}
+
+/// A 1 mV square pulse rising from a baseline: a vertical stroke up, a
+/// short flat top, and a vertical stroke back down by the same height.
+pub struct CalibrationPulse;
+
+impl CalibrationStrategy for CalibrationPulse {
+    fn source(&self) -> CalibrationSource {
+        CalibrationSource::CalibrationPulse
+    }
+
+    fn points_per_mv(&self, input: &CalibrationInput) -> Option<f64> {
+        let on_baseline = |y: f64| input.baselines.iter().any(|b| (b - y).abs() < 1.0);
+        input.paths.iter().find_map(|path| {
+            path.segments.windows(3).find_map(|w| {
+                let ((up0, up1), (top0, top1), (down0, down1)) = (w[0], w[1], w[2]);
+                let height = up0.y - up1.y;
+                let vertical = (up1.x - up0.x).abs() < 0.1 && (down1.x - down0.x).abs() < 0.1;
+                let flat =
+                    (top1.y - top0.y).abs() < 0.1 && (2.0..=20.0).contains(&(top1.x - top0.x));
+                let same_height = (down1.y - down0.y - height).abs() < 0.5;
+                (vertical
+                    && flat
+                    && same_height
+                    && (5.0..=100.0).contains(&height)
+                    && on_baseline(up0.y))
+                .then_some(height)
+            })
+        })
+    }
+}
+
+/// ECG paper grid spacing: evenly spaced horizontal lines 1 mm (minor box)
+/// or 5 mm (major box) apart at the paper's scale, read at 10 mm/mV.
+pub struct GridMeasurement;
+
+/// Fewest evenly spaced lines taken as a grid.
+const MIN_GRID_LINES: usize = 10;
+
+impl CalibrationStrategy for GridMeasurement {
+    fn source(&self) -> CalibrationSource {
+        CalibrationSource::GridMeasurement
+    }
+
+    fn points_per_mv(&self, input: &CalibrationInput) -> Option<f64> {
+        let mut ys: Vec<f64> = input
+            .paths
+            .iter()
+            .flat_map(|p| &p.segments)
+            .filter(|(p1, p2)| (p1.y - p2.y).abs() < 0.01 && (p2.x - p1.x).abs() > 50.0)
+            .map(|(p1, _)| p1.y)
+            .filter(|y| input.baselines.iter().all(|b| (b - y).abs() > 0.5))
+            .collect();
+        ys.sort_by(f64::total_cmp);
+        ys.dedup_by(|b, a| (*b - *a).abs() < 0.1);
+        let mut spacings: Vec<f64> = ys.windows(2).map(|w| w[1] - w[0]).collect();
+        if spacings.len() + 1 < MIN_GRID_LINES {
+            return None;
+        }
+        let mid = spacings.len() / 2;
+        let spacing = *spacings.select_nth_unstable_by(mid, f64::total_cmp).1;
+        let even = spacings
+            .iter()
+            .filter(|&&s| (s - spacing).abs() < spacing * 0.02)
+            .count();
+        if even + 1 < MIN_GRID_LINES {
+            return None;
+        }
+        // Under 8 pt apart is a minor box, else a major box
+        let points_per_mm = if spacing < 8.0 {
+            spacing
+        } else {
+            spacing / 5.0
+        };
+        Some(10.0 * points_per_mm)
+    }
+}
+
+/// The layout profile's calibration.
+pub struct ProfileDefault;
+
+impl CalibrationStrategy for ProfileDefault {
+    fn source(&self) -> CalibrationSource {
+        CalibrationSource::Default
+    }
+
+    fn points_per_mv(&self, input: &CalibrationInput) -> Option<f64> {
+        Some(input.options.profile.cal_pt_per_mv)
+    }
+}
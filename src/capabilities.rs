@@ -0,0 +1,188 @@
+use std::io::Write;
+
+use crate::extractor::EXTRACTORS;
+
+/// Whether a format is read or written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Read,
+    Write,
+}
+
+impl Direction {
+    /// Identifier for the listing, e.g. "read".
+    pub fn name(self) -> &'static str {
+        match self {
+            Direction::Read => "read",
+            Direction::Write => "write",
+        }
+    }
+}
+
+/// A file format this build reads or writes, and what it can hold.
+#[derive(Debug, Clone, Copy)]
+pub struct FormatInfo {
+    /// Format name, e.g. "EDF+".
+    pub name: &'static str,
+    pub direction: Direction,
+    /// How to select it, e.g. "--bdf".
+    pub usage: &'static str,
+    /// Holds more than one signal.
+    pub multi_lead: bool,
+    /// Holds annotations, e.g. R peaks.
+    pub annotations: bool,
+    /// Holds the samples without quantizing them to integer steps.
+    pub lossless: bool,
+    /// One-line description.
+    pub description: &'static str,
+}
+
+/// The formats this build reads and writes.
+pub static FORMATS: &[FormatInfo] = &[
+    FormatInfo {
+        name: "PDF",
+        direction: Direction::Read,
+        usage: "convert <PDF>",
+        multi_lead: false,
+        annotations: false,
+        lossless: true,
+        description: "Vector ECG report, or scanned strip image; see --list-profiles",
+    },
+    FormatInfo {
+        name: "EDF+/BDF+",
+        direction: Direction::Read,
+        usage: "inspect <FILE>, --append",
+        multi_lead: true,
+        annotations: true,
+        lossless: false,
+        description: "Header and signals of an existing file",
+    },
+    FormatInfo {
+        name: "EDF+",
+        direction: Direction::Write,
+        usage: "(default)",
+        multi_lead: true,
+        annotations: true,
+        lossless: false,
+        description: "16-bit samples; continuous or discontinuous (EDF+D)",
+    },
+    FormatInfo {
+        name: "BDF+",
+        direction: Direction::Write,
+        usage: "--bdf",
+        multi_lead: true,
+        annotations: true,
+        lossless: false,
+        description: "24-bit samples, for finer quantization",
+    },
+    FormatInfo {
+        name: "EDF",
+        direction: Direction::Write,
+        usage: "--edf-classic",
+        multi_lead: false,
+        annotations: false,
+        lossless: false,
+        description: "Plain 16-bit EDF with the ECG signal only, for legacy software",
+    },
+    FormatInfo {
+        name: "GDF",
+        direction: Direction::Write,
+        usage: "--gdf",
+        multi_lead: true,
+        annotations: true,
+        lossless: true,
+        description: "GDF v2 with 32-bit float samples, for BioSig and SigViewer",
+    },
+    FormatInfo {
+        name: "DICOM",
+        direction: Direction::Write,
+        usage: "--dicom FILE",
+        multi_lead: false,
+        annotations: true,
+        lossless: false,
+        description: "General ECG waveform, 16-bit at 1 uV per bit",
+    },
+    FormatInfo {
+        name: "JSON",
+        direction: Direction::Write,
+        usage: "--json FILE",
+        multi_lead: true,
+        annotations: true,
+        lossless: true,
+        description: "Waveform with calibration and report metadata",
+    },
+    FormatInfo {
+        name: "CSV",
+        direction: Direction::Write,
+        usage: "--csv FILE",
+        multi_lead: true,
+        annotations: false,
+        lossless: true,
+        description: "Samples with a time column",
+    },
+    FormatInfo {
+        name: "NPY",
+        direction: Direction::Write,
+        usage: "--npy FILE",
+        multi_lead: true,
+        annotations: false,
+        lossless: true,
+        description: "NumPy float64 array",
+    },
+];
+
+fn yes_no(value: bool) -> &'static str {
+    if value {
+        "yes"
+    } else {
+        "no"
+    }
+}
+
+/// Print [`FORMATS`] as a table to `out`.
+pub fn list_formats(out: &mut impl Write) -> std::io::Result<()> {
+    writeln!(
+        out,
+        "{:<10} {:<6} {:<26} {:<6} {:<6} {:<8} DESCRIPTION",
+        "FORMAT", "MODE", "USAGE", "LEADS", "ANNOT", "LOSSLESS"
+    )?;
+    for f in FORMATS {
+        writeln!(
+            out,
+            "{:<10} {:<6} {:<26} {:<6} {:<6} {:<8} {}",
+            f.name,
+            f.direction.name(),
+            f.usage,
+            if f.multi_lead { "multi" } else { "one" },
+            yes_no(f.annotations),
+            yes_no(f.lossless),
+            f.description
+        )?;
+    }
+    Ok(())
+}
+
+/// Print each built-in extractor with its layout profile to `out`, for
+/// `--profile`.
+pub fn list_profiles(out: &mut impl Write) -> std::io::Result<()> {
+    for extractor in EXTRACTORS {
+        writeln!(out, "{}: {}", extractor.name(), extractor.description())?;
+        if extractor.name() == "generic" {
+            writeln!(out, "  layout detected from the PDF's stroked paths")?;
+            continue;
+        }
+        let layout = extractor.layout();
+        let trace = &layout.trace;
+        writeln!(
+            out,
+            "  page {}, {} Hz, at least {} rows, {:.3} pt/mV by default",
+            layout.page, layout.sample_rate, layout.rows, layout.cal_pt_per_mv
+        )?;
+        writeln!(
+            out,
+            "  trace color {:?}, width {}-{} pt, at least {} segments",
+            trace.color, trace.width_min, trace.width_max, trace.min_segments
+        )?;
+    }
+    Ok(())
+}
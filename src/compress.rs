@@ -0,0 +1,161 @@
+use anyhow::Result;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+
+/// Compression of an output file, chosen by its extension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    /// gzip, as `gzip` and most archives read it (`.gz`).
+    Gzip,
+    /// Zstandard (`.zst`), smaller and faster; needs the `zstd` feature.
+    Zstd,
+}
+
+impl Compression {
+    /// File name extension, without the dot.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Compression::Gzip => "gz",
+            Compression::Zstd => "zst",
+        }
+    }
+
+    /// The compression a path's extension calls for, if any.
+    pub fn of_path(path: &str) -> Option<Self> {
+        [Compression::Gzip, Compression::Zstd]
+            .into_iter()
+            .find(|c| has_extension(path, c.extension()))
+    }
+
+    /// `path` with this compression's extension added, e.g. "ecg.edf" gives
+    /// "ecg.edf.gz", unless it has it already.
+    pub fn add_extension(self, path: &str) -> String {
+        if has_extension(path, self.extension()) {
+            path.to_string()
+        } else {
+            format!("{}.{}", path, self.extension())
+        }
+    }
+}
+
+/// `path` without a `.gz` or `.zst` extension, and the compression it
+/// called for, so names can be built on the uncompressed file's name.
+pub fn split_extension(path: &str) -> (&str, Option<Compression>) {
+    match Compression::of_path(path) {
+        Some(compression) => {
+            let end = path.len() - compression.extension().len() - 1;
+            (&path[..end], Some(compression))
+        }
+        None => (path, None),
+    }
+}
+
+fn has_extension(path: &str, extension: &str) -> bool {
+    std::path::Path::new(path)
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case(extension))
+}
+
+/// A writer compressing into `W`, or passing through to it.
+pub enum Encoder<W: Write> {
+    Plain(W),
+    Gzip(GzEncoder<W>),
+    #[cfg(feature = "zstd")]
+    Zstd(zstd::Encoder<'static, W>),
+}
+
+impl<W: Write> Encoder<W> {
+    /// Compress into `out` with `compression`, or not at all with `None`.
+    pub fn new(compression: Option<Compression>, out: W) -> Result<Self> {
+        Ok(match compression {
+            None => Encoder::Plain(out),
+            Some(Compression::Gzip) => {
+                Encoder::Gzip(GzEncoder::new(out, flate2::Compression::default()))
+            }
+            #[cfg(feature = "zstd")]
+            Some(Compression::Zstd) => Encoder::Zstd(zstd::Encoder::new(out, 0)?),
+            #[cfg(not(feature = "zstd"))]
+            Some(Compression::Zstd) => return Err(no_zstd()),
+        })
+    }
+
+    /// Write the end of the compressed stream and flush, returning `W`.
+    pub fn finish(self) -> Result<W> {
+        let mut out = match self {
+            Encoder::Plain(out) => out,
+            Encoder::Gzip(encoder) => encoder.finish()?,
+            #[cfg(feature = "zstd")]
+            Encoder::Zstd(encoder) => encoder.finish()?,
+        };
+        out.flush()?;
+        Ok(out)
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Encoder::Plain(out) => out.write(buf),
+            Encoder::Gzip(encoder) => encoder.write(buf),
+            #[cfg(feature = "zstd")]
+            Encoder::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Encoder::Plain(out) => out.flush(),
+            Encoder::Gzip(encoder) => encoder.flush(),
+            #[cfg(feature = "zstd")]
+            Encoder::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+/// Create the file at `path`, compressed as its extension calls for. Call
+/// [`Encoder::finish`] once written.
+pub fn create(path: &str) -> Result<Encoder<BufWriter<File>>> {
+    let compression = Compression::of_path(path);
+    #[cfg(not(feature = "zstd"))]
+    if compression == Some(Compression::Zstd) {
+        return Err(no_zstd());
+    }
+    Encoder::new(compression, BufWriter::new(File::create(path)?))
+}
+
+/// Write `bytes` to the file at `path`, compressed as its extension calls for.
+pub fn write(path: &str, bytes: impl AsRef<[u8]>) -> Result<()> {
+    let mut file = create(path)?;
+    file.write_all(bytes.as_ref())?;
+    file.finish()?;
+    Ok(())
+}
+
+/// `bytes` compressed with `compression`.
+pub fn encode(compression: Compression, bytes: &[u8]) -> Result<Vec<u8>> {
+    let mut encoder = Encoder::new(Some(compression), Vec::new())?;
+    encoder.write_all(bytes)?;
+    encoder.finish()
+}
+
+/// Read the file at `path`, decompressed as its extension calls for.
+pub fn read(path: &str) -> Result<Vec<u8>> {
+    let file = File::open(path)?;
+    let mut bytes = Vec::new();
+    match Compression::of_path(path) {
+        None => std::io::BufReader::new(file).read_to_end(&mut bytes)?,
+        Some(Compression::Gzip) => GzDecoder::new(file).read_to_end(&mut bytes)?,
+        #[cfg(feature = "zstd")]
+        Some(Compression::Zstd) => zstd::Decoder::new(file)?.read_to_end(&mut bytes)?,
+        #[cfg(not(feature = "zstd"))]
+        Some(Compression::Zstd) => return Err(no_zstd()),
+    };
+    Ok(bytes)
+}
+
+#[cfg(not(feature = "zstd"))]
+fn no_zstd() -> anyhow::Error {
+    anyhow::anyhow!("Zstandard compression needs the zstd feature; build with --features zstd")
+}
@@ -0,0 +1,137 @@
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+use crate::calibration::PT_PER_MM;
+use crate::convert::ConvertOptions;
+use crate::dicom_write::DicomStudy;
+use crate::edf_write::EdfHeader;
+use crate::extractor;
+use crate::profile::LayoutProfile;
+use crate::signal_writer::OutputFormat;
+
+/// Name of the config file read from the current directory when no
+/// `--config` is given.
+pub const CONFIG_FILE: &str = "kardia2edf.toml";
+
+/// Conversion defaults from a `kardia2edf.toml` file, so a clinic's
+/// calibration, patient fields, output format, filters, and vendor profile
+/// needn't be given on every command line. Every field is optional; the
+/// command line overrides what the file sets.
+///
+/// ```toml
+/// format = "bdf"
+/// profile = "kardiamobile-1l"
+/// mm_per_mv = 10.0
+/// recording_id = "X X Cardiology_Clinic"
+/// highpass = 0.5
+/// notch = 50
+///
+/// [dicom]
+/// referring_physician = "Doe^Jane"
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Output format: "edf" (EDF+), "bdf" (BDF+), "gdf", or "edf-classic".
+    pub format: Option<OutputFormat>,
+    /// Built-in profile, a vendor's extractor and layout, e.g.
+    /// "kardiamobile-1l" or "generic".
+    pub profile: Option<String>,
+    /// JSON layout profile, relative to the config file.
+    pub layout_profile: Option<PathBuf>,
+    /// Calibration in millimeters per millivolt, overriding the scale
+    /// printed on or measured from the report.
+    pub mm_per_mv: Option<f64>,
+    /// EDF+ patient identification field, e.g. "X F 02-AUG-1951 Jane_Doe".
+    pub patient: Option<String>,
+    /// EDF+ recording identification field, after the start date unless it
+    /// begins with "Startdate".
+    pub recording_id: Option<String>,
+    /// High-pass filter cutoff in Hz.
+    pub highpass: Option<f64>,
+    /// Low-pass filter cutoff in Hz.
+    pub lowpass: Option<f64>,
+    /// Mains frequency of the notch filter, 50 or 60 Hz.
+    pub notch: Option<f64>,
+    /// DICOM patient and study attributes.
+    #[serde(default)]
+    pub dicom: DicomStudy,
+}
+
+impl Config {
+    /// Read and check a config file.
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Cannot read config file {}", path.display()))?;
+        let mut config: Self = toml::from_str(&text)
+            .with_context(|| format!("Invalid config file {}", path.display()))?;
+        config
+            .check()
+            .with_context(|| format!("Invalid config file {}", path.display()))?;
+        if let (Some(layout), Some(dir)) = (&config.layout_profile, path.parent()) {
+            config.layout_profile = Some(dir.join(layout));
+        }
+        Ok(config)
+    }
+
+    /// The config file to use: `path` if given, otherwise
+    /// [`CONFIG_FILE`] in the current directory if there is one.
+    pub fn find(path: Option<&str>) -> Result<Option<Self>> {
+        match path {
+            Some(path) => Self::from_file(Path::new(path)).map(Some),
+            None if Path::new(CONFIG_FILE).is_file() => {
+                Self::from_file(Path::new(CONFIG_FILE)).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Check the values the TOML types don't.
+    fn check(&self) -> Result<()> {
+        if let Some(name) = &self.profile {
+            extractor::find(name)?;
+        }
+        if let Some(mm) = self.mm_per_mv.filter(|mm| !(mm.is_finite() && *mm > 0.0)) {
+            return Err(anyhow!("mm_per_mv must be positive, got {}", mm));
+        }
+        if let Some(hz) = self.notch.filter(|hz| *hz != 50.0 && *hz != 60.0) {
+            return Err(anyhow!("notch must be 50 or 60, got {}", hz));
+        }
+        Ok(())
+    }
+
+    /// Set these defaults on `options`.
+    pub fn apply(&self, mut options: ConvertOptions) -> Result<ConvertOptions> {
+        if let Some(format) = self.format {
+            options = options.format(format);
+        }
+        if let Some(name) = &self.profile {
+            options = options.extractor(name);
+        }
+        if let Some(path) = &self.layout_profile {
+            options = options.profile(LayoutProfile::from_file(&path.to_string_lossy())?);
+        }
+        if let Some(mm) = self.mm_per_mv {
+            options = options.cal_pt_per_mv(mm * PT_PER_MM);
+        }
+        let mut header = EdfHeader::new();
+        if let Some(patient) = &self.patient {
+            header = header.patient(patient);
+        }
+        if let Some(id) = &self.recording_id {
+            header = header.recording_id(id);
+        }
+        options = options.header(header);
+        if let Some(hz) = self.highpass {
+            options = options.highpass(hz);
+        }
+        if let Some(hz) = self.lowpass {
+            options = options.lowpass(hz);
+        }
+        if let Some(hz) = self.notch {
+            options = options.notch(hz);
+        }
+        Ok(options.dicom_study(self.dicom.clone()))
+    }
+}
@@ -0,0 +1,221 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::path::Path;
+
+use crate::audit::{outln, Entry};
+use crate::delineate::{self, Intervals};
+use crate::ecg_process::PathCounts;
+use crate::hrv::{self, Irregularity};
+use crate::qc::{self, QcScore};
+use crate::recording::EcgRecording;
+use crate::sample_flags::{self, SampleFlag};
+
+/// Version of the JSON layout below; bumped on incompatible changes.
+const FORMAT_VERSION: u32 = 1;
+
+/// Top-level JSON document.
+#[derive(Serialize)]
+struct ConversionReport<'a> {
+    format_version: u32,
+    input: &'a str,
+    output: &'a str,
+    /// "converted" or "failed".
+    outcome: &'static str,
+    error: Option<String>,
+    /// Whether the conversion failed or printed warnings.
+    needs_review: bool,
+    warnings: Vec<&'a str>,
+    /// Statistics of the recording read; `None` if it couldn't be read.
+    extraction: Option<Extraction<'a>>,
+}
+
+#[derive(Serialize)]
+struct Extraction<'a> {
+    /// Layout profile name.
+    profile: &'a str,
+    calibration: Calibration<'a>,
+    pages: Vec<Page>,
+    rows_detected: usize,
+    samples: usize,
+    sample_rate: usize,
+    duration_seconds: f64,
+    /// Lowest and highest voltage, in millivolts.
+    voltage_range_mv: Option<[f64; 2]>,
+    samples_by_flag: SamplesByFlag,
+    /// Gaps in rows filled by interpolation.
+    gaps_filled: usize,
+    /// Gaps too long to interpolate, left out of EDF+/BDF+ files.
+    gaps_missing: usize,
+    heart_rate: HeartRate<'a>,
+    /// Median PR, QRS, QT, and QTc intervals of the delineated beats.
+    intervals: Intervals,
+    qc: QcScore,
+}
+
+#[derive(Serialize)]
+struct Calibration<'a> {
+    points_per_mv: f64,
+    /// Where the calibration came from, e.g. "footer_text".
+    source: &'a str,
+}
+
+#[derive(Serialize)]
+struct Page {
+    page: u32,
+    /// Drawing paths read as trace; `None` for traced images.
+    paths: Option<PathCounts>,
+    rows: Vec<Row>,
+}
+
+#[derive(Serialize)]
+struct Row {
+    row: usize,
+    trace_points: usize,
+    samples: usize,
+    /// Offset removed by detrending, in millivolts.
+    offset_mv: f64,
+    /// Whether the row had no usable trace and is all missing samples.
+    failed: bool,
+}
+
+#[derive(Serialize)]
+struct SamplesByFlag {
+    measured: usize,
+    interpolated: usize,
+    missing: usize,
+    clipped: usize,
+}
+
+#[derive(Serialize)]
+struct HeartRate<'a> {
+    /// Mean heart rate from the detected R-peaks, in beats per minute.
+    computed_bpm: Option<f64>,
+    /// Heart rate printed on the report, in beats per minute.
+    printed_bpm: Option<f64>,
+    beats: usize,
+    /// RR-interval irregularity and whether it suggests atrial
+    /// fibrillation, next to the determination printed on the report.
+    irregularity: Option<Irregularity>,
+    printed_determination: Option<&'a str>,
+}
+
+/// Path of the report written for an output: the output path with its
+/// extension replaced by ".report.json".
+pub fn report_path(output: &str) -> String {
+    Path::new(output)
+        .with_extension("report.json")
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Write the JSON report of converting `input` to `output`: the outcome,
+/// the warnings from the audit trail, and, if the recording was read, its
+/// extraction statistics (paths found, rows and samples per row, gaps
+/// filled, calibration, voltage range, heart rate, and PR, QRS, QT, and QTc
+/// intervals), for batch pipelines to pick out the conversions that need
+/// review.
+pub fn write_report(
+    path: &str,
+    input: &str,
+    output: &str,
+    recording: Option<&EcgRecording>,
+    trail: &[Entry],
+    outcome: &Result<()>,
+) -> Result<()> {
+    let warnings: Vec<&str> = trail
+        .iter()
+        .filter_map(|entry| match entry {
+            Entry::Warning(text) => Some(text.trim()),
+            _ => None,
+        })
+        .collect();
+    let report = ConversionReport {
+        format_version: FORMAT_VERSION,
+        input,
+        output,
+        outcome: if outcome.is_ok() {
+            "converted"
+        } else {
+            "failed"
+        },
+        error: outcome.as_ref().err().map(|e| format!("{:#}", e)),
+        needs_review: outcome.is_err() || !warnings.is_empty(),
+        warnings,
+        extraction: recording.map(extraction),
+    };
+    std::fs::write(path, serde_json::to_string_pretty(&report)? + "\n")?;
+    outln!("Conversion report written: {}", path);
+    Ok(())
+}
+
+fn extraction(recording: &EcgRecording) -> Extraction<'_> {
+    let pages: Vec<Page> = recording
+        .rows
+        .iter()
+        .map(|(&page, rows)| Page {
+            page,
+            paths: recording.paths.get(&page).copied(),
+            rows: rows
+                .iter()
+                .map(|r| Row {
+                    row: r.row,
+                    trace_points: r.trace_points,
+                    samples: r.voltages.len(),
+                    offset_mv: r.offset,
+                    failed: r.x_range.is_none(),
+                })
+                .collect(),
+        })
+        .collect();
+    let count = |of: SampleFlag| recording.flags.iter().filter(|&&f| f == of).count();
+    let spans = |of: SampleFlag| sample_flags::spans(&recording.flags, of, recording.sample_rate);
+    let (min, max) = recording
+        .signal
+        .iter()
+        .fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), &v| {
+            (lo.min(v), hi.max(v))
+        });
+
+    let fs = recording.sample_rate as f64;
+    let r_peaks: Vec<usize> = recording
+        .annotations
+        .iter()
+        .filter(|a| a.text == "R")
+        .map(|a| (a.onset * fs).round() as usize)
+        .collect();
+    let hrv = hrv::summarize(&r_peaks, recording.sample_rate);
+    let provenance = &recording.provenance;
+    Extraction {
+        profile: &provenance.calibration.profile,
+        calibration: Calibration {
+            points_per_mv: provenance.calibration.points_per_mv,
+            source: &provenance.calibration.source,
+        },
+        rows_detected: pages.iter().map(|p| p.rows.len()).sum(),
+        pages,
+        samples: recording.signal.len(),
+        sample_rate: recording.sample_rate,
+        duration_seconds: recording.duration(),
+        voltage_range_mv: (min <= max).then_some([min, max]),
+        samples_by_flag: SamplesByFlag {
+            measured: count(SampleFlag::Measured),
+            interpolated: count(SampleFlag::Interpolated),
+            missing: count(SampleFlag::Missing),
+            clipped: count(SampleFlag::Clipped),
+        },
+        gaps_filled: spans(SampleFlag::Interpolated).len(),
+        gaps_missing: spans(SampleFlag::Missing).len(),
+        heart_rate: HeartRate {
+            computed_bpm: hrv.as_ref().map(|h| h.mean_hr_bpm),
+            printed_bpm: recording.report.heart_rate_bpm,
+            beats: r_peaks.len(),
+            irregularity: hrv.and_then(|h| h.irregularity),
+            printed_determination: recording.report.determination.as_deref(),
+        },
+        intervals: delineate::intervals(
+            &delineate::delineate(&recording.signal, &r_peaks, recording.sample_rate),
+            recording.sample_rate,
+        ),
+        qc: qc::score(recording),
+    }
+}
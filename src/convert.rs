@@ -0,0 +1,1570 @@
+use anyhow::{anyhow, Context, Result};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::audit::{self, errln, outln};
+use crate::compress::{self, Compression};
+use crate::dicom_write::{self, DicomStudy};
+use crate::dsp::Filter;
+use crate::ecg_process::{Detrend, RowProcessing};
+use crate::edf_write::{self, Annotation, EdfHeader, EdfSignal, Gap, WriteSettings};
+use crate::extractor::{self, EcgPdfExtractor, ExtractedEcg, ReportPdf, TraceChoice};
+use crate::failure::Failure;
+use crate::pdf_text::DateOrder;
+#[cfg(feature = "plot")]
+use crate::plot::{self, PlotSettings};
+use crate::profile::{ExtractionConfig, LayoutProfile};
+use crate::provenance::{self, Provenance};
+use crate::recording::EcgRecording;
+use crate::sample_flags::{self, SampleFlag};
+use crate::signal_writer::{OutputFormat, Quantization, Unit};
+use crate::text_policy::TextPolicy;
+use crate::{
+    conversion_report, delineate, dsp, ecg_process, hrv, noise, qc, sample_export, time_shift,
+    verify, waveform_json,
+};
+
+/// Longest gap in a strip row, in seconds, filled by interpolation unless
+/// set otherwise; longer gaps make the EDF+ output discontinuous.
+pub const DEFAULT_MAX_INTERPOLATED_GAP: f64 = 0.25;
+
+/// A pre-write hook, run on the recording between processing and writing.
+pub type Hook = fn(&mut EcgRecording) -> Result<()>;
+
+/// A step of a long-running conversion, passed to the
+/// [`ConvertOptions::on_progress`] callback.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Progress {
+    /// `done` of the `total` input files of a merge or watch folder read.
+    Files { done: usize, total: usize },
+    /// `done` of the `total` strip pages digitized, with `rows` strip rows
+    /// read from them so far.
+    Pages {
+        done: usize,
+        total: usize,
+        rows: usize,
+    },
+}
+
+/// A progress callback, called on the converting thread after each step.
+pub type ProgressFn = fn(Progress);
+
+/// Options for [`convert`].
+///
+/// Construct with [`ConvertOptions::new`] (or `Default`) and the builder
+/// methods. The struct is non-exhaustive so new options can be added in
+/// minor releases without breaking callers.
+///
+/// ```no_run
+/// use kardiamobile_1l_ecg_convert_pdf_to_edf::{convert, ConvertOptions};
+///
+/// let options = ConvertOptions::new().append(true);
+/// convert("ecg.pdf", "session.edf", &options)?;
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+#[derive(Debug, Clone, Default)]
+#[non_exhaustive]
+pub struct ConvertOptions {
+    /// Name of the extractor reading the PDF; detected from the PDF if unset.
+    pub extractor: Option<String>,
+    /// Which strip to read when the report has enhanced and original ones.
+    pub trace: TraceChoice,
+    /// Offset removed from each strip row before concatenation.
+    pub detrend: Detrend,
+    /// Trim repeated samples and remove steps where strip rows join.
+    pub continuous_rows: bool,
+    /// Longest gap in a row, in seconds, filled by interpolation; see
+    /// [`DEFAULT_MAX_INTERPOLATED_GAP`].
+    pub max_interpolated_gap: Option<f64>,
+    /// Write the rows that extract when others fail, with the failed rows
+    /// as missing samples, instead of failing.
+    pub allow_partial: bool,
+    /// Page, default calibration, sampling rate, and strip geometry of the PDF.
+    pub profile: LayoutProfile,
+    /// Overrides of the trace filter of whichever profile is used.
+    pub extraction: ExtractionConfig,
+    /// Calibration in PDF points per millivolt, overriding the one printed
+    /// on or measured from the report.
+    pub cal_pt_per_mv: Option<f64>,
+    /// Header fields overriding the ones built from the PDF: the EDF+
+    /// patient and recording identification, and the ECG lead's label,
+    /// transducer, and prefiltering.
+    pub header: EdfHeader,
+    /// Shift timestamps by a per-patient offset kept in this mapping file.
+    pub time_shift: Option<String>,
+    /// Replace the patient identification read from the PDF with "X"s.
+    pub anonymize: bool,
+    /// Append to the output as a new segment of an EDF+D session file.
+    pub append: bool,
+    /// Compress the output and its CSV and JSON sidecars.
+    pub compress: Option<Compression>,
+    /// Write the heart-rate/HRV summary to this JSON file.
+    pub hrv_json: Option<String>,
+    /// Also write the waveform and its metadata to this JSON file.
+    pub json: Option<String>,
+    /// Where to write the signal plotted on ECG paper, if anywhere.
+    #[cfg(feature = "plot")]
+    pub plot: Option<String>,
+    /// Resolution and rows of the plot.
+    #[cfg(feature = "plot")]
+    pub plot_settings: PlotSettings,
+    /// Also write a DICOM General ECG waveform to this file.
+    pub dicom: Option<String>,
+    /// Patient and study attributes for the DICOM output.
+    pub dicom_study: DicomStudy,
+    /// Also write the samples as CSV to this file.
+    pub csv: Option<String>,
+    /// Also write the samples as a NumPy array to this file.
+    pub npy: Option<String>,
+    /// Write the CSV and NumPy exports as one file per signal.
+    pub split_channels: bool,
+    /// Write each row's trace points as CSV to this directory.
+    pub debug_rows: Option<String>,
+    /// Write the extracted trace paths and final samples as SVG to this file.
+    pub debug_overlay: Option<String>,
+    /// Add a channel flagging each sample as measured, interpolated, padded, missing, or clipped.
+    pub flags_channel: bool,
+    /// Write each sample's flag as CSV to this file.
+    pub flags_csv: Option<String>,
+    /// Add a channel of the beat-to-beat heart rate from the R-peaks.
+    pub heart_rate_channel: bool,
+    /// Output format: 16-bit EDF+, 24-bit BDF+, or GDF.
+    pub format: OutputFormat,
+    /// How non-ASCII characters in header text fields are written.
+    pub text_policy: TextPolicy,
+    /// Order of the day and month in numeric dates, overriding the profile's.
+    pub date_order: DateOrder,
+    /// Data record duration in seconds, if not the default.
+    pub record_duration: Option<f64>,
+    /// Physical (min, max) of signals by label, instead of the range of
+    /// each signal's own samples.
+    pub phys_ranges: BTreeMap<String, (f64, f64)>,
+    /// Fixed resolution of the ECG signals, instead of fitting each
+    /// recording's physical range to its samples.
+    pub quantization: Option<Quantization>,
+    /// Physical dimension of the ECG signals.
+    pub unit: Unit,
+    /// Seconds of the recording skipped before the output starts.
+    pub window_start: f64,
+    /// Seconds of the recording written, from `window_start`; all if `None`.
+    pub window_duration: Option<f64>,
+    /// Split the output into files of this many seconds.
+    pub segment: Option<f64>,
+    /// Fix the header fields that vary between runs, for byte-identical
+    /// output.
+    pub deterministic: bool,
+    /// Pre-write hooks, run in order.
+    pub hooks: Vec<Hook>,
+    /// Called as strip pages and batch files are done.
+    pub on_progress: Option<ProgressFn>,
+    /// Write the conversion's decision trail to a `.log` next to the output.
+    pub audit_log: bool,
+    /// Write extraction statistics and warnings to a `.report.json` next to
+    /// the output.
+    pub report_json: bool,
+    /// Re-read the written file and check it against the signals.
+    pub verify: bool,
+    /// Write the output's SHA-256 to a `.sha256` sidecar.
+    pub checksum: bool,
+    /// Fail conversions whose quality score is below this, from 0 to 1.
+    pub qc_fail_below: Option<f64>,
+    /// Annotate each beat's P, QRS, and T wave boundaries.
+    pub delineate: bool,
+    /// Annotate pacemaker spikes as "Pacing".
+    pub detect_pacing: bool,
+    /// Replace single-sample spikes at PDF subpath joins.
+    pub despike: bool,
+    /// Flip lead I when most of its beats are inverted.
+    pub fix_inversion: bool,
+    /// Cutoff in Hz of a high-pass filter removing baseline wander.
+    pub highpass: Option<f64>,
+    /// Cutoff in Hz of a low-pass filter smoothing digitization steps.
+    pub lowpass: Option<f64>,
+    /// Mains frequency in Hz to remove with a notch filter.
+    pub notch: Option<f64>,
+}
+
+impl ConvertOptions {
+    /// Options with the KardiaMobile 1L defaults.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read PDFs with the named extractor (see [`extractor::EXTRACTORS`])
+    /// instead of detecting it from each PDF.
+    pub fn extractor(mut self, name: impl Into<String>) -> Self {
+        self.extractor = Some(name.into());
+        self
+    }
+
+    /// Read the enhanced-filter strip (the default), the original
+    /// unfiltered one, or both as two signals.
+    pub fn trace(mut self, trace: TraceChoice) -> Self {
+        self.trace = trace;
+        self
+    }
+
+    /// Remove each strip row's median or mean before the rows are joined,
+    /// a quick fix for printed DC offsets when baseline-wander filtering
+    /// is more than needed.
+    pub fn detrend(mut self, detrend: Detrend) -> Self {
+        self.detrend = detrend;
+        self
+    }
+
+    /// Join strip rows continuously: trim samples a row repeats from the
+    /// end of the previous one, and shift a row that starts with a step
+    /// away from where the previous one ended.
+    pub fn continuous_rows(mut self, continuous: bool) -> Self {
+        self.continuous_rows = continuous;
+        self
+    }
+
+    /// Interpolate gaps in strip rows, where trace points are missing, up to
+    /// this many seconds long; data records wholly inside longer gaps are
+    /// left out of EDF+/BDF+ output, which is then discontinuous (EDF+D).
+    pub fn max_interpolated_gap(mut self, seconds: f64) -> Self {
+        self.max_interpolated_gap = Some(seconds);
+        self
+    }
+
+    /// Write the strip rows that extract when others fail heuristics, with
+    /// each failed row as missing samples: left out of EDF+/BDF+ output,
+    /// which is then discontinuous (EDF+D), and annotated as a gap.
+    pub fn allow_partial(mut self, allow_partial: bool) -> Self {
+        self.allow_partial = allow_partial;
+        self
+    }
+
+    /// How strip rows at `sample_rate` are cleaned up as they are joined,
+    /// from these options.
+    pub fn row_processing(&self, sample_rate: usize) -> RowProcessing {
+        let max_gap = self
+            .max_interpolated_gap
+            .unwrap_or(DEFAULT_MAX_INTERPOLATED_GAP);
+        RowProcessing {
+            max_interpolated_gap: Some((max_gap * sample_rate as f64).round() as usize),
+            detrend: self.detrend,
+            continuous: self.continuous_rows,
+            allow_partial: self.allow_partial,
+        }
+    }
+
+    /// Use a layout profile, e.g. one read with [`LayoutProfile::from_file`].
+    pub fn profile(mut self, profile: LayoutProfile) -> Self {
+        self.profile = profile;
+        self
+    }
+
+    /// Override the trace color, width, or segment count of whichever layout
+    /// profile is used, including an extractor's own.
+    pub fn extraction(mut self, extraction: ExtractionConfig) -> Self {
+        self.extraction = extraction;
+        self
+    }
+
+    /// Set the page number (1-based) holding the ECG strip.
+    pub fn page(mut self, page: u32) -> Self {
+        self.profile.page = page;
+        self
+    }
+
+    /// Override the calibration, in PDF points per millivolt (see
+    /// [`crate::calibration`] for where it comes from otherwise).
+    pub fn cal_pt_per_mv(mut self, cal_pt_per_mv: f64) -> Self {
+        self.cal_pt_per_mv = Some(cal_pt_per_mv);
+        self
+    }
+
+    /// Set the sampling rate of the printed trace's points, in Hz.
+    pub fn sample_rate(mut self, sample_rate: usize) -> Self {
+        self.profile.sample_rate = sample_rate;
+        self
+    }
+
+    /// Override the EDF+ patient identification field.
+    pub fn patient(mut self, patient: impl Into<String>) -> Self {
+        self.header = self.header.patient(&patient.into());
+        self
+    }
+
+    /// Override header fields: the patient and recording identification,
+    /// and the ECG lead's label, transducer, and prefiltering.
+    pub fn header(mut self, header: EdfHeader) -> Self {
+        self.header = header;
+        self
+    }
+
+    /// Shift the start date/time and birthdate by a random number of days
+    /// per patient, kept in this JSON mapping file (created if missing), so
+    /// research datasets are temporally de-identified while intervals
+    /// between a patient's recordings are kept; see [`crate::time_shift`].
+    pub fn time_shift(mut self, mapping_path: impl Into<String>) -> Self {
+        self.time_shift = Some(mapping_path.into());
+        self
+    }
+
+    /// Strip the patient's name, sex, and birthdate read from the PDF,
+    /// writing "X X X X" as EDF+ does for unknown subfields, in the header
+    /// and in every sidecar output. A patient field set with
+    /// [`ConvertOptions::patient`] is kept, as a pseudonym. Runs after the
+    /// time shift, which is keyed on the original patient.
+    pub fn anonymize(mut self, anonymize: bool) -> Self {
+        self.anonymize = anonymize;
+        self
+    }
+
+    /// Append to an EDF+D session file instead of overwriting the output.
+    pub fn append(mut self, append: bool) -> Self {
+        self.append = append;
+        self
+    }
+
+    /// Compress the output and the CSV and JSON sidecars, adding `.gz` or
+    /// `.zst` to their paths, e.g. "ecg.edf" is written as "ecg.edf.gz".
+    /// Any output path already ending in `.gz` or `.zst` is compressed
+    /// without this. Compressed files can't be appended to.
+    pub fn compress(mut self, compression: Compression) -> Self {
+        self.compress = Some(compression);
+        self
+    }
+
+    /// The path an output given as `path` is written to: with the
+    /// extension of [`ConvertOptions::compress`] added, if set.
+    pub fn output_path(&self, path: &str) -> String {
+        match self.compress {
+            Some(compression) => compression.add_extension(path),
+            None => path.to_string(),
+        }
+    }
+
+    /// Write the heart-rate/HRV summary to a JSON sidecar file.
+    pub fn hrv_json(mut self, path: impl Into<String>) -> Self {
+        self.hrv_json = Some(path.into());
+        self
+    }
+
+    /// Also write the waveform, calibration, and report metadata as JSON,
+    /// for consumers that can't read EDF.
+    pub fn json(mut self, path: impl Into<String>) -> Self {
+        self.json = Some(path.into());
+        self
+    }
+
+    /// Also plot lead I as an image of ECG paper at 25 mm/s and 10 mm/mV,
+    /// to check the extraction by eye: PNG if the path ends in `.png`, SVG
+    /// otherwise; see [`plot::write_svg`] and [`plot::write_png`].
+    #[cfg(feature = "plot")]
+    pub fn plot(mut self, path: impl Into<String>) -> Self {
+        self.plot = Some(path.into());
+        self
+    }
+
+    /// Set the resolution of a PNG plot, in dots per inch; 150 by default.
+    #[cfg(feature = "plot")]
+    pub fn plot_dpi(mut self, dpi: f64) -> Self {
+        self.plot_settings.dpi = dpi;
+        self
+    }
+
+    /// Split the plot into this many rows, each a whole number of seconds
+    /// long, instead of 10-second rows.
+    #[cfg(feature = "plot")]
+    pub fn plot_rows(mut self, rows: usize) -> Self {
+        self.plot_settings.rows = Some(rows);
+        self
+    }
+
+    /// Also write a DICOM General ECG Waveform Storage file, e.g. for PACS.
+    pub fn dicom(mut self, path: impl Into<String>) -> Self {
+        self.dicom = Some(path.into());
+        self
+    }
+
+    /// Set the patient and study attributes for the DICOM output.
+    pub fn dicom_study(mut self, study: DicomStudy) -> Self {
+        self.dicom_study = study;
+        self
+    }
+
+    /// Also write the samples as CSV, with a time column and provenance comments.
+    pub fn csv(mut self, path: impl Into<String>) -> Self {
+        self.csv = Some(path.into());
+        self
+    }
+
+    /// Also write the samples as a NumPy .npy array.
+    pub fn npy(mut self, path: impl Into<String>) -> Self {
+        self.npy = Some(path.into());
+        self
+    }
+
+    /// Write the CSV and NumPy exports as one file per signal, named with
+    /// the signal label, for scripts that expect single-lead files.
+    pub fn split_channels(mut self, split: bool) -> Self {
+        self.split_channels = split;
+        self
+    }
+
+    /// Write each strip row's trace points to `row_<n>.csv` in this
+    /// directory, with the source path of each point, to see which strokes
+    /// landed in which row when the output looks scrambled.
+    pub fn debug_rows(mut self, dir: impl Into<String>) -> Self {
+        self.debug_rows = Some(dir.into());
+        self
+    }
+
+    /// Draw each strip page's trace paths, colored by the row they were
+    /// assigned to, and the final samples on the same axes as an SVG file,
+    /// to see dropped segments and strokes in the wrong row at a glance.
+    pub fn debug_overlay(mut self, path: impl Into<String>) -> Self {
+        self.debug_overlay = Some(path.into());
+        self
+    }
+
+    /// Add a "Sample flags" channel after lead I, with each sample's
+    /// [`SampleFlag::code`]: 0 for samples read from the PDF, 1 for samples
+    /// interpolated across gaps in a row, 2 for padding at the end of the
+    /// last data record, 3 missing, 4 clipped, 5 lead-off, and 6 despiked,
+    /// so statistics can leave out synthesized samples.
+    pub fn flags_channel(mut self, flags_channel: bool) -> Self {
+        self.flags_channel = flags_channel;
+        self
+    }
+
+    /// Write each sample's flag as CSV, by its [`SampleFlag::name`]:
+    /// measured, interpolated, padded, missing, clipped, lead_off, or
+    /// despiked.
+    pub fn flags_csv(mut self, path: impl Into<String>) -> Self {
+        self.flags_csv = Some(path.into());
+        self
+    }
+
+    /// Add a "Heart rate" channel of the beat-to-beat heart rate in bpm,
+    /// from the "R" annotations of the QRS detector, at
+    /// [`hrv::HEART_RATE_CHANNEL_RATE`] and aligned in time with the ECG,
+    /// as sleep and HRV analysis tools expect.
+    pub fn heart_rate_channel(mut self, heart_rate_channel: bool) -> Self {
+        self.heart_rate_channel = heart_rate_channel;
+        self
+    }
+
+    /// Set the output format, e.g. [`OutputFormat::Bdf`] for 24-bit samples.
+    pub fn format(mut self, format: OutputFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Set how non-ASCII characters in header text fields are written:
+    /// transliterated to ASCII (the default), kept as latin1, or rejected.
+    pub fn text_policy(mut self, text_policy: TextPolicy) -> Self {
+        self.text_policy = text_policy;
+        self
+    }
+
+    /// Set the order of the day and month in the report's numeric dates, e.g.
+    /// [`DateOrder::DayFirst`] for "03/02/2026" as 3 February. With
+    /// [`DateOrder::Auto`], the default, the profile's order is used, or
+    /// month first if the report prints AM/PM times, otherwise day first.
+    pub fn date_order(mut self, order: DateOrder) -> Self {
+        self.date_order = order;
+        self
+    }
+
+    /// Set the data record duration in seconds, e.g. 0.2 for short records.
+    /// A duration that doesn't hold a whole number of samples of every
+    /// signal is moved to the nearest one that does, with a warning.
+    pub fn record_duration(mut self, seconds: f64) -> Self {
+        self.record_duration = Some(seconds);
+        self
+    }
+
+    /// Set the physical range written for the signal labeled `label`, e.g.
+    /// a range shared by a site's recordings; samples outside it are
+    /// clipped. Signals without one get the range of their own samples.
+    pub fn phys_range(mut self, label: impl Into<String>, min: f64, max: f64) -> Self {
+        self.phys_ranges.insert(label.into(), (min, max));
+        self
+    }
+
+    /// Write the ECG signals (those in millivolts) with a fixed resolution,
+    /// so all converted files share identical scaling. Ranges set with
+    /// [`ConvertOptions::phys_range`] take precedence. GDF samples are
+    /// floats, so it doesn't apply to GDF.
+    pub fn quantization(mut self, quantization: Quantization) -> Self {
+        self.quantization = Some(quantization);
+        self
+    }
+
+    /// Write the ECG signals (those in millivolts) in `unit`, scaling their
+    /// samples and setting their physical dimension in every output format.
+    /// Ranges set with [`ConvertOptions::phys_range`] are in this unit.
+    pub fn unit(mut self, unit: Unit) -> Self {
+        self.unit = unit;
+        self
+    }
+
+    /// Write only `duration` seconds of the recording (or the rest of it, if
+    /// `None`) from `start` seconds in, e.g. to skip a noisy first 2
+    /// seconds or export one 10-second window; the start time in the
+    /// header moves to the window's start (see [`EcgRecording::trim`]).
+    pub fn window(mut self, start: f64, duration: Option<f64>) -> Self {
+        self.window_start = start;
+        self.window_duration = duration;
+        self
+    }
+
+    /// Split the output into sequential files of `seconds` each (the last
+    /// may be shorter), named with a `_segment_<n>` suffix from 1, e.g.
+    /// `ecg_segment_1.edf`, each starting at its own start time, for tools
+    /// that cap file duration. JSON, plot, DICOM, CSV, NumPy, and flags CSV
+    /// outputs are split alike.
+    pub fn segment(mut self, seconds: f64) -> Self {
+        self.segment = Some(seconds);
+        self
+    }
+
+    /// Make output byte-identical across runs on the same input and options,
+    /// so regression pipelines can compare hashes: the provenance and audit
+    /// log conversion time is taken from `SOURCE_DATE_EPOCH` (or 0) instead
+    /// of the clock, and DICOM UIDs are derived from the input and options
+    /// hashes and the recording start instead of random bits. Random
+    /// `--time-shift` offsets are unaffected; give a fixed shift instead.
+    pub fn deterministic(mut self, on: bool) -> Self {
+        self.deterministic = on;
+        self
+    }
+
+    /// The writer settings from these options.
+    pub fn write_settings(&self) -> WriteSettings {
+        WriteSettings {
+            text_policy: self.text_policy,
+            record_duration: self.record_duration,
+            gaps: Vec::new(),
+            equipment: None,
+            recording_id: self.header.recording_id.clone(),
+            input_sha256: None,
+            annotation_samples: None,
+        }
+    }
+
+    /// Register a pre-write hook, run after any already registered.
+    ///
+    /// Hooks run between processing and writing, so site-specific filters
+    /// or annotations can change the recording before it is saved.
+    ///
+    /// ```no_run
+    /// use kardiamobile_1l_ecg_convert_pdf_to_edf::edf_write::Annotation;
+    /// use kardiamobile_1l_ecg_convert_pdf_to_edf::{convert, ConvertOptions, EcgRecording};
+    ///
+    /// fn mark_reviewed(recording: &mut EcgRecording) -> anyhow::Result<()> {
+    ///     recording.annotations.push(Annotation::new(0.0, "Reviewed"));
+    ///     Ok(())
+    /// }
+    ///
+    /// let options = ConvertOptions::new().hook(mark_reviewed);
+    /// convert("ecg.pdf", "ecg.edf", &options)?;
+    /// # Ok::<(), anyhow::Error>(())
+    /// ```
+    pub fn hook(mut self, hook: Hook) -> Self {
+        self.hooks.push(hook);
+        self
+    }
+
+    /// Report progress to `callback` after each strip page digitized, and
+    /// after each file of a [`merge`](crate::merge::merge) or watched
+    /// folder, e.g. to draw a progress bar.
+    pub fn on_progress(mut self, callback: ProgressFn) -> Self {
+        self.on_progress = Some(callback);
+        self
+    }
+
+    /// SHA-256 of every setting, apart from the hooks and progress
+    /// callback, so a cache can tell conversions with other settings apart.
+    pub fn settings_sha256(&self) -> String {
+        let settings = Self {
+            hooks: Vec::new(),
+            on_progress: None,
+            ..self.clone()
+        };
+        provenance::sha256_hex(format!("{:?}", settings).as_bytes())
+    }
+
+    /// Report `progress` to the callback, if there is one.
+    pub(crate) fn progress(&self, progress: Progress) {
+        if let Some(callback) = self.on_progress {
+            callback(progress);
+        }
+    }
+
+    /// Write an audit log next to the output, named like it with a `.log`
+    /// extension: the settings, the profile matched, the calibration
+    /// source, the thresholds used, the trace paths discarded, the
+    /// warnings, and the outcome. Written when the conversion fails, too.
+    pub fn audit_log(mut self, audit_log: bool) -> Self {
+        self.audit_log = audit_log;
+        self
+    }
+
+    /// Write a JSON report next to the output, named like it with a
+    /// `.report.json` extension: the outcome, the warnings, and the
+    /// extraction statistics (paths found, rows detected, samples per row,
+    /// gaps filled, calibration, voltage range, and heart rate), so batch
+    /// pipelines can pick out the conversions that need review. Written
+    /// when the conversion fails, too.
+    pub fn report_json(mut self, report_json: bool) -> Self {
+        self.report_json = report_json;
+        self
+    }
+
+    /// Fail the conversion, before anything is written, when the
+    /// recording's quality score (see [`qc::score`]) is below `score`, so
+    /// broken extractions don't produce plausible-looking output. The
+    /// recording is scored as written, after any pre-write hooks.
+    pub fn qc_fail_below(mut self, score: f64) -> Self {
+        self.qc_fail_below = Some(score);
+        self
+    }
+
+    /// Delineate each beat's P wave, QRS complex, and T wave, annotate
+    /// their boundaries ("P onset", "P offset", "QRS onset", "QRS offset",
+    /// "T end"), and print the median PR, QRS, QT, and QTc intervals (see
+    /// [`delineate::delineate`]).
+    pub fn delineate(mut self, delineate: bool) -> Self {
+        self.delineate = delineate;
+        self
+    }
+
+    /// Find pacemaker spikes, narrow jumps of at least 0.5 mV within one
+    /// sample that fall back within 10 ms, in the extracted signal before
+    /// despiking or filtering, and annotate each as "Pacing".
+    pub fn detect_pacing(mut self, detect: bool) -> Self {
+        self.detect_pacing = detect;
+        self
+    }
+
+    /// Replace single-sample spikes of over 1 mV, as where PDF subpaths
+    /// join, with the midpoint of their neighbours before filtering, flag
+    /// them as despiked, and count them in the quality score's report.
+    /// Pacemaker spikes are removed too.
+    pub fn despike(mut self, despike: bool) -> Self {
+        self.despike = despike;
+        self
+    }
+
+    /// Flip lead I when most of its QRS complexes point down, as when the
+    /// device was held backwards, and annotate the correction. Without
+    /// this, inverted recordings are only warned about.
+    pub fn fix_inversion(mut self, fix: bool) -> Self {
+        self.fix_inversion = fix;
+        self
+    }
+
+    /// Remove baseline wander from breathing and movement below `cutoff_hz`
+    /// (e.g. 0.5) from lead I and the other millivolt signals with a
+    /// zero-phase Butterworth high-pass filter, recorded in their
+    /// prefiltering fields as "HP:0.5Hz".
+    pub fn highpass(mut self, cutoff_hz: f64) -> Self {
+        self.highpass = Some(cutoff_hz);
+        self
+    }
+
+    /// Smooth the steps of the digitized PDF polyline above `cutoff_hz`
+    /// (e.g. 40) in lead I and the other millivolt signals with a
+    /// zero-phase Butterworth low-pass filter, for visual review, recorded
+    /// in their prefiltering fields as "LP:40Hz".
+    pub fn lowpass(mut self, cutoff_hz: f64) -> Self {
+        self.lowpass = Some(cutoff_hz);
+        self
+    }
+
+    /// Remove mains interference at `mains_hz` (50 or 60) from lead I and
+    /// the other millivolt signals with a narrow zero-phase notch filter,
+    /// recorded in their prefiltering fields as "N:50Hz".
+    pub fn notch(mut self, mains_hz: f64) -> Self {
+        self.notch = Some(mains_hz);
+        self
+    }
+
+    /// The filters to apply to the extracted signals, in order.
+    pub fn filters(&self) -> Vec<Filter> {
+        [
+            self.highpass.map(Filter::HighPass),
+            self.lowpass.map(Filter::LowPass),
+            self.notch.map(Filter::Notch),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+
+    /// After writing, re-read the EDF+ (or BDF+ or plain EDF) file, scale
+    /// its samples back to physical values, and fail the conversion unless
+    /// each is within one quantization step of the signal written. GDF
+    /// files aren't checked.
+    pub fn verify(mut self, verify: bool) -> Self {
+        self.verify = verify;
+        self
+    }
+
+    /// Write the output's SHA-256 next to it, named like it with `.sha256`
+    /// added, in the format `sha256sum --check` reads, so copies can be
+    /// checked against it. Each segment gets its own; an appended session's
+    /// covers the whole session.
+    pub fn checksum(mut self, checksum: bool) -> Self {
+        self.checksum = checksum;
+        self
+    }
+}
+
+/// Convert a Kardia ECG report PDF into an EDF+ file.
+pub fn convert(pdf_path: &str, edf_path: &str, options: &ConvertOptions) -> Result<()> {
+    let edf_path = &options.output_path(edf_path);
+    if !options.audit_log && !options.report_json {
+        return convert_unlogged(pdf_path, edf_path, options).map(|_| ());
+    }
+    audit::start();
+    let (recording, outcome) = match convert_unlogged(pdf_path, edf_path, options) {
+        Ok(recording) => (Some(recording), Ok(())),
+        Err(e) => (None, Err(e)),
+    };
+    let trail = audit::finish();
+    if options.audit_log {
+        let log_path = audit::log_path(edf_path);
+        audit::write_log(&log_path, pdf_path, edf_path, options, &trail, &outcome)
+            .with_context(|| format!("Failed to write audit log {}", log_path))
+            .context(Failure::WriteFailed)?;
+    }
+    if options.report_json {
+        let report_path = conversion_report::report_path(edf_path);
+        conversion_report::write_report(
+            &report_path,
+            pdf_path,
+            edf_path,
+            recording.as_ref(),
+            &trail,
+            &outcome,
+        )
+        .with_context(|| format!("Failed to write conversion report {}", report_path))
+        .context(Failure::WriteFailed)?;
+    }
+    outcome
+}
+
+/// Read, run the hooks on, and write the recording, returning it.
+fn convert_unlogged(
+    pdf_path: &str,
+    edf_path: &str,
+    options: &ConvertOptions,
+) -> Result<EcgRecording> {
+    let mut recording = read_recording(pdf_path, options)?;
+    run_hooks(&mut recording, options)?;
+    match options.segment {
+        Some(seconds) => write_segments(&recording, edf_path, options, seconds),
+        None => write_recording(&recording, edf_path, options),
+    }
+    .context(Failure::WriteFailed)?;
+    Ok(recording)
+}
+
+/// Convert a Kardia ECG report PDF held in memory, returning the output
+/// file's content, so a service can convert uploads without temporary
+/// files.
+///
+/// Nothing is written to disk: options that write files, such as
+/// appending, segments, checksums, audit logs, conversion reports, sidecar
+/// outputs like [`ConvertOptions::json`], debug dumps, and the
+/// [`ConvertOptions::time_shift`] mapping, are refused. With
+/// [`ConvertOptions::compress`], the content returned is compressed.
+///
+/// ```no_run
+/// use kardiamobile_1l_ecg_convert_pdf_to_edf::{convert_bytes, ConvertOptions};
+///
+/// let pdf = std::fs::read("ecg.pdf")?;
+/// let edf = convert_bytes(&pdf, &ConvertOptions::new())?;
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+pub fn convert_bytes(pdf: &[u8], options: &ConvertOptions) -> Result<Vec<u8>> {
+    check_in_memory(options)?;
+    let mut recording = read_recording_bytes(pdf, options)?;
+    run_hooks(&mut recording, options)?;
+    let mut edf = Vec::new();
+    write_output(&recording, Output::Memory(&mut edf), options).context(Failure::WriteFailed)?;
+    Ok(edf)
+}
+
+/// Refuse options that write files, which an in-memory conversion such as
+/// [`convert_bytes`] doesn't.
+pub(crate) fn check_in_memory(options: &ConvertOptions) -> Result<()> {
+    let writes_files = [
+        (options.append, "Appending"),
+        (options.segment.is_some(), "Segmenting"),
+        (options.checksum, "A checksum"),
+        (options.audit_log, "An audit log"),
+        (options.report_json, "A conversion report"),
+        (options.json.is_some(), "A JSON waveform"),
+        (options.hrv_json.is_some(), "An HRV summary"),
+        #[cfg(feature = "plot")]
+        (options.plot.is_some(), "A plot"),
+        (options.dicom.is_some(), "A DICOM file"),
+        (options.csv.is_some(), "A CSV export"),
+        (options.npy.is_some(), "A NumPy export"),
+        (options.flags_csv.is_some(), "A sample flags CSV"),
+        (options.debug_rows.is_some(), "A row dump"),
+        (options.debug_overlay.is_some(), "A debug overlay"),
+        (options.time_shift.is_some(), "A time-shift mapping"),
+    ];
+    match writes_files.iter().find(|(set, _)| *set) {
+        Some((_, what)) => Err(anyhow!(
+            "{} needs files, but in-memory conversions write none",
+            what
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Run the pre-write hooks on the recording, re-scoring it if they may
+/// have changed it, then fail the conversion if its quality score is below
+/// the options' threshold.
+pub(crate) fn run_hooks(recording: &mut EcgRecording, options: &ConvertOptions) -> Result<()> {
+    if !options.hooks.is_empty() {
+        for hook in &options.hooks {
+            hook(recording)?;
+        }
+        let score = qc::score(recording).score;
+        if recording.provenance.qc_score != Some(score) {
+            outln!("Quality score after hooks: {:.2}", score);
+        }
+        recording.provenance.qc_score = Some(score);
+    }
+    if let (Some(min), Some(score)) = (options.qc_fail_below, recording.provenance.qc_score) {
+        if score < min {
+            return Err(anyhow!(
+                "Quality score {:.2} is below the --qc-fail-below threshold {}; \
+                 nothing was written",
+                score,
+                min
+            )
+            .context(Failure::QcFailed));
+        }
+    }
+    Ok(())
+}
+
+/// Write the recording as sequential files of `seconds` each, with every
+/// output path given a `_segment_<n>` suffix.
+fn write_segments(
+    recording: &EcgRecording,
+    edf_path: &str,
+    options: &ConvertOptions,
+    seconds: f64,
+) -> Result<()> {
+    if !(seconds.is_finite() && seconds > 0.0) {
+        return Err(anyhow!("Segment length must be positive, got {}", seconds));
+    }
+    if options.append {
+        return Err(anyhow!("Can't append segments to an EDF+D session file"));
+    }
+    let count = (recording.duration() / seconds).ceil() as usize;
+    for n in 1..=count {
+        let mut segment = recording.clone();
+        segment.trim((n - 1) as f64 * seconds, Some(seconds))?;
+        let suffixed = |path: &Option<String>| path.as_deref().map(|p| segment_path(p, n));
+        let segment_options = ConvertOptions {
+            json: suffixed(&options.json),
+            #[cfg(feature = "plot")]
+            plot: suffixed(&options.plot),
+            dicom: suffixed(&options.dicom),
+            csv: suffixed(&options.csv),
+            npy: suffixed(&options.npy),
+            flags_csv: suffixed(&options.flags_csv),
+            ..options.clone()
+        };
+        outln!(
+            "\nSegment {} of {}: {:.3} s from {:.3} s",
+            n,
+            count,
+            segment.duration(),
+            (n - 1) as f64 * seconds
+        );
+        let path = segment_path(edf_path, n);
+        write_recording(&segment, &path, &segment_options)?;
+        let format = options.format.writer(options.write_settings()).name();
+        outln!(
+            "{} file written: {} ({} bytes)",
+            format.trim_end_matches('+'),
+            path,
+            std::fs::metadata(&path)?.len()
+        );
+    }
+    Ok(())
+}
+
+/// An output path with a `_segment_<n>` suffix on its file stem, e.g.
+/// "ecg.edf" and 2 give "ecg_segment_2.edf", and "ecg.edf.gz" and 2 give
+/// "ecg_segment_2.edf.gz".
+fn segment_path(path: &str, n: usize) -> String {
+    let (path, compression) = compress::split_extension(path);
+    let file = Path::new(path);
+    let stem = file.file_stem().map_or("".into(), |s| s.to_string_lossy());
+    let name = match file.extension() {
+        Some(ext) => format!("{}_segment_{}.{}", stem, n, ext.to_string_lossy()),
+        None => format!("{}_segment_{}", stem, n),
+    };
+    let path = file.with_file_name(name).to_string_lossy().into_owned();
+    match compression {
+        Some(compression) => compression.add_extension(&path),
+        None => path,
+    }
+}
+
+/// HRV summary JSON: the summary's fields followed by the provenance.
+#[derive(Serialize)]
+struct HrvJson<'a> {
+    #[serde(flatten)]
+    hrv: &'a hrv::HrvSummary,
+    provenance: &'a Provenance,
+}
+
+/// The extractor named in the options, or else the one that recognizes the
+/// PDF, and the layout profile it reads with: its own, unless the options
+/// choose another, with the options' trace filter overrides.
+pub(crate) fn choose_extractor(
+    pdf: &ReportPdf,
+    options: &ConvertOptions,
+) -> Result<(&'static dyn EcgPdfExtractor, LayoutProfile)> {
+    let extractor = match &options.extractor {
+        Some(name) => extractor::find(name)?,
+        None => extractor::detect(pdf).unwrap_or_else(|| {
+            let fallback = extractor::EXTRACTORS[0];
+            errln!(
+                "Warning: no extractor recognized the PDF; using {} \
+                 (try --profile generic for other vendors)",
+                fallback.name()
+            );
+            fallback
+        }),
+    };
+    let mut profile = if options.profile == LayoutProfile::default() {
+        extractor.layout()
+    } else {
+        options.profile.clone()
+    };
+    options.extraction.apply(&mut profile)?;
+    Ok((extractor, profile))
+}
+
+/// Extract and process the ECG recording from an ECG report PDF.
+///
+/// The extractor is the one named in the options, or else the one that
+/// recognizes the PDF (see [`extractor::detect`]).
+pub fn read_recording(pdf_path: &str, options: &ConvertOptions) -> Result<EcgRecording> {
+    let bytes = std::fs::read(pdf_path).context(Failure::InputNotFound)?;
+    read_recording_bytes(&bytes, options)
+}
+
+/// Extract and process the ECG recording from the bytes of a report PDF,
+/// which are also hashed for the provenance.
+fn read_recording_bytes(bytes: &[u8], options: &ConvertOptions) -> Result<EcgRecording> {
+    let pdf = ReportPdf::load_mem(bytes).context(Failure::InvalidPdf)?;
+
+    let (extractor, profile) = choose_extractor(&pdf, options)?;
+    outln!("Extractor: {}", extractor.description());
+    if audit::is_recording() {
+        audit::detail(format!(
+            "Layout profile {} and thresholds used: {}",
+            profile.name,
+            serde_json::to_string_pretty(&profile)?
+        ));
+        audit::detail(format!(
+            "Row processing: {:?}",
+            options.row_processing(profile.sample_rate)
+        ));
+    }
+    let options = &options.clone().profile(profile);
+    let mut provenance = Provenance::new(Some(bytes), options);
+    let ExtractedEcg {
+        mut signal,
+        mut flags,
+        sample_rate,
+        trace_filter,
+        mut extra_signals,
+        start,
+        patient,
+        report,
+        calibration,
+        rows,
+        paths,
+    } = extractor
+        .extract(&pdf, options)
+        .context(Failure::NoWaveform)?;
+    provenance.calibration.points_per_mv = calibration.points_per_mv;
+    provenance.calibration.source = calibration.source.name().to_string();
+    provenance.calibration.sample_rate = sample_rate;
+    let pacing = if options.detect_pacing {
+        let spikes = ecg_process::detect_pacing_spikes(&signal, sample_rate);
+        outln!("Pacing spikes detected: {}", spikes.len());
+        spikes
+    } else {
+        Vec::new()
+    };
+    if options.despike {
+        ecg_process::despike(&mut signal, &mut flags);
+    }
+    let filters = options.filters();
+    if !filters.is_empty() {
+        let labels: Vec<String> = filters.iter().map(Filter::label).collect();
+        outln!("Filters applied: {}", labels.join(" "));
+        signal = dsp::apply_filters_around_gaps(&filters, &signal, &flags, sample_rate as f64)?;
+        for extra in extra_signals.iter_mut().filter(|s| s.phys_dim == "mV") {
+            extra.samples = dsp::apply_filters(&filters, &extra.samples, extra.sample_rate)?;
+            extra.prefilter = dsp::prefilter_text(&extra.prefilter, &filters);
+        }
+    }
+    ecg_process::flag_lead_off(&signal, &mut flags, sample_rate);
+    let polarity = ecg_process::qrs_polarity(&signal, sample_rate);
+    let inverted = polarity.is_inverted();
+    if inverted && options.fix_inversion {
+        outln!(
+            "Lead I inverted in {} of {} beats; flipped",
+            polarity.inverted,
+            polarity.inverted + polarity.upright
+        );
+        signal.iter_mut().for_each(|v| *v = -*v);
+    } else if inverted {
+        errln!(
+            "Warning: lead I looks inverted, with the QRS pointing down in {} of {} beats, \
+             as when the device is held backwards; use --fix-inversion to flip it",
+            polarity.inverted,
+            polarity.inverted + polarity.upright
+        );
+    }
+
+    let duration_sec = signal.len() as f64 / sample_rate as f64;
+    let min_v = signal.iter().cloned().fold(f64::INFINITY, f64::min);
+    let max_v = signal.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+
+    outln!("\nTotal samples: {}", signal.len());
+    outln!("Duration: {:.2} seconds", duration_sec);
+    outln!("Sampling rate: {} Hz", sample_rate);
+    outln!("Voltage range: [{:.3}, {:.3}] mV", min_v, max_v);
+    if let Some(n) = noise::estimate_noise(&signal) {
+        outln!(
+            "Noise floor: {:.4} mV RMS, signal {:.4} mV RMS, SNR {:.1} dB",
+            n.noise_rms,
+            n.signal_rms,
+            n.snr_db
+        );
+    }
+
+    // Detect R-peaks and annotate them
+    let r_peaks = ecg_process::detect_r_peaks(&signal, sample_rate);
+    outln!("R-peaks detected: {}", r_peaks.len());
+
+    // Heart rate and HRV, cross-checked against the rate printed on the report
+    if let Some(determination) = &report.determination {
+        outln!("Report determination: {}", determination);
+    }
+    if let Some(device) = &report.device {
+        match &report.firmware {
+            Some(firmware) => outln!("Report device: {} (firmware {})", device, firmware),
+            None => outln!("Report device: {}", device),
+        }
+    }
+    if let Some(printed) = report.duration_seconds {
+        if (printed - duration_sec).abs() > 1.0 {
+            errln!(
+                "Warning: the report gives a {} s recording, but {:.2} s of trace were read",
+                printed,
+                duration_sec
+            );
+        }
+    }
+    if let Some(hrv) = hrv::summarize(&r_peaks, sample_rate) {
+        outln!(
+            "Heart rate: mean {:.1} BPM, min {:.1}, max {:.1}",
+            hrv.mean_hr_bpm,
+            hrv.min_hr_bpm,
+            hrv.max_hr_bpm
+        );
+        outln!(
+            "HRV: SDNN {:.1} ms, RMSSD {:.1} ms",
+            hrv.sdnn_ms,
+            hrv.rmssd_ms
+        );
+        if let Some(irregularity) = &hrv.irregularity {
+            outln!(
+                "RR irregularity: normalized RMSSD {:.3}, SD1 {:.1} ms, SD2 {:.1} ms, \
+                 entropy {:.2}; atrial fibrillation {}",
+                irregularity.normalized_rmssd,
+                irregularity.sd1_ms,
+                irregularity.sd2_ms,
+                irregularity.shannon_entropy,
+                if irregularity.af_likely {
+                    "likely"
+                } else {
+                    "unlikely"
+                }
+            );
+            if let Some(determination) = &report.determination {
+                let printed_af = determination.to_lowercase().contains("atrial fibrillation");
+                if printed_af != irregularity.af_likely {
+                    errln!(
+                        "Warning: the RR irregularity makes atrial fibrillation {}, but the \
+                         report's determination is \"{}\"",
+                        if irregularity.af_likely {
+                            "likely"
+                        } else {
+                            "unlikely"
+                        },
+                        determination
+                    );
+                }
+            }
+        }
+        if let Some(printed) = report.heart_rate_bpm {
+            outln!("Report heart rate: {:.0} BPM", printed);
+            if (hrv.mean_hr_bpm - printed).abs() > 5.0 {
+                errln!("Warning: computed heart rate differs from the report by more than 5 BPM");
+            }
+        }
+        if let Some(path) = &options.hrv_json {
+            let json = HrvJson {
+                hrv: &hrv,
+                provenance: &provenance,
+            };
+            let path = options.output_path(path);
+            compress::write(&path, serde_json::to_string_pretty(&json)?)?;
+            outln!("HRV summary written: {}", path);
+        }
+    } else {
+        errln!("Warning: too few beats for a heart-rate summary");
+    }
+    let mut annotations: Vec<Annotation> = r_peaks
+        .iter()
+        .map(|&i| Annotation::new(i as f64 / sample_rate as f64, "R"))
+        .collect();
+    if options.delineate {
+        let beats = delineate::delineate(&signal, &r_peaks, sample_rate);
+        let intervals = delineate::intervals(&beats, sample_rate);
+        let ms = |interval: Option<f64>| match interval {
+            Some(ms) => format!("{:.0} ms", ms),
+            None => "-".to_string(),
+        };
+        outln!(
+            "Intervals over {} beats: PR {}, QRS {}, QT {}, QTc {}",
+            intervals.beats,
+            ms(intervals.pr_ms),
+            ms(intervals.qrs_ms),
+            ms(intervals.qt_ms),
+            ms(intervals.qtc_ms)
+        );
+        annotations.extend(delineate::annotations(&beats, sample_rate));
+    }
+    annotations.extend(
+        pacing
+            .iter()
+            .map(|&i| Annotation::new(i as f64 / sample_rate as f64, "Pacing")),
+    );
+    if inverted && options.fix_inversion {
+        annotations.push(Annotation::new(0.0, "Polarity inverted, corrected"));
+    }
+    let gaps = sample_flags::gaps(&flags, sample_rate);
+    if !gaps.is_empty() {
+        let missing: f64 = gaps.iter().map(|g| g.duration).sum();
+        errln!(
+            "Warning: {:.2} seconds of the recording are missing, in {} gap(s)",
+            missing,
+            gaps.len()
+        );
+        annotations.extend(gaps.iter().map(|g| Annotation {
+            onset: g.onset,
+            duration: Some(g.duration),
+            text: "Gap".to_string(),
+        }));
+    }
+    let lead_off = sample_flags::spans(&flags, SampleFlag::LeadOff, sample_rate);
+    if !lead_off.is_empty() {
+        errln!(
+            "Warning: the trace is flat at the baseline for {:.2} seconds, in {} span(s); \
+             the electrodes were likely off",
+            lead_off.iter().map(|s| s.duration).sum::<f64>(),
+            lead_off.len()
+        );
+        annotations.extend(lead_off.iter().map(|s| Annotation {
+            onset: s.onset,
+            duration: Some(s.duration),
+            text: "Lead off".to_string(),
+        }));
+    }
+    let clipped = sample_flags::spans(&flags, SampleFlag::Clipped, sample_rate);
+    if !clipped.is_empty() {
+        errln!(
+            "Warning: the printed trace is clipped in {} span(s); flattened peaks there \
+             are not physiological",
+            clipped.len()
+        );
+        annotations.extend(clipped.iter().map(|c| Annotation {
+            onset: c.onset,
+            duration: Some(c.duration),
+            text: "Amplitude clipped".to_string(),
+        }));
+    }
+    annotations.sort_by(|a, b| a.onset.total_cmp(&b.onset));
+
+    let mut recording = EcgRecording {
+        signal,
+        flags,
+        sample_rate,
+        trace_filter,
+        start,
+        patient,
+        annotations,
+        extra_signals,
+        report,
+        rows,
+        paths,
+        filters,
+        provenance,
+    };
+    if let Some(path) = &options.time_shift {
+        time_shift::apply(&mut recording, path)?;
+    }
+    if options.anonymize && options.header.patient.is_none() {
+        recording.patient = edf_write::patient_identification(None, None, None);
+    }
+    if options.window_start > 0.0 || options.window_duration.is_some() {
+        recording.trim(options.window_start, options.window_duration)?;
+        outln!(
+            "Window: {:.3} s from {:.3} s",
+            recording.duration(),
+            options.window_start
+        );
+    }
+    let qc = qc::score(&recording);
+    outln!(
+        "Quality score: {:.2} (duration {:.2}, flat line {:.2}, clipping {:.2}, noise {:.2})",
+        qc.score,
+        qc.duration,
+        qc.flat_line,
+        qc.clipping,
+        qc.noise
+    );
+    if qc.despiked_samples > 0 {
+        outln!("Despiked samples: {}", qc.despiked_samples);
+    }
+    recording.provenance.qc_score = Some(qc.score);
+    Ok(recording)
+}
+
+/// The beat-to-beat heart-rate channel from the recording's "R"
+/// annotations, or `None`, with a warning, if it has fewer than two.
+fn heart_rate_signal(recording: &EcgRecording) -> Option<EdfSignal> {
+    let beats: Vec<f64> = recording
+        .annotations
+        .iter()
+        .filter(|a| a.text == "R")
+        .map(|a| a.onset)
+        .collect();
+    let rate = hrv::HEART_RATE_CHANNEL_RATE;
+    let Some(bpm) = hrv::heart_rate_series(&beats, recording.duration(), rate) else {
+        errln!("Warning: too few R-peaks for a heart-rate channel; leaving it out");
+        return None;
+    };
+    let last = bpm.last().copied().unwrap_or(0.0);
+    Some(EdfSignal {
+        transducer: "Beat-to-beat rate of detected R-peaks".to_string(),
+        pad_value: last,
+        ..EdfSignal::new("Heart rate", "bpm", rate, bpm)
+    })
+}
+
+/// The configured physical ranges by signal label, with the fixed
+/// quantization's range for the ECG signals without one.
+fn fixed_phys_ranges(
+    signals: &[EdfSignal],
+    options: &ConvertOptions,
+) -> Result<BTreeMap<String, (f64, f64)>> {
+    let mut ranges = options.phys_ranges.clone();
+    let Some(quantization) = options.quantization else {
+        return Ok(ranges);
+    };
+    let Some(digital) = options.format.digital_range() else {
+        errln!("Warning: GDF samples are floats; ignoring the fixed quantization");
+        return Ok(ranges);
+    };
+    // Use the limits as the 8-character header fields hold them, so the
+    // samples are scaled exactly as readers will scale them back
+    let (min, max) = quantization.phys_range(digital)?;
+    let scale = options.unit.per_mv();
+    let written = |v: f64| edf_write::format_edf_num(v * scale).parse::<f64>();
+    let (min, max) = (written(min)?, written(max)?);
+    let lsb_uv = (max - min) / (digital.1 - digital.0) as f64 * 1000.0 / scale;
+    outln!(
+        "Quantization: [{}, {}] {}, {:.4} µV per bit",
+        min,
+        max,
+        options.unit.phys_dim(),
+        lsb_uv
+    );
+    let unit = options.unit.phys_dim();
+    for signal in signals.iter().filter(|s| s.phys_dim == unit) {
+        ranges.entry(signal.label.clone()).or_insert((min, max));
+    }
+    Ok(ranges)
+}
+
+/// Set the configured physical ranges on the signals with those labels,
+/// warning about samples that will be clipped.
+fn apply_phys_ranges(
+    signals: &mut [EdfSignal],
+    ranges: &BTreeMap<String, (f64, f64)>,
+) -> Result<()> {
+    for (label, &(min, max)) in ranges {
+        if !(min.is_finite() && max.is_finite() && min < max) {
+            return Err(anyhow!(
+                "Physical range of {:?} must have min below max, got {}:{}",
+                label,
+                min,
+                max
+            ));
+        }
+        let Some(signal) = signals.iter_mut().find(|s| &s.label == label) else {
+            let labels: Vec<_> = signals.iter().map(|s| s.label.as_str()).collect();
+            return Err(anyhow!(
+                "No signal labeled {:?} for its physical range; signals: {}",
+                label,
+                labels.join(", ")
+            ));
+        };
+        let clipped = signal
+            .samples
+            .iter()
+            .filter(|&&v| v < min || v > max)
+            .count();
+        if clipped > 0 {
+            errln!(
+                "Warning: {} samples of {} are outside its physical range [{}, {}] and will be clipped",
+                clipped, label, min, max
+            );
+        }
+        signal.phys_range = Some((min, max));
+    }
+    Ok(())
+}
+
+/// The signals written for a recording: lead I with the configured header
+/// fields, then the optional flags channel, the extra signals, and the
+/// optional heart-rate channel, with the ECG signals in the configured unit
+/// and the physical ranges set.
+pub(crate) fn recording_signals(
+    recording: &EcgRecording,
+    options: &ConvertOptions,
+) -> Result<Vec<EdfSignal>> {
+    let mut signals = vec![EdfSignal {
+        prefilter: dsp::prefilter_text(
+            &recording.trace_filter.prefilter(recording.report.mains_hz),
+            &recording.filters,
+        ),
+        ..EdfSignal::ecg(recording.signal.clone(), recording.sample_rate)
+    }];
+    options.header.apply(&mut signals[0]);
+    if options.flags_channel {
+        sample_flags::check_len(&recording.flags, recording.signal.len())?;
+        signals.push(sample_flags::flags_signal(
+            &recording.flags,
+            recording.sample_rate,
+        ));
+    }
+    signals.extend(recording.extra_signals.iter().cloned());
+    if options.heart_rate_channel {
+        signals.extend(heart_rate_signal(recording));
+    }
+    let scale = options.unit.per_mv();
+    for signal in signals.iter_mut().filter(|s| s.phys_dim == "mV") {
+        signal.samples.iter_mut().for_each(|v| *v *= scale);
+        signal.phys_range = signal
+            .phys_range
+            .map(|(min, max)| (min * scale, max * scale));
+        signal.pad_value *= scale;
+        signal.phys_dim = options.unit.phys_dim().to_string();
+    }
+    let phys_ranges = fixed_phys_ranges(&signals, options)?;
+    apply_phys_ranges(&mut signals, &phys_ranges)?;
+    Ok(signals)
+}
+
+/// The annotations written for a recording: its provenance at onset 0,
+/// then the recording's own.
+pub(crate) fn recording_annotations(recording: &EcgRecording) -> Vec<Annotation> {
+    let mut annotations = vec![Annotation::new(
+        0.0,
+        format!("Provenance {}", recording.provenance.summary()),
+    )];
+    annotations.extend(recording.annotations.iter().cloned());
+    annotations
+}
+
+/// The stretches of a recording whose samples are missing.
+pub(crate) fn recording_gaps(recording: &EcgRecording) -> Result<Vec<Gap>> {
+    if !recording.flags.contains(&SampleFlag::Missing) {
+        return Ok(Vec::new());
+    }
+    sample_flags::check_len(&recording.flags, recording.signal.len())?;
+    Ok(sample_flags::gaps(&recording.flags, recording.sample_rate))
+}
+
+/// Write the recording as an EDF+ (or BDF+ or GDF) file, or append it to an
+/// EDF+D (or BDF+D) session file, and optionally as JSON, DICOM, CSV, and NumPy.
+pub fn write_recording(
+    recording: &EcgRecording,
+    edf_path: &str,
+    options: &ConvertOptions,
+) -> Result<()> {
+    write_output(
+        recording,
+        Output::File(&options.output_path(edf_path)),
+        options,
+    )
+}
+
+/// Where [`write_output`] writes the recording.
+enum Output<'a> {
+    /// A file at this path, appended to with the append option.
+    File(&'a str),
+    /// A buffer in memory, with nothing written next to it; see
+    /// [`check_in_memory`].
+    Memory(&'a mut Vec<u8>),
+}
+
+/// Write the recording, as [`write_recording`] does, to a file or memory.
+fn write_output(recording: &EcgRecording, output: Output, options: &ConvertOptions) -> Result<()> {
+    let signals = recording_signals(recording, options)?;
+    let annotations = recording_annotations(recording);
+    let gaps = recording_gaps(recording)?;
+    let writer = options.format.writer(WriteSettings {
+        gaps,
+        equipment: recording.report.device.clone(),
+        input_sha256: recording.provenance.input_sha256.clone(),
+        ..options.write_settings()
+    });
+    // Plain EDF is for software that reads one ECG signal and no annotations
+    let written = if options.format == OutputFormat::EdfClassic {
+        if signals.len() > 1 {
+            let left_out: Vec<&str> = signals[1..].iter().map(|s| s.label.as_str()).collect();
+            outln!("Plain EDF: leaving out {}", left_out.join(", "));
+        }
+        outln!("Plain EDF: leaving out {} annotations", annotations.len());
+        &signals[..1]
+    } else {
+        &signals[..]
+    };
+    let (start, patient) = (recording.start, &recording.patient);
+    let verify = options.verify && options.format != OutputFormat::Gdf;
+    match output {
+        Output::File(path) => {
+            if Compression::of_path(path).is_some() {
+                // Written whole in memory, so a rejected header or failed
+                // check leaves no file
+                if options.append {
+                    return Err(anyhow!("Can't append to compressed {}", path));
+                }
+                let mut bytes = Vec::new();
+                writer.write_to(&mut bytes, written, start, patient, &annotations)?;
+                if verify {
+                    let mut reader = std::io::Cursor::new(&bytes[..]);
+                    verify::verify_read(&mut reader, path, written, start)?;
+                }
+                compress::write(path, bytes)?;
+            } else {
+                if options.append {
+                    writer.append(path, written, start, patient, &annotations)?;
+                } else {
+                    writer.write(path, written, start, patient, &annotations)?;
+                }
+                if verify {
+                    verify::verify_written(path, written, start)?;
+                }
+            }
+            if options.checksum {
+                let path = provenance::write_checksum(path)?;
+                outln!("Checksum written: {}", path);
+            }
+        }
+        Output::Memory(bytes) => {
+            writer.write_to(bytes, written, start, patient, &annotations)?;
+            if verify {
+                let mut reader = std::io::Cursor::new(&bytes[..]);
+                verify::verify_read(&mut reader, "the output", written, start)?;
+            }
+            if let Some(compression) = options.compress {
+                *bytes = compress::encode(compression, bytes)?;
+            }
+        }
+    }
+    if options.verify && !verify {
+        errln!("Warning: GDF output isn't verified");
+    }
+
+    if let Some(path) = &options.json {
+        waveform_json::write_waveform_json(&options.output_path(path), recording, &signals)?;
+    }
+    #[cfg(feature = "plot")]
+    if let Some(path) = &options.plot {
+        plot::write_plot(path, recording, &options.plot_settings)?;
+    }
+    if let Some(path) = &options.dicom {
+        let mut study = options.dicom_study.clone();
+        if options.deterministic {
+            let provenance = &recording.provenance;
+            study.uid_seed = Some(format!(
+                "{} {} {}",
+                provenance.input_sha256.as_deref().unwrap_or_default(),
+                provenance.options_sha256,
+                recording
+                    .start
+                    .map(|start| start.format("%Y-%m-%dT%H:%M:%S%.6f").to_string())
+                    .unwrap_or_default()
+            ));
+        }
+        dicom_write::write_dicom(path, recording, &study)?;
+    }
+    if let Some(path) = &options.csv {
+        let path = options.output_path(path);
+        sample_export::write_csv(&path, recording, &signals, options.split_channels)?;
+    }
+    if let Some(path) = &options.npy {
+        sample_export::write_npy(path, &signals, options.split_channels)?;
+    }
+    if let Some(path) = &options.flags_csv {
+        sample_flags::check_len(&recording.flags, recording.signal.len())?;
+        let path = options.output_path(path);
+        sample_flags::write_flags_csv(&path, &recording.flags, recording.sample_rate)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flatten(recording: &mut EcgRecording) -> Result<()> {
+        recording.signal.iter_mut().for_each(|v| *v = 0.0);
+        Ok(())
+    }
+
+    #[test]
+    fn scores_the_recording_the_hooks_leave() {
+        let mut recording = crate::demo::demo_recording(300);
+        recording.provenance.qc_score = Some(qc::score(&recording).score);
+        let options = ConvertOptions::new().qc_fail_below(0.5);
+        run_hooks(&mut recording, &options).unwrap();
+
+        let options = options.hook(flatten);
+        let err = run_hooks(&mut recording, &options).unwrap_err();
+        assert_eq!(err.downcast_ref::<Failure>(), Some(&Failure::QcFailed));
+        assert_eq!(recording.provenance.qc_score, Some(0.0));
+    }
+}
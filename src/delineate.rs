@@ -0,0 +1,236 @@
+use serde::Serialize;
+
+use crate::dsp::Biquad;
+use crate::ecg_process::median;
+use crate::edf_write::Annotation;
+
+/// Half-width, in seconds, of the window around an R-peak holding its QRS
+/// complex.
+const QRS_HALF_WIDTH: f64 = 0.1;
+
+/// Fraction of the QRS complex's steepest slope below which the trace
+/// counts as flat, ending the complex.
+const QRS_SLOPE_FRACTION: f64 = 0.1;
+
+/// Time, in seconds, the trace must stay flat to end the QRS complex, so a
+/// turning point between the Q, R, and S waves doesn't.
+const FLAT_SECONDS: f64 = 0.01;
+
+/// Earliest and latest T-wave samples after the QRS offset, in seconds.
+const T_SEARCH: (f64, f64) = (0.08, 0.5);
+
+/// Nearest and furthest P-wave samples before the QRS onset, in seconds.
+const P_SEARCH: (f64, f64) = (0.02, 0.25);
+
+/// Smallest amplitude, in millivolts, of a P wave; atrial fibrillation has
+/// none.
+const P_MIN_MV: f64 = 0.03;
+
+/// Fraction of the P wave's amplitude at which it starts and ends.
+const P_EDGE_FRACTION: f64 = 0.2;
+
+/// Cutoff in Hz of the low-pass smoothing before slopes are taken.
+const SMOOTHING_HZ: f64 = 40.0;
+
+/// Fiducial points of one beat, as sample indices.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Beat {
+    /// Start of the P wave, if one was found.
+    pub p_onset: Option<usize>,
+    /// End of the P wave, if one was found.
+    pub p_offset: Option<usize>,
+    /// Start of the QRS complex.
+    pub qrs_onset: usize,
+    /// The R-peak the beat was found from.
+    pub r_peak: usize,
+    /// End of the QRS complex (the J point).
+    pub qrs_offset: usize,
+    /// End of the T wave, if one was found.
+    pub t_end: Option<usize>,
+    /// Samples since the previous R-peak, if any.
+    pub rr: Option<usize>,
+}
+
+/// Median intervals over the delineated beats, in milliseconds; `None`
+/// where no beat had the waves to measure one.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Intervals {
+    /// Number of beats delineated.
+    pub beats: usize,
+    /// P onset to QRS onset.
+    pub pr_ms: Option<f64>,
+    /// QRS onset to QRS offset.
+    pub qrs_ms: Option<f64>,
+    /// QRS onset to T end.
+    pub qt_ms: Option<f64>,
+    /// QT corrected for heart rate with Bazett's formula, QT / sqrt(RR).
+    pub qtc_ms: Option<f64>,
+}
+
+/// Delineate the beat at each R-peak with a derivative-based method.
+///
+/// The signal is smoothed with a 40 Hz low-pass, and the QRS complex
+/// extends either side of the R-peak until the slope stays below a tenth
+/// of the complex's steepest for 10 ms. The level at the QRS onset is the
+/// isoelectric baseline. The T wave is the largest deflection from it 80
+/// to 500 ms after the QRS offset, ending where the tangent at the
+/// steepest point of its trailing limb crosses the baseline. The P wave is
+/// the largest deflection 20 to 250 ms before the QRS onset, if over 0.03
+/// mV, starting and ending where it is within a fifth of its amplitude of
+/// the baseline. Beats too near either end of the signal are skipped.
+pub fn delineate(signal: &[f64], r_peaks: &[usize], sample_rate: usize) -> Vec<Beat> {
+    let n = signal.len();
+    let fs = sample_rate as f64;
+    if n < 3 {
+        return Vec::new();
+    }
+    let smooth = Biquad::lowpass(SMOOTHING_HZ.min(0.4 * fs), fs).filtfilt(signal);
+    let slope: Vec<f64> = (0..n)
+        .map(|i| (smooth[(i + 1).min(n - 1)] - smooth[i.saturating_sub(1)]) * fs / 2.0)
+        .collect();
+    let samples = |seconds: f64| (seconds * fs).round() as usize;
+    let half_width = samples(QRS_HALF_WIDTH);
+    let flat_run = samples(FLAT_SECONDS).max(2);
+
+    r_peaks
+        .iter()
+        .enumerate()
+        .filter_map(|(k, &r)| {
+            let lo = r.checked_sub(half_width)?;
+            let hi = r + half_width;
+            if hi >= n {
+                return None;
+            }
+            let steepest = slope[lo..=hi].iter().fold(0.0, |m: f64, d| m.max(d.abs()));
+            let flat = |i: usize| slope[i].abs() < QRS_SLOPE_FRACTION * steepest;
+            let qrs_onset = complex_edge(r, lo, flat, flat_run);
+            let qrs_offset = complex_edge(r, hi, flat, flat_run);
+            let baseline = smooth[qrs_onset];
+            let deviation = |i: usize| smooth[i] - baseline;
+
+            // T wave, ending before the next beat's QRS complex
+            let mut t_hi = (qrs_offset + samples(T_SEARCH.1)).min(n - 1);
+            if let Some(&next) = r_peaks.get(k + 1) {
+                t_hi = t_hi.min(next.saturating_sub(half_width));
+            }
+            let t_lo = qrs_offset + samples(T_SEARCH.0);
+            let t_end = (t_lo < t_hi)
+                .then(|| {
+                    let peak = largest(t_lo..=t_hi, |i| deviation(i).abs())?;
+                    let sign = deviation(peak).signum();
+                    let steepest = largest(peak..=t_hi, |i| -slope[i] * sign)?;
+                    if slope[steepest] * sign >= 0.0 {
+                        return None;
+                    }
+                    let crossing = -deviation(steepest) / slope[steepest] * fs;
+                    Some(
+                        (steepest as f64 + crossing)
+                            .round()
+                            .clamp(steepest as f64, t_hi as f64) as usize,
+                    )
+                })
+                .flatten();
+
+            // P wave, after the previous beat's QRS complex
+            let mut p_lo = qrs_onset.saturating_sub(samples(P_SEARCH.1));
+            if let Some(&prev) = k.checked_sub(1).and_then(|k| r_peaks.get(k)) {
+                p_lo = p_lo.max(prev + half_width);
+            }
+            let p_hi = qrs_onset.saturating_sub(samples(P_SEARCH.0));
+            let p_wave = (p_lo < p_hi)
+                .then(|| {
+                    let peak = largest(p_lo..=p_hi, |i| deviation(i).abs())?;
+                    let amplitude = deviation(peak).abs();
+                    if amplitude < P_MIN_MV {
+                        return None;
+                    }
+                    let sign = deviation(peak).signum();
+                    let inside = |i: usize| deviation(i) * sign > P_EDGE_FRACTION * amplitude;
+                    let onset = (p_lo..peak).rev().find(|&i| !inside(i)).unwrap_or(p_lo);
+                    let offset = (peak..qrs_onset).find(|&i| !inside(i)).unwrap_or(qrs_onset);
+                    Some((onset, offset))
+                })
+                .flatten();
+
+            Some(Beat {
+                p_onset: p_wave.map(|(onset, _)| onset),
+                p_offset: p_wave.map(|(_, offset)| offset),
+                qrs_onset,
+                r_peak: r,
+                qrs_offset,
+                t_end,
+                rr: k.checked_sub(1).map(|k| r - r_peaks[k]),
+            })
+        })
+        .collect()
+}
+
+/// Walk from `from` toward `to` until `flat` holds for `run` samples in a
+/// row, returning the first of them, or `to` if it never does.
+fn complex_edge(from: usize, to: usize, flat: impl Fn(usize) -> bool, run: usize) -> usize {
+    let path: Vec<usize> = if to < from {
+        (to..from).rev().collect()
+    } else {
+        (from + 1..=to).collect()
+    };
+    let mut count = 0;
+    for (step, &i) in path.iter().enumerate() {
+        count = if flat(i) { count + 1 } else { 0 };
+        if count == run {
+            return path[step + 1 - run];
+        }
+    }
+    to
+}
+
+/// The index in `range` where `value` is largest.
+fn largest(range: impl Iterator<Item = usize>, value: impl Fn(usize) -> f64) -> Option<usize> {
+    range.max_by(|&a, &b| value(a).total_cmp(&value(b)))
+}
+
+/// Median PR, QRS, QT, and QTc intervals of the beats.
+pub fn intervals(beats: &[Beat], sample_rate: usize) -> Intervals {
+    let ms = |samples: usize| samples as f64 * 1000.0 / sample_rate as f64;
+    let qt = |b: &Beat| b.t_end.map(|t| ms(t - b.qrs_onset));
+    Intervals {
+        beats: beats.len(),
+        pr_ms: median(
+            beats
+                .iter()
+                .filter_map(|b| b.p_onset.map(|p| ms(b.qrs_onset - p)))
+                .collect(),
+        ),
+        qrs_ms: median(
+            beats
+                .iter()
+                .map(|b| ms(b.qrs_offset - b.qrs_onset))
+                .collect(),
+        ),
+        qt_ms: median(beats.iter().filter_map(qt).collect()),
+        qtc_ms: median(
+            beats
+                .iter()
+                .filter_map(|b| Some(qt(b)? / (ms(b.rr?) / 1000.0).sqrt()))
+                .collect(),
+        ),
+    }
+}
+
+/// The beats' fiducial points as EDF+ annotations: "P onset", "P offset",
+/// "QRS onset", "QRS offset", and "T end".
+pub fn annotations(beats: &[Beat], sample_rate: usize) -> Vec<Annotation> {
+    let fs = sample_rate as f64;
+    beats
+        .iter()
+        .flat_map(|b| {
+            [
+                (b.p_onset, "P onset"),
+                (b.p_offset, "P offset"),
+                (Some(b.qrs_onset), "QRS onset"),
+                (Some(b.qrs_offset), "QRS offset"),
+                (b.t_end, "T end"),
+            ]
+        })
+        .filter_map(|(i, text)| Some(Annotation::new(i? as f64 / fs, text)))
+        .collect()
+}
@@ -0,0 +1,211 @@
+use anyhow::Result;
+use chrono::NaiveDate;
+use lopdf::content::{Content, Operation};
+use lopdf::{dictionary, Document, Object, ObjectId, Stream};
+
+use crate::convert::ConvertOptions;
+use crate::edf_write::{self, Annotation};
+use crate::pdf_text::{ReportInfo, TraceFilter};
+use crate::profile::LayoutProfile;
+use crate::provenance::Provenance;
+use crate::recording::EcgRecording;
+use crate::sample_flags::SampleFlag;
+
+/// Length of the demo recording in seconds, as for a standard Kardia recording.
+pub const DEMO_DURATION: f64 = 30.0;
+
+/// Mean heart rate of the demo recording in BPM.
+pub const DEMO_HEART_RATE: f64 = 72.0;
+
+/// A PQRST wave component: offset from the R peak (s), amplitude (mV), width (s).
+const WAVES: [(f64, f64, f64); 5] = [
+    (-0.200, 0.12, 0.025),  // P
+    (-0.030, -0.08, 0.008), // Q
+    (0.000, 1.00, 0.010),   // R
+    (0.030, -0.20, 0.010),  // S
+    (0.250, 0.30, 0.040),   // T
+];
+
+/// Build a synthetic 30-second lead I recording with "R" annotations.
+///
+/// Beats are sums of Gaussian P, Q, R, S, and T waves at 72 BPM, with a
+/// slow respiratory variation in the RR interval and a small baseline
+/// wander. The output is fully deterministic and contains no patient data.
+pub fn demo_recording(sample_rate: usize) -> EcgRecording {
+    let fs = sample_rate as f64;
+    let n = (DEMO_DURATION * fs) as usize;
+
+    // Beat times, with the RR interval varying over a 4-second breath
+    let mut beats = Vec::new();
+    let mut t = 0.5;
+    while t < DEMO_DURATION - 0.5 {
+        beats.push(t);
+        t += 60.0 / DEMO_HEART_RATE + 0.04 * (2.0 * std::f64::consts::PI * t / 4.0).sin();
+    }
+
+    let signal: Vec<f64> = (0..n)
+        .map(|i| {
+            let t = i as f64 / fs;
+            let wander = 0.04 * (2.0 * std::f64::consts::PI * 0.2 * t).sin();
+            let beat: f64 = beats
+                .iter()
+                .filter(|&&b| (t - b).abs() < 0.6)
+                .flat_map(|&b| {
+                    WAVES.iter().map(move |&(offset, amp, width)| {
+                        let d = (t - b - offset) / width;
+                        amp * (-0.5 * d * d).exp()
+                    })
+                })
+                .sum();
+            wander + beat
+        })
+        .collect();
+
+    let start = NaiveDate::from_ymd_opt(2026, 1, 1).and_then(|d| d.and_hms_opt(9, 0, 0));
+    EcgRecording {
+        flags: vec![SampleFlag::Measured; signal.len()],
+        signal,
+        sample_rate,
+        trace_filter: TraceFilter::Enhanced,
+        start,
+        patient: edf_write::patient_identification(None, None, Some("Demo Patient")),
+        annotations: beats
+            .iter()
+            .map(|&b| Annotation::new((b * fs).round() / fs, "R"))
+            .collect(),
+        extra_signals: Vec::new(),
+        report: ReportInfo::default(),
+        rows: Default::default(),
+        paths: Default::default(),
+        filters: Vec::new(),
+        provenance: Provenance::new(None, &ConvertOptions::new().sample_rate(sample_rate)),
+    }
+}
+
+/// Write a recording as a two-page PDF laid out like a Kardia report:
+/// a summary page of text, then the strip page in the given profile's
+/// layout, so it can be converted back with the same profile.
+pub fn write_demo_pdf(recording: &EcgRecording, profile: &LayoutProfile, path: &str) -> Result<()> {
+    const PAGE_WIDTH: f64 = 612.0;
+    const PAGE_HEIGHT: f64 = 792.0;
+    // Strip geometry in top-left-origin points
+    const ROW_TOP: f64 = 160.0;
+    const ROW_SPACING: f64 = 170.0;
+    const X_START: f64 = 22.5;
+    const X_STEP: f64 = 0.25;
+    // Samples per trace path; Kardia draws each row as a chain of short paths
+    const CHUNK: usize = 120;
+
+    let real = |v: f64| Object::Real(v as f32);
+    let op = |operator: &str, operands: Vec<Object>| Operation::new(operator, operands);
+    let text = |x: f64, y: f64, s: &str| {
+        vec![
+            op("BT", vec![]),
+            op("Tf", vec!["F1".into(), 10.into()]),
+            op("Td", vec![real(x), real(PAGE_HEIGHT - y)]),
+            op("Tj", vec![Object::string_literal(s)]),
+            op("ET", vec![]),
+        ]
+    };
+
+    // Summary page: the labels the converter reads, with values beside them
+    let beats: Vec<f64> = recording.annotations.iter().map(|a| a.onset).collect();
+    let heart_rate = match (beats.first(), beats.last()) {
+        (Some(first), Some(last)) if beats.len() > 1 => {
+            60.0 * (beats.len() - 1) as f64 / (last - first)
+        }
+        _ => 0.0,
+    };
+    let recorded_on = recording
+        .start
+        .map(|t| t.format("%A, %B %-d, %Y at %-I:%M:%S %p").to_string())
+        .unwrap_or_default();
+    let mut summary = text(40.0, 60.0, "KardiaMobile 1L ECG Recording (synthetic demo)");
+    for (i, (label, value)) in [
+        ("Patient:", "Demo Patient".to_string()),
+        ("Recorded on:", recorded_on),
+        ("Heart Rate:", format!("{:.0} BPM", heart_rate)),
+    ]
+    .iter()
+    .enumerate()
+    {
+        let y = 100.0 + 20.0 * i as f64;
+        summary.extend(text(40.0, y, label));
+        summary.extend(text(120.0, y, value));
+    }
+
+    // Strip page: baselines, then the trace split evenly across the rows
+    let rows = profile.rows;
+    let per_row = recording.signal.len().div_ceil(rows);
+    let baseline_y = |row: usize| ROW_TOP + ROW_SPACING * row as f64;
+    let mut strip = vec![
+        op("RG", vec![0.into(), 0.into(), 0.into()]),
+        op("w", vec![real(0.4)]),
+    ];
+    for row in 0..rows {
+        let y = PAGE_HEIGHT - baseline_y(row);
+        strip.push(op("m", vec![real(X_START - 2.5), real(y)]));
+        strip.push(op("l", vec![real(PAGE_WIDTH - X_START + 2.5), real(y)]));
+    }
+    strip.push(op("S", vec![]));
+
+    for (row, samples) in recording.signal.chunks(per_row).enumerate() {
+        let point = |i: usize| {
+            let x = X_START + X_STEP * i as f64;
+            let y = PAGE_HEIGHT - baseline_y(row) + samples[i] * profile.cal_pt_per_mv;
+            (real(x), real(y))
+        };
+        // Each chunk starts at the previous chunk's last point
+        for start in (0..samples.len().saturating_sub(1)).step_by(CHUNK) {
+            let end = (start + CHUNK).min(samples.len() - 1);
+            let (x, y) = point(start);
+            strip.push(op("m", vec![x, y]));
+            for i in start + 1..=end {
+                let (x, y) = point(i);
+                strip.push(op("l", vec![x, y]));
+            }
+            strip.push(op("S", vec![]));
+        }
+    }
+
+    let mut doc = Document::with_version("1.5");
+    let pages_id = doc.new_object_id();
+    let font_id = doc.add_object(dictionary! {
+        "Type" => "Font",
+        "Subtype" => "Type1",
+        "BaseFont" => "Helvetica",
+    });
+    let resources_id = doc.add_object(dictionary! {
+        "Font" => dictionary! { "F1" => font_id },
+    });
+    let mut add_page = |operations: Vec<Operation>| -> Result<ObjectId> {
+        let content = Content { operations }.encode()?;
+        let content_id = doc.add_object(Stream::new(dictionary! {}, content));
+        Ok(doc.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+            "Contents" => content_id,
+        }))
+    };
+    let kids: Vec<Object> = vec![add_page(summary)?.into(), add_page(strip)?.into()];
+    let count = kids.len() as i64;
+    doc.objects.insert(
+        pages_id,
+        Object::Dictionary(dictionary! {
+            "Type" => "Pages",
+            "Kids" => kids,
+            "Count" => count,
+            "Resources" => resources_id,
+            "MediaBox" => vec![0.into(), 0.into(), real(PAGE_WIDTH), real(PAGE_HEIGHT)],
+        }),
+    );
+    let catalog_id = doc.add_object(dictionary! {
+        "Type" => "Catalog",
+        "Pages" => pages_id,
+    });
+    doc.trailer.set("Root", catalog_id);
+    doc.compress();
+    doc.save(path)?;
+
+    Ok(())
+}
@@ -0,0 +1,323 @@
+use anyhow::{anyhow, Result};
+use chrono::NaiveDateTime;
+use serde::Deserialize;
+use std::collections::hash_map::RandomState;
+use std::collections::BTreeMap;
+use std::hash::{BuildHasher, Hasher};
+
+use crate::audit::outln;
+use crate::edf_write::{parse_edf_patient, DEFAULT_EQUIPMENT};
+use crate::provenance::sha256_hex;
+use crate::recording::EcgRecording;
+
+/// General ECG Waveform Storage SOP class.
+const GENERAL_ECG_STORAGE: &str = "1.2.840.10008.5.1.4.1.1.9.1.2";
+
+/// Explicit VR Little Endian transfer syntax.
+const EXPLICIT_VR_LITTLE_ENDIAN: &str = "1.2.840.10008.1.2.1";
+
+/// Implementation class UID of this writer (a UUID-derived "2.25" UID).
+const IMPLEMENTATION_CLASS_UID: &str = "2.25.148712640391127382926347052893478312017";
+
+const IMPLEMENTATION_VERSION: &str = "KARDIA2EDF_0_1";
+
+/// Digital sample resolution, in microvolts per bit.
+const SENSITIVITY_UV: f64 = 1.0;
+
+/// Patient and study attributes not printed on the report, from the
+/// command line or a config file.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DicomStudy {
+    /// Patient ID (0010,0020); defaults to the EDF+ patient code.
+    pub patient_id: Option<String>,
+    /// Accession number (0008,0050).
+    pub accession_number: Option<String>,
+    /// Study ID (0020,0010).
+    pub study_id: Option<String>,
+    /// Referring physician's name (0008,0090), in DICOM "Family^Given" form.
+    pub referring_physician: Option<String>,
+    /// Study description (0008,1030).
+    pub study_description: Option<String>,
+    /// Text the study, series, and instance UIDs are derived from, so
+    /// converting the same input again gives the same UIDs; random if
+    /// `None`.
+    #[serde(skip)]
+    pub uid_seed: Option<String>,
+}
+
+/// A DICOM data set, kept sorted by tag as the encoding requires.
+#[derive(Default)]
+struct DataSet(BTreeMap<(u16, u16), Vec<u8>>);
+
+/// Value representations with a 4-byte length in explicit VR encoding.
+const LONG_VRS: [&[u8; 2]; 6] = [b"OB", b"OW", b"SQ", b"UN", b"UT", b"UR"];
+
+impl DataSet {
+    /// Add an element with an already encoded value, padded to even length.
+    fn put(&mut self, tag: (u16, u16), vr: &[u8; 2], mut value: Vec<u8>) {
+        if value.len() % 2 == 1 {
+            // UIDs pad with NUL, binary values with zero, text with space
+            value.push(match vr {
+                b"UI" | b"OB" => 0,
+                _ => b' ',
+            });
+        }
+        let mut element = Vec::with_capacity(value.len() + 12);
+        element.extend_from_slice(&tag.0.to_le_bytes());
+        element.extend_from_slice(&tag.1.to_le_bytes());
+        element.extend_from_slice(vr);
+        if LONG_VRS.contains(&vr) {
+            element.extend_from_slice(&[0, 0]);
+            element.extend_from_slice(&(value.len() as u32).to_le_bytes());
+        } else {
+            element.extend_from_slice(&(value.len() as u16).to_le_bytes());
+        }
+        element.extend_from_slice(&value);
+        self.0.insert(tag, element);
+    }
+
+    /// Add a text element (Latin-1, as declared by Specific Character Set).
+    fn text(&mut self, tag: (u16, u16), vr: &[u8; 2], value: &str) {
+        let bytes = value
+            .chars()
+            .map(|c| u8::try_from(u32::from(c)).unwrap_or(b'?'))
+            .collect();
+        self.put(tag, vr, bytes);
+    }
+
+    fn us(&mut self, tag: (u16, u16), value: u16) {
+        self.put(tag, b"US", value.to_le_bytes().to_vec());
+    }
+
+    fn ul(&mut self, tag: (u16, u16), value: u32) {
+        self.put(tag, b"UL", value.to_le_bytes().to_vec());
+    }
+
+    /// Add a sequence of items, each with a defined length.
+    fn seq(&mut self, tag: (u16, u16), items: Vec<DataSet>) {
+        let mut value = Vec::new();
+        for item in items {
+            let bytes = item.encode();
+            value.extend_from_slice(&0xFFFEu16.to_le_bytes());
+            value.extend_from_slice(&0xE000u16.to_le_bytes());
+            value.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+            value.extend_from_slice(&bytes);
+        }
+        self.put(tag, b"SQ", value);
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        self.0.values().flatten().copied().collect()
+    }
+}
+
+/// A coded concept for a code sequence item.
+fn code(value: &str, scheme: &str, version: Option<&str>, meaning: &str) -> DataSet {
+    let mut item = DataSet::default();
+    item.text((0x0008, 0x0100), b"SH", value);
+    item.text((0x0008, 0x0102), b"SH", scheme);
+    if let Some(version) = version {
+        item.text((0x0008, 0x0103), b"SH", version);
+    }
+    item.text((0x0008, 0x0104), b"LO", meaning);
+    item
+}
+
+/// Make a new unique "2.25" UID from 128 random bits, or, with a seed, from
+/// the first 128 bits of the SHA-256 of the seed and `kind`.
+fn new_uid(seed: Option<&str>, kind: &str) -> String {
+    let value = match seed {
+        Some(seed) => {
+            let digest = sha256_hex(format!("{} {}", seed, kind).as_bytes());
+            u128::from_str_radix(&digest[..32], 16).unwrap_or_default()
+        }
+        None => {
+            let random = || RandomState::new().build_hasher().finish();
+            (u128::from(random()) << 64) | u128::from(random())
+        }
+    };
+    format!("2.25.{}", value)
+}
+
+/// Format a name as a DICOM person name, "Family^Given".
+fn person_name(name: &str) -> String {
+    match name.rsplit_once(' ') {
+        Some((given, family)) => format!("{}^{}", family, given),
+        None => name.to_string(),
+    }
+}
+
+/// Write the lead I signal as a DICOM General ECG Waveform Storage object.
+///
+/// Patient name, sex, and birthdate come from the recording's EDF+ patient
+/// field, study date and time from its start, and R-peak annotations become
+/// waveform annotations. Samples are 16-bit at 1 uV per bit.
+pub fn write_dicom(path: &str, recording: &EcgRecording, study: &DicomStudy) -> Result<()> {
+    let n_samples = recording.signal.len();
+    if n_samples == 0 {
+        return Err(anyhow!("No samples to write to DICOM"));
+    }
+    let seed = study.uid_seed.as_deref();
+    let sop_instance_uid = new_uid(seed, "instance");
+    let patient = parse_edf_patient(&recording.patient);
+    let start: Option<NaiveDateTime> = recording.start;
+    let date = start
+        .map(|t| t.format("%Y%m%d").to_string())
+        .unwrap_or_default();
+    let time = start
+        .map(|t| t.format("%H%M%S").to_string())
+        .unwrap_or_default();
+
+    let mut ds = DataSet::default();
+
+    // SOP Common
+    ds.text((0x0008, 0x0005), b"CS", "ISO_IR 100");
+    ds.text((0x0008, 0x0016), b"UI", GENERAL_ECG_STORAGE);
+    ds.text((0x0008, 0x0018), b"UI", &sop_instance_uid);
+
+    // Patient
+    let name = patient.name.as_deref().map(person_name).unwrap_or_default();
+    let patient_id = study
+        .patient_id
+        .clone()
+        .or(patient.code)
+        .unwrap_or_default();
+    ds.text((0x0010, 0x0010), b"PN", &name);
+    ds.text((0x0010, 0x0020), b"LO", &patient_id);
+    let birthdate = patient.birthdate.map(|d| d.format("%Y%m%d").to_string());
+    ds.text((0x0010, 0x0030), b"DA", &birthdate.unwrap_or_default());
+    ds.text((0x0010, 0x0040), b"CS", &patient.sex.unwrap_or_default());
+
+    // General Study
+    ds.text((0x0008, 0x0020), b"DA", &date);
+    ds.text((0x0008, 0x0030), b"TM", &time);
+    let accession = study.accession_number.as_deref().unwrap_or_default();
+    ds.text((0x0008, 0x0050), b"SH", accession);
+    let physician = study.referring_physician.as_deref().unwrap_or_default();
+    ds.text((0x0008, 0x0090), b"PN", physician);
+    if let Some(description) = &study.study_description {
+        ds.text((0x0008, 0x1030), b"LO", description);
+    }
+    ds.text((0x0020, 0x000D), b"UI", &new_uid(seed, "study"));
+    ds.text(
+        (0x0020, 0x0010),
+        b"SH",
+        study.study_id.as_deref().unwrap_or_default(),
+    );
+
+    // General Series
+    ds.text((0x0008, 0x0060), b"CS", "ECG");
+    ds.text((0x0020, 0x000E), b"UI", &new_uid(seed, "series"));
+    ds.text((0x0020, 0x0011), b"IS", "1");
+
+    // General Equipment
+    ds.text((0x0008, 0x0070), b"LO", "AliveCor");
+    let model = recording.report.device.as_deref();
+    ds.text((0x0008, 0x1090), b"LO", model.unwrap_or(DEFAULT_EQUIPMENT));
+    let provenance = &recording.provenance;
+    // Software Versions is multi-valued: the device firmware, then this tool
+    let mut software = format!("{} {}", provenance.tool, provenance.tool_version);
+    if let Some(firmware) = &recording.report.firmware {
+        software = format!("{}\\{}", firmware, software);
+    }
+    ds.text((0x0018, 0x1020), b"LO", &software);
+    ds.text((0x0008, 0x2111), b"ST", &provenance.summary());
+
+    // Waveform Identification
+    ds.text((0x0020, 0x0013), b"IS", "1");
+    ds.text((0x0008, 0x0023), b"DA", &date);
+    ds.text((0x0008, 0x0033), b"TM", &time);
+    let acquired = start.map(|t| t.format("%Y%m%d%H%M%S").to_string());
+    ds.text((0x0008, 0x002A), b"DT", &acquired.unwrap_or_default());
+
+    // Acquisition Context (required, may be empty)
+    ds.seq((0x0040, 0x0555), Vec::new());
+
+    // Waveform: one multiplex group with the lead I channel
+    let mut channel = DataSet::default();
+    channel.seq(
+        (0x003A, 0x0208),
+        vec![code(
+            "5.6.3-9-1",
+            "SCPECG",
+            Some("1.3"),
+            "Lead I (Einthoven)",
+        )],
+    );
+    channel.text((0x003A, 0x0210), b"DS", &SENSITIVITY_UV.to_string());
+    channel.seq(
+        (0x003A, 0x0211),
+        vec![code("uV", "UCUM", Some("1.4"), "microvolt")],
+    );
+    channel.text((0x003A, 0x0212), b"DS", "1");
+    channel.text((0x003A, 0x0213), b"DS", "0");
+    channel.text((0x003A, 0x0215), b"DS", "0");
+    channel.us((0x003A, 0x021A), 16);
+
+    let data: Vec<u8> = recording
+        .signal
+        .iter()
+        .map(|&mv| {
+            (mv * 1000.0 / SENSITIVITY_UV)
+                .round()
+                .clamp(-32768.0, 32767.0) as i16
+        })
+        .flat_map(i16::to_le_bytes)
+        .collect();
+
+    let mut group = DataSet::default();
+    // Reconstructed from a printed trace, not acquired directly
+    group.text((0x003A, 0x0004), b"CS", "DERIVED");
+    group.us((0x003A, 0x0005), 1);
+    group.ul((0x003A, 0x0010), n_samples as u32);
+    group.text((0x003A, 0x001A), b"DS", &recording.sample_rate.to_string());
+    group.text((0x003A, 0x0020), b"SH", "RHYTHM");
+    group.seq((0x003A, 0x0200), vec![channel]);
+    group.us((0x5400, 0x1004), 16);
+    group.text((0x5400, 0x1006), b"CS", "SS");
+    group.put((0x5400, 0x1010), b"OW", data);
+    ds.seq((0x5400, 0x0100), vec![group]);
+
+    // Waveform Annotation: each annotation as a point on channel 1
+    let fs = recording.sample_rate as f64;
+    let annotations: Vec<DataSet> = recording
+        .annotations
+        .iter()
+        .map(|a| {
+            let mut item = DataSet::default();
+            item.text((0x0070, 0x0006), b"ST", &a.text);
+            // (multiplex group, channel) pair
+            let channels = [1u16, 1].iter().flat_map(|v| v.to_le_bytes()).collect();
+            item.put((0x0040, 0xA0B0), b"US", channels);
+            item.text((0x0040, 0xA130), b"CS", "POINT");
+            // Sample positions are 1-based
+            let position = (a.onset * fs).round() as u32 + 1;
+            item.ul((0x0040, 0xA132), position);
+            item
+        })
+        .collect();
+    if !annotations.is_empty() {
+        ds.seq((0x0040, 0xB020), annotations);
+    }
+
+    // File meta information, with its group length first
+    let mut meta = DataSet::default();
+    meta.put((0x0002, 0x0001), b"OB", vec![0, 1]);
+    meta.text((0x0002, 0x0002), b"UI", GENERAL_ECG_STORAGE);
+    meta.text((0x0002, 0x0003), b"UI", &sop_instance_uid);
+    meta.text((0x0002, 0x0010), b"UI", EXPLICIT_VR_LITTLE_ENDIAN);
+    meta.text((0x0002, 0x0012), b"UI", IMPLEMENTATION_CLASS_UID);
+    meta.text((0x0002, 0x0013), b"SH", IMPLEMENTATION_VERSION);
+    let meta_length = meta.encode().len() as u32;
+    meta.ul((0x0002, 0x0000), meta_length);
+
+    let mut bytes = vec![0u8; 128];
+    bytes.extend_from_slice(b"DICM");
+    bytes.extend_from_slice(&meta.encode());
+    bytes.extend_from_slice(&ds.encode());
+    std::fs::write(path, bytes)?;
+
+    outln!("DICOM file written: {}", path);
+    Ok(())
+}
@@ -0,0 +1,317 @@
+use anyhow::{anyhow, Result};
+use std::f64::consts::PI;
+
+use crate::sample_flags::SampleFlag;
+
+/// Quality factor of the mains notch: about 1.7 Hz wide at 50 Hz, narrow
+/// enough to leave the QRS complex's spectrum around it.
+const NOTCH_Q: f64 = 30.0;
+
+/// A filter applied to the extracted signal.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Filter {
+    /// Butterworth high-pass at this cutoff, in Hz, removing baseline
+    /// wander from breathing and movement.
+    HighPass(f64),
+    /// Butterworth low-pass at this cutoff, in Hz, smoothing the steps of
+    /// the digitized PDF polyline.
+    LowPass(f64),
+    /// Notch at this mains frequency, in Hz.
+    Notch(f64),
+}
+
+impl Filter {
+    /// The filter in EDF prefiltering notation, e.g. "HP:0.5Hz" or "N:50Hz".
+    pub fn label(&self) -> String {
+        match self {
+            Filter::HighPass(hz) => format!("HP:{}Hz", hz),
+            Filter::LowPass(hz) => format!("LP:{}Hz", hz),
+            Filter::Notch(hz) => format!("N:{}Hz", hz),
+        }
+    }
+
+    /// The filter's center or cutoff frequency, in Hz.
+    pub fn frequency(&self) -> f64 {
+        match *self {
+            Filter::HighPass(hz) | Filter::LowPass(hz) | Filter::Notch(hz) => hz,
+        }
+    }
+
+    fn biquad(&self, sample_rate: f64) -> Result<Biquad> {
+        let hz = self.frequency();
+        if !(hz > 0.0 && hz < sample_rate / 2.0) {
+            return Err(anyhow!(
+                "Can't apply {} at a {} Hz sampling rate: the frequency must be between 0 \
+                 and {} Hz",
+                self.label(),
+                sample_rate,
+                sample_rate / 2.0
+            ));
+        }
+        Ok(match *self {
+            Filter::HighPass(hz) => Biquad::highpass(hz, sample_rate),
+            Filter::LowPass(hz) => Biquad::lowpass(hz, sample_rate),
+            Filter::Notch(hz) => Biquad::notch(hz, NOTCH_Q, sample_rate),
+        })
+    }
+}
+
+/// Apply filters in order, each forward and backward so the waveform isn't
+/// shifted in time.
+pub fn apply_filters(filters: &[Filter], signal: &[f64], sample_rate: f64) -> Result<Vec<f64>> {
+    let mut filtered = signal.to_vec();
+    for filter in filters {
+        filtered = filter.biquad(sample_rate)?.filtfilt(&filtered);
+    }
+    Ok(filtered)
+}
+
+/// Apply filters like [`apply_filters`] to each run of samples that isn't
+/// flagged [`SampleFlag::Missing`] on its own, so the filters don't ring
+/// across the gaps; the missing samples are left as they are.
+pub fn apply_filters_around_gaps(
+    filters: &[Filter],
+    signal: &[f64],
+    flags: &[SampleFlag],
+    sample_rate: f64,
+) -> Result<Vec<f64>> {
+    let mut filtered = signal.to_vec();
+    let missing = |i: usize| flags.get(i) == Some(&SampleFlag::Missing);
+    let mut start = 0;
+    while start < signal.len() {
+        if missing(start) {
+            start += 1;
+            continue;
+        }
+        let end = (start..signal.len())
+            .find(|&i| missing(i))
+            .unwrap_or(signal.len());
+        let run = apply_filters(filters, &signal[start..end], sample_rate)?;
+        filtered[start..end].copy_from_slice(&run);
+        start = end;
+    }
+    Ok(filtered)
+}
+
+/// Prefiltering header text: `base` followed by the filters' labels.
+pub fn prefilter_text(base: &str, filters: &[Filter]) -> String {
+    let labels = filters.iter().map(Filter::label);
+    [base.to_string()]
+        .into_iter()
+        .filter(|b| !b.is_empty())
+        .chain(labels)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// A second-order IIR section (RBJ audio-EQ-cookbook coefficients,
+/// normalized so a0 = 1).
+#[derive(Debug, Clone, Copy)]
+pub struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+}
+
+impl Biquad {
+    /// Butterworth (Q = 1/sqrt(2)) low-pass at `cutoff` Hz.
+    pub fn lowpass(cutoff: f64, sample_rate: f64) -> Self {
+        let (cos_w, alpha) = Self::prewarp(cutoff, sample_rate);
+        let b1 = 1.0 - cos_w;
+        Self::normalized(
+            [b1 / 2.0, b1, b1 / 2.0],
+            [1.0 + alpha, -2.0 * cos_w, 1.0 - alpha],
+        )
+    }
+
+    /// Butterworth (Q = 1/sqrt(2)) high-pass at `cutoff` Hz.
+    pub fn highpass(cutoff: f64, sample_rate: f64) -> Self {
+        let (cos_w, alpha) = Self::prewarp(cutoff, sample_rate);
+        let b1 = 1.0 + cos_w;
+        Self::normalized(
+            [b1 / 2.0, -b1, b1 / 2.0],
+            [1.0 + alpha, -2.0 * cos_w, 1.0 - alpha],
+        )
+    }
+
+    /// Notch at `center` Hz with quality factor `q` (center over bandwidth).
+    pub fn notch(center: f64, q: f64, sample_rate: f64) -> Self {
+        let w = 2.0 * PI * center / sample_rate;
+        let (cos_w, alpha) = (w.cos(), w.sin() / (2.0 * q));
+        Self::normalized(
+            [1.0, -2.0 * cos_w, 1.0],
+            [1.0 + alpha, -2.0 * cos_w, 1.0 - alpha],
+        )
+    }
+
+    fn prewarp(cutoff: f64, sample_rate: f64) -> (f64, f64) {
+        let w = 2.0 * PI * cutoff / sample_rate;
+        (w.cos(), w.sin() / (2.0 * std::f64::consts::FRAC_1_SQRT_2))
+    }
+
+    fn normalized(b: [f64; 3], a: [f64; 3]) -> Self {
+        Self {
+            b: [b[0] / a[0], b[1] / a[0], b[2] / a[0]],
+            a: [a[1] / a[0], a[2] / a[0]],
+        }
+    }
+
+    /// Filter a signal forward (direct form I, zero initial state).
+    pub fn filter(&self, input: &[f64]) -> Vec<f64> {
+        self.filter_from(input, 0.0, 0.0)
+    }
+
+    /// Filter a signal forward, then backward, for zero phase shift, each
+    /// pass starting as if its first sample had always been the input, so
+    /// a signal away from zero doesn't start with a step response.
+    pub fn filtfilt(&self, input: &[f64]) -> Vec<f64> {
+        let dc_gain = (self.b[0] + self.b[1] + self.b[2]) / (1.0 + self.a[0] + self.a[1]);
+        let pass = |signal: &[f64]| {
+            let first = signal.first().copied().unwrap_or(0.0);
+            self.filter_from(signal, first, first * dc_gain)
+        };
+        let mut forward = pass(input);
+        forward.reverse();
+        let mut output = pass(&forward);
+        output.reverse();
+        output
+    }
+
+    /// Filter with the previous inputs at `x0` and outputs at `y0`.
+    fn filter_from(&self, input: &[f64], x0: f64, y0: f64) -> Vec<f64> {
+        let (mut x1, mut x2, mut y1, mut y2) = (x0, x0, y0, y0);
+        input
+            .iter()
+            .map(|&x| {
+                let y = self.b[0] * x + self.b[1] * x1 + self.b[2] * x2
+                    - self.a[0] * y1
+                    - self.a[1] * y2;
+                x2 = x1;
+                x1 = x;
+                y2 = y1;
+                y1 = y;
+                y
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FS: f64 = 300.0;
+
+    fn sine(hz: f64, seconds: f64) -> Vec<f64> {
+        (0..(seconds * FS) as usize)
+            .map(|i| (2.0 * PI * hz * i as f64 / FS).sin())
+            .collect()
+    }
+
+    /// The middle half of a signal, away from the filters' edge effects.
+    fn middle(signal: &[f64]) -> &[f64] {
+        &signal[signal.len() / 4..signal.len() * 3 / 4]
+    }
+
+    /// RMS of the filtered signal's middle over the input's.
+    fn gain(filter: Filter, hz: f64, seconds: f64) -> f64 {
+        let input = sine(hz, seconds);
+        let output = apply_filters(&[filter], &input, FS).unwrap();
+        let rms = |s: &[f64]| (s.iter().map(|v| v * v).sum::<f64>() / s.len() as f64).sqrt();
+        rms(middle(&output)) / rms(middle(&input))
+    }
+
+    /// Largest difference between a pass-band sine and its filtered copy,
+    /// which is small only without a phase shift.
+    fn distortion(filter: Filter, hz: f64) -> f64 {
+        let input = sine(hz, 20.0);
+        let output = apply_filters(&[filter], &input, FS).unwrap();
+        middle(&input)
+            .iter()
+            .zip(middle(&output))
+            .map(|(a, b)| (a - b).abs())
+            .fold(0.0, f64::max)
+    }
+
+    #[test]
+    fn notch_removes_mains_and_keeps_the_rest() {
+        let notch = Filter::Notch(50.0);
+        assert!(gain(notch, 50.0, 20.0) < 0.01);
+        assert!((gain(notch, 10.0, 20.0) - 1.0).abs() < 0.01);
+        assert!(distortion(notch, 10.0) < 0.01);
+    }
+
+    #[test]
+    fn highpass_removes_wander_and_keeps_the_rest() {
+        let highpass = Filter::HighPass(0.5);
+        assert!(gain(highpass, 0.1, 120.0) < 0.01);
+        // Forward and backward, the cutoff is passed at half amplitude
+        assert!((gain(highpass, 0.5, 120.0) - 0.5).abs() < 0.02);
+        assert!((gain(highpass, 10.0, 20.0) - 1.0).abs() < 0.01);
+        assert!(distortion(highpass, 10.0) < 0.01);
+    }
+
+    #[test]
+    fn lowpass_removes_noise_and_keeps_the_rest() {
+        let lowpass = Filter::LowPass(40.0);
+        assert!(gain(lowpass, 120.0, 20.0) < 0.01);
+        assert!((gain(lowpass, 40.0, 20.0) - 0.5).abs() < 0.02);
+        assert!((gain(lowpass, 5.0, 20.0) - 1.0).abs() < 0.01);
+        assert!(distortion(lowpass, 5.0) < 0.01);
+    }
+
+    #[test]
+    fn rejects_frequencies_outside_the_band() {
+        let signal = sine(10.0, 1.0);
+        for filter in [
+            Filter::LowPass(150.0),
+            Filter::HighPass(200.0),
+            Filter::Notch(150.0),
+            Filter::HighPass(0.0),
+            Filter::LowPass(-1.0),
+            Filter::Notch(f64::NAN),
+        ] {
+            assert!(
+                apply_filters(&[filter], &signal, FS).is_err(),
+                "{:?}",
+                filter
+            );
+        }
+    }
+
+    #[test]
+    fn filters_each_side_of_a_gap_on_its_own() {
+        // A 1 mV offset either side of a gap of zeros: filtered run by run,
+        // the high-pass removes the offset and nothing rings at the edges
+        let n = (4.0 * FS) as usize;
+        let gap = n / 2..n / 2 + FS as usize;
+        let flags: Vec<SampleFlag> = (0..n)
+            .map(|i| {
+                if gap.contains(&i) {
+                    SampleFlag::Missing
+                } else {
+                    SampleFlag::Measured
+                }
+            })
+            .collect();
+        let signal: Vec<f64> = (0..n)
+            .map(|i| if gap.contains(&i) { 0.0 } else { 1.0 })
+            .collect();
+        let filter = [Filter::HighPass(0.5)];
+
+        let output = apply_filters_around_gaps(&filter, &signal, &flags, FS).unwrap();
+        assert!(output.iter().all(|v| v.abs() < 1e-9));
+
+        let across = apply_filters(&filter, &signal, FS).unwrap();
+        assert!(across[gap.start - 1].abs() > 0.1);
+    }
+
+    #[test]
+    fn labels_filters_for_the_prefiltering_field() {
+        let filters = [Filter::HighPass(0.5), Filter::Notch(50.0)];
+        assert_eq!(prefilter_text("", &filters), "HP:0.5Hz N:50Hz");
+        assert_eq!(
+            prefilter_text("LP:40Hz", &filters),
+            "LP:40Hz HP:0.5Hz N:50Hz"
+        );
+    }
+}
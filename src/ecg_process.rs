@@ -1,43 +1,214 @@
 use anyhow::{anyhow, Result};
+use serde::Serialize;
 use std::collections::HashMap;
 
+use crate::audit::{self, errln, outln};
+use crate::dsp::Biquad;
+use crate::noise;
 use crate::pdf_extract::{DrawingPath, Point};
+use crate::profile::LayoutProfile;
+use crate::sample_flags::SampleFlag;
+
+/// Horizontal lines closer together than this, in points, are one baseline.
+const BASELINE_CLUSTER: f64 = 1.0;
+
+/// Largest difference from the median row spacing, as a fraction of it,
+/// between neighboring baselines.
+const ROW_SPACING_TOLERANCE: f64 = 0.1;
 
 /// Extract the baseline y-coordinates for each row from horizontal grid lines.
 ///
 /// The 1-lead PDF displays the single lead across multiple rows on one page.
-/// Each row has a horizontal baseline at its center.
-pub fn extract_baselines(paths: &[DrawingPath]) -> Result<Vec<f64>> {
-    for path in paths {
-        let (r, g, b) = path.color;
-        // Must be black
-        if r != 0.0 || g != 0.0 || b != 0.0 {
-            continue;
-        }
-        // Width ~0.4
-        if !(0.35 < path.width && path.width < 0.45) {
-            continue;
+/// Each row has a horizontal baseline at its center. Every visible baseline
+/// is returned, top to bottom, so 60-second and other longer strips with
+/// more rows than the profile's minimum are read in full.
+///
+/// Long horizontal lines in the baseline style are collected from all
+/// paths and clustered by y, each cluster's median being a candidate. Rows
+/// may be separated by lines in the same style, and the strip framed by
+/// them, so the baselines are the candidates nearest the trace paths, kept
+/// to the longest evenly spaced run and extended by candidates continuing
+/// that spacing (rows whose trace is missing). Without trace paths, they
+/// are the longest evenly spaced run of candidates.
+pub fn extract_baselines(paths: &[DrawingPath], profile: &LayoutProfile) -> Result<Vec<f64>> {
+    // Horizontal lines spanning the strip, within the visible page area
+    let mut y_values: Vec<f64> = paths
+        .iter()
+        .filter(|path| profile.baseline.matches(path.color, path.width))
+        .flat_map(|path| &path.segments)
+        .filter(|segment| is_baseline_segment(segment, profile))
+        .map(|(p1, _)| p1.y)
+        .collect();
+    // Need a line per row, at least
+    if y_values.len() < profile.baseline.min_segments {
+        return Err(anyhow!("Could not find baseline grid lines in PDF"));
+    }
+
+    // One candidate per cluster of lines, top to bottom; lines drawn twice
+    // count once
+    y_values.sort_by(f64::total_cmp);
+    let mut candidates: Vec<f64> = Vec::new();
+    let mut cluster: Vec<f64> = Vec::new();
+    for y in y_values {
+        if cluster
+            .last()
+            .is_some_and(|&last| y - last >= BASELINE_CLUSTER)
+        {
+            candidates.extend(median(std::mem::take(&mut cluster)));
         }
-        // Need at least 4 segments
-        if path.segments.len() < 4 {
-            continue;
+        cluster.push(y);
+    }
+    candidates.extend(median(cluster));
+
+    // Median y of each trace path
+    let trace_ys: Vec<f64> = paths
+        .iter()
+        .filter(|path| {
+            profile.trace.matches(path.color, path.width)
+                && path.segments.len() >= profile.trace.min_segments
+        })
+        .filter_map(|path| median(path.segments.iter().map(|(p1, _)| p1.y).collect()))
+        .collect();
+    let baselines = traced_baselines(&candidates, &trace_ys, profile.max_row_distance)
+        .unwrap_or_else(|| evenly_spaced_run(&candidates).to_vec());
+    if baselines.len() >= profile.rows {
+        Ok(baselines)
+    } else {
+        Err(anyhow!("Could not find baseline grid lines in PDF"))
+    }
+}
+
+/// Whether a segment is a horizontal line long enough, and high enough on
+/// the page, to be a baseline.
+pub(crate) fn is_baseline_segment((p1, p2): &(Point, Point), profile: &LayoutProfile) -> bool {
+    (p1.y - p2.y).abs() < 0.01
+        && (p2.x - p1.x).abs() > profile.min_baseline_length
+        && p1.y < profile.max_baseline_y
+}
+
+/// The candidates nearest a trace path's median y, within `max_distance`,
+/// kept to their longest evenly spaced run and extended by candidates
+/// continuing its spacing; `None` if no trace path is near a candidate.
+fn traced_baselines(candidates: &[f64], trace_ys: &[f64], max_distance: f64) -> Option<Vec<f64>> {
+    let mut traced = vec![false; candidates.len()];
+    for &y in trace_ys {
+        let nearest = candidates
+            .iter()
+            .enumerate()
+            .map(|(i, c)| (i, (c - y).abs()))
+            .filter(|&(_, d)| d <= max_distance)
+            .min_by(|a, b| a.1.total_cmp(&b.1));
+        if let Some((i, _)) = nearest {
+            traced[i] = true;
         }
+    }
+    let traced: Vec<f64> = candidates
+        .iter()
+        .zip(traced)
+        .filter_map(|(&c, t)| t.then_some(c))
+        .collect();
+    let mut run = evenly_spaced_run(&traced).to_vec();
+    let (&first, &last) = (run.first()?, run.last()?);
+    if run.len() < 2 {
+        return Some(run);
+    }
+    let spacing = (last - first) / (run.len() - 1) as f64;
+    let near = |y: f64| {
+        candidates
+            .iter()
+            .copied()
+            .find(|c| (c - y).abs() <= ROW_SPACING_TOLERANCE * spacing)
+    };
+    let mut y = first;
+    while let Some(c) = near(y - spacing) {
+        run.insert(0, c);
+        y = c;
+    }
+    let mut y = last;
+    while let Some(c) = near(y + spacing) {
+        run.push(c);
+        y = c;
+    }
+    Some(run)
+}
 
-        let mut y_values = Vec::new();
-        for (p1, p2) in &path.segments {
-            // Horizontal line spanning > 500 units
-            if (p1.y - p2.y).abs() < 0.01 && (p2.x - p1.x).abs() > 500.0 {
-                y_values.push(p1.y);
-            }
+/// The longest run of neighboring values spaced within
+/// [`ROW_SPACING_TOLERANCE`] of the median spacing; the first if several
+/// are as long.
+fn evenly_spaced_run(values: &[f64]) -> &[f64] {
+    let spacings: Vec<f64> = values.windows(2).map(|w| w[1] - w[0]).collect();
+    let Some(spacing) = median(spacings.clone()) else {
+        return values;
+    };
+    let even = |s: f64| (s - spacing).abs() <= ROW_SPACING_TOLERANCE * spacing;
+    let (mut best, mut start) = (0..1, 0);
+    for (i, &s) in spacings.iter().enumerate() {
+        if !even(s) {
+            start = i + 1;
+        } else if i + 2 - start > best.len() {
+            best = start..i + 2;
         }
+    }
+    &values[best]
+}
+
+/// What a report page shows, as far as digitizing goes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageKind {
+    /// ECG strip rows drawn along baselines.
+    Strip,
+    /// Summary text, trend graphs, or anything else not to digitize.
+    Other,
+}
 
-        // Only keep baselines within visible page area (y < 760)
-        let visible: Vec<f64> = y_values.into_iter().filter(|&y| y < 760.0).collect();
-        if visible.len() >= 4 {
-            return Ok(visible[..4].to_vec());
+/// Which rows, from the x-span of each row's trace, have no trace running
+/// along them: every row but the last (which may end early) spans at least
+/// half the widest row, and the last has some trace.
+fn incomplete_rows(spans: &[f64]) -> Vec<bool> {
+    let widest = spans.iter().cloned().fold(0.0, f64::max);
+    spans
+        .iter()
+        .enumerate()
+        .map(|(ri, &span)| span <= 0.0 || (ri + 1 < spans.len() && span < widest / 2.0))
+        .collect()
+}
+
+/// Classify a page from its drawing paths.
+///
+/// A strip page has the profile's baselines, and a trace running along each
+/// row (see [`incomplete_rows`]), or with `allow_partial` along most rows.
+/// Summary pages of emailed "Enhanced PDF" reports draw trend graphs with
+/// strokes much like the trace, but not along such rows.
+pub fn classify_page(
+    paths: &[DrawingPath],
+    profile: &LayoutProfile,
+    allow_partial: bool,
+) -> PageKind {
+    let Ok(baselines) = extract_baselines(paths, profile) else {
+        return PageKind::Other;
+    };
+    let mut spans = vec![(f64::INFINITY, f64::NEG_INFINITY); baselines.len()];
+    for trace in assign_traces(paths, &baselines, profile) {
+        if let Some(row) = trace.row {
+            let (min_x, max_x) = &mut spans[row];
+            for p in &trace.points {
+                *min_x = min_x.min(p.x);
+                *max_x = max_x.max(p.x);
+            }
         }
     }
-    Err(anyhow!("Could not find baseline grid lines in PDF"))
+    let widths: Vec<f64> = spans.iter().map(|(min, max)| max - min).collect();
+    let incomplete = incomplete_rows(&widths).iter().filter(|&&i| i).count();
+    let allowed = if allow_partial {
+        (widths.len() - 1) / 2
+    } else {
+        0
+    };
+    if !widths.is_empty() && incomplete <= allowed {
+        PageKind::Strip
+    } else {
+        PageKind::Other
+    }
 }
 
 /// Extract ECG waveform points grouped by row.
@@ -45,33 +216,129 @@ pub fn extract_baselines(paths: &[DrawingPath]) -> Result<Vec<f64>> {
 /// For a 1-lead PDF, the single lead is displayed across multiple rows,
 /// each representing a consecutive time segment.
 ///
+/// Each trace path is assigned to the row whose baseline is nearest its
+/// y-center. Very tall waves can reach into the neighboring row, pulling a
+/// path's center toward it; with the profile's `reattach_overshoot`, paths
+/// that continue one another (one starts where the other ends) are chained
+/// into strokes, and each stroke is assigned by its median y instead.
+///
 /// Returns: row_index -> list of (x, y) points sorted by x.
 pub fn extract_ecg_waveform_rows(
     paths: &[DrawingPath],
     baselines: &[f64],
+    profile: &LayoutProfile,
+) -> HashMap<usize, Vec<Point>> {
+    let traces = assign_traces(paths, baselines, profile);
+    trace_rows(&traces, paths.len(), baselines.len())
+}
+
+/// Group assigned trace paths' points by row, sorted by x, as
+/// [`extract_ecg_waveform_rows`] does, for callers that assigned the paths
+/// themselves; `drawn` is the page's number of drawing paths.
+pub fn trace_rows(
+    traces: &[AssignedTrace],
+    drawn: usize,
+    n_rows: usize,
 ) -> HashMap<usize, Vec<Point>> {
-    let mut rows: HashMap<usize, Vec<Point>> = HashMap::new();
-    for i in 0..baselines.len() {
-        rows.insert(i, Vec::new());
+    let assigned = traces.iter().filter(|trace| trace.row.is_some()).count();
+    audit::detail(format!(
+        "Trace paths: {} of {} drawn paths match the trace style; {} assigned \
+         to rows, {} discarded as too far from every baseline",
+        traces.len(),
+        drawn,
+        assigned,
+        traces.len() - assigned
+    ));
+
+    // Reserve each row's points up front, so rows grow without reallocating
+    let mut row_sizes = vec![0; n_rows];
+    for trace in traces {
+        if let Some(row) = trace.row {
+            row_sizes[row] += trace.points.len();
+        }
     }
+    let mut rows: HashMap<usize, Vec<Point>> = row_sizes
+        .into_iter()
+        .enumerate()
+        .map(|(i, n)| (i, Vec::with_capacity(n)))
+        .collect();
 
-    for path in paths {
-        let (r, g, b) = path.color;
-        // Must be black
-        if r != 0.0 || g != 0.0 || b != 0.0 {
-            continue;
+    let mut reattached = 0;
+    for trace in traces {
+        if let Some(row) = trace.row {
+            reattached += usize::from(trace.reattached);
+            rows.entry(row).or_default().extend(&trace.points);
+        }
+    }
+    if reattached > 0 {
+        outln!(
+            "Reattached {} overshooting trace path(s) to their rows",
+            reattached
+        );
+    }
+
+    // Sort each row's points by x-coordinate
+    for points in rows.values_mut() {
+        points.sort_by(|a, b| a.x.total_cmp(&b.x));
+    }
+
+    rows
+}
+
+/// A trace path's points and the row they were assigned to.
+#[derive(Debug, Clone)]
+pub struct AssignedTrace {
+    /// Index of the source path among the page's drawing paths.
+    pub path: usize,
+    pub points: Vec<Point>,
+    /// Assigned row, or `None` if the path is too far from every baseline.
+    pub row: Option<usize>,
+    /// Whether the path went to its stroke's row rather than its own nearest
+    /// row (see [`extract_ecg_waveform_rows`]).
+    pub reattached: bool,
+}
+
+/// How many of a strip page's drawing paths were read as its trace.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct PathCounts {
+    /// Drawing paths on the page.
+    pub drawn: usize,
+    /// Paths matching the trace style.
+    pub trace: usize,
+    /// Trace paths assigned to a row; the rest were too far from every
+    /// baseline.
+    pub assigned: usize,
+}
+
+impl PathCounts {
+    pub fn new(paths: &[DrawingPath], traces: &[AssignedTrace]) -> Self {
+        Self {
+            drawn: paths.len(),
+            trace: traces.len(),
+            assigned: traces.iter().filter(|t| t.row.is_some()).count(),
         }
-        // Width ~0.4
-        if !(0.35 < path.width && path.width < 0.45) {
+    }
+}
+
+/// Pick out the trace paths and assign each to a row, in path order.
+pub fn assign_traces(
+    paths: &[DrawingPath],
+    baselines: &[f64],
+    profile: &LayoutProfile,
+) -> Vec<AssignedTrace> {
+    let mut traces: Vec<(usize, Vec<Point>)> = Vec::with_capacity(paths.len());
+    for (index, path) in paths.iter().enumerate() {
+        // Must match the trace color and width (black, ~0.4 for Kardia)
+        if !profile.trace.matches(path.color, path.width) {
             continue;
         }
         // ECG paths have many segments
-        if path.segments.len() < 40 {
+        if path.segments.len() < profile.trace.min_segments {
             continue;
         }
 
         // Extract points from line segments, deduplicating adjacent shared endpoints
-        let mut points: Vec<Point> = Vec::new();
+        let mut points: Vec<Point> = Vec::with_capacity(path.segments.len() + 1);
         for (p1, p2) in &path.segments {
             if points.is_empty()
                 || (points.last().unwrap().x - p1.x).abs() > 0.001
@@ -82,35 +349,258 @@ pub fn extract_ecg_waveform_rows(
             points.push(*p2);
         }
 
-        if points.is_empty() {
-            continue;
+        if !points.is_empty() {
+            traces.push((index, points));
         }
+    }
 
-        // Determine which row by y-center proximity to baselines
-        let y_sum: f64 = points.iter().map(|p| p.y).sum();
-        let y_center = y_sum / points.len() as f64;
-
+    // Determine which row by y-center proximity to baselines
+    let nearest_row = |y: f64| {
         let mut min_dist = f64::INFINITY;
         let mut best_row = 0usize;
         for (ri, bl) in baselines.iter().enumerate() {
-            let dist = (y_center - bl).abs();
+            let dist = (y - bl).abs();
             if dist < min_dist {
                 min_dist = dist;
                 best_row = ri;
             }
         }
+        (best_row, min_dist)
+    };
+    let y_center = |points: &[Point]| points.iter().map(|p| p.y).sum::<f64>() / points.len() as f64;
+
+    let chain_rows: Vec<(usize, f64)> = if profile.reattach_overshoot {
+        let chains = chain_traces(traces.iter().map(|(_, points)| points.as_slice()));
+        // Median y of each chain, gathered in one buffer reused across chains
+        let mut order: Vec<usize> = (0..traces.len()).collect();
+        order.sort_by_key(|&i| chains[i]);
+        let mut ys: Vec<f64> = Vec::with_capacity(traces.iter().map(|(_, p)| p.len()).sum());
+        let by_chain: Vec<(usize, f64)> = order
+            .chunk_by(|&a, &b| chains[a] == chains[b])
+            .map(|members| {
+                ys.clear();
+                for &i in members {
+                    ys.extend(traces[i].1.iter().map(|p| p.y));
+                }
+                let mid = ys.len() / 2;
+                let (_, median, _) = ys.select_nth_unstable_by(mid, |a, b| a.total_cmp(b));
+                nearest_row(*median)
+            })
+            .collect();
+        chains.iter().map(|&c| by_chain[c]).collect()
+    } else {
+        traces
+            .iter()
+            .map(|(_, points)| nearest_row(y_center(points)))
+            .collect()
+    };
+
+    traces
+        .into_iter()
+        .zip(chain_rows)
+        .map(|((path, points), (row, dist))| {
+            let (own_row, own_dist) = nearest_row(y_center(&points));
+            let assigned = dist < profile.max_row_distance;
+            AssignedTrace {
+                path,
+                row: assigned.then_some(row),
+                reattached: assigned && (own_row != row || own_dist >= profile.max_row_distance),
+                points,
+            }
+        })
+        .collect()
+}
 
-        if min_dist < 80.0 {
-            rows.entry(best_row).or_default().extend(points);
+/// Write each row's trace points to `row_<n>.csv` in `dir`, and points too
+/// far from every baseline to `unassigned.csv`, as "x,y,path" rows (PDF
+/// coordinates, y down, and the source path index), for checking which
+/// strokes landed in which row.
+pub fn write_debug_rows(dir: &str, traces: &[AssignedTrace], baselines: &[f64]) -> Result<()> {
+    std::fs::create_dir_all(dir)?;
+    let dir = std::path::Path::new(dir);
+    let rows = (0..baselines.len()).map(Some).chain([None]);
+    for row in rows {
+        let mut csv = String::new();
+        match row {
+            Some(r) => csv.push_str(&format!("# baseline y {:.3}\n", baselines[r])),
+            None => csv.push_str("# farther than max_row_distance from every baseline\n"),
+        }
+        csv.push_str("x,y,path\n");
+        for trace in traces.iter().filter(|t| t.row == row) {
+            for p in &trace.points {
+                csv.push_str(&format!("{:.3},{:.3},{}\n", p.x, p.y, trace.path));
+            }
         }
+        let name = match row {
+            Some(r) => format!("row_{}.csv", r),
+            None => "unassigned.csv".to_string(),
+        };
+        std::fs::write(dir.join(name), csv)?;
     }
+    outln!("Row debug CSVs written: {}", dir.display());
+    Ok(())
+}
 
-    // Sort each row's points by x-coordinate
-    for points in rows.values_mut() {
-        points.sort_by(|a, b| a.x.partial_cmp(&b.x).unwrap());
+/// Colors of the trace paths assigned to alternate rows in the overlay.
+const OVERLAY_ROW_COLORS: [&str; 2] = ["#2ca02c", "#ff7f0e"];
+
+/// Draw the page's drawing paths, its trace paths colored by the row they
+/// were assigned to (red if none), the baselines, and each row's final
+/// samples mapped back onto the page, all in PDF coordinates, as an SVG
+/// file, so dropped segments and strokes in the wrong row stand out.
+pub fn write_debug_overlay(
+    path: &str,
+    paths: &[DrawingPath],
+    traces: &[AssignedTrace],
+    rows: &[RowSeries],
+    cal_pt_per_mv: f64,
+) -> Result<()> {
+    use std::fmt::Write as _;
+
+    let points = paths
+        .iter()
+        .flat_map(|p| &p.segments)
+        .flat_map(|&(a, b)| [a, b]);
+    let (mut left, mut top, mut right, mut bottom) = (0.0, 0.0, 0.0, 0.0f64);
+    for p in points {
+        left = p.x.min(left);
+        top = p.y.min(top);
+        right = p.x.max(right);
+        bottom = p.y.max(bottom);
     }
+    let (width, height) = (right - left, bottom - top);
+    let mut svg = String::new();
+    writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}pt" height="{h}pt" viewBox="{x} {y} {w} {h}">"#,
+        x = left,
+        y = top,
+        w = width,
+        h = height
+    )?;
+    writeln!(
+        svg,
+        r#"<rect x="{}" y="{}" width="{}" height="{}" fill="white"/>"#,
+        left, top, width, height
+    )?;
 
-    rows
+    let segments_path = |segments: &mut dyn Iterator<Item = &(Point, Point)>| {
+        let mut d = String::new();
+        for (a, b) in segments {
+            let _ = write!(d, "M{:.2} {:.2}L{:.2} {:.2}", a.x, a.y, b.x, b.y);
+        }
+        d
+    };
+    let trace_paths: Vec<usize> = traces.iter().map(|t| t.path).collect();
+    let others = segments_path(
+        &mut paths
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| !trace_paths.contains(i))
+            .flat_map(|(_, p)| &p.segments),
+    );
+    writeln!(
+        svg,
+        r##"<path d="{}" stroke="#c8c8c8" stroke-width="0.2" fill="none"/>"##,
+        others
+    )?;
+    let d: String = rows
+        .iter()
+        .map(|r| format!("M{} {:.2}H{}", left, r.baseline, right))
+        .collect();
+    writeln!(
+        svg,
+        r##"<path d="{}" stroke="#808080" stroke-width="0.3" stroke-dasharray="4 2" fill="none"/>"##,
+        d
+    )?;
+
+    // Trace paths as extracted, by assigned row
+    for trace in traces {
+        let color = match trace.row {
+            Some(row) => OVERLAY_ROW_COLORS[row % OVERLAY_ROW_COLORS.len()],
+            None => "#d62728",
+        };
+        writeln!(
+            svg,
+            r#"<path d="{}" stroke="{}" stroke-width="1" fill="none"/>"#,
+            segments_path(&mut paths[trace.path].segments.iter()),
+            color
+        )?;
+    }
+
+    // Final samples, spread evenly over the row's trace points; missing
+    // samples break the line
+    let mut d = String::new();
+    for row in rows {
+        let Some((x0, x1)) = row.x_range else {
+            continue;
+        };
+        let step = (x1 - x0) / row.voltages.len().saturating_sub(1).max(1) as f64;
+        let mut pen_down = false;
+        for (i, (&v, &flag)) in row.voltages.iter().zip(&row.flags).enumerate() {
+            if flag == SampleFlag::Missing {
+                pen_down = false;
+                continue;
+            }
+            let (x, y) = (
+                x0 + i as f64 * step,
+                row.baseline - (v + row.offset) * cal_pt_per_mv,
+            );
+            write!(d, "{}{:.2} {:.2}", if pen_down { "L" } else { "M" }, x, y)?;
+            pen_down = true;
+        }
+    }
+    writeln!(
+        svg,
+        r##"<path d="{}" stroke="#1f77b4" stroke-width="0.4" fill="none"/>"##,
+        d
+    )?;
+
+    let legend = [
+        ("#c8c8c8", "other paths"),
+        (OVERLAY_ROW_COLORS[0], "trace, even rows"),
+        (OVERLAY_ROW_COLORS[1], "trace, odd rows"),
+        ("#d62728", "trace, no row"),
+        ("#1f77b4", "final samples"),
+    ];
+    for (i, (color, text)) in legend.iter().enumerate() {
+        writeln!(
+            svg,
+            r#"<text x="{}" y="{}" font-family="sans-serif" font-size="8" fill="{}">{}</text>"#,
+            left + 4.0 + i as f64 * 90.0,
+            top + 10.0,
+            color,
+            text
+        )?;
+    }
+    svg.push_str("</svg>\n");
+
+    std::fs::write(path, svg)?;
+    outln!("Debug overlay written: {}", path);
+    Ok(())
+}
+
+/// Link trace paths that continue one another (a path starting where an
+/// earlier one ends) into chains. Returns each trace's chain index.
+fn chain_traces<'a>(traces: impl Iterator<Item = &'a [Point]>) -> Vec<usize> {
+    // Endpoints compared on a 0.01-unit grid
+    let key = |p: &Point| ((p.x * 100.0).round() as i64, (p.y * 100.0).round() as i64);
+
+    let mut chains = Vec::new();
+    let mut open_ends: HashMap<(i64, i64), usize> = HashMap::new();
+    let mut n_chains = 0;
+    for points in traces {
+        let chain = match open_ends.remove(&key(&points[0])) {
+            Some(c) => c,
+            None => {
+                n_chains += 1;
+                n_chains - 1
+            }
+        };
+        open_ends.insert(key(points.last().unwrap()), chain);
+        chains.push(chain);
+    }
+    chains
 }
 
 /// Convert (x, y) points to voltage values in millivolts.
@@ -124,45 +614,748 @@ pub fn points_to_voltage(points: &[Point], baseline_y: f64, cal_pt_per_mv: f64)
         .collect()
 }
 
-/// Process all rows: deduplicate, convert to voltages, concatenate.
-pub fn concatenate_to_signal(
+/// Fill gaps in a row, where points are more than 1.5 times the row's
+/// median spacing apart, with points interpolated at that spacing, flagging
+/// each point as measured, interpolated, or, in gaps of more than
+/// `max_interpolated` samples, missing.
+fn fill_gaps(
+    points: &[Point],
+    flags: &mut Vec<SampleFlag>,
+    max_interpolated: Option<usize>,
+) -> Vec<Point> {
+    let mut dxs: Vec<f64> = points.windows(2).map(|w| w[1].x - w[0].x).collect();
+    if dxs.is_empty() {
+        flags.extend(points.iter().map(|_| SampleFlag::Measured));
+        return points.to_vec();
+    }
+    let mid = dxs.len() / 2;
+    let dx = *dxs.select_nth_unstable_by(mid, f64::total_cmp).1;
+
+    let mut filled = Vec::with_capacity(points.len());
+    for (i, &p) in points.iter().enumerate() {
+        if let Some(prev) = i.checked_sub(1).map(|j| points[j]) {
+            let gap = p.x - prev.x;
+            if gap > dx * 1.5 {
+                let missing = (gap / dx).round() as usize - 1;
+                let flag = if max_interpolated.is_some_and(|max| missing > max) {
+                    SampleFlag::Missing
+                } else {
+                    SampleFlag::Interpolated
+                };
+                for k in 1..=missing {
+                    let t = k as f64 / (missing + 1) as f64;
+                    filled.push(Point {
+                        x: prev.x + gap * t,
+                        y: prev.y + (p.y - prev.y) * t,
+                    });
+                    flags.push(flag);
+                }
+            }
+        }
+        filled.push(p);
+        flags.push(SampleFlag::Measured);
+    }
+    filled
+}
+
+/// Fewest samples in a flat run at a row's extreme taken as clipping; a
+/// printed peak is never flat for this long.
+const MIN_CLIPPED_RUN: usize = 4;
+
+/// Largest difference, in millivolts, between samples of a clipped run.
+const CLIP_TOLERANCE: f64 = 1e-3;
+
+/// Flag measured samples where the row's trace runs flat at its highest or
+/// lowest level for at least [`MIN_CLIPPED_RUN`] samples, as when the
+/// device clipped the display at the strip's plotting bounds. An extreme at
+/// the row's median level is a flat isoelectric line, not clipping.
+pub(crate) fn flag_clipping(voltages: &[f64], flags: &mut [SampleFlag]) {
+    let Some(level) = median(voltages.to_vec()) else {
+        return;
+    };
+    let max = voltages.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    let min = voltages.iter().cloned().fold(f64::INFINITY, f64::min);
+    for extreme in [max, min] {
+        if (extreme - level).abs() <= CLIP_TOLERANCE {
+            continue;
+        }
+        let mut i = 0;
+        while i < voltages.len() {
+            let start = i;
+            while i < voltages.len()
+                && (voltages[i] - extreme).abs() <= CLIP_TOLERANCE
+                && flags[i] == SampleFlag::Measured
+            {
+                i += 1;
+            }
+            if i - start >= MIN_CLIPPED_RUN {
+                flags[start..i].fill(SampleFlag::Clipped);
+            }
+            i = i.max(start + 1);
+        }
+    }
+}
+
+/// Shortest flat run, in seconds, taken as the electrodes being off; the
+/// isoelectric line between beats is never flat for this long.
+const MIN_LEAD_OFF_SECONDS: f64 = 1.0;
+
+/// Largest range, in millivolts, of the samples in a lead-off run.
+const LEAD_OFF_TOLERANCE: f64 = 0.01;
+
+/// Largest distance, in millivolts, of a lead-off run from the signal's
+/// median level.
+const LEAD_OFF_BASELINE: f64 = 0.05;
+
+/// Flag runs of at least [`MIN_LEAD_OFF_SECONDS`] where the joined signal
+/// is flat at its baseline, as when the electrodes were off, so they aren't
+/// taken for real signal. Only measured and interpolated samples are
+/// flagged. Returns the number of samples flagged.
+pub fn flag_lead_off(signal: &[f64], flags: &mut [SampleFlag], sample_rate: usize) -> usize {
+    let Some(level) = median(signal.to_vec()) else {
+        return 0;
+    };
+    let min_run = (MIN_LEAD_OFF_SECONDS * sample_rate as f64).ceil() as usize;
+    let flat = |flag: SampleFlag| matches!(flag, SampleFlag::Measured | SampleFlag::Interpolated);
+    let mut flagged = 0;
+    let mut i = 0;
+    while i < signal.len() {
+        let start = i;
+        let (mut low, mut high) = (f64::INFINITY, f64::NEG_INFINITY);
+        while i < signal.len() && flat(flags[i]) {
+            let (l, h) = (low.min(signal[i]), high.max(signal[i]));
+            if h - l > LEAD_OFF_TOLERANCE || (signal[i] - level).abs() > LEAD_OFF_BASELINE {
+                break;
+            }
+            (low, high) = (l, h);
+            i += 1;
+        }
+        if i - start >= min_run {
+            flags[start..i].fill(SampleFlag::LeadOff);
+            flagged += i - start;
+        }
+        i = i.max(start + 1);
+    }
+    flagged
+}
+
+/// Smallest jump, in millivolts, from both neighbours to a single sample
+/// taken as a spike; a QRS complex changes far less from one sample to the
+/// next.
+const SPIKE_MV: f64 = 1.0;
+
+/// Replace single-sample spikes, where a measured sample jumps more than
+/// [`SPIKE_MV`] away from both neighbours in the same direction, as at the
+/// joins of PDF subpaths, with the midpoint of its neighbours, and flag
+/// them as despiked. Returns the number of samples replaced.
+pub fn despike(signal: &mut [f64], flags: &mut [SampleFlag]) -> usize {
+    let mut despiked = 0;
+    for i in 1..signal.len().saturating_sub(1) {
+        if flags[i] != SampleFlag::Measured {
+            continue;
+        }
+        let (before, after) = (signal[i] - signal[i - 1], signal[i] - signal[i + 1]);
+        if before.signum() == after.signum() && before.abs().min(after.abs()) > SPIKE_MV {
+            signal[i] = (signal[i - 1] + signal[i + 1]) / 2.0;
+            flags[i] = SampleFlag::Despiked;
+            despiked += 1;
+        }
+    }
+    despiked
+}
+
+/// Smallest jump, in millivolts, from one sample to the next at the start
+/// of a pacing spike; the steepest QRS upstroke is several times slower.
+const PACE_MIN_MV: f64 = 0.5;
+
+/// Longest time, in seconds, a pacing spike takes to fall back halfway to
+/// where it started.
+const PACE_MAX_WIDTH_SECONDS: f64 = 0.01;
+
+/// Find pacemaker spikes: a jump of at least [`PACE_MIN_MV`] within one
+/// sample that falls at least halfway back within
+/// [`PACE_MAX_WIDTH_SECONDS`]. Returns the sample index of each spike's
+/// start.
+pub fn detect_pacing_spikes(signal: &[f64], sample_rate: usize) -> Vec<usize> {
+    let max_width = ((PACE_MAX_WIDTH_SECONDS * sample_rate as f64).round() as usize).max(1);
+    let mut spikes = Vec::new();
+    let mut i = 1;
+    while i < signal.len() {
+        let (start, jump) = (signal[i - 1], signal[i] - signal[i - 1]);
+        let end = (i + max_width).min(signal.len() - 1);
+        let back = (i + 1..=end).find(|&k| (signal[k] - start) * jump.signum() <= jump.abs() / 2.0);
+        match back {
+            Some(k) if jump.abs() >= PACE_MIN_MV => {
+                spikes.push(i);
+                i = k + 1;
+            }
+            _ => i += 1,
+        }
+    }
+    spikes
+}
+
+/// Offset removed from each row before the rows are concatenated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Detrend {
+    /// Keep the voltages relative to the printed baseline.
+    #[default]
+    None,
+    /// Subtract each row's median, robust to tall QRS complexes.
+    RowMedian,
+    /// Subtract each row's mean.
+    RowMean,
+}
+
+impl Detrend {
+    /// Subtract the row's offset from its voltages, returning the offset.
+    fn apply(self, voltages: &mut [f64]) -> f64 {
+        let offset = match self {
+            Detrend::None => return 0.0,
+            Detrend::RowMean => voltages.iter().sum::<f64>() / voltages.len() as f64,
+            Detrend::RowMedian => {
+                let mut sorted = voltages.to_vec();
+                let mid = sorted.len() / 2;
+                *sorted.select_nth_unstable_by(mid, f64::total_cmp).1
+            }
+        };
+        voltages.iter_mut().for_each(|v| *v -= offset);
+        offset
+    }
+}
+
+/// How strip rows are cleaned up as they are joined into one signal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RowProcessing {
+    /// Longest gap in a row, in samples, filled by interpolation; longer
+    /// gaps are flagged [`SampleFlag::Missing`]. `None` interpolates all.
+    pub max_interpolated_gap: Option<usize>,
+    /// Offset removed from each row.
+    pub detrend: Detrend,
+    /// Trim samples repeated from the end of the previous row, and shift
+    /// a row whose start steps away from where the previous row ended.
+    pub continuous: bool,
+    /// Fill rows that fail to extract with missing samples instead of
+    /// failing.
+    pub allow_partial: bool,
+}
+
+/// Fewest distinct trace points in a row that extracted.
+const MIN_ROW_POINTS: usize = 2;
+
+/// Number of points with distinct x-coordinates, and the x-span, of a row.
+fn row_extent(points: &[Point]) -> (usize, f64) {
+    let mut count = 0;
+    let mut last_x = f64::NAN;
+    let (mut min_x, mut max_x) = (f64::INFINITY, f64::NEG_INFINITY);
+    for p in points {
+        if last_x.is_nan() || (p.x - last_x).abs() > 0.01 {
+            count += 1;
+            last_x = p.x;
+        }
+        min_x = min_x.min(p.x);
+        max_x = max_x.max(p.x);
+    }
+    (count, (max_x - min_x).max(0.0))
+}
+
+pub(crate) fn median(mut values: Vec<f64>) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+    let mid = values.len() / 2;
+    Some(*values.select_nth_unstable_by(mid, f64::total_cmp).1)
+}
+
+/// Most samples a row may repeat from the end of the previous row.
+const MAX_OVERLAP: usize = 64;
+
+/// Fewest samples taken as a repeated region, so a couple of similar
+/// samples by chance aren't trimmed.
+const MIN_OVERLAP: usize = 4;
+
+/// Largest difference, in mV, between a row's first samples and the
+/// previous row's last samples for them to count as repeated.
+const OVERLAP_TOLERANCE: f64 = 0.005;
+
+/// Number of samples at the start of `next` that repeat the end of `prev`:
+/// the longest run matching within [`OVERLAP_TOLERANCE`] that isn't flat,
+/// since a flat stretch matches any length.
+fn row_overlap(prev: &[f64], next: &[f64]) -> usize {
+    let max = MAX_OVERLAP.min(prev.len()).min(next.len());
+    (MIN_OVERLAP..=max)
+        .rev()
+        .find(|&k| {
+            let tail = &prev[prev.len() - k..];
+            let head = &next[..k];
+            let matches = tail
+                .iter()
+                .zip(head)
+                .all(|(a, b)| (a - b).abs() < OVERLAP_TOLERANCE);
+            let (lo, hi) = head
+                .iter()
+                .fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), &v| {
+                    (lo.min(v), hi.max(v))
+                });
+            matches && hi - lo > 4.0 * OVERLAP_TOLERANCE
+        })
+        .unwrap_or(0)
+}
+
+/// Step, in mV, from where `prev` was heading to the start of `next`, if it
+/// is larger than the sample-to-sample changes on either side of the join.
+fn row_step(prev: &[f64], next: &[f64]) -> Option<f64> {
+    let n = prev.len();
+    if n < 2 || next.len() < 2 {
+        return None;
+    }
+    let predicted = prev[n - 1] + (prev[n - 1] - prev[n - 2]);
+    let step = next[0] - predicted;
+    let local = prev[n.saturating_sub(5)..]
+        .windows(2)
+        .chain(next[..next.len().min(5)].windows(2))
+        .map(|w| (w[1] - w[0]).abs())
+        .fold(0.0, f64::max);
+    (step.abs() > (4.0 * local).max(OVERLAP_TOLERANCE)).then_some(step)
+}
+
+/// One strip row's samples before the rows are joined, for callers that
+/// stitch rows their own way; see [`row_series`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct RowSeries {
+    /// Row index, top to bottom.
+    pub row: usize,
+    /// Baseline (PDF y-coordinate, top-left origin) of the row.
+    pub baseline: f64,
+    /// Number of distinct trace points the row was read from.
+    pub trace_points: usize,
+    /// x-coordinates of the row's first and last trace points, in points;
+    /// `None` for a row that didn't extract, whose samples are all missing.
+    pub x_range: Option<(f64, f64)>,
+    /// Voltages in millivolts, gap-filled and detrended, still including
+    /// any samples repeated from the previous row.
+    pub voltages: Vec<f64>,
+    /// Whether each voltage was measured, interpolated, missing, or clipped.
+    pub flags: Vec<SampleFlag>,
+    /// Offset in millivolts removed from the row by detrending.
+    pub offset: f64,
+}
+
+/// Process each row on its own: deduplicate, fill gaps, convert to
+/// voltages, flag clipping, and detrend. Rows that fail to extract are
+/// errors, or with `allow_partial` are filled with missing samples, as
+/// many as the other full rows have.
+pub fn row_series(
     rows: &HashMap<usize, Vec<Point>>,
     baselines: &[f64],
     cal_pt_per_mv: f64,
-) -> Result<Vec<f64>> {
-    let mut all_voltages = Vec::new();
+    processing: RowProcessing,
+) -> Result<Vec<RowSeries>> {
+    // Deduplicated points of the current row, reused across rows
+    let mut deduped: Vec<Point> =
+        Vec::with_capacity(rows.values().map(Vec::len).max().unwrap_or(0));
+
+    let row_points = |ri: usize| rows.get(&ri).map_or(&[][..], Vec::as_slice);
+    let extents: Vec<(usize, f64)> = (0..baselines.len())
+        .map(|ri| row_extent(row_points(ri)))
+        .collect();
+    let spans: Vec<f64> = extents
+        .iter()
+        .map(|&(count, span)| if count < MIN_ROW_POINTS { 0.0 } else { span })
+        .collect();
+    let failed = incomplete_rows(&spans);
+    if failed.iter().all(|&f| f) {
+        return Err(anyhow!("No strip row has a trace"));
+    }
+    if let Some(ri) = failed.iter().position(|&f| f) {
+        if !processing.allow_partial {
+            return Err(anyhow!(
+                "Row {} has no usable trace ({} points); use --allow-partial \
+                 to write the other rows with the row left out",
+                ri,
+                extents[ri].0
+            ));
+        }
+    }
+    // Samples in place of a failed row: the median of the other full rows
+    let full_rows = |last: bool| {
+        let counts = (0..baselines.len())
+            .filter(|&ri| !failed[ri] && (last || ri + 1 < baselines.len()))
+            .map(|ri| extents[ri].0 as f64)
+            .collect();
+        median(counts)
+    };
+    let failed_len = full_rows(false).or_else(|| full_rows(true)).unwrap_or(0.0) as usize;
 
-    for ri in 0..baselines.len() {
-        let points = rows.get(&ri).ok_or_else(|| anyhow!("Missing row {}", ri))?;
-        if points.is_empty() {
-            eprintln!("Row {}: no data", ri);
+    let mut series = Vec::with_capacity(baselines.len());
+    for (ri, &baseline) in baselines.iter().enumerate() {
+        let points = row_points(ri);
+        if failed[ri] {
+            series.push(RowSeries {
+                row: ri,
+                baseline,
+                trace_points: extents[ri].0,
+                x_range: None,
+                voltages: vec![0.0; failed_len],
+                flags: vec![SampleFlag::Missing; failed_len],
+                offset: 0.0,
+            });
             continue;
         }
 
         // Remove duplicate x-coordinates (boundary points between segments)
-        let mut deduped = vec![points[0]];
-        for i in 1..points.len() {
-            if (points[i].x - deduped.last().unwrap().x).abs() > 0.01 {
-                deduped.push(points[i]);
+        deduped.clear();
+        deduped.push(points[0]);
+        for p in &points[1..] {
+            if (p.x - deduped.last().unwrap().x).abs() > 0.01 {
+                deduped.push(*p);
+            }
+        }
+
+        let mut flags = Vec::with_capacity(deduped.len());
+        let filled = fill_gaps(&deduped, &mut flags, processing.max_interpolated_gap);
+        let mut voltages = points_to_voltage(&filled, baseline, cal_pt_per_mv);
+        flag_clipping(&voltages, &mut flags);
+        let offset = processing.detrend.apply(&mut voltages);
+        series.push(RowSeries {
+            row: ri,
+            baseline,
+            trace_points: extents[ri].0,
+            x_range: Some((deduped[0].x, deduped[deduped.len() - 1].x)),
+            voltages,
+            flags,
+            offset,
+        });
+    }
+    Ok(series)
+}
+
+/// Join rows from [`row_series`] into one signal, in order, with a flag per
+/// sample. With `processing.continuous`, samples repeated from the end of
+/// the previous row are trimmed and a step at the join is removed.
+pub fn join_rows(rows: &[RowSeries], processing: RowProcessing) -> (Vec<f64>, Vec<SampleFlag>) {
+    let mut all_voltages = Vec::with_capacity(rows.iter().map(|r| r.voltages.len()).sum());
+    let mut flags = Vec::with_capacity(all_voltages.capacity());
+    let mut after_failed = false;
+
+    for row in rows {
+        let ri = row.row;
+        let Some((first_x, last_x)) = row.x_range else {
+            errln!(
+                "Row {}: not extracted ({} points); {} samples marked missing",
+                ri,
+                row.trace_points,
+                row.voltages.len()
+            );
+            all_voltages.extend_from_slice(&row.voltages);
+            flags.extend_from_slice(&row.flags);
+            after_failed = true;
+            continue;
+        };
+
+        let mut voltages = row.voltages.clone();
+        let mut row_flags = row.flags.clone();
+        let count = |flag| row.flags.iter().filter(|&&f| f == flag).count();
+        let interpolated = count(SampleFlag::Interpolated);
+        let clipped = count(SampleFlag::Clipped);
+        let missing = count(SampleFlag::Missing);
+        let mut notes = String::new();
+        if interpolated > 0 {
+            notes.push_str(&format!(", {} interpolated", interpolated));
+        }
+        if clipped > 0 {
+            notes.push_str(&format!(", {} clipped", clipped));
+        }
+        if missing > 0 {
+            notes.push_str(&format!(", {} missing", missing));
+        }
+        if processing.detrend != Detrend::None {
+            notes.push_str(&format!(", offset {:.3} mV removed", row.offset));
+        }
+        if processing.continuous && !all_voltages.is_empty() && !after_failed {
+            let overlap = row_overlap(&all_voltages, &voltages);
+            if overlap > 0 {
+                voltages.drain(..overlap);
+                row_flags.drain(..overlap);
+                notes.push_str(&format!(", {} repeated samples trimmed", overlap));
+            }
+            if let Some(step) = row_step(&all_voltages, &voltages) {
+                voltages.iter_mut().for_each(|v| *v -= step);
+                notes.push_str(&format!(", {:.3} mV step at join removed", step));
             }
         }
 
-        let voltages = points_to_voltage(&deduped, baselines[ri], cal_pt_per_mv);
         let min_v = voltages.iter().cloned().fold(f64::INFINITY, f64::min);
         let max_v = voltages.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
 
-        println!(
-            "Row {}: {} samples, x:[{:.1}-{:.1}], range [{:.3}, {:.3}] mV",
+        let snr = noise::estimate_noise(&voltages)
+            .map(|n| format!(", noise {:.4} mV, SNR {:.1} dB", n.noise_rms, n.snr_db))
+            .unwrap_or_default();
+
+        outln!(
+            "Row {}: {} samples, x:[{:.1}-{:.1}], range [{:.3}, {:.3}] mV{}{}",
             ri,
             voltages.len(),
-            deduped.first().unwrap().x,
-            deduped.last().unwrap().x,
+            first_x,
+            last_x,
             min_v,
-            max_v
+            max_v,
+            snr,
+            notes
         );
 
         all_voltages.extend(voltages);
+        flags.extend(row_flags);
+        after_failed = false;
+    }
+
+    (all_voltages, flags)
+}
+
+/// Process all rows and join them into one signal: [`row_series`], then
+/// [`join_rows`].
+pub fn concatenate_to_signal(
+    rows: &HashMap<usize, Vec<Point>>,
+    baselines: &[f64],
+    cal_pt_per_mv: f64,
+    processing: RowProcessing,
+) -> Result<(Vec<f64>, Vec<SampleFlag>)> {
+    let series = row_series(rows, baselines, cal_pt_per_mv, processing)?;
+    Ok(join_rows(&series, processing))
+}
+
+/// Detect QRS complexes with a Pan-Tompkins style detector.
+///
+/// The signal is band-passed (5-15 Hz), differentiated, squared, and
+/// integrated over a 150 ms window. Peaks of the integrated signal are
+/// classified as QRS or noise against adaptive thresholds, with a 200 ms
+/// refractory period and a search-back at half threshold when no beat is
+/// found within 1.66 average RR intervals.
+///
+/// Returns the sample index of each R-peak, located as the highest point
+/// of the raw signal near the detected QRS (lead I has an upright R wave).
+pub fn detect_r_peaks(signal: &[f64], sample_rate: usize) -> Vec<usize> {
+    let fs = sample_rate as f64;
+    let n = signal.len();
+    if n < 2 * sample_rate {
+        return Vec::new();
+    }
+
+    // Band-pass to keep QRS energy, rejecting baseline wander and T waves
+    let band = Biquad::lowpass(15.0, fs).filter(&Biquad::highpass(5.0, fs).filter(signal));
+
+    // Five-point derivative, then squaring
+    let at = |i: usize, k: isize| band[(i as isize + k).clamp(0, n as isize - 1) as usize];
+    let squared: Vec<f64> = (0..n)
+        .map(|i| {
+            let d = (2.0 * at(i, 2) + at(i, 1) - at(i, -1) - 2.0 * at(i, -2)) * fs / 8.0;
+            d * d
+        })
+        .collect();
+
+    // Moving-window integration
+    let window = ((0.150 * fs).round() as usize).max(1);
+    let mut integrated = vec![0.0; n];
+    let mut sum = 0.0;
+    for i in 0..n {
+        sum += squared[i];
+        if i >= window {
+            sum -= squared[i - window];
+        }
+        integrated[i] = sum / window as f64;
+    }
+
+    // Initialize signal and noise peak levels from the first two seconds
+    let learn = &integrated[..2 * sample_rate];
+    let mut spki = learn.iter().cloned().fold(0.0, f64::max) * 0.25;
+    let mut npki = learn.iter().sum::<f64>() / learn.len() as f64 * 0.5;
+
+    let refractory = (0.200 * fs) as usize;
+    let mut qrs: Vec<usize> = Vec::new();
+    let mut rr_avg: Option<f64> = None;
+    // Best sub-threshold peak since the last QRS, for search-back
+    let mut candidate: Option<(usize, f64)> = None;
+
+    for i in 1..n - 1 {
+        let peak = integrated[i];
+        if !(peak > integrated[i - 1] && peak >= integrated[i + 1]) {
+            continue;
+        }
+        let threshold = npki + 0.25 * (spki - npki);
+        let since_last = qrs.last().map(|&last| i - last);
+        if since_last.is_some_and(|d| d < refractory) {
+            continue;
+        }
+
+        // Search back for a missed beat when the gap is too long
+        if let (Some(gap), Some(rr), Some((c, c_peak))) = (since_last, rr_avg, candidate) {
+            if gap as f64 > 1.66 * rr && c_peak > threshold * 0.5 {
+                spki = 0.25 * c_peak + 0.75 * spki;
+                accept_qrs(&mut qrs, &mut rr_avg, c);
+                candidate = None;
+                if i - c < refractory {
+                    continue;
+                }
+            }
+        }
+
+        if peak > threshold {
+            spki = 0.125 * peak + 0.875 * spki;
+            accept_qrs(&mut qrs, &mut rr_avg, i);
+            candidate = None;
+        } else {
+            npki = 0.125 * peak + 0.875 * npki;
+            if candidate.is_none_or(|(_, c_peak)| peak > c_peak) {
+                candidate = Some((i, peak));
+            }
+        }
+    }
+
+    // The integrated peak lags the QRS; find the highest point of the raw
+    // signal in the preceding integration window
+    let mut peaks: Vec<usize> = Vec::new();
+    for &i in &qrs {
+        let lo = i.saturating_sub(window + window / 2);
+        let hi = (i + window / 4).min(n - 1);
+        let r = lo
+            + signal[lo..=hi]
+                .iter()
+                .enumerate()
+                .max_by(|a, b| a.1.total_cmp(b.1))
+                .map(|(k, _)| k)
+                .unwrap_or(0);
+        if peaks.last().is_none_or(|&last| r >= last + refractory) {
+            peaks.push(r);
+        }
+    }
+
+    peaks
+}
+
+/// Record a detected QRS and update the running RR-interval average.
+fn accept_qrs(qrs: &mut Vec<usize>, rr_avg: &mut Option<f64>, i: usize) {
+    if let Some(&last) = qrs.last() {
+        let rr = (i - last) as f64;
+        *rr_avg = Some(match *rr_avg {
+            Some(avg) => 0.875 * avg + 0.125 * rr,
+            None => rr,
+        });
+    }
+    qrs.push(i);
+}
+
+/// Half-width, in seconds, of the window around each detected beat whose
+/// largest deflection gives the QRS polarity.
+const QRS_HALF_WIDTH: f64 = 0.1;
+
+/// Half-width, in seconds, of the window around each detected beat whose
+/// median is the beat's baseline level.
+const BEAT_BASELINE_HALF_WIDTH: f64 = 0.5;
+
+/// How many detected beats have an upright or an inverted QRS complex.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Polarity {
+    /// Beats deflecting further above their baseline than below it.
+    pub upright: usize,
+    /// Beats deflecting further below their baseline than above it.
+    pub inverted: usize,
+}
+
+impl Polarity {
+    /// Whether most beats are inverted, as when the device was held
+    /// backwards; lead I normally has an upright R wave.
+    pub fn is_inverted(&self) -> bool {
+        self.inverted > self.upright
+    }
+}
+
+/// Classify each beat found by [`detect_r_peaks`] as upright or inverted,
+/// by whether its QRS complex deflects further above or below the median
+/// level of the second around it.
+pub fn qrs_polarity(signal: &[f64], sample_rate: usize) -> Polarity {
+    let fs = sample_rate as f64;
+    let qrs = (QRS_HALF_WIDTH * fs).round() as usize;
+    let around = (BEAT_BASELINE_HALF_WIDTH * fs).round() as usize;
+    let window =
+        |i: usize, half: usize| &signal[i.saturating_sub(half)..(i + half + 1).min(signal.len())];
+    let mut polarity = Polarity::default();
+    for i in detect_r_peaks(signal, sample_rate) {
+        let Some(level) = median(window(i, around).to_vec()) else {
+            continue;
+        };
+        let beat = window(i, qrs);
+        let up = beat.iter().cloned().fold(f64::NEG_INFINITY, f64::max) - level;
+        let down = level - beat.iter().cloned().fold(f64::INFINITY, f64::min);
+        if down > up {
+            polarity.inverted += 1;
+        } else {
+            polarity.upright += 1;
+        }
+    }
+    polarity
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::demo::demo_recording;
+
+    /// The demo recording's R-peak sample indexes, from its "R" annotations.
+    fn demo_beats(sample_rate: usize) -> (Vec<f64>, Vec<usize>) {
+        let recording = demo_recording(sample_rate);
+        let beats = recording
+            .annotations
+            .iter()
+            .filter(|a| a.text == "R")
+            .map(|a| (a.onset * sample_rate as f64).round() as usize)
+            .collect();
+        (recording.signal, beats)
+    }
+
+    /// Assert that `found` has one peak within `tolerance` samples of each
+    /// of the `expected` beats.
+    fn assert_peaks(found: &[usize], expected: &[usize], tolerance: usize) {
+        assert_eq!(found.len(), expected.len(), "found {:?}", found);
+        for (&f, &e) in found.iter().zip(expected) {
+            assert!(f.abs_diff(e) <= tolerance, "peak at {}, beat at {}", f, e);
+        }
+    }
+
+    #[test]
+    fn finds_every_demo_beat() {
+        for sample_rate in [300, 500] {
+            let (signal, beats) = demo_beats(sample_rate);
+            let tolerance = sample_rate / 100; // 10 ms
+            assert_peaks(&detect_r_peaks(&signal, sample_rate), &beats, tolerance);
+        }
+    }
+
+    #[test]
+    fn ignores_mains_hum_and_wander() {
+        let (mut signal, beats) = demo_beats(300);
+        for (i, v) in signal.iter_mut().enumerate() {
+            let t = i as f64 / 300.0;
+            *v += 0.05 * (2.0 * std::f64::consts::PI * 50.0 * t).sin()
+                + 0.3 * (2.0 * std::f64::consts::PI * 0.1 * t).sin();
+        }
+        assert_peaks(&detect_r_peaks(&signal, 300), &beats, 3);
     }
 
-    Ok(all_voltages)
+    #[test]
+    fn finds_nothing_in_flat_or_short_signals() {
+        assert!(detect_r_peaks(&[0.0; 3000], 300).is_empty());
+        let (signal, _) = demo_beats(300);
+        assert!(detect_r_peaks(&signal[..599], 300).is_empty());
+    }
+
+    #[test]
+    fn tells_inverted_beats_apart() {
+        let (signal, beats) = demo_beats(300);
+        let upright = qrs_polarity(&signal, 300);
+        assert_eq!((upright.upright, upright.inverted), (beats.len(), 0));
+        assert!(!upright.is_inverted());
+
+        let inverted: Vec<f64> = signal.iter().map(|v| -v).collect();
+        assert!(qrs_polarity(&inverted, 300).is_inverted());
+    }
 }
@@ -0,0 +1,268 @@
+use anyhow::{anyhow, Result};
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
+use std::io::{Read, Seek, SeekFrom};
+
+use crate::tal;
+
+/// Per-signal header fields of an EDF file.
+#[derive(Debug, Clone)]
+pub struct SignalHeader {
+    pub label: String,
+    pub transducer: String,
+    pub phys_dim: String,
+    pub phys_min: f64,
+    pub phys_max: f64,
+    pub dig_min: i32,
+    pub dig_max: i32,
+    pub prefilter: String,
+    pub samples_per_record: usize,
+}
+
+impl SignalHeader {
+    /// Whether this is an EDF+ or BDF+ annotation signal.
+    pub fn is_annotations(&self) -> bool {
+        self.label == "EDF Annotations" || self.label == "BDF Annotations"
+    }
+
+    /// Physical units per digital step: physical = gain * digital + offset.
+    pub fn gain(&self) -> f64 {
+        (self.phys_max - self.phys_min) / (self.dig_max - self.dig_min) as f64
+    }
+
+    /// Physical value at digital zero: physical = gain * digital + offset.
+    pub fn offset(&self) -> f64 {
+        self.phys_max - self.gain() * self.dig_max as f64
+    }
+
+    /// Convert a digital sample value to its physical value.
+    pub fn to_physical(&self, digital: i32) -> f64 {
+        self.gain() * digital as f64 + self.offset()
+    }
+}
+
+/// Main header fields of an EDF file, followed by its signal headers.
+#[derive(Debug, Clone)]
+pub struct Header {
+    pub version: String,
+    /// Bytes per sample: 2 for EDF, 3 for BDF (version field "\xFFBIOSEMI").
+    pub sample_bytes: usize,
+    pub patient: String,
+    pub recording: String,
+    pub start_date: String,
+    pub start_time: String,
+    pub header_bytes: usize,
+    /// Reserved field; "EDF+C" or "EDF+D" for EDF+ files.
+    pub reserved: String,
+    /// Number of data records, or -1 if unknown (still being recorded).
+    pub n_records: i64,
+    /// Duration of one data record in seconds.
+    pub record_duration: f64,
+    pub signals: Vec<SignalHeader>,
+}
+
+impl Header {
+    /// Size of one data record in bytes.
+    pub fn record_bytes(&self) -> usize {
+        self.signals
+            .iter()
+            .map(|s| s.samples_per_record * self.sample_bytes)
+            .sum()
+    }
+
+    /// Whether this is a 24-bit BDF/BDF+ file.
+    pub fn is_bdf(&self) -> bool {
+        self.sample_bytes == 3
+    }
+
+    /// Byte offset of a signal's samples within a data record.
+    pub fn signal_offset(&self, index: usize) -> usize {
+        self.signals[..index]
+            .iter()
+            .map(|s| s.samples_per_record * self.sample_bytes)
+            .sum()
+    }
+
+    /// Index of the "EDF Annotations" (or BDF+ "BDF Annotations") signal, if any.
+    pub fn annotation_index(&self) -> Option<usize> {
+        self.signals.iter().position(|s| s.is_annotations())
+    }
+
+    /// Start date and time of the recording.
+    pub fn start_datetime(&self) -> Result<NaiveDateTime> {
+        parse_start(&self.start_date, &self.start_time)
+    }
+}
+
+/// Parse EDF "dd.mm.yy" and "hh.mm.ss" start fields.
+///
+/// Two-digit years use the EDF clipping rule: 85-99 are 1985-1999,
+/// 00-84 are 2000-2084.
+pub fn parse_start(date: &str, time: &str) -> Result<NaiveDateTime> {
+    let d: Vec<u32> = split_numbers(date)?;
+    let t: Vec<u32> = split_numbers(time)?;
+    if d.len() != 3 || t.len() != 3 {
+        return Err(anyhow!("Invalid EDF start date/time: {} {}", date, time));
+    }
+    let year = if d[2] >= 85 { 1900 + d[2] } else { 2000 + d[2] };
+    let date = NaiveDate::from_ymd_opt(year as i32, d[1], d[0])
+        .ok_or_else(|| anyhow!("Invalid EDF start date: {}", date))?;
+    let time = NaiveTime::from_hms_opt(t[0], t[1], t[2])
+        .ok_or_else(|| anyhow!("Invalid EDF start time: {}", time))?;
+    Ok(date.and_time(time))
+}
+
+fn split_numbers(field: &str) -> Result<Vec<u32>> {
+    field
+        .split('.')
+        .map(|part| {
+            part.parse::<u32>()
+                .map_err(|_| anyhow!("Invalid number {:?} in {:?}", part, field))
+        })
+        .collect()
+}
+
+/// Read an ASCII header field, trimming the space padding.
+fn field(buf: &[u8], start: usize, width: usize) -> String {
+    String::from_utf8_lossy(&buf[start..start + width])
+        .trim()
+        .to_string()
+}
+
+/// Read and parse a numeric header field.
+fn number<T: std::str::FromStr>(buf: &[u8], start: usize, width: usize, name: &str) -> Result<T> {
+    let s = field(buf, start, width);
+    s.parse()
+        .map_err(|_| anyhow!("Invalid {} header field: {:?}", name, s))
+}
+
+/// Read the main header and all signal headers of an EDF/EDF+ file.
+pub fn read_header<R: Read>(reader: &mut R) -> Result<Header> {
+    let mut main = [0u8; 256];
+    reader.read_exact(&mut main)?;
+
+    let n_signals: usize = number(&main, 252, 4, "number of signals")?;
+    let mut sig = vec![0u8; n_signals * 256];
+    reader.read_exact(&mut sig)?;
+
+    // Signal headers are interleaved: all labels, then all transducers, etc.
+    let mut offset = 0;
+    let mut column = |width: usize| {
+        let start = offset;
+        offset += width * n_signals;
+        move |i: usize| start + i * width
+    };
+    let label = column(16);
+    let transducer = column(80);
+    let phys_dim = column(8);
+    let phys_min = column(8);
+    let phys_max = column(8);
+    let dig_min = column(8);
+    let dig_max = column(8);
+    let prefilter = column(80);
+    let samples = column(8);
+
+    let mut signals = Vec::with_capacity(n_signals);
+    for i in 0..n_signals {
+        signals.push(SignalHeader {
+            label: field(&sig, label(i), 16),
+            transducer: field(&sig, transducer(i), 80),
+            phys_dim: field(&sig, phys_dim(i), 8),
+            phys_min: number(&sig, phys_min(i), 8, "physical minimum")?,
+            phys_max: number(&sig, phys_max(i), 8, "physical maximum")?,
+            dig_min: number(&sig, dig_min(i), 8, "digital minimum")?,
+            dig_max: number(&sig, dig_max(i), 8, "digital maximum")?,
+            prefilter: field(&sig, prefilter(i), 80),
+            samples_per_record: number(&sig, samples(i), 8, "samples per record")?,
+        });
+    }
+
+    // BDF marks its version field with a leading 0xFF byte
+    let sample_bytes = if main[0] == 0xFF { 3 } else { 2 };
+
+    Ok(Header {
+        version: field(&main, 0, 8),
+        sample_bytes,
+        patient: field(&main, 8, 80),
+        recording: field(&main, 88, 80),
+        start_date: field(&main, 168, 8),
+        start_time: field(&main, 176, 8),
+        header_bytes: number(&main, 184, 8, "header size")?,
+        reserved: field(&main, 192, 44),
+        n_records: number(&main, 236, 8, "number of data records")?,
+        record_duration: number(&main, 244, 8, "record duration")?,
+        signals,
+    })
+}
+
+/// Read the onset (seconds since file start) of a data record from the
+/// time-keeping TAL at the start of its annotation signal.
+pub fn read_record_onset<R: Read + Seek>(
+    reader: &mut R,
+    header: &Header,
+    record: usize,
+) -> Result<f64> {
+    let ann = header
+        .annotation_index()
+        .ok_or_else(|| anyhow!("No EDF Annotations signal"))?;
+    let pos = header.header_bytes + record * header.record_bytes() + header.signal_offset(ann);
+    reader.seek(SeekFrom::Start(pos as u64))?;
+
+    let mut buf = vec![0u8; header.signals[ann].samples_per_record * header.sample_bytes];
+    reader.read_exact(&mut buf)?;
+
+    // Only the first TAL, so damaged annotations later in the record don't
+    // hide its onset
+    let first = buf.split(|&b| b == 0).next().unwrap_or_default();
+    let tals = tal::decode(first).map_err(|e| anyhow!("Record {}: {}", record, e))?;
+    tals.first()
+        .map(|t| t.onset)
+        .ok_or_else(|| anyhow!("Record {}: missing time-keeping TAL", record))
+}
+
+/// Read every TAL of a data record's annotation signal, starting with its
+/// time-keeping TAL.
+pub fn read_record_tals<R: Read + Seek>(
+    reader: &mut R,
+    header: &Header,
+    record: usize,
+) -> Result<Vec<tal::Tal>> {
+    let ann = header
+        .annotation_index()
+        .ok_or_else(|| anyhow!("No EDF Annotations signal"))?;
+    let pos = header.header_bytes + record * header.record_bytes() + header.signal_offset(ann);
+    reader.seek(SeekFrom::Start(pos as u64))?;
+
+    let mut buf = vec![0u8; header.signals[ann].samples_per_record * header.sample_bytes];
+    reader.read_exact(&mut buf)?;
+    tal::decode(&buf).map_err(|e| anyhow!("Record {}: {}", record, e))
+}
+
+/// Read all digital samples of one signal, across every data record.
+///
+/// Samples are little-endian 16-bit (EDF) or 24-bit (BDF) two's complement.
+pub fn read_digital_samples<R: Read + Seek>(
+    reader: &mut R,
+    header: &Header,
+    signal: usize,
+) -> Result<Vec<i32>> {
+    let n_records =
+        usize::try_from(header.n_records).map_err(|_| anyhow!("Unknown number of data records"))?;
+    let n = header.signals[signal].samples_per_record;
+    let mut samples = Vec::with_capacity(n_records * n);
+    let width = header.sample_bytes;
+    let mut buf = vec![0u8; n * width];
+
+    for record in 0..n_records {
+        let pos =
+            header.header_bytes + record * header.record_bytes() + header.signal_offset(signal);
+        reader.seek(SeekFrom::Start(pos as u64))?;
+        reader.read_exact(&mut buf)?;
+        samples.extend(buf.chunks_exact(width).map(|b| match b {
+            [lo, hi] => i16::from_le_bytes([*lo, *hi]) as i32,
+            // Sign-extend 24 bits by placing them in the top of an i32
+            _ => i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8,
+        }));
+    }
+
+    Ok(samples)
+}
@@ -0,0 +1,355 @@
+use anyhow::Result;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
+
+use crate::edf_read::{self, Header};
+use crate::edf_write::{patient_subfield_problem, recording_subfield_problem};
+use crate::tal;
+
+/// Problems of one kind reported before the rest are summarized.
+const MAX_REPEATS: usize = 5;
+
+/// How serious a problem is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// Readers may misread the file or reject it.
+    Error,
+    /// The file departs from the specification, but most readers cope.
+    Warning,
+}
+
+/// A problem found in a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    pub severity: Severity,
+    pub message: String,
+}
+
+/// Result of validating a file: what it is, and its problems.
+#[derive(Debug, Clone)]
+pub struct Validation {
+    /// File type, e.g. "EDF+C" or "BDF", once the header could be read.
+    pub kind: Option<String>,
+    pub problems: Vec<Problem>,
+}
+
+impl Validation {
+    /// Whether any problem is an error.
+    pub fn has_errors(&self) -> bool {
+        self.problems.iter().any(|p| p.severity == Severity::Error)
+    }
+
+    fn error(&mut self, message: impl Into<String>) {
+        self.problems.push(Problem {
+            severity: Severity::Error,
+            message: message.into(),
+        });
+    }
+
+    fn warning(&mut self, message: impl Into<String>) {
+        self.problems.push(Problem {
+            severity: Severity::Warning,
+            message: message.into(),
+        });
+    }
+}
+
+/// Check an EDF, EDF+, BDF, or BDF+ file against the specification: header
+/// field syntax, header size, record count against file size, digital and
+/// physical ranges, sample values, and for EDF+ the patient and recording
+/// subfields and the TALs of every data record.
+///
+/// Fails only when the file can't be read; problems in it are returned.
+pub fn validate_edf(path: &str) -> Result<Validation> {
+    let file = File::open(path)?;
+    let file_len = file.metadata()?.len();
+    let mut reader = BufReader::new(file);
+    let mut validation = Validation {
+        kind: None,
+        problems: Vec::new(),
+    };
+
+    let mut main = [0u8; 256];
+    if reader.read_exact(&mut main).is_err() {
+        validation.error(format!(
+            "file is {} bytes, shorter than the 256-byte header",
+            file_len
+        ));
+        return Ok(validation);
+    }
+    check_text(&mut validation, &main);
+    reader.seek(SeekFrom::Start(0))?;
+    let header = match edf_read::read_header(&mut reader) {
+        Ok(header) => header,
+        Err(e) => {
+            validation.error(format!("header can't be read: {}", e));
+            return Ok(validation);
+        }
+    };
+    let plus = header.reserved.starts_with("EDF+") || header.reserved.starts_with("BDF+");
+    validation.kind = Some(match (header.is_bdf(), plus) {
+        (false, false) => "EDF".to_string(),
+        (true, false) => "BDF".to_string(),
+        (_, true) => header.reserved.chars().take(5).collect(),
+    });
+
+    check_main_header(&mut validation, &header, file_len, plus);
+    check_signals(&mut validation, &header);
+    if header.n_records > 0 && !validation.has_errors() {
+        check_samples(&mut validation, &mut reader, &header)?;
+        if plus {
+            check_tals(&mut validation, &mut reader, &header)?;
+        }
+    }
+    Ok(validation)
+}
+
+/// Check that the main header's text is printable ASCII, apart from BDF's
+/// leading 0xFF version byte.
+fn check_text(validation: &mut Validation, main: &[u8; 256]) {
+    let text = if main[0] == 0xFF {
+        &main[1..]
+    } else {
+        &main[..]
+    };
+    if let Some(b) = text.iter().find(|b| !(b' '..=b'~').contains(*b)) {
+        validation.warning(format!(
+            "header has a byte outside printable ASCII (0x{:02X})",
+            b
+        ));
+    }
+}
+
+fn check_main_header(validation: &mut Validation, header: &Header, file_len: u64, plus: bool) {
+    let bdf_version = header.is_bdf() && header.version == "\u{FFFD}BIOSEMI";
+    if header.version != "0" && !bdf_version {
+        validation.error(format!(
+            "version {:?} is neither \"0\" (EDF) nor 0xFF \"BIOSEMI\" (BDF)",
+            header.version
+        ));
+    }
+    let start = edf_read::parse_start(&header.start_date, &header.start_time);
+    if let Err(e) = &start {
+        validation.error(e.to_string());
+    }
+    let expected_header = 256 * (header.signals.len() + 1);
+    if header.header_bytes != expected_header {
+        validation.error(format!(
+            "header size is {} bytes, but {} signals need {}",
+            header.header_bytes,
+            header.signals.len(),
+            expected_header
+        ));
+    }
+    // EDF+ allows a zero duration only for files of annotations alone
+    let annotations_only = plus && header.signals.iter().all(|s| s.is_annotations());
+    if header.record_duration < 0.0 || (header.record_duration == 0.0 && !annotations_only) {
+        validation.error(format!(
+            "record duration {} s is not positive",
+            header.record_duration
+        ));
+    }
+
+    // Record count against the file size
+    let record_bytes = header.record_bytes() as u64;
+    let data_bytes = file_len.saturating_sub(header.header_bytes as u64);
+    if header.n_records < 0 {
+        validation.warning("number of data records is -1 (unknown, still being recorded)");
+    } else if record_bytes > 0 {
+        let expected = header.header_bytes as u64 + header.n_records as u64 * record_bytes;
+        if file_len != expected {
+            validation.error(format!(
+                "file is {} bytes, but {} records of {} bytes after the header need {}; \
+                 it holds {} whole records",
+                file_len,
+                header.n_records,
+                record_bytes,
+                expected,
+                data_bytes / record_bytes
+            ));
+        }
+    }
+    if record_bytes > 61440 {
+        validation.warning(format!(
+            "data records of {} bytes are larger than the 61440 bytes EDF recommends",
+            record_bytes
+        ));
+    }
+
+    if plus {
+        if !matches!(header.reserved.get(3..5), Some("+C" | "+D")) {
+            let format = &header.reserved[..3];
+            validation.error(format!(
+                "reserved field {:?} is neither {}+C nor {}+D",
+                header.reserved, format, format
+            ));
+        }
+        let has_subfield_date = header.recording.split(' ').nth(1) != Some("X");
+        let start = start.ok().filter(|_| has_subfield_date);
+        let problems = [
+            patient_subfield_problem(&header.patient),
+            recording_subfield_problem(&header.recording, start),
+        ];
+        for problem in problems.into_iter().flatten() {
+            validation.warning(problem);
+        }
+        if header.annotation_index().is_none() {
+            validation.error("EDF+ file has no annotation signal");
+        }
+    }
+}
+
+fn check_signals(validation: &mut Validation, header: &Header) {
+    let (format_min, format_max) = if header.is_bdf() {
+        (-8388608, 8388607)
+    } else {
+        (-32768, 32767)
+    };
+    for (i, s) in header.signals.iter().enumerate() {
+        let name = format!("signal {} ({:?})", i, s.label);
+        if s.dig_min >= s.dig_max {
+            validation.error(format!(
+                "{} digital minimum {} is not below its maximum {}",
+                name, s.dig_min, s.dig_max
+            ));
+        }
+        if s.dig_min < format_min || s.dig_max > format_max {
+            validation.error(format!(
+                "{} digital range [{}, {}] exceeds the sample width's [{}, {}]",
+                name, s.dig_min, s.dig_max, format_min, format_max
+            ));
+        }
+        if s.phys_min == s.phys_max {
+            validation.error(format!(
+                "{} physical minimum and maximum are both {}",
+                name, s.phys_min
+            ));
+        } else if s.phys_min > s.phys_max && !s.is_annotations() {
+            validation.warning(format!(
+                "{} physical range [{}, {}] is inverted",
+                name, s.phys_min, s.phys_max
+            ));
+        }
+        if s.samples_per_record == 0 {
+            validation.error(format!("{} has no samples per data record", name));
+        }
+    }
+}
+
+/// Check that every data signal's samples are within its digital range.
+fn check_samples<R: Read + Seek>(
+    validation: &mut Validation,
+    reader: &mut R,
+    header: &Header,
+) -> Result<()> {
+    for (i, s) in header.signals.iter().enumerate() {
+        if s.is_annotations() {
+            continue;
+        }
+        let samples = edf_read::read_digital_samples(reader, header, i)?;
+        let outside = samples
+            .iter()
+            .filter(|&&v| v < s.dig_min || v > s.dig_max)
+            .count();
+        if outside > 0 {
+            validation.warning(format!(
+                "signal {} ({:?}) has {} samples outside its digital range [{}, {}]",
+                i, s.label, outside, s.dig_min, s.dig_max
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Check every data record's TALs: their syntax, a time-keeping TAL first,
+/// and record onsets that follow the record duration (in EDF+C, exactly).
+fn check_tals<R: Read + Seek>(
+    validation: &mut Validation,
+    reader: &mut R,
+    header: &Header,
+) -> Result<()> {
+    let Some(ann) = header.annotation_index() else {
+        return Ok(());
+    };
+    let continuous = header.reserved.get(3..5) == Some("+C");
+    let mut buf = vec![0u8; header.signals[ann].samples_per_record * header.sample_bytes];
+    let mut first_onset = None;
+    let mut previous = None;
+    let mut repeats = 0;
+    for record in 0..header.n_records as usize {
+        let pos = header.header_bytes + record * header.record_bytes() + header.signal_offset(ann);
+        reader.seek(SeekFrom::Start(pos as u64))?;
+        reader.read_exact(&mut buf)?;
+        let problem = match tal::decode(&buf) {
+            Err(e) => Some(e.to_string()),
+            Ok(tals) => match tals.first() {
+                Some(first) if first.texts.first().is_some_and(String::is_empty) => {
+                    let onset = first.onset;
+                    let start = *first_onset.get_or_insert(onset);
+                    let expected = start + record as f64 * header.record_duration;
+                    let problem = if continuous && (onset - expected).abs() > 1e-3 {
+                        Some(format!(
+                            "onset +{} in a continuous file, where +{} is due",
+                            tal::format_seconds(onset),
+                            tal::format_seconds(expected)
+                        ))
+                    } else {
+                        previous
+                            .filter(|&p: &f64| onset < p + header.record_duration - 1e-3)
+                            .map(|p| {
+                                format!(
+                                    "onset +{} overlaps the previous record at +{}",
+                                    tal::format_seconds(onset),
+                                    tal::format_seconds(p)
+                                )
+                            })
+                    };
+                    previous = Some(onset);
+                    problem
+                }
+                _ => Some("no time-keeping TAL first".to_string()),
+            },
+        };
+        if let Some(problem) = problem {
+            repeats += 1;
+            if repeats <= MAX_REPEATS {
+                validation.error(format!("data record {}: {}", record, problem));
+            }
+        }
+    }
+    if repeats > MAX_REPEATS {
+        validation.error(format!(
+            "{} more data records have TAL problems",
+            repeats - MAX_REPEATS
+        ));
+    }
+    Ok(())
+}
+
+/// Validate a file and print its problems to `out`; fails if any is an
+/// error.
+pub fn print_validation(out: &mut impl Write, path: &str) -> Result<()> {
+    let validation = validate_edf(path)?;
+    for problem in &validation.problems {
+        let severity = match problem.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        writeln!(out, "{}: {}", severity, problem.message)?;
+    }
+    let kind = validation.kind.as_deref().unwrap_or("EDF");
+    if validation.has_errors() {
+        anyhow::bail!("{} is not a valid {} file", path, kind);
+    }
+    let warnings = validation.problems.len();
+    if warnings > 0 {
+        writeln!(
+            out,
+            "{}: valid {} file, with {} warning(s)",
+            path, kind, warnings
+        )?;
+    } else {
+        writeln!(out, "{}: valid {} file", path, kind)?;
+    }
+    Ok(())
+}
@@ -1,131 +1,1540 @@
-use anyhow::Result;
-use std::fs::File;
-use std::io::Write;
+use anyhow::{anyhow, Result};
+use chrono::{Datelike, NaiveDate, NaiveDateTime, Timelike};
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+use crate::audit::{errln, outln};
+use crate::edf_read;
+use crate::pdf_text::TraceFilter;
+use crate::provenance::TOOL_NAME;
+use crate::tal::{self, format_seconds, Tal};
+use crate::text_policy::TextPolicy;
+
+/// Longest data record duration tried when fitting signal rates, in seconds.
+const MAX_RECORD_DURATION: usize = 60;
+
+/// Samples in the annotation channel per record (matches pyedflib default).
+const ANNOTATION_SAMPLES: usize = 57;
+
+/// Start date/time written when the recording start is unknown
+/// (the EDF clipping date, as the spec recommends).
+const UNKNOWN_START: (&str, &str) = ("01.01.85", "00.00.00");
+
+/// Years the two-digit EDF start date field can represent.
+pub const EDF_YEARS: RangeInclusive<i32> = 1985..=2084;
 
 /// Write a space-padded ASCII field of exact width.
-fn write_field(file: &mut File, value: &str, width: usize) -> Result<()> {
-    let mut buf = value.as_bytes().to_vec();
+fn write_field(out: &mut impl Write, value: &str, width: usize) -> Result<()> {
+    write_bytes(out, value.as_bytes().to_vec(), width)
+}
+
+/// Write a space-padded text field of exact width, encoded by the text policy.
+fn write_text(
+    out: &mut impl Write,
+    policy: TextPolicy,
+    field: &str,
+    value: &str,
+    width: usize,
+) -> Result<()> {
+    write_bytes(out, policy.encode(field, value)?, width)
+}
+
+fn write_bytes(out: &mut impl Write, mut buf: Vec<u8>, width: usize) -> Result<()> {
     buf.resize(width, b' '); // right-pad with spaces
     buf.truncate(width); // ensure exact width
-    file.write_all(&buf)?;
+    out.write_all(&buf)?;
     Ok(())
 }
 
-/// Convert a physical voltage value to a 16-bit digital value.
-fn voltage_to_digital(voltage: f64, phys_min: f64, phys_max: f64) -> i16 {
-    let dig_min: f64 = -32768.0;
-    let dig_max: f64 = 32767.0;
+/// Output file format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format {
+    /// EDF+ with 16-bit samples.
+    #[default]
+    Edf,
+    /// BDF+ (BioSemi) with 24-bit samples, for finer quantization.
+    Bdf,
+}
+
+impl Format {
+    /// Bytes per sample.
+    fn sample_bytes(self) -> usize {
+        match self {
+            Format::Edf => 2,
+            Format::Bdf => 3,
+        }
+    }
+
+    /// Digital minimum and maximum sample values.
+    pub fn digital_range(self) -> (i32, i32) {
+        match self {
+            Format::Edf => (-32768, 32767),
+            Format::Bdf => (-8388608, 8388607),
+        }
+    }
+
+    /// Version field bytes (BDF marks it with a leading 0xFF byte).
+    fn version(self) -> &'static [u8] {
+        match self {
+            Format::Edf => b"0       ",
+            Format::Bdf => b"\xFFBIOSEMI",
+        }
+    }
+
+    /// Reserved field for a continuous or discontinuous file.
+    fn reserved(self, continuous: bool) -> &'static str {
+        match (self, continuous) {
+            (Format::Edf, true) => "EDF+C",
+            (Format::Edf, false) => "EDF+D",
+            (Format::Bdf, true) => "BDF+C",
+            (Format::Bdf, false) => "BDF+D",
+        }
+    }
+
+    /// Reserved field for a continuous or discontinuous file, followed by
+    /// the converter's name and version, e.g. "EDF+C kardia2edf 0.1.0".
+    fn reserved_with_tool(self, continuous: bool) -> String {
+        format!(
+            "{} {} {}",
+            self.reserved(continuous),
+            TOOL_NAME,
+            env!("CARGO_PKG_VERSION")
+        )
+    }
+
+    /// Label of the annotation signal.
+    fn annotation_label(self) -> &'static str {
+        match self {
+            Format::Edf => "EDF Annotations",
+            Format::Bdf => "BDF Annotations",
+        }
+    }
+
+    /// Default annotation channel size in samples (114 bytes either way).
+    fn annotation_samples(self) -> usize {
+        (ANNOTATION_SAMPLES * 2).div_ceil(self.sample_bytes())
+    }
+}
+
+/// Convert a physical value to a digital value in the format's range.
+fn voltage_to_digital(voltage: f64, phys_min: f64, phys_max: f64, format: Format) -> i32 {
+    let (dig_min, dig_max) = format.digital_range();
+    let (dig_min, dig_max) = (dig_min as f64, dig_max as f64);
     let scaled = dig_min + (voltage - phys_min) / (phys_max - phys_min) * (dig_max - dig_min);
-    scaled.round().clamp(-32768.0, 32767.0) as i16
+    scaled.round().clamp(dig_min, dig_max) as i32
 }
 
-/// Build EDF+ TAL (Time-stamped Annotation List) bytes for a data record.
-fn make_annotation_bytes(onset_seconds: usize, annotation_samples: usize) -> Vec<u8> {
-    let tal = format!("+{}\x14\x14", onset_seconds);
-    let mut bytes = tal.into_bytes();
-    let total_bytes = annotation_samples * 2;
-    bytes.resize(total_bytes, 0); // null-pad to fill annotation channel
-    bytes
+/// A signal channel to write, sampled at its own rate.
+#[derive(Debug, Clone)]
+pub struct EdfSignal {
+    pub label: String,
+    pub transducer: String,
+    /// Physical dimension (unit), e.g. "mV" or "bpm".
+    pub phys_dim: String,
+    pub prefilter: String,
+    /// Sampling rate in Hz; may be below 1 Hz for slow trend channels.
+    pub sample_rate: f64,
+    pub samples: Vec<f64>,
+    /// Physical range; computed from the samples with a 0.1 margin if unset.
+    pub phys_range: Option<(f64, f64)>,
+    /// Physical value written past the end of the samples to fill the last
+    /// data record.
+    pub pad_value: f64,
 }
 
-/// Write the ECG signal as an EDF+ file.
-pub fn write_edf(path: &str, signal: &[f64], sample_rate: usize) -> Result<()> {
-    let record_duration: usize = 1; // 1 second per data record
-    let samples_per_record = sample_rate * record_duration;
-    let n_records = (signal.len() + samples_per_record - 1) / samples_per_record;
-    let n_signals: usize = 2; // EKG + Annotations
-    let annotation_samples: usize = 57; // matches pyedflib default
+impl EdfSignal {
+    /// A signal with empty transducer and prefiltering fields.
+    pub fn new(label: &str, phys_dim: &str, sample_rate: f64, samples: Vec<f64>) -> Self {
+        Self {
+            label: label.to_string(),
+            transducer: String::new(),
+            phys_dim: phys_dim.to_string(),
+            prefilter: String::new(),
+            sample_rate,
+            samples,
+            phys_range: None,
+            pad_value: 0.0,
+        }
+    }
+
+    /// The KardiaMobile 1L lead I ECG signal in millivolts.
+    pub fn ecg(samples: Vec<f64>, sample_rate: usize) -> Self {
+        Self {
+            transducer: "KardiaMobile 1L electrode".to_string(),
+            prefilter: TraceFilter::Enhanced.prefilter(None),
+            ..Self::new("EKG I", "mV", sample_rate as f64, samples)
+        }
+    }
+
+    /// Physical minimum and maximum written to the header.
+    pub(crate) fn physical_range(&self) -> (f64, f64) {
+        self.phys_range.unwrap_or_else(|| {
+            let min = self.samples.iter().cloned().fold(f64::INFINITY, f64::min);
+            let max = self
+                .samples
+                .iter()
+                .cloned()
+                .fold(f64::NEG_INFINITY, f64::max);
+            if min.is_finite() && max.is_finite() {
+                (min - 0.1, max + 0.1)
+            } else {
+                (-1.0, 1.0)
+            }
+        })
+    }
+}
+
+/// A stretch of the recording without data, in seconds from its start.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Gap {
+    pub onset: f64,
+    pub duration: f64,
+}
+
+/// Settings for writing a file, shared by the format backends.
+#[derive(Debug, Clone, Default)]
+pub struct WriteSettings {
+    /// How non-ASCII characters in header text fields are written.
+    pub text_policy: TextPolicy,
+    /// Data record duration in seconds; by default the shortest whole
+    /// number of seconds in which every signal has whole samples.
+    pub record_duration: Option<f64>,
+    /// Gaps in the recording. EDF+/BDF+ files leave out data records that
+    /// lie wholly inside one and are then marked discontinuous, with each
+    /// record's onset keeping its time; GDF files keep every record.
+    pub gaps: Vec<Gap>,
+    /// Recording device for the recording ID's equipment subfield, if
+    /// printed on the report; [`DEFAULT_EQUIPMENT`] otherwise.
+    pub equipment: Option<String>,
+    /// Recording identification field replacing the one built from the
+    /// start date and equipment; see [`EdfHeader::recording_id`].
+    pub recording_id: Option<String>,
+    /// SHA-256 of the source document, as lowercase hex; EDF+/BDF+ files
+    /// hold its first 32 digits in each signal's reserved field.
+    pub input_sha256: Option<String>,
+    /// Samples per data record of a new session file's annotation signal,
+    /// raised to what its first recording needs; by default that need
+    /// with room to spare. See [`session_annotation_samples`].
+    pub annotation_samples: Option<usize>,
+}
+
+/// Recording device assumed when the report doesn't name one.
+pub const DEFAULT_EQUIPMENT: &str = "KardiaMobile 1L";
+
+/// Header text fields set by the caller instead of built from the report.
+///
+/// Each setter replaces control characters with '?' and truncates the
+/// value to its field's width, with a warning; non-ASCII characters are
+/// left for the [`TextPolicy`] applied when the header is written, which
+/// transliterates them to ASCII by default. The signal fields apply to the
+/// ECG lead, the first signal.
+///
+/// ```
+/// use kardiamobile_1l_ecg_convert_pdf_to_edf::edf_write::EdfHeader;
+///
+/// let header = EdfHeader::new()
+///     .recording_id("Study_42 Site_B")
+///     .transducer("Stainless steel finger electrodes")
+///     .label("ECG I");
+/// assert_eq!(header.label.as_deref(), Some("ECG I"));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct EdfHeader {
+    /// Patient identification field (80 characters), e.g.
+    /// "X F 02-AUG-1951 Jane_Doe".
+    pub patient: Option<String>,
+    /// Recording identification field (80 characters).
+    pub recording_id: Option<String>,
+    /// Transducer type of the ECG lead (80 characters).
+    pub transducer: Option<String>,
+    /// Prefiltering of the ECG lead (80 characters).
+    pub prefilter: Option<String>,
+    /// Label of the ECG lead (16 characters).
+    pub label: Option<String>,
+}
+
+impl EdfHeader {
+    /// A header with every field built from the report.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the patient identification field.
+    pub fn patient(mut self, patient: &str) -> Self {
+        self.patient = Some(header_text("Patient", patient, 80));
+        self
+    }
+
+    /// Set the recording identification field. EDF+ readers expect it to
+    /// begin with a "Startdate" subfield, so unless the value does, the
+    /// recording's "Startdate dd-MMM-yyyy" is written before it.
+    pub fn recording_id(mut self, recording_id: &str) -> Self {
+        self.recording_id = Some(header_text("Recording", recording_id, 80));
+        self
+    }
+
+    /// Set the ECG lead's transducer type.
+    pub fn transducer(mut self, transducer: &str) -> Self {
+        self.transducer = Some(header_text("Transducer", transducer, 80));
+        self
+    }
+
+    /// Set the ECG lead's prefiltering, instead of the filter printed on
+    /// the report.
+    pub fn prefilter(mut self, prefilter: &str) -> Self {
+        self.prefilter = Some(header_text("Prefiltering", prefilter, 80));
+        self
+    }
+
+    /// Set the ECG lead's label.
+    pub fn label(mut self, label: &str) -> Self {
+        self.label = Some(header_text("Label", label, 16));
+        self
+    }
+
+    /// Set the signal fields of the ECG lead.
+    pub fn apply(&self, lead: &mut EdfSignal) {
+        for (field, value) in [
+            (&mut lead.transducer, &self.transducer),
+            (&mut lead.prefilter, &self.prefilter),
+            (&mut lead.label, &self.label),
+        ] {
+            if let Some(value) = value {
+                field.clone_from(value);
+            }
+        }
+    }
+}
+
+/// A header text value with control characters replaced by '?', trimmed,
+/// and truncated to `width` characters.
+fn header_text(field: &str, value: &str, width: usize) -> String {
+    let text: String = value
+        .trim()
+        .chars()
+        .map(|c| if c.is_control() { '?' } else { c })
+        .collect();
+    if text.chars().count() <= width {
+        return text;
+    }
+    let truncated: String = text.chars().take(width).collect();
+    let truncated = truncated.trim_end().to_string();
+    errln!(
+        "Warning: {} {:?} is longer than the {} characters of its field; writing {:?}",
+        field,
+        text,
+        width,
+        truncated
+    );
+    truncated
+}
+
+/// Whether each of `n_records` data records lies wholly inside a gap.
+fn records_in_gaps(gaps: &[Gap], n_records: usize, record_duration: f64) -> Vec<bool> {
+    (0..n_records)
+        .map(|rec| {
+            let start = rec as f64 * record_duration;
+            let end = start + record_duration;
+            gaps.iter()
+                .any(|g| g.onset <= start + 1e-9 && end <= g.onset + g.duration + 1e-9)
+        })
+        .collect()
+}
+
+/// Move the annotations of left-out records to the nearest record kept
+/// before them, or else after them, so none are lost.
+fn move_skipped_annotations(by_record: &mut [Vec<&Annotation>], skip: &[bool]) {
+    let kept: Vec<usize> = (0..by_record.len()).filter(|&r| !skip[r]).collect();
+    for rec in (0..by_record.len()).filter(|&r| skip[r]) {
+        let target = kept
+            .iter()
+            .rev()
+            .find(|&&k| k < rec)
+            .or_else(|| kept.first())
+            .copied();
+        if let Some(target) = target {
+            let moved = std::mem::take(&mut by_record[rec]);
+            by_record[target].extend(moved);
+        }
+    }
+}
+
+/// Pick the data record duration, in which every signal must have a whole
+/// number of samples, or record timing drifts from the rounded counts.
+///
+/// A requested duration that fits is used as is; one that doesn't, e.g.
+/// 0.01 s at 250 Hz (2.5 samples), is moved to the nearest duration that
+/// does, with a warning. Without a request, the shortest whole-second
+/// duration that fits is used, e.g. 1 s for 300 Hz + 1 Hz, 2 s for 0.5 Hz.
+pub(crate) fn record_duration_for(signals: &[EdfSignal], requested: Option<f64>) -> Result<f64> {
+    let fits = |d: f64| {
+        signals.iter().all(|s| {
+            let n = s.sample_rate * d;
+            n >= 1.0 - 1e-9 && (n - n.round()).abs() < 1e-6
+        })
+    };
+    let no_fit = || {
+        anyhow!(
+            "No record duration up to {} s fits sample rates {:?}",
+            MAX_RECORD_DURATION,
+            signals.iter().map(|s| s.sample_rate).collect::<Vec<_>>()
+        )
+    };
+    let Some(requested) = requested else {
+        return (1..=MAX_RECORD_DURATION)
+            .map(|d| d as f64)
+            .find(|&d| fits(d))
+            .ok_or_else(no_fit);
+    };
+    if !(requested > 0.0 && requested <= MAX_RECORD_DURATION as f64) {
+        return Err(anyhow!(
+            "Record duration {} s is outside 0-{} s",
+            requested,
+            MAX_RECORD_DURATION
+        ));
+    }
+    if fits(requested) {
+        return Ok(requested);
+    }
+
+    // Durations that fit are the multiples of the shortest one, searched
+    // in whole milliseconds
+    let step = (1..=MAX_RECORD_DURATION * 1000)
+        .map(|ms| ms as f64 / 1000.0)
+        .find(|&d| fits(d))
+        .ok_or_else(no_fit)?;
+    let multiple = (requested / step)
+        .round()
+        .clamp(1.0, (MAX_RECORD_DURATION as f64 / step).floor());
+    let adjusted = multiple * step;
+    let uneven: Vec<String> = signals
+        .iter()
+        .map(|s| (s, s.sample_rate * requested))
+        .filter(|(_, n)| (n - n.round()).abs() >= 1e-6)
+        .map(|(s, n)| {
+            format!(
+                "{} samples of {} at {} Hz",
+                n,
+                s.label.trim(),
+                s.sample_rate
+            )
+        })
+        .collect();
+    errln!(
+        "Warning: a {} s record would hold {}; using {} s records instead",
+        requested,
+        uneven.join(", "),
+        format_seconds(adjusted)
+    );
+    Ok(adjusted)
+}
+
+/// An EDF+ annotation.
+#[derive(Debug, Clone)]
+pub struct Annotation {
+    /// Onset in seconds from the start of the recording.
+    pub onset: f64,
+    /// Duration in seconds, if any.
+    pub duration: Option<f64>,
+    pub text: String,
+}
+
+impl Annotation {
+    /// An annotation without a duration.
+    pub fn new(onset: f64, text: impl Into<String>) -> Self {
+        Self {
+            onset,
+            duration: None,
+            text: text.into(),
+        }
+    }
+}
+
+/// Build EDF+ TAL (Time-stamped Annotation List) bytes for a data record:
+/// the time-keeping TAL with the record onset, then one TAL per annotation.
+///
+/// Annotation onsets are relative to `offset_seconds`. The result is not padded.
+fn make_annotation_bytes(
+    onset_seconds: f64,
+    offset_seconds: f64,
+    annotations: &[&Annotation],
+) -> Vec<u8> {
+    let mut tals = vec![Tal::time_keeping(onset_seconds)];
+    tals.extend(
+        annotations
+            .iter()
+            .map(|a| Tal::new(offset_seconds + a.onset, a.duration, a.text.as_str())),
+    );
+    tal::encode(&tals)
+}
+
+/// Group annotations by the data record containing their onset.
+fn annotations_by_record(
+    annotations: &[Annotation],
+    n_records: usize,
+    record_duration: f64,
+) -> Vec<Vec<&Annotation>> {
+    let mut by_record = vec![Vec::new(); n_records];
+    for a in annotations {
+        // Nudged so onsets on a record boundary aren't rounded into the
+        // previous record with fractional durations
+        let rec = ((a.onset.max(0.0) + 1e-9) / record_duration) as usize;
+        by_record[rec.min(n_records.saturating_sub(1))].push(a);
+    }
+    by_record
+}
+
+/// Samples needed in the annotation channel to hold every record's TALs.
+fn annotation_samples_needed(
+    by_record: &[Vec<&Annotation>],
+    record_duration: f64,
+    offset_seconds: f64,
+    format: Format,
+) -> usize {
+    by_record
+        .iter()
+        .enumerate()
+        .map(|(rec, anns)| {
+            let onset = offset_seconds + rec as f64 * record_duration;
+            make_annotation_bytes(onset, offset_seconds, anns)
+                .len()
+                .div_ceil(format.sample_bytes())
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+/// Samples per data record a session's annotation signal needs for the
+/// TALs of a recording starting at `start`, in a session starting at
+/// `session_start`. Give the largest over every recording as
+/// [`WriteSettings::annotation_samples`] when writing the first, so the
+/// rest can be appended.
+pub fn session_annotation_samples(
+    format: Format,
+    signals: &[EdfSignal],
+    start: NaiveDateTime,
+    session_start: NaiveDateTime,
+    annotations: &[Annotation],
+    settings: &WriteSettings,
+) -> Result<usize> {
+    let record_duration = record_duration_for(signals, settings.record_duration)?;
+    let n_records = signals
+        .iter()
+        .map(|s| {
+            let n = (s.sample_rate * record_duration).round() as usize;
+            s.samples.len().div_ceil(n)
+        })
+        .max()
+        .unwrap_or(0);
+    // Onsets count from the session's start second, as the header has it
+    let session_second = session_start.with_nanosecond(0).unwrap_or(session_start);
+    let onset = (start - session_second).num_milliseconds() as f64 / 1000.0;
+    let skip = records_in_gaps(&settings.gaps, n_records, record_duration);
+    let mut by_record = annotations_by_record(annotations, n_records, record_duration);
+    move_skipped_annotations(&mut by_record, &skip);
+    Ok(annotation_samples_needed(
+        &by_record,
+        record_duration,
+        onset,
+        format,
+    ))
+}
+
+/// Layout and scaling shared by all data records of a file.
+struct RecordLayout {
+    format: Format,
+    record_duration: f64,
+    /// Samples per record for each signal, in signal order.
+    samples_per_record: Vec<usize>,
+    /// Physical (min, max) for each signal, in signal order.
+    phys_ranges: Vec<(f64, f64)>,
+    annotation_samples: usize,
+}
+
+impl RecordLayout {
+    /// Number of records needed to hold the longest signal.
+    fn n_records(&self, signals: &[EdfSignal]) -> usize {
+        signals
+            .iter()
+            .zip(&self.samples_per_record)
+            .map(|(s, &n)| s.samples.len().div_ceil(n))
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+/// Make an EDF+ subfield: spaces become underscores, control characters
+/// become '?', and an empty value becomes "X". Non-ASCII characters are
+/// left for the [`TextPolicy`] applied when the header is written.
+fn edf_subfield(value: &str) -> String {
+    let value = value.trim();
+    if value.is_empty() {
+        return "X".to_string();
+    }
+    value
+        .chars()
+        .map(|c| match c {
+            ' ' => '_',
+            _ if c.is_control() => '?',
+            _ => c,
+        })
+        .collect()
+}
+
+/// Build an EDF+ patient identification field:
+/// "<code> <sex> <birthdate> <name>", with "X" for unknown subfields.
+pub fn patient_identification(
+    sex: Option<char>,
+    birthdate: Option<NaiveDate>,
+    name: Option<&str>,
+) -> String {
+    let sex = match sex {
+        Some('M') => "M",
+        Some('F') => "F",
+        _ => "X",
+    };
+    let birthdate = birthdate
+        .map(|d| d.format("%d-%b-%Y").to_string().to_uppercase())
+        .unwrap_or_else(|| "X".to_string());
+    let name = edf_subfield(name.unwrap_or(""));
+    format!("X {} {} {}", sex, birthdate, name)
+}
+
+/// Patient details parsed back from an EDF+ patient identification field
+/// ("code sex dd-MMM-yyyy Name_With_Underscores"; "X" for unknown).
+pub(crate) struct EdfPatient {
+    pub code: Option<String>,
+    pub sex: Option<String>,
+    pub birthdate: Option<NaiveDate>,
+    pub name: Option<String>,
+}
+
+pub(crate) fn parse_edf_patient(field: &str) -> EdfPatient {
+    let mut parts = field.split_whitespace();
+    let mut next = || parts.next().filter(|p| *p != "X").map(str::to_string);
+    let code = next();
+    let sex = next().filter(|s| s == "M" || s == "F");
+    let birthdate = next().and_then(|d| NaiveDate::parse_from_str(&d, "%d-%b-%Y").ok());
+    let name = next().map(|n| n.replace('_', " "));
+    EdfPatient {
+        code,
+        sex,
+        birthdate,
+        name,
+    }
+}
+
+/// Write signals as an EDF+ file.
+///
+/// Signals may have different sampling rates; each gets its own number of
+/// samples per data record, and the record duration is chosen so that all
+/// of them are whole numbers. A signal shorter than the others is padded
+/// with its `pad_value` to the end of the last record.
+///
+/// ```no_run
+/// use kardiamobile_1l_ecg_convert_pdf_to_edf::edf_write::{write_edf, EdfSignal, WriteSettings};
+///
+/// // 30 s of ECG at 300 Hz and a heart-rate trend at 1 Hz, in 1 s records
+/// # let (ecg, bpm) = (vec![0.0; 9000], vec![72.0; 30]);
+/// let signals = [
+///     EdfSignal::ecg(ecg, 300),
+///     EdfSignal::new("Heart rate", "bpm", 1.0, bpm),
+/// ];
+/// write_edf("ecg.edf", &signals, None, "X X X X", &[], WriteSettings::default())?;
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+pub fn write_edf(
+    path: &str,
+    signals: &[EdfSignal],
+    start: Option<NaiveDateTime>,
+    patient: &str,
+    annotations: &[Annotation],
+    settings: WriteSettings,
+) -> Result<()> {
+    let style = HeaderStyle::plus(Format::Edf, settings);
+    write_file(create(path), signals, start, patient, annotations, style).map(drop)
+}
+
+/// Write signals as EDF+ to `out`, such as a buffer in memory, otherwise
+/// like [`write_edf`].
+pub fn write_edf_to(
+    out: &mut dyn Write,
+    signals: &[EdfSignal],
+    start: Option<NaiveDateTime>,
+    patient: &str,
+    annotations: &[Annotation],
+    settings: WriteSettings,
+) -> Result<()> {
+    let style = HeaderStyle::plus(Format::Edf, settings);
+    write_file(|| Ok(out), signals, start, patient, annotations, style).map(drop)
+}
+
+/// Write signals as a 24-bit BDF+ file, otherwise like [`write_edf`].
+pub fn write_bdf(
+    path: &str,
+    signals: &[EdfSignal],
+    start: Option<NaiveDateTime>,
+    patient: &str,
+    annotations: &[Annotation],
+    settings: WriteSettings,
+) -> Result<()> {
+    let style = HeaderStyle::plus(Format::Bdf, settings);
+    write_file(create(path), signals, start, patient, annotations, style).map(drop)
+}
+
+/// Write signals as BDF+ to `out`, otherwise like [`write_bdf`].
+pub fn write_bdf_to(
+    out: &mut dyn Write,
+    signals: &[EdfSignal],
+    start: Option<NaiveDateTime>,
+    patient: &str,
+    annotations: &[Annotation],
+    settings: WriteSettings,
+) -> Result<()> {
+    let style = HeaderStyle::plus(Format::Bdf, settings);
+    write_file(|| Ok(out), signals, start, patient, annotations, style).map(drop)
+}
+
+/// Write signals as a plain EDF file, for software that doesn't read EDF+:
+/// no annotation signal, so no annotations, and no discontinuities, so
+/// records inside gaps are written with the samples they hold.
+pub fn write_edf_classic(
+    path: &str,
+    signals: &[EdfSignal],
+    start: Option<NaiveDateTime>,
+    patient: &str,
+    settings: WriteSettings,
+) -> Result<()> {
+    let style = HeaderStyle::classic(settings);
+    write_file(create(path), signals, start, patient, &[], style).map(drop)
+}
+
+/// Write signals as plain EDF to `out`, otherwise like [`write_edf_classic`].
+pub fn write_edf_classic_to(
+    out: &mut dyn Write,
+    signals: &[EdfSignal],
+    start: Option<NaiveDateTime>,
+    patient: &str,
+    settings: WriteSettings,
+) -> Result<()> {
+    let style = HeaderStyle::classic(settings);
+    write_file(|| Ok(out), signals, start, patient, &[], style).map(drop)
+}
+
+/// Opens a new file at `path` for [`write_file`], replacing any existing one.
+fn create(path: &str) -> impl FnOnce() -> Result<File> + '_ {
+    move || Ok(File::create(path)?)
+}
+
+/// Append signals to an EDF+D session file as a new segment.
+///
+/// The segment's onset is this recording's start time relative to the
+/// session's start time, so gaps between recordings are preserved. The
+/// session's signal layout and physical ranges are kept, so the signals
+/// must match it, and samples outside the ranges are clipped.
+/// If the session file doesn't exist yet, it is created as EDF+D.
+pub fn append_edf(
+    path: &str,
+    signals: &[EdfSignal],
+    start: Option<NaiveDateTime>,
+    patient: &str,
+    annotations: &[Annotation],
+    settings: WriteSettings,
+) -> Result<()> {
+    let style = HeaderStyle::session(Format::Edf, settings);
+    append_file(path, signals, start, patient, annotations, style)
+}
+
+/// Append signals to an EDF+D session read from and written to `session`,
+/// such as a buffer in memory, otherwise like [`append_edf`]. An empty
+/// `session` gets a new session file. A partial data record at the end is
+/// written over, but isn't cut off if the segment is shorter.
+pub fn append_edf_to(
+    session: &mut (impl Read + Write + Seek),
+    signals: &[EdfSignal],
+    start: Option<NaiveDateTime>,
+    patient: &str,
+    annotations: &[Annotation],
+    settings: WriteSettings,
+) -> Result<()> {
+    let style = HeaderStyle::session(Format::Edf, settings);
+    append_to(
+        session,
+        "the session",
+        signals,
+        start,
+        patient,
+        annotations,
+        style,
+    )
+    .map(drop)
+}
+
+/// Append signals to a 24-bit BDF+D session file, otherwise like [`append_edf`].
+pub fn append_bdf(
+    path: &str,
+    signals: &[EdfSignal],
+    start: Option<NaiveDateTime>,
+    patient: &str,
+    annotations: &[Annotation],
+    settings: WriteSettings,
+) -> Result<()> {
+    let style = HeaderStyle::session(Format::Bdf, settings);
+    append_file(path, signals, start, patient, annotations, style)
+}
+
+/// Append signals to a BDF+D session in `session`, otherwise like
+/// [`append_edf_to`].
+pub fn append_bdf_to(
+    session: &mut (impl Read + Write + Seek),
+    signals: &[EdfSignal],
+    start: Option<NaiveDateTime>,
+    patient: &str,
+    annotations: &[Annotation],
+    settings: WriteSettings,
+) -> Result<()> {
+    let style = HeaderStyle::session(Format::Bdf, settings);
+    append_to(
+        session,
+        "the session",
+        signals,
+        start,
+        patient,
+        annotations,
+        style,
+    )
+    .map(drop)
+}
+
+/// The recording start an appended segment needs for its onset.
+fn require_start(start: Option<NaiveDateTime>) -> Result<NaiveDateTime> {
+    start.ok_or_else(|| anyhow!("Cannot append without a recording start time"))
+}
+
+/// Append to the session file at `path`, creating it if missing, and cut
+/// off whatever follows the new segment.
+fn append_file(
+    path: &str,
+    signals: &[EdfSignal],
+    start: Option<NaiveDateTime>,
+    patient: &str,
+    annotations: &[Annotation],
+    style: HeaderStyle,
+) -> Result<()> {
+    let start = require_start(start)?;
+    if !Path::new(path).exists() {
+        return write_file(
+            create(path),
+            signals,
+            Some(start),
+            patient,
+            annotations,
+            style,
+        )
+        .map(drop);
+    }
+    let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+    let end = append_to(
+        &mut file,
+        path,
+        signals,
+        Some(start),
+        patient,
+        annotations,
+        style,
+    )?;
+    // Drop any partial record the new segment didn't write over
+    file.set_len(end)?;
+    Ok(())
+}
+
+/// Append to `session`, named `name` in messages, writing a new session
+/// file if it is empty, and return where the new segment ends.
+fn append_to(
+    session: &mut (impl Read + Write + Seek),
+    name: &str,
+    signals: &[EdfSignal],
+    start: Option<NaiveDateTime>,
+    patient: &str,
+    annotations: &[Annotation],
+    style: HeaderStyle,
+) -> Result<u64> {
+    let start = require_start(start)?;
+    if session.seek(SeekFrom::End(0))? == 0 {
+        let out = write_file(
+            || Ok(&mut *session),
+            signals,
+            Some(start),
+            patient,
+            annotations,
+            style,
+        )?;
+        return Ok(out.stream_position()?);
+    }
+    session.rewind()?;
+    let HeaderStyle {
+        format, settings, ..
+    } = style;
+    let header = edf_read::read_header(session)?;
+
+    let plus = &format.reserved(true)[..4];
+    if !header.reserved.starts_with(plus) || header.is_bdf() != (format == Format::Bdf) {
+        return Err(anyhow!("{} is not an {} file", name, plus));
+    }
+    let ann_index = header
+        .annotation_index()
+        .ok_or_else(|| anyhow!("{} has no {} signal", name, format.annotation_label()))?;
+    if ann_index != signals.len() || header.signals.len() != signals.len() + 1 {
+        return Err(anyhow!(
+            "{} has {} data signals, but the recording has {}",
+            name,
+            header.signals.len() - 1,
+            signals.len()
+        ));
+    }
+    for (existing, signal) in header.signals.iter().zip(signals) {
+        let expected = signal.sample_rate * header.record_duration;
+        if existing.label != signal.label
+            || (existing.samples_per_record as f64 - expected).abs() > 1e-9
+        {
+            return Err(anyhow!(
+                "{} signal {:?} ({} samples per {} s record) does not match {:?} at {} Hz",
+                name,
+                existing.label,
+                existing.samples_per_record,
+                header.record_duration,
+                signal.label,
+                signal.sample_rate
+            ));
+        }
+    }
+    if let Some(d) = settings
+        .record_duration
+        .filter(|&d| (d - header.record_duration).abs() > 1e-9)
+    {
+        errln!(
+            "Warning: {} has {} s records; ignoring the requested {} s",
+            name,
+            header.record_duration,
+            d
+        );
+    }
+    if header.patient != patient.trim() {
+        errln!(
+            "Warning: session patient {:?} differs from recording patient {:?}",
+            header.patient,
+            patient
+        );
+    }
+    if header.n_records < 0 {
+        return Err(anyhow!("{} has an unknown number of data records", name));
+    }
+    let n_existing = header.n_records as usize;
+
+    // The session ends one record after the onset of its last record
+    let session_end = if n_existing > 0 {
+        edf_read::read_record_onset(session, &header, n_existing - 1)? + header.record_duration
+    } else {
+        0.0
+    };
+
+    let onset = (start - header.start_datetime()?).num_milliseconds() as f64 / 1000.0;
+    if onset < session_end {
+        return Err(anyhow!(
+            "Recording starts at +{} s, before the end of {} at +{} s",
+            onset,
+            name,
+            session_end
+        ));
+    }
+
+    let layout = RecordLayout {
+        format,
+        record_duration: header.record_duration,
+        samples_per_record: signals
+            .iter()
+            .zip(&header.signals)
+            .map(|(_, h)| h.samples_per_record)
+            .collect(),
+        phys_ranges: header.signals[..signals.len()]
+            .iter()
+            .map(|h| (h.phys_min, h.phys_max))
+            .collect(),
+        annotation_samples: header.signals[ann_index].samples_per_record,
+    };
+    for (signal, &(phys_min, phys_max)) in signals.iter().zip(&layout.phys_ranges) {
+        let clipped = signal
+            .samples
+            .iter()
+            .filter(|&&v| v < phys_min || v > phys_max)
+            .count();
+        if clipped > 0 {
+            errln!(
+                "Warning: {} {} samples outside the session range [{}, {}] {} are clipped",
+                clipped,
+                signal.label,
+                phys_min,
+                phys_max,
+                signal.phys_dim
+            );
+        }
+    }
+
+    let n_records = layout.n_records(signals);
+    let skip = records_in_gaps(&settings.gaps, n_records, layout.record_duration);
+    let n_new = skip.iter().filter(|&&s| !s).count();
+    let mut by_record = annotations_by_record(annotations, n_records, layout.record_duration);
+    move_skipped_annotations(&mut by_record, &skip);
+    let needed = annotation_samples_needed(&by_record, layout.record_duration, onset, format);
+    if needed > layout.annotation_samples {
+        return Err(anyhow!(
+            "Annotations need {} samples per record but {} has {}",
+            needed,
+            name,
+            layout.annotation_samples
+        ));
+    }
+
+    // Write after the last whole record, over any partial one
+    let data_end = header.header_bytes + n_existing * header.record_bytes();
+    session.seek(SeekFrom::Start(data_end as u64))?;
+    write_records(session, signals, &by_record, &skip, &layout, onset)?;
+    let end = session.stream_position()?;
+
+    // Update the reserved and number-of-records header fields, keeping
+    // whatever follows the continuity mark
+    session.seek(SeekFrom::Start(192))?;
+    let reserved = format!(
+        "{}{}",
+        format.reserved(false),
+        header.reserved.get(5..).unwrap_or("")
+    );
+    write_field(session, &reserved, 44)?;
+    write_field(session, &(n_existing + n_new).to_string(), 8)?;
+
+    outln!(
+        "Appended {} records at onset +{} s to {}",
+        n_new,
+        onset,
+        name
+    );
+
+    Ok(end)
+}
+
+/// Format, continuity, and settings of a new file.
+#[derive(Debug, Clone)]
+struct HeaderStyle {
+    format: Format,
+    /// Marked continuous ("+C") rather than discontinuous ("+D").
+    continuous: bool,
+    /// EDF+ or BDF+, with an annotation signal, rather than plain EDF.
+    plus: bool,
+    settings: WriteSettings,
+}
+
+impl HeaderStyle {
+    /// A continuous EDF+ or BDF+ file.
+    fn plus(format: Format, settings: WriteSettings) -> Self {
+        Self {
+            format,
+            continuous: true,
+            plus: true,
+            settings,
+        }
+    }
+
+    /// A discontinuous EDF+ or BDF+ session file. A session holds many
+    /// documents, each hashed in its provenance annotation, so no one
+    /// document's hash goes in the header.
+    fn session(format: Format, settings: WriteSettings) -> Self {
+        Self {
+            format,
+            continuous: false,
+            plus: true,
+            settings: WriteSettings {
+                input_sha256: None,
+                ..settings
+            },
+        }
+    }
+
+    /// A plain EDF file, warning that gaps can't be left out of it.
+    fn classic(settings: WriteSettings) -> Self {
+        if !settings.gaps.is_empty() {
+            errln!("Warning: plain EDF can't leave out records inside gaps; writing their samples");
+        }
+        Self {
+            format: Format::Edf,
+            continuous: true,
+            plus: false,
+            settings,
+        }
+    }
+}
+
+/// Write a new EDF+ or BDF+ file, marked continuous ("+C") or discontinuous
+/// ("+D"), or a plain EDF file, to the output `open` gives, which is opened
+/// only once the header is checked, returning it.
+fn write_file<W: Write>(
+    open: impl FnOnce() -> Result<W>,
+    signals: &[EdfSignal],
+    start: Option<NaiveDateTime>,
+    patient: &str,
+    annotations: &[Annotation],
+    style: HeaderStyle,
+) -> Result<W> {
+    let HeaderStyle {
+        format,
+        continuous,
+        plus,
+        settings,
+    } = style;
+    let policy = settings.text_policy;
+    if let Some(t) = start.filter(|t| !EDF_YEARS.contains(&t.year())) {
+        return Err(anyhow!(
+            "Start date {} is outside the EDF range {}-{}",
+            t.date(),
+            EDF_YEARS.start(),
+            EDF_YEARS.end()
+        ));
+    }
+
+    // The start fields hold whole seconds; EDF+ keeps the fraction as the
+    // onset of the first data record, and annotations are offset by it too
+    let subsecond = match start {
+        Some(t) if plus => t.nanosecond() as f64 / 1e9,
+        _ => 0.0,
+    };
+
+    let record_duration = record_duration_for(signals, settings.record_duration)?;
+    let mut layout = RecordLayout {
+        format,
+        record_duration,
+        samples_per_record: signals
+            .iter()
+            .map(|s| (s.sample_rate * record_duration).round() as usize)
+            .collect(),
+        phys_ranges: signals.iter().map(|s| s.physical_range()).collect(),
+        annotation_samples: format.annotation_samples(),
+    };
+    let n_records = layout.n_records(signals);
+    let n_signals = signals.len() + usize::from(plus); // data signals + Annotations
     let header_bytes = 256 + n_signals * 256;
 
-    // Compute physical range with margin
-    let phys_min = signal.iter().cloned().fold(f64::INFINITY, f64::min) - 0.1;
-    let phys_max = signal.iter().cloned().fold(f64::NEG_INFINITY, f64::max) + 0.1;
+    // Records wholly inside gaps are left out, making the file
+    // discontinuous; plain EDF can't be, so it keeps them
+    let skip = if plus {
+        records_in_gaps(&settings.gaps, n_records, record_duration)
+    } else {
+        vec![false; n_records]
+    };
+    let n_written = skip.iter().filter(|&&s| !s).count();
+    let continuous = continuous && n_written == n_records;
+    if n_written < n_records {
+        outln!(
+            "Leaving out {} data records inside gaps; writing a discontinuous file",
+            n_records - n_written
+        );
+    }
+
+    // Size the annotation channel to fit the busiest record. Sessions get
+    // room to spare, as later segments have longer onsets in their TALs.
+    let mut by_record = annotations_by_record(annotations, n_records, record_duration);
+    move_skipped_annotations(&mut by_record, &skip);
+    let needed = annotation_samples_needed(&by_record, record_duration, subsecond, format);
+    let spare = if continuous {
+        0
+    } else {
+        format.annotation_samples()
+    };
+    let wanted = match settings.annotation_samples {
+        Some(n) if !continuous => n.max(needed),
+        _ => needed + spare,
+    };
+    layout.annotation_samples = if plus {
+        wanted.max(format.annotation_samples())
+    } else {
+        0
+    };
+
+    // Start fields, and the EDF+ recording ID with its Startdate subfield
+    let (start_date, start_time, startdate) = match start {
+        Some(t) => (
+            t.format("%d.%m.%y").to_string(),
+            t.format("%H.%M.%S").to_string(),
+            t.format("%d-%b-%Y").to_string().to_uppercase(),
+        ),
+        None => (
+            UNKNOWN_START.0.to_string(),
+            UNKNOWN_START.1.to_string(),
+            "X".to_string(),
+        ),
+    };
+    let recording_id = match &settings.recording_id {
+        Some(id) if id.starts_with("Startdate ") => id.clone(),
+        Some(id) => format!("Startdate {} {}", startdate, id),
+        None => {
+            let equipment = settings.equipment.as_deref().unwrap_or(DEFAULT_EQUIPMENT);
+            format!(
+                "Startdate {} X X {}",
+                startdate,
+                equipment.split_whitespace().collect::<Vec<_>>().join("_")
+            )
+        }
+    };
 
-    let mut file = File::create(path)?;
+    // Check the header before opening the output, so a rejected field
+    // doesn't leave a partial file behind
+    check_header(
+        HeaderText {
+            policy,
+            plus,
+            patient,
+            recording_id: &recording_id,
+            start,
+        },
+        signals,
+        &layout,
+        n_written,
+    )?;
+
+    let mut out = open()?;
 
     // === Main header (256 bytes) ===
-    write_field(&mut file, "0", 8)?; // version
-    write_field(&mut file, "X M 04-MAY-1970 Joel_Henderson", 80)?; // patient ID (EDF+)
-    write_field(
-        &mut file,
-        "Startdate 13-FEB-2026 X X KardiaMobile_1L",
-        80,
-    )?; // recording ID
-    write_field(&mut file, "13.02.26", 8)?; // start date
-    write_field(&mut file, "22.42.00", 8)?; // start time
-    write_field(&mut file, &header_bytes.to_string(), 8)?; // header size
-    write_field(&mut file, "EDF+C", 44)?; // reserved (EDF+ continuous)
-    write_field(&mut file, &n_records.to_string(), 8)?; // num data records
-    write_field(&mut file, &record_duration.to_string(), 8)?; // record duration
-    write_field(&mut file, &n_signals.to_string(), 4)?; // num signals
+    out.write_all(format.version())?; // version
+    write_text(&mut out, policy, "Patient", patient, 80)?; // patient ID (EDF+)
+    write_text(&mut out, policy, "Recording", &recording_id, 80)?; // recording ID
+    write_field(&mut out, &start_date, 8)?; // start date
+    write_field(&mut out, &start_time, 8)?; // start time
+    write_field(&mut out, &header_bytes.to_string(), 8)?; // header size
+    let reserved = if plus {
+        format.reserved_with_tool(continuous)
+    } else {
+        String::new()
+    };
+    write_field(&mut out, &reserved, 44)?; // reserved (continuous/discontinuous, tool)
+    write_field(&mut out, &n_written.to_string(), 8)?; // num data records
+    write_field(&mut out, &format_seconds(record_duration), 8)?; // record duration
+    write_field(&mut out, &n_signals.to_string(), 4)?; // num signals
 
     // === Signal headers (interleaved: all labels, then all transducers, etc.) ===
+    // Each field is written for every data signal, then for the annotation signal.
 
     // Labels (16 bytes each)
-    write_field(&mut file, "EKG I", 16)?;
-    write_field(&mut file, "EDF Annotations", 16)?;
+    for s in signals {
+        write_text(&mut out, policy, "Label", &s.label, 16)?;
+    }
+    if plus {
+        write_field(&mut out, format.annotation_label(), 16)?;
+    }
 
     // Transducer type (80 bytes each)
-    write_field(&mut file, "KardiaMobile 1L electrode", 80)?;
-    write_field(&mut file, "", 80)?;
+    for s in signals {
+        write_text(&mut out, policy, "Transducer", &s.transducer, 80)?;
+    }
+    if plus {
+        write_field(&mut out, "", 80)?;
+    }
 
     // Physical dimension (8 bytes each)
-    write_field(&mut file, "mV", 8)?;
-    write_field(&mut file, "", 8)?;
+    for s in signals {
+        write_text(&mut out, policy, "Physical dimension", &s.phys_dim, 8)?;
+    }
+    if plus {
+        write_field(&mut out, "", 8)?;
+    }
 
     // Physical minimum (8 bytes each)
-    write_field(&mut file, &format_edf_num(phys_min), 8)?;
-    write_field(&mut file, "-1", 8)?;
+    for &(phys_min, _) in &layout.phys_ranges {
+        write_field(&mut out, &format_edf_num(phys_min), 8)?;
+    }
+    if plus {
+        write_field(&mut out, "-1", 8)?;
+    }
 
     // Physical maximum (8 bytes each)
-    write_field(&mut file, &format_edf_num(phys_max), 8)?;
-    write_field(&mut file, "1", 8)?;
-
-    // Digital minimum (8 bytes each)
-    write_field(&mut file, "-32768", 8)?;
-    write_field(&mut file, "-32768", 8)?;
+    for &(_, phys_max) in &layout.phys_ranges {
+        write_field(&mut out, &format_edf_num(phys_max), 8)?;
+    }
+    if plus {
+        write_field(&mut out, "1", 8)?;
+    }
 
-    // Digital maximum (8 bytes each)
-    write_field(&mut file, "32767", 8)?;
-    write_field(&mut file, "32767", 8)?;
+    // Digital minimum and maximum (8 bytes each), the annotation signal's too
+    let (dig_min, dig_max) = format.digital_range();
+    for _ in 0..n_signals {
+        write_field(&mut out, &dig_min.to_string(), 8)?;
+    }
+    for _ in 0..n_signals {
+        write_field(&mut out, &dig_max.to_string(), 8)?;
+    }
 
     // Prefiltering (80 bytes each)
-    write_field(&mut file, "Enhanced Filter, 50Hz mains", 80)?;
-    write_field(&mut file, "", 80)?;
+    for s in signals {
+        write_text(&mut out, policy, "Prefiltering", &s.prefilter, 80)?;
+    }
+    if plus {
+        write_field(&mut out, "", 80)?;
+    }
 
     // Number of samples per data record (8 bytes each)
-    write_field(&mut file, &samples_per_record.to_string(), 8)?;
-    write_field(&mut file, &annotation_samples.to_string(), 8)?;
+    for n in &layout.samples_per_record {
+        write_field(&mut out, &n.to_string(), 8)?;
+    }
+    if plus {
+        write_field(&mut out, &layout.annotation_samples.to_string(), 8)?;
+    }
 
-    // Reserved (32 bytes each)
-    write_field(&mut file, "", 32)?;
-    write_field(&mut file, "", 32)?;
+    // Reserved (32 bytes each): the source document's hash, truncated
+    let input_hash = match &settings.input_sha256 {
+        Some(hash) if plus => &hash[..hash.len().min(32)],
+        _ => "",
+    };
+    for _ in 0..n_signals {
+        write_field(&mut out, input_hash, 32)?;
+    }
 
     // === Data records ===
-    for rec in 0..n_records {
-        // ECG samples
-        let start = rec * samples_per_record;
-        for i in 0..samples_per_record {
-            let idx = start + i;
-            let phys_val = if idx < signal.len() {
-                signal[idx]
-            } else {
-                0.0
-            };
-            let dig_val = voltage_to_digital(phys_val, phys_min, phys_max);
-            file.write_all(&dig_val.to_le_bytes())?;
+    write_records(&mut out, signals, &by_record, &skip, &layout, subsecond)?;
+
+    Ok(out)
+}
+
+/// Largest data record the EDF specification recommends, in bytes.
+const MAX_RECORD_BYTES: usize = 61440;
+
+/// The header text fields of a new file, and how they are written.
+struct HeaderText<'a> {
+    policy: TextPolicy,
+    /// EDF+ (or BDF+), whose patient and recording fields have subfields.
+    plus: bool,
+    patient: &'a str,
+    recording_id: &'a str,
+    start: Option<NaiveDateTime>,
+}
+
+/// Check the header against the EDF(+) specification: fail on fields that
+/// readers couldn't parse, or that the text policy rejects, and warn about
+/// ones that some readers reject, such as text that will be truncated or
+/// isn't printable ASCII, or EDF+ patient and recording fields without
+/// their subfields.
+fn check_header(
+    text: HeaderText,
+    signals: &[EdfSignal],
+    layout: &RecordLayout,
+    n_records: usize,
+) -> Result<()> {
+    let mut fields = vec![
+        ("Patient", text.patient, 80),
+        ("Recording", text.recording_id, 80),
+    ];
+    for s in signals {
+        fields.extend([
+            ("Label", s.label.as_str(), 16),
+            ("Transducer", s.transducer.as_str(), 80),
+            ("Physical dimension", s.phys_dim.as_str(), 8),
+            ("Prefiltering", s.prefilter.as_str(), 80),
+        ]);
+    }
+    for (field, value, width) in fields {
+        let bytes = text.policy.encode(field, value)?;
+        if bytes.iter().any(|b| !(b' '..=b'~').contains(b)) {
+            errln!(
+                "Warning: {} {:?} is not printable ASCII, as EDF requires",
+                field,
+                value
+            );
+        }
+        if bytes.len() > width {
+            errln!(
+                "Warning: {} {:?} is longer than its {}-character field and is truncated",
+                field,
+                value,
+                width
+            );
+        }
+    }
+
+    // Numeric fields must fit their 8 characters (4 for the signal count)
+    let n_signals = signals.len() + usize::from(text.plus);
+    for ((s, &(min, max)), &n) in signals
+        .iter()
+        .zip(&layout.phys_ranges)
+        .zip(&layout.samples_per_record)
+    {
+        if !(min.is_finite() && max.is_finite() && min < max) {
+            return Err(anyhow!(
+                "Signal {:?} has physical range [{}, {}]; its minimum must be below its maximum",
+                s.label,
+                min,
+                max
+            ));
+        }
+        for value in [min, max] {
+            if format_edf_num(value).len() > 8 {
+                return Err(anyhow!(
+                    "Signal {:?} physical limit {} does not fit the 8-character header field",
+                    s.label,
+                    value
+                ));
+            }
+        }
+        if n == 0 || n > 99_999_999 {
+            return Err(anyhow!(
+                "Signal {:?} has {} samples per data record",
+                s.label,
+                n
+            ));
+        }
+    }
+    if n_signals > 9999 || n_records > 99_999_999 {
+        return Err(anyhow!(
+            "{} signals in {} data records do not fit the header",
+            n_signals,
+            n_records
+        ));
+    }
+    let record_bytes = (layout.samples_per_record.iter().sum::<usize>()
+        + layout.annotation_samples)
+        * layout.format.sample_bytes();
+    if record_bytes > MAX_RECORD_BYTES {
+        errln!(
+            "Warning: data records of {} bytes are larger than the {} bytes EDF recommends; \
+             use a shorter --record-duration",
+            record_bytes,
+            MAX_RECORD_BYTES
+        );
+    }
+
+    if text.plus {
+        let problems = [
+            patient_subfield_problem(text.patient),
+            recording_subfield_problem(text.recording_id, text.start),
+        ];
+        for problem in problems.into_iter().flatten() {
+            errln!("Warning: {}", problem);
+        }
+    }
+    Ok(())
+}
+
+/// What is wrong with an EDF+ patient field, unless it is "code sex
+/// birthdate name", with sex M, F, or X and birthdate dd-MMM-yyyy or X.
+pub(crate) fn patient_subfield_problem(patient: &str) -> Option<String> {
+    let subfields: Vec<&str> = patient.split(' ').collect();
+    let valid = subfields.len() >= 4
+        && subfields[..4].iter().all(|s| !s.is_empty())
+        && ["M", "F", "X"].contains(&subfields[1])
+        && (subfields[2] == "X" || parse_edf_date(subfields[2]).is_some());
+    (!valid).then(|| {
+        format!(
+            "patient {:?} is not an EDF+ patient field \
+             (\"code sex dd-MMM-yyyy name\", X where unknown)",
+            patient
+        )
+    })
+}
+
+/// What is wrong with an EDF+ recording field, unless it is "Startdate
+/// dd-MMM-yyyy admincode technician equipment", with the start date
+/// matching the recording's.
+pub(crate) fn recording_subfield_problem(
+    recording_id: &str,
+    start: Option<NaiveDateTime>,
+) -> Option<String> {
+    let subfields: Vec<&str> = recording_id.split(' ').collect();
+    let date_matches = match (subfields.get(1), start) {
+        (Some(&"X"), None) => true,
+        (Some(date), Some(t)) => parse_edf_date(date) == Some(t.date()),
+        _ => false,
+    };
+    let valid = subfields.len() >= 5
+        && subfields[0] == "Startdate"
+        && subfields[..5].iter().all(|s| !s.is_empty())
+        && date_matches;
+    (!valid).then(|| {
+        format!(
+            "recording {:?} is not an EDF+ recording field \
+             (\"Startdate dd-MMM-yyyy admincode technician equipment\", \
+             with the start date)",
+            recording_id
+        )
+    })
+}
+
+/// Parse an EDF+ dd-MMM-yyyy date, e.g. "04-MAY-1970", with the month in
+/// capitals as the specification requires.
+fn parse_edf_date(date: &str) -> Option<NaiveDate> {
+    if date.len() != 11 || date != date.to_uppercase() {
+        return None;
+    }
+    NaiveDate::parse_from_str(date, "%d-%b-%Y").ok()
+}
+
+/// Write the signals as data records, each followed by its annotation TALs.
+///
+/// Within a record, each signal's block of samples follows the previous
+/// signal's, in header order. Record onsets start at `onset_seconds`;
+/// records marked in `skip` are left out, keeping later records' onsets.
+fn write_records(
+    out: &mut impl Write,
+    signals: &[EdfSignal],
+    by_record: &[Vec<&Annotation>],
+    skip: &[bool],
+    layout: &RecordLayout,
+    onset_seconds: f64,
+) -> Result<()> {
+    let sample_bytes = layout.format.sample_bytes();
+    let annotation_bytes = layout.annotation_samples * sample_bytes;
+    let record_bytes =
+        layout.samples_per_record.iter().sum::<usize>() * sample_bytes + annotation_bytes;
+
+    // Each record is assembled in one buffer, reused across records, and
+    // written with a single call
+    let mut record = Vec::with_capacity(record_bytes);
+    for (rec, annotations) in by_record.iter().enumerate() {
+        if skip[rec] {
+            continue;
+        }
+        record.clear();
+        for ((signal, &n), &(phys_min, phys_max)) in signals
+            .iter()
+            .zip(&layout.samples_per_record)
+            .zip(&layout.phys_ranges)
+        {
+            let start = rec * n;
+            for i in 0..n {
+                let idx = start + i;
+                let phys_val = signal.samples.get(idx).copied().unwrap_or(signal.pad_value);
+                let dig_val = voltage_to_digital(phys_val, phys_min, phys_max, layout.format);
+                // Little-endian two's complement, truncated to the sample width
+                record.extend_from_slice(&dig_val.to_le_bytes()[..sample_bytes]);
+            }
         }
 
-        // Annotation samples (TAL)
-        let annotation_bytes = make_annotation_bytes(rec * record_duration, annotation_samples);
-        file.write_all(&annotation_bytes)?;
+        // Annotation samples (TAL), null-padded to fill the annotation
+        // channel, if the file has one
+        if annotation_bytes > 0 {
+            let tal_start = record.len();
+            record.extend_from_slice(&make_annotation_bytes(
+                onset_seconds + rec as f64 * layout.record_duration,
+                onset_seconds,
+                annotations,
+            ));
+            record.resize(tal_start + annotation_bytes, 0);
+        }
+        out.write_all(&record)?;
     }
 
     Ok(())
 }
 
 /// Format a floating point number for an EDF header field (max 8 chars).
-fn format_edf_num(val: f64) -> String {
+pub(crate) fn format_edf_num(val: f64) -> String {
     // Try full precision, progressively reduce if too long
     for precision in (0..=6).rev() {
         let s = format!("{:.prec$}", val, prec = precision);
@@ -135,3 +1544,64 @@ fn format_edf_num(val: f64) -> String {
     }
     format!("{:.0}", val)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn start(seconds: u32) -> Option<NaiveDateTime> {
+        NaiveDate::from_ymd_opt(2024, 5, 4)?.and_hms_opt(10, 0, seconds)
+    }
+
+    fn ecg(seconds: usize) -> Vec<EdfSignal> {
+        vec![EdfSignal::ecg(vec![0.5; seconds * 300], 300)]
+    }
+
+    #[test]
+    fn writes_to_memory() {
+        let mut out = Vec::new();
+        let annotations = [Annotation::new(1.0, "R")];
+        let settings = WriteSettings::default();
+        write_edf_to(&mut out, &ecg(10), start(0), "X", &annotations, settings).unwrap();
+
+        let header = edf_read::read_header(&mut Cursor::new(&out)).unwrap();
+        assert!(header.reserved.starts_with("EDF+C"));
+        assert_eq!(header.signals.len(), 2);
+        assert_eq!(header.signals[0].label, "EKG I");
+        let records = header.n_records as usize;
+        assert_eq!(
+            out.len(),
+            header.header_bytes + records * header.record_bytes()
+        );
+    }
+
+    #[test]
+    fn rejected_header_writes_nothing() {
+        let mut out = Vec::new();
+        let too_late = NaiveDate::from_ymd_opt(2100, 1, 1)
+            .and_then(|d| d.and_hms_opt(0, 0, 0))
+            .unwrap();
+        let settings = WriteSettings::default();
+        let result = write_edf_to(&mut out, &ecg(1), Some(too_late), "X", &[], settings);
+        assert!(result.is_err());
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn appends_to_a_session_in_memory() {
+        let mut session = Cursor::new(Vec::new());
+        for (at, seconds) in [(0, 10), (30, 5)] {
+            let settings = WriteSettings::default();
+            append_edf_to(&mut session, &ecg(seconds), start(at), "X", &[], settings).unwrap();
+        }
+
+        session.rewind().unwrap();
+        let header = edf_read::read_header(&mut session).unwrap();
+        assert!(header.reserved.starts_with("EDF+D"));
+        let records = header.n_records as usize;
+        let mut onset = |r| edf_read::read_record_onset(&mut session, &header, r).unwrap();
+        assert_eq!(onset(0), 0.0);
+        assert_eq!(onset(records - 1) + header.record_duration, 35.0);
+    }
+}
@@ -0,0 +1,920 @@
+use anyhow::{anyhow, Result};
+use chrono::{Datelike, NaiveDateTime};
+use lopdf::{Document, Object};
+use std::collections::BTreeMap;
+
+use crate::audit::{errln, outln};
+use crate::calibration::{self, Calibration, CalibrationInput};
+use crate::convert::{ConvertOptions, Progress};
+use crate::ecg_process::{PathCounts, RowProcessing, RowSeries};
+use crate::edf_write::EdfSignal;
+use crate::pdf_extract::DrawingPath;
+use crate::pdf_text::{ReportInfo, TextRun, TraceFilter};
+use crate::profile::LayoutProfile;
+#[cfg(feature = "raster")]
+use crate::raster;
+use crate::sample_flags::SampleFlag;
+use crate::{ecg_process, edf_write, layout_detect, pdf_extract, pdf_repair, pdf_text};
+
+/// A loaded report PDF with its text, shared by detection and extraction.
+pub struct ReportPdf {
+    pub doc: Document,
+    /// Text runs of each page, in page order.
+    pub page_runs: Vec<Vec<TextRun>>,
+    /// Text lines of all pages, in reading order.
+    pub lines: Vec<String>,
+    /// Producer entry of the document information dictionary.
+    pub producer: Option<String>,
+    /// Creator entry of the document information dictionary.
+    pub creator: Option<String>,
+}
+
+impl ReportPdf {
+    /// Parse a PDF from memory and read its text. A PDF whose
+    /// cross-reference table is damaged, e.g. truncated, is read from a
+    /// rebuilt table, with warnings listing what was recovered.
+    pub fn load_mem(bytes: &[u8]) -> Result<Self> {
+        let doc = match Document::load_mem(bytes) {
+            Ok(doc) => doc,
+            Err(error) => load_damaged(bytes, error)?,
+        };
+        let page_runs = doc
+            .get_pages()
+            .values()
+            .map(|&id| pdf_text::extract_text_runs(&doc, id))
+            .collect::<Result<Vec<_>>>()?;
+        let lines = page_runs
+            .iter()
+            .flat_map(|runs| pdf_text::group_lines(runs))
+            .collect();
+        let info_text = |key: &[u8]| {
+            let info = doc.trailer.get(b"Info").ok()?;
+            let info = match info {
+                Object::Reference(id) => doc.get_dictionary(*id).ok()?,
+                Object::Dictionary(dict) => dict,
+                _ => return None,
+            };
+            match info.get(key).ok()? {
+                Object::String(bytes, _) => Some(String::from_utf8_lossy(bytes).into_owned()),
+                _ => None,
+            }
+        };
+        let producer = info_text(b"Producer");
+        let creator = info_text(b"Creator");
+        Ok(Self {
+            doc,
+            page_runs,
+            lines,
+            producer,
+            creator,
+        })
+    }
+
+    /// Whether any text line, the producer, or the creator contains `text`.
+    pub fn mentions(&self, text: &str) -> bool {
+        self.lines.iter().any(|l| l.contains(text))
+            || self.producer.as_deref().is_some_and(|p| p.contains(text))
+            || self.creator.as_deref().is_some_and(|c| c.contains(text))
+    }
+}
+
+/// Read a PDF that failed to load with `error` from a rebuilt
+/// cross-reference table (see [`pdf_repair::rebuild_xref`]).
+fn load_damaged(bytes: &[u8], error: lopdf::Error) -> Result<Document> {
+    errln!(
+        "Warning: PDF is damaged ({}); rebuilding its cross-reference table",
+        error
+    );
+    let recover = || -> Result<Document> {
+        let (repaired, report) = pdf_repair::rebuild_xref(bytes)?;
+        for line in report.warnings() {
+            errln!("Warning: {}", line);
+        }
+        let doc = Document::load_mem(&repaired)?;
+        errln!("Warning: recovered {} page(s)", doc.get_pages().len());
+        Ok(doc)
+    };
+    recover().map_err(|e| {
+        anyhow!(
+            "Cannot read PDF ({}), and recovering it failed: {}",
+            error,
+            e
+        )
+    })
+}
+
+/// What an extractor reads from a report PDF, before beat detection.
+#[derive(Debug, Clone)]
+pub struct ExtractedEcg {
+    /// Lead I voltage samples in millivolts.
+    pub signal: Vec<f64>,
+    /// Whether each sample was measured, interpolated, missing, or clipped.
+    pub flags: Vec<SampleFlag>,
+    /// Sampling rate in Hz.
+    pub sample_rate: usize,
+    /// Filtering of the lead I strip.
+    pub trace_filter: TraceFilter,
+    /// Other traces read from the report, such as the original-filter strip.
+    pub extra_signals: Vec<EdfSignal>,
+    /// Recording start date/time, if found.
+    pub start: Option<NaiveDateTime>,
+    /// EDF+ patient identification field.
+    pub patient: String,
+    /// Findings and device details printed on the report.
+    pub report: ReportInfo,
+    /// Calibration the trace was scaled with, and its source.
+    pub calibration: Calibration,
+    /// The lead I strip's rows before they were joined, by page number.
+    pub rows: BTreeMap<u32, Vec<RowSeries>>,
+    /// How many of each strip page's drawing paths were read as the lead I
+    /// trace, by page number; empty for traced images.
+    pub paths: BTreeMap<u32, PathCounts>,
+}
+
+/// Which strip to read when a report has both an enhanced-filter and an
+/// original (unfiltered) strip.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TraceChoice {
+    /// The enhanced-filter strip, or the original one if that's all there is.
+    #[default]
+    Enhanced,
+    /// The original strip.
+    Original,
+    /// Both: the enhanced strip as lead I and the original as a second signal.
+    Both,
+}
+
+/// Reads the ECG waveform and report details from one vendor's PDFs.
+///
+/// Implementations are listed in [`EXTRACTORS`]; [`detect`] picks the one
+/// most confident it recognizes a PDF.
+pub trait EcgPdfExtractor: Sync {
+    /// Identifier used to select the extractor, e.g. "kardiamobile-1l".
+    fn name(&self) -> &'static str;
+
+    /// Device or app whose reports this extractor reads.
+    fn description(&self) -> &'static str;
+
+    /// How confident the extractor is that it reads this PDF; 0 for not at
+    /// all, higher for stronger evidence such as vendor-specific labels.
+    fn detect(&self, pdf: &ReportPdf) -> u32;
+
+    /// Strip layout read when the options keep the default layout profile.
+    fn layout(&self) -> LayoutProfile {
+        LayoutProfile::default()
+    }
+
+    /// Read the waveform and report details, with the options' layout profile.
+    fn extract(&self, pdf: &ReportPdf, options: &ConvertOptions) -> Result<ExtractedEcg>;
+}
+
+/// All extractors; the first is the fallback when none recognizes a PDF.
+pub static EXTRACTORS: &[&dyn EcgPdfExtractor] = &[&KardiaMobile1L, &Withings, &Samsung, &Generic];
+
+/// Look up an extractor by name.
+pub fn find(name: &str) -> Result<&'static dyn EcgPdfExtractor> {
+    EXTRACTORS
+        .iter()
+        .copied()
+        .find(|e| e.name() == name)
+        .ok_or_else(|| {
+            let names: Vec<_> = EXTRACTORS.iter().map(|e| e.name()).collect();
+            anyhow!(
+                "Unknown extractor {:?}; available: {}",
+                name,
+                names.join(", ")
+            )
+        })
+}
+
+/// The extractor most confident it reads this PDF, if any recognizes it.
+pub fn detect(pdf: &ReportPdf) -> Option<&'static dyn EcgPdfExtractor> {
+    EXTRACTORS
+        .iter()
+        .copied()
+        .map(|e| (e.detect(pdf), e))
+        .filter(|&(score, _)| score > 0)
+        .max_by_key(|&(score, _)| score)
+        .map(|(_, e)| e)
+}
+
+/// AliveCor KardiaMobile 1L reports from the Kardia app, or other PDFs
+/// with a vector strip described by the options' layout profile.
+pub struct KardiaMobile1L;
+
+impl EcgPdfExtractor for KardiaMobile1L {
+    fn name(&self) -> &'static str {
+        "kardiamobile-1l"
+    }
+
+    fn description(&self) -> &'static str {
+        "AliveCor KardiaMobile 1L (Kardia app report)"
+    }
+
+    fn detect(&self, pdf: &ReportPdf) -> u32 {
+        if pdf.mentions("Kardia Determination") || pdf.mentions("AliveCor") {
+            2
+        } else if pdf.mentions("Kardia") {
+            1
+        } else {
+            0
+        }
+    }
+
+    fn extract(&self, pdf: &ReportPdf, options: &ConvertOptions) -> Result<ExtractedEcg> {
+        extract_vector_strip(pdf, options)
+    }
+}
+
+/// Withings ScanWatch and Move ECG reports from the Health Mate app.
+pub struct Withings;
+
+impl EcgPdfExtractor for Withings {
+    fn name(&self) -> &'static str {
+        "withings"
+    }
+
+    fn description(&self) -> &'static str {
+        "Withings ScanWatch / Move ECG (Health Mate report)"
+    }
+
+    fn detect(&self, pdf: &ReportPdf) -> u32 {
+        if pdf.mentions("Withings") {
+            2
+        } else {
+            0
+        }
+    }
+
+    fn layout(&self) -> LayoutProfile {
+        LayoutProfile::withings()
+    }
+
+    fn extract(&self, pdf: &ReportPdf, options: &ConvertOptions) -> Result<ExtractedEcg> {
+        extract_vector_strip(pdf, options)
+    }
+}
+
+/// Samsung Health Monitor ECG reports from Galaxy Watch.
+pub struct Samsung;
+
+impl EcgPdfExtractor for Samsung {
+    fn name(&self) -> &'static str {
+        "samsung"
+    }
+
+    fn description(&self) -> &'static str {
+        "Samsung Health Monitor ECG (Galaxy Watch report)"
+    }
+
+    fn detect(&self, pdf: &ReportPdf) -> u32 {
+        if pdf.mentions("Samsung Health Monitor") {
+            2
+        } else if pdf.mentions("Samsung") {
+            1
+        } else {
+            0
+        }
+    }
+
+    fn layout(&self) -> LayoutProfile {
+        LayoutProfile::samsung()
+    }
+
+    fn extract(&self, pdf: &ReportPdf, options: &ConvertOptions) -> Result<ExtractedEcg> {
+        extract_vector_strip(pdf, options)
+    }
+}
+
+/// Any vector ECG PDF, with the trace, rows, and baselines found from path
+/// geometry (see [`layout_detect::detect_layout`]) instead of a vendor's
+/// stroke colors and widths. Never detected; choose it by name.
+pub struct Generic;
+
+impl EcgPdfExtractor for Generic {
+    fn name(&self) -> &'static str {
+        "generic"
+    }
+
+    fn description(&self) -> &'static str {
+        "Generic vector ECG (layout found from path geometry)"
+    }
+
+    fn detect(&self, _pdf: &ReportPdf) -> u32 {
+        0
+    }
+
+    fn layout(&self) -> LayoutProfile {
+        LayoutProfile {
+            name: "generic".to_string(),
+            ..LayoutProfile::default()
+        }
+    }
+
+    fn extract(&self, pdf: &ReportPdf, options: &ConvertOptions) -> Result<ExtractedEcg> {
+        let (start, patient) = report_details(pdf, options);
+
+        // Find the layout of every page, and the page whose trace zig-zags
+        // the most
+        let mut pages: Vec<(u32, Vec<DrawingPath>, layout_detect::DetectedLayout)> = Vec::new();
+        for (&number, &page_id) in &pdf.doc.get_pages() {
+            let geometry = pdf_extract::get_page_geometry(&pdf.doc, page_id)?;
+            let paths = pdf_extract::extract_paths(
+                &pdf.doc,
+                page_id,
+                &geometry,
+                options.profile.filled_trace,
+            )?;
+            if let Ok(layout) = layout_detect::detect_layout(&paths, &options.profile) {
+                pages.push((number, paths, layout));
+            }
+        }
+        let best = pages
+            .iter()
+            .enumerate()
+            .max_by_key(|(i, (_, _, layout))| (layout.score, std::cmp::Reverse(*i)))
+            .map(|(i, _)| i)
+            .ok_or_else(|| {
+                anyhow!(
+                    "No waveform found: no page has stroked paths that zig-zag like an ECG trace"
+                )
+            });
+        let best = match best {
+            Ok(best) => best,
+            #[cfg(feature = "raster")]
+            Err(e) => return extract_raster_strip(pdf, options, (start, patient), e),
+            #[cfg(not(feature = "raster"))]
+            Err(e) => return Err(e),
+        };
+        let (number, paths, layout) = &pages[best];
+        let calibration = calibrate_strip(pdf, paths, &layout.baselines, options);
+        let best_profile = layout.profile.clone();
+        let [r, g, b] = best_profile.trace.color;
+        outln!(
+            "Generic layout: page {}, trace color ({:.3}, {:.3}, {:.3}) width {:.2}, {} rows, {} Hz at 25 mm/s",
+            number,
+            r,
+            g,
+            b,
+            (best_profile.trace.width_min + best_profile.trace.width_max) / 2.0,
+            best_profile.rows,
+            best_profile.sample_rate
+        );
+
+        // Pages continuing the strip have a trace in the same style and at
+        // the same sampling rate
+        pages.retain(|(_, _, layout)| {
+            layout.profile.trace == best_profile.trace
+                && layout.profile.sample_rate == best_profile.sample_rate
+        });
+        let multi_page = pages.len() > 1;
+        let groups = select_trace_pages(pdf, pages, |(number, _, _)| *number, options.trace)?;
+        let sample_rate = best_profile.sample_rate;
+        let traces = join_traces(
+            groups,
+            sample_rate,
+            options,
+            |(number, mut paths, layout)| {
+                let profile = LayoutProfile {
+                    page: number,
+                    cal_pt_per_mv: calibration.points_per_mv,
+                    ..layout.profile
+                };
+                // Lines drawn in the trace's style but not part of it are left
+                // out, keeping path indices for --debug-rows
+                for (i, path) in paths.iter_mut().enumerate() {
+                    if layout.trace.binary_search(&i).is_err() {
+                        path.segments.clear();
+                    }
+                }
+                let digitized = digitize_strip(
+                    &paths,
+                    &layout.baselines,
+                    &profile,
+                    StripDebug::new(options, number, multi_page),
+                    options.row_processing(profile.sample_rate),
+                )?;
+                print_page_progress(&digitized, &profile);
+                Ok(digitized)
+            },
+        )?;
+
+        Ok(ExtractedEcg {
+            signal: traces.signal,
+            flags: traces.flags,
+            sample_rate,
+            trace_filter: traces.filter,
+            extra_signals: traces.extra_signals,
+            start,
+            patient,
+            report: pdf_text::find_report_info(&pdf.page_runs),
+            calibration,
+            rows: traces.rows,
+            paths: traces.paths,
+        })
+    }
+}
+
+/// Read a vector-drawn strip laid out as the options' profile describes,
+/// from every strip page in order, with the start date/time and patient
+/// from the report text.
+fn extract_vector_strip(pdf: &ReportPdf, options: &ConvertOptions) -> Result<ExtractedEcg> {
+    let (start, patient) = report_details(pdf, options);
+
+    // Extract drawing paths from the strip pages, or trace a scanned strip
+    let pages = match find_strip_pages(&pdf.doc, &options.profile, options.allow_partial) {
+        Ok(pages) => pages,
+        #[cfg(feature = "raster")]
+        Err(e) => return extract_raster_strip(pdf, options, (start, patient), e),
+        #[cfg(not(feature = "raster"))]
+        Err(e) => return Err(e),
+    };
+    let multi_page = pages.len() > 1;
+    if multi_page {
+        let numbers: Vec<String> = pages.iter().map(|(n, _)| n.to_string()).collect();
+        outln!("Strip pages: {}", numbers.join(", "));
+    }
+
+    // Digitize each page's rows, calibrated from the first page
+    let groups = select_trace_pages(pdf, pages, |(number, _)| *number, options.trace)?;
+    let mut calibration = None;
+    let traces = join_traces(
+        groups,
+        options.profile.sample_rate,
+        options,
+        |(number, paths)| {
+            let baselines = ecg_process::extract_baselines(&paths, &options.profile)?;
+            let calibration = *calibration
+                .get_or_insert_with(|| calibrate_strip(pdf, &paths, &baselines, options));
+            let profile = LayoutProfile {
+                page: number,
+                cal_pt_per_mv: calibration.points_per_mv,
+                ..options.profile.clone()
+            };
+            let digitized = digitize_strip(
+                &paths,
+                &baselines,
+                &profile,
+                StripDebug::new(options, number, multi_page),
+                options.row_processing(profile.sample_rate),
+            )?;
+            print_page_progress(&digitized, &profile);
+            Ok(digitized)
+        },
+    )?;
+
+    Ok(ExtractedEcg {
+        signal: traces.signal,
+        flags: traces.flags,
+        sample_rate: options.profile.sample_rate,
+        trace_filter: traces.filter,
+        extra_signals: traces.extra_signals,
+        start,
+        patient,
+        report: pdf_text::find_report_info(&pdf.page_runs),
+        calibration: calibration.expect("find_strip_pages returns at least one page"),
+        rows: traces.rows,
+        paths: traces.paths,
+    })
+}
+
+/// Trace the strip from the images of a scanned report, for PDFs where no
+/// vector strip was found (`vector_error`); see [`raster::trace_strip`].
+/// Each page's image with the most trace rows is read, from every page
+/// that has one.
+#[cfg(feature = "raster")]
+fn extract_raster_strip(
+    pdf: &ReportPdf,
+    options: &ConvertOptions,
+    (start, patient): (Option<NaiveDateTime>, String),
+    vector_error: anyhow::Error,
+) -> Result<ExtractedEcg> {
+    let sample_rate = options.profile.sample_rate;
+    let mut pages = Vec::new();
+    for (&number, &page_id) in &pdf.doc.get_pages() {
+        let geometry = pdf_extract::get_page_geometry(&pdf.doc, page_id)?;
+        let traced = raster::page_images(&pdf.doc, page_id, &geometry)?
+            .into_iter()
+            .filter_map(|image| Some((raster::trace_strip(&image, sample_rate)?, image)))
+            .max_by_key(|(strip, _)| strip.baselines.len());
+        if let Some((strip, image)) = traced {
+            pages.push((number, strip, image));
+        }
+    }
+    if pages.is_empty() {
+        return Err(anyhow!(
+            "{}; no page image has ECG trace rows either",
+            vector_error
+        ));
+    }
+    errln!(
+        "Warning: no vector strip found ({}); tracing the strip from the page images, \
+         which is limited by their resolution",
+        vector_error
+    );
+
+    if options.debug_rows.is_some() {
+        errln!("Warning: --debug-rows is not written for traced images");
+    }
+    if options.debug_overlay.is_some() {
+        errln!("Warning: --debug-overlay is not written for traced images");
+    }
+    let groups = vec![(TraceFilter::default(), pages)];
+    let mut calibration = None;
+    let traces = join_traces(groups, sample_rate, options, |(number, strip, image)| {
+        outln!(
+            "Page {}: image {}x{} pixels at {:.0} dpi",
+            number,
+            image.image.width,
+            image.image.height,
+            image.dpi()
+        );
+        let calibration = *calibration
+            .get_or_insert_with(|| calibrate_strip(pdf, &[], &strip.baselines, options));
+        digitize_raster(number, &strip, calibration.points_per_mv, options)
+    })?;
+
+    Ok(ExtractedEcg {
+        signal: traces.signal,
+        flags: traces.flags,
+        sample_rate,
+        trace_filter: traces.filter,
+        extra_signals: traces.extra_signals,
+        start,
+        patient,
+        report: pdf_text::find_report_info(&pdf.page_runs),
+        calibration: calibration.expect("pages is not empty"),
+        rows: traces.rows,
+        paths: traces.paths,
+    })
+}
+
+/// Concatenate a traced image's rows into one voltage signal.
+#[cfg(feature = "raster")]
+fn digitize_raster(
+    page: u32,
+    strip: &raster::RasterStrip,
+    cal_pt_per_mv: f64,
+    options: &ConvertOptions,
+) -> Result<DigitizedPage> {
+    outln!(
+        "Baselines (PDF y-coordinates): {:?}",
+        strip
+            .baselines
+            .iter()
+            .map(|b| format!("{:.1}", b))
+            .collect::<Vec<_>>()
+    );
+    let processing = options.row_processing(options.profile.sample_rate);
+    let rows = ecg_process::row_series(&strip.rows, &strip.baselines, cal_pt_per_mv, processing)?;
+    let (signal, flags) = ecg_process::join_rows(&rows, processing);
+    let digitized = DigitizedPage {
+        page,
+        signal,
+        flags,
+        rows,
+        paths: None,
+    };
+    print_page_progress(&digitized, &options.profile);
+    Ok(digitized)
+}
+
+/// Strip pages grouped by the filtering named in their footers, as chosen:
+/// the lead I group first, then the original-filter group for
+/// [`TraceChoice::Both`]. Pages that name no filtering count as enhanced.
+fn select_trace_pages<T>(
+    pdf: &ReportPdf,
+    pages: Vec<T>,
+    number: impl Fn(&T) -> u32,
+    choice: TraceChoice,
+) -> Result<Vec<(TraceFilter, Vec<T>)>> {
+    let page_numbers: Vec<u32> = pdf.doc.get_pages().keys().copied().collect();
+    let filter_of = |page: &T| {
+        page_numbers
+            .iter()
+            .position(|&n| n == number(page))
+            .and_then(|i| pdf_text::find_trace_filter(&pdf.page_runs[i]))
+            .unwrap_or_default()
+    };
+    let (enhanced, original): (Vec<T>, Vec<T>) = pages
+        .into_iter()
+        .partition(|page| filter_of(page) == TraceFilter::Enhanced);
+    let list = |pages: &[T]| {
+        let numbers: Vec<String> = pages.iter().map(|p| number(p).to_string()).collect();
+        numbers.join(", ")
+    };
+    if !original.is_empty() {
+        outln!(
+            "Enhanced-filter strip pages: {}; original-filter strip pages: {}",
+            if enhanced.is_empty() {
+                "none".to_string()
+            } else {
+                list(&enhanced)
+            },
+            list(&original)
+        );
+    }
+    let missing = |filter: TraceFilter| {
+        anyhow!(
+            "No {}-filter strip page found; the PDF's strip pages are {}-filter only",
+            filter.name(),
+            if filter == TraceFilter::Enhanced {
+                "original"
+            } else {
+                "enhanced"
+            }
+        )
+    };
+    Ok(match choice {
+        TraceChoice::Enhanced if enhanced.is_empty() => {
+            errln!(
+                "Warning: no enhanced-filter strip page found; reading the original-filter strip"
+            );
+            vec![(TraceFilter::Original, original)]
+        }
+        TraceChoice::Enhanced => vec![(TraceFilter::Enhanced, enhanced)],
+        TraceChoice::Original if original.is_empty() => return Err(missing(TraceFilter::Original)),
+        TraceChoice::Original => vec![(TraceFilter::Original, original)],
+        TraceChoice::Both if enhanced.is_empty() => return Err(missing(TraceFilter::Enhanced)),
+        TraceChoice::Both if original.is_empty() => return Err(missing(TraceFilter::Original)),
+        TraceChoice::Both => vec![
+            (TraceFilter::Enhanced, enhanced),
+            (TraceFilter::Original, original),
+        ],
+    })
+}
+
+/// Lead I, from the first group of pages, and any other trace as an extra
+/// signal labeled with its filtering.
+struct Traces {
+    filter: TraceFilter,
+    signal: Vec<f64>,
+    flags: Vec<SampleFlag>,
+    rows: BTreeMap<u32, Vec<RowSeries>>,
+    paths: BTreeMap<u32, PathCounts>,
+    extra_signals: Vec<EdfSignal>,
+}
+
+/// A strip page's rows, and the signal joined from them.
+struct DigitizedPage {
+    page: u32,
+    signal: Vec<f64>,
+    flags: Vec<SampleFlag>,
+    rows: Vec<RowSeries>,
+    /// The page's drawing paths read as trace; `None` for traced images.
+    paths: Option<PathCounts>,
+}
+
+/// Digitize each group's pages in order and join them into [`Traces`],
+/// reporting each page done to the options' progress callback.
+fn join_traces<T>(
+    groups: Vec<(TraceFilter, Vec<T>)>,
+    sample_rate: usize,
+    options: &ConvertOptions,
+    mut digitize: impl FnMut(T) -> Result<DigitizedPage>,
+) -> Result<Traces> {
+    let total = groups.iter().map(|(_, pages)| pages.len()).sum();
+    let (mut done, mut rows_done) = (0, 0);
+    let mut traces: Option<Traces> = None;
+    for (filter, pages) in groups {
+        let mut signal = Vec::new();
+        let mut flags = Vec::new();
+        let mut rows = BTreeMap::new();
+        let mut paths = BTreeMap::new();
+        for page in pages {
+            let digitized = digitize(page)?;
+            signal.extend(digitized.signal);
+            flags.extend(digitized.flags);
+            paths.extend(digitized.paths.map(|counts| (digitized.page, counts)));
+            done += 1;
+            rows_done += digitized.rows.len();
+            options.progress(Progress::Pages {
+                done,
+                total,
+                rows: rows_done,
+            });
+            rows.insert(digitized.page, digitized.rows);
+        }
+        match &mut traces {
+            None => {
+                traces = Some(Traces {
+                    filter,
+                    signal,
+                    flags,
+                    rows,
+                    paths,
+                    extra_signals: Vec::new(),
+                })
+            }
+            Some(traces) => traces.extra_signals.push(EdfSignal {
+                label: format!("EKG I {}", filter.name()),
+                prefilter: filter.prefilter(None),
+                ..EdfSignal::ecg(signal, sample_rate)
+            }),
+        }
+    }
+    traces.ok_or_else(|| anyhow!("No ECG strip page found"))
+}
+
+/// Where to write a strip page's debugging output, if anywhere.
+struct StripDebug {
+    /// Directory for the row CSVs.
+    rows: Option<String>,
+    /// SVG file for the overlay.
+    overlay: Option<String>,
+}
+
+impl StripDebug {
+    /// The options' debugging output for a page, in a `page_<n>`
+    /// subdirectory, or with a `_page_<n>` file name suffix, when the strip
+    /// spans several pages.
+    fn new(options: &ConvertOptions, page: u32, multi_page: bool) -> Self {
+        let rows = options.debug_rows.as_deref().map(|dir| {
+            if multi_page {
+                format!("{}/page_{}", dir, page)
+            } else {
+                dir.to_string()
+            }
+        });
+        let overlay = options.debug_overlay.as_deref().map(|path| {
+            if !multi_page {
+                return path.to_string();
+            }
+            let file = std::path::Path::new(path);
+            let stem = file.file_stem().map_or("".into(), |s| s.to_string_lossy());
+            let name = match file.extension() {
+                Some(ext) => format!("{}_page_{}.{}", stem, page, ext.to_string_lossy()),
+                None => format!("{}_page_{}", stem, page),
+            };
+            file.with_file_name(name).to_string_lossy().into_owned()
+        });
+        StripDebug { rows, overlay }
+    }
+}
+
+/// Print a strip page's row count, samples, and duration.
+fn print_page_progress(digitized: &DigitizedPage, profile: &LayoutProfile) {
+    let samples = digitized.signal.len();
+    outln!(
+        "Page {}: {} rows, {} samples ({:.2} s)",
+        digitized.page,
+        digitized.rows.len(),
+        samples,
+        samples as f64 / profile.sample_rate as f64
+    );
+}
+
+/// The strip's calibration from the first strategy in
+/// [`calibration::STRATEGIES`] that finds one.
+fn calibrate_strip(
+    pdf: &ReportPdf,
+    paths: &[DrawingPath],
+    baselines: &[f64],
+    options: &ConvertOptions,
+) -> Calibration {
+    let calibration = calibration::calibrate(&CalibrationInput {
+        options,
+        lines: &pdf.lines,
+        paths,
+        baselines,
+    });
+    outln!(
+        "Calibration: {:.4} pt/mV ({})",
+        calibration.points_per_mv,
+        calibration.source.name()
+    );
+    calibration
+}
+
+/// The recording start date/time and EDF+ patient field from the report
+/// text, or the patient from the options.
+fn report_details(pdf: &ReportPdf, options: &ConvertOptions) -> (Option<NaiveDateTime>, String) {
+    // Read the recording start date/time and patient details from the report
+    // text, with numeric dates in the order the options, the profile, or the
+    // report's AM/PM times call for
+    let order = options
+        .date_order
+        .or(options.profile.date_order)
+        .resolve(&pdf.lines);
+    let start = pdf_text::find_recording_start(&pdf.lines, order).filter(|t| {
+        let in_range = edf_write::EDF_YEARS.contains(&t.year());
+        if !in_range {
+            errln!(
+                "Warning: recording date {} is outside the EDF range; writing it as unknown",
+                t.date()
+            );
+        }
+        in_range
+    });
+    match start {
+        Some(t) => outln!("Recorded on: {}", t.format("%Y-%m-%d %H:%M:%S")),
+        None => errln!("Warning: recording date/time not found in PDF text"),
+    }
+
+    let patient = match &options.header.patient {
+        Some(p) => p.clone(),
+        None => {
+            let found = pdf_text::find_patient(&pdf.page_runs, start, order);
+            if found.name.is_none() {
+                errln!("Warning: patient name not found in PDF text");
+            }
+            edf_write::patient_identification(found.sex, found.birthdate, found.name.as_deref())
+        }
+    };
+    if options.anonymize && options.header.patient.is_none() {
+        outln!("Patient: removed for anonymization");
+    } else {
+        outln!("Patient: {}", patient);
+    }
+    (start, patient)
+}
+
+/// Assign the strip page's trace paths to the rows on `baselines` and
+/// concatenate the rows into one voltage signal, with a flag per sample.
+fn digitize_strip(
+    paths: &[DrawingPath],
+    baselines: &[f64],
+    profile: &LayoutProfile,
+    debug: StripDebug,
+    processing: RowProcessing,
+) -> Result<DigitizedPage> {
+    outln!(
+        "Baselines (PDF y-coordinates): {:?}",
+        baselines
+            .iter()
+            .map(|b| format!("{:.1}", b))
+            .collect::<Vec<_>>()
+    );
+
+    let traces = ecg_process::assign_traces(paths, baselines, profile);
+    if let Some(dir) = &debug.rows {
+        ecg_process::write_debug_rows(dir, &traces, baselines)?;
+    }
+
+    // Extract waveform rows
+    let rows = ecg_process::trace_rows(&traces, paths.len(), baselines.len());
+
+    // Convert each row to voltages, then concatenate them into a single signal
+    let rows = ecg_process::row_series(&rows, baselines, profile.cal_pt_per_mv, processing)?;
+    if let Some(path) = &debug.overlay {
+        ecg_process::write_debug_overlay(path, paths, &traces, &rows, profile.cal_pt_per_mv)?;
+    }
+    let (signal, flags) = ecg_process::join_rows(&rows, processing);
+    Ok(DigitizedPage {
+        page: profile.page,
+        signal,
+        flags,
+        rows,
+        paths: Some(PathCounts::new(paths, &traces)),
+    })
+}
+
+/// Page numbers and drawing paths of the strip pages, in page order: those
+/// that classify as a strip from the profile's page on, or else from the
+/// first page that does, so summary and trend pages are never digitized.
+fn find_strip_pages(
+    doc: &Document,
+    profile: &LayoutProfile,
+    allow_partial: bool,
+) -> Result<Vec<(u32, Vec<DrawingPath>)>> {
+    let pages = doc.get_pages();
+    if !pages.contains_key(&profile.page) {
+        return Err(anyhow!("Page {} not found", profile.page));
+    }
+    let strips_from = |first: u32| -> Result<Vec<(u32, Vec<DrawingPath>)>> {
+        let mut strips = Vec::new();
+        for (&number, &page_id) in pages.range(first..) {
+            let geometry = pdf_extract::get_page_geometry(doc, page_id)?;
+            let paths = pdf_extract::extract_paths(doc, page_id, &geometry, profile.filled_trace)?;
+            if ecg_process::classify_page(&paths, profile, allow_partial)
+                == ecg_process::PageKind::Strip
+            {
+                strips.push((number, paths));
+            }
+        }
+        Ok(strips)
+    };
+    let mut strips = strips_from(profile.page)?;
+    if strips.is_empty() {
+        strips = strips_from(0)?;
+    }
+    match strips.first() {
+        Some(&(number, _)) if number != profile.page => outln!(
+            "Page {} is not an ECG strip; reading the strip from page {}",
+            profile.page,
+            number
+        ),
+        Some(_) => {}
+        None => {
+            return Err(anyhow!(
+                "No ECG strip page found: no page has the layout's baselines with a trace along {}",
+                if allow_partial {
+                    "most rows"
+                } else {
+                    "each row (use --allow-partial if only some rows are readable)"
+                }
+            ))
+        }
+    }
+    Ok(strips)
+}
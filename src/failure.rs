@@ -0,0 +1,69 @@
+use std::fmt;
+
+/// Class of a failed conversion, attached to its error as context where
+/// the failure happens, so callers can branch on it without parsing the
+/// message: the command-line tool's exit status and the HTTP service's
+/// metrics labels come from it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Failure {
+    /// The input file doesn't exist or can't be read.
+    InputNotFound,
+    /// No ECG waveform could be extracted from the PDF.
+    NoWaveform,
+    /// The quality score is below the `qc_fail_below` threshold.
+    QcFailed,
+    /// An output file couldn't be written.
+    WriteFailed,
+    /// The input isn't a PDF that can be parsed, even after repair.
+    InvalidPdf,
+    /// Any other failure, such as invalid options.
+    Other,
+}
+
+impl Failure {
+    /// The class of a conversion error: the outermost [`Failure`] in its
+    /// context, or else a missing file or unparseable PDF among its
+    /// causes, or else [`Failure::Other`].
+    pub fn of(error: &anyhow::Error) -> Self {
+        if let Some(&failure) = error.downcast_ref::<Failure>() {
+            return failure;
+        }
+        for cause in error.chain() {
+            if let Some(e) = cause.downcast_ref::<std::io::Error>() {
+                if e.kind() == std::io::ErrorKind::NotFound {
+                    return Failure::InputNotFound;
+                }
+            }
+            if cause.downcast_ref::<lopdf::Error>().is_some() {
+                return Failure::InvalidPdf;
+            }
+        }
+        Failure::Other
+    }
+
+    /// Process exit status for this failure: 1 for other failures, then
+    /// 2 to 6 in the order of the variants.
+    pub const fn exit_code(self) -> u8 {
+        match self {
+            Failure::Other => 1,
+            Failure::InputNotFound => 2,
+            Failure::NoWaveform => 3,
+            Failure::QcFailed => 4,
+            Failure::WriteFailed => 5,
+            Failure::InvalidPdf => 6,
+        }
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Failure::InputNotFound => "Cannot read the input",
+            Failure::NoWaveform => "No ECG waveform found in the PDF",
+            Failure::QcFailed => "Quality check failed",
+            Failure::WriteFailed => "Cannot write the output",
+            Failure::InvalidPdf => "The input isn't a readable PDF",
+            Failure::Other => "Conversion failed",
+        })
+    }
+}
@@ -0,0 +1,293 @@
+use anyhow::{anyhow, Result};
+use chrono::{Datelike, NaiveDateTime, Timelike};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use crate::edf_write::{
+    parse_edf_patient, record_duration_for, Annotation, EdfSignal, WriteSettings, DEFAULT_EQUIPMENT,
+};
+use crate::text_policy::TextPolicy;
+
+/// Version field written; 2.20 has the tag-length-value header 3 used for
+/// event descriptions.
+const VERSION: &[u8; 8] = b"GDF 2.20";
+
+/// GDFTYP code for 32-bit float samples.
+const GDFTYP_FLOAT32: u32 = 16;
+
+/// Event type of a QRS fiducial point, used for "R" annotations.
+const EVENT_QRS: u16 = 0x0501;
+
+/// Highest user-defined event type; codes 1..=255 are described in header 3.
+const MAX_USER_EVENT: usize = 0xFF;
+
+/// Header 3 tag of the user-defined event description table.
+const TAG_EVENT_DESCRIPTIONS: u8 = 1;
+
+/// Append a NUL-padded text field of exact width, encoded by the text policy.
+fn put_text(
+    buf: &mut Vec<u8>,
+    policy: TextPolicy,
+    field: &str,
+    value: &str,
+    width: usize,
+) -> Result<()> {
+    let mut bytes = policy.encode(field, value)?;
+    bytes.resize(width, 0);
+    buf.extend_from_slice(&bytes);
+    Ok(())
+}
+
+/// GDF time: days since year 0 (as Matlab's datenum) in 32.32 fixed point.
+fn gdf_time(t: NaiveDateTime) -> u64 {
+    let days = t.date().num_days_from_ce() as u64 + 366;
+    let fraction = (((t.num_seconds_from_midnight() as u64) << 32) + 43200) / 86400;
+    (days << 32) + fraction
+}
+
+/// ISO/IEEE 11073 physical dimension code for a unit, or 0 if unknown.
+fn dimension_code(unit: &str) -> u16 {
+    match unit {
+        "V" => 4256,
+        "mV" => 4274,
+        "uV" => 4275,
+        _ => 0,
+    }
+}
+
+/// Write signals as a GDF v2 file, for BioSig, SigViewer, and other
+/// BCI/biosignal toolchains.
+///
+/// Samples are 32-bit floats in physical units. "R" annotations become QRS
+/// fiducial point events; other annotation texts become user-defined event
+/// types described in header 3.
+pub fn write_gdf(
+    path: &str,
+    signals: &[EdfSignal],
+    start: Option<NaiveDateTime>,
+    patient: &str,
+    annotations: &[Annotation],
+    settings: WriteSettings,
+) -> Result<()> {
+    let create = || Ok(BufWriter::new(File::create(path)?));
+    write_file(create, signals, start, patient, annotations, settings)
+}
+
+/// Write signals as GDF v2 to `out`, such as a buffer in memory, otherwise
+/// like [`write_gdf`].
+pub fn write_gdf_to(
+    out: &mut dyn Write,
+    signals: &[EdfSignal],
+    start: Option<NaiveDateTime>,
+    patient: &str,
+    annotations: &[Annotation],
+    settings: WriteSettings,
+) -> Result<()> {
+    write_file(|| Ok(out), signals, start, patient, annotations, settings)
+}
+
+/// Write a GDF v2 file to the output `open` gives, which is opened only
+/// once the header is built.
+fn write_file<W: Write>(
+    open: impl FnOnce() -> Result<W>,
+    signals: &[EdfSignal],
+    start: Option<NaiveDateTime>,
+    patient: &str,
+    annotations: &[Annotation],
+    settings: WriteSettings,
+) -> Result<()> {
+    let text_policy = settings.text_policy;
+    let record_duration = record_duration_for(signals, settings.record_duration)?;
+    let samples_per_record: Vec<usize> = signals
+        .iter()
+        .map(|s| (s.sample_rate * record_duration).round() as usize)
+        .collect();
+    let n_records = signals
+        .iter()
+        .zip(&samples_per_record)
+        .map(|(s, &n)| s.samples.len().div_ceil(n))
+        .max()
+        .unwrap_or(0);
+    let n_signals = signals.len();
+
+    // Events, positioned in samples of the first signal
+    let event_rate = signals.first().map_or(1.0, |s| s.sample_rate);
+    let mut descriptions: Vec<&str> = Vec::new();
+    let mut events = Vec::new();
+    for a in annotations {
+        let typ = if a.text == "R" {
+            EVENT_QRS
+        } else {
+            let code = match descriptions.iter().position(|&d| d == a.text) {
+                Some(i) => i + 1,
+                None => {
+                    descriptions.push(&a.text);
+                    descriptions.len()
+                }
+            };
+            if code > MAX_USER_EVENT {
+                return Err(anyhow!(
+                    "GDF allows at most {} distinct annotation texts",
+                    MAX_USER_EVENT
+                ));
+            }
+            code as u16
+        };
+        // Positions are 1-based
+        let pos = (a.onset * event_rate).round() as u32 + 1;
+        let dur = a.duration.map_or(0, |d| (d * event_rate).round() as u32);
+        events.push((pos, typ, dur));
+    }
+
+    // Header 3: the event description table, then a zero tag, padded to a block
+    let mut header3 = Vec::new();
+    if !descriptions.is_empty() {
+        let mut table = Vec::new();
+        for d in &descriptions {
+            table.extend_from_slice(d.as_bytes());
+            table.push(0);
+        }
+        header3.push(TAG_EVENT_DESCRIPTIONS);
+        header3.extend_from_slice(&(table.len() as u32).to_le_bytes()[..3]);
+        header3.extend_from_slice(&table);
+        header3.resize(header3.len().div_ceil(256) * 256, 0);
+    }
+    let header_blocks = 1 + n_signals + header3.len() / 256;
+
+    let edf_patient = parse_edf_patient(patient);
+    let mut header = Vec::with_capacity(header_blocks * 256);
+
+    // === Fixed header (256 bytes) ===
+    header.extend_from_slice(VERSION);
+    put_text(&mut header, text_policy, "Patient", patient, 66)?; // patient ID
+    header.extend_from_slice(&[0; 10]); // reserved
+    header.push(0); // smoking, alcohol, drug abuse, medication: unknown
+    header.push(0); // weight: unknown
+    header.push(0); // height: unknown
+    let gender = match edf_patient.sex.as_deref() {
+        Some("M") => 1,
+        Some("F") => 2,
+        _ => 0,
+    };
+    header.push(gender); // gender, handedness, impairments
+    let recording_id = settings
+        .recording_id
+        .as_deref()
+        .or(settings.equipment.as_deref())
+        .unwrap_or(DEFAULT_EQUIPMENT);
+    put_text(&mut header, text_policy, "Recording", recording_id, 64)?; // recording ID
+    header.extend_from_slice(&[0; 16]); // recording location
+    header.extend_from_slice(&start.map_or(0, gdf_time).to_le_bytes());
+    let birthday = edf_patient
+        .birthdate
+        .map(|d| gdf_time(d.and_hms_opt(0, 0, 0).unwrap_or_default()));
+    header.extend_from_slice(&birthday.unwrap_or(0).to_le_bytes());
+    header.extend_from_slice(&(header_blocks as u16).to_le_bytes());
+    header.extend_from_slice(&[0; 6]); // patient classification
+    header.extend_from_slice(&0u64.to_le_bytes()); // equipment provider
+    header.extend_from_slice(&[0; 6]); // reserved
+    header.extend_from_slice(&[0; 6]); // head size
+    header.extend_from_slice(&[0; 24]); // reference and ground electrode positions
+    header.extend_from_slice(&(n_records as i64).to_le_bytes());
+    let (numerator, denominator) = duration_ratio(record_duration);
+    header.extend_from_slice(&numerator.to_le_bytes());
+    header.extend_from_slice(&denominator.to_le_bytes());
+    header.extend_from_slice(&(n_signals as u16).to_le_bytes());
+    header.extend_from_slice(&[0; 2]); // reserved
+
+    // === Signal headers (interleaved, as in EDF) ===
+    // Samples are stored in physical units, so digital and physical ranges match.
+    let ranges: Vec<(f64, f64)> = signals.iter().map(|s| s.physical_range()).collect();
+    for s in signals {
+        put_text(&mut header, text_policy, "Label", &s.label, 16)?;
+    }
+    for s in signals {
+        put_text(&mut header, text_policy, "Transducer", &s.transducer, 80)?;
+    }
+    for s in signals {
+        put_text(
+            &mut header,
+            text_policy,
+            "Physical dimension",
+            &s.phys_dim,
+            6,
+        )?;
+    }
+    for s in signals {
+        header.extend_from_slice(&dimension_code(&s.phys_dim).to_le_bytes());
+    }
+    for _ in 0..2 {
+        for &(min, _) in &ranges {
+            header.extend_from_slice(&min.to_le_bytes());
+        }
+        for &(_, max) in &ranges {
+            header.extend_from_slice(&max.to_le_bytes());
+        }
+    }
+    for s in signals {
+        put_text(&mut header, text_policy, "Prefiltering", &s.prefilter, 68)?;
+    }
+    for _ in 0..3 {
+        // Low-pass, high-pass, and notch filters: unknown
+        for _ in signals {
+            header.extend_from_slice(&f32::NAN.to_le_bytes());
+        }
+    }
+    for &n in &samples_per_record {
+        header.extend_from_slice(&(n as u32).to_le_bytes());
+    }
+    for _ in signals {
+        header.extend_from_slice(&GDFTYP_FLOAT32.to_le_bytes());
+    }
+    for _ in signals {
+        header.extend_from_slice(&[0; 12]); // electrode position
+    }
+    header.resize(header.len() + n_signals, 0xFF); // electrode impedance: unknown
+    for _ in signals {
+        header.extend_from_slice(&[0; 19]); // reserved
+    }
+    header.extend_from_slice(&header3);
+
+    let mut out = open()?;
+    out.write_all(&header)?;
+
+    // === Data records ===
+    for rec in 0..n_records {
+        for (signal, &n) in signals.iter().zip(&samples_per_record) {
+            for i in rec * n..(rec + 1) * n {
+                let v = signal.samples.get(i).copied().unwrap_or(signal.pad_value) as f32;
+                out.write_all(&v.to_le_bytes())?;
+            }
+        }
+    }
+
+    // === Event table (mode 3: positions, types, channels, durations) ===
+    out.write_all(&[3])?;
+    out.write_all(&(events.len() as u32).to_le_bytes()[..3])?;
+    out.write_all(&(event_rate as f32).to_le_bytes())?;
+    for &(pos, _, _) in &events {
+        out.write_all(&pos.to_le_bytes())?;
+    }
+    for &(_, typ, _) in &events {
+        out.write_all(&typ.to_le_bytes())?;
+    }
+    for _ in &events {
+        out.write_all(&0u16.to_le_bytes())?; // all channels
+    }
+    for &(_, _, dur) in &events {
+        out.write_all(&dur.to_le_bytes())?;
+    }
+    out.flush()?;
+
+    Ok(())
+}
+
+/// A record duration in seconds as a reduced fraction of whole milliseconds.
+fn duration_ratio(seconds: f64) -> (u32, u32) {
+    let millis = (seconds * 1000.0).round() as u32;
+    let (mut a, mut b) = (millis, 1000);
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    (millis / a, 1000 / a)
+}
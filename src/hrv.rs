@@ -0,0 +1,247 @@
+use serde::Serialize;
+
+/// Heart-rate and heart-rate-variability summary from detected R-peaks.
+#[derive(Debug, Clone, Serialize)]
+pub struct HrvSummary {
+    /// Number of detected beats.
+    pub beats: usize,
+    /// Mean heart rate in beats per minute, from the mean RR interval.
+    pub mean_hr_bpm: f64,
+    /// Slowest beat-to-beat heart rate in beats per minute.
+    pub min_hr_bpm: f64,
+    /// Fastest beat-to-beat heart rate in beats per minute.
+    pub max_hr_bpm: f64,
+    /// Standard deviation of RR intervals in milliseconds.
+    pub sdnn_ms: f64,
+    /// Root mean square of successive RR differences in milliseconds.
+    pub rmssd_ms: f64,
+    /// RR intervals in milliseconds.
+    pub rr_intervals_ms: Vec<f64>,
+    /// RR-interval irregularity, with enough intervals to tell.
+    pub irregularity: Option<Irregularity>,
+}
+
+/// Fewest RR intervals for the irregularity measures.
+const MIN_IRREGULARITY_INTERVALS: usize = 12;
+
+/// Histogram bins for the Shannon entropy of the RR intervals.
+const ENTROPY_BINS: usize = 16;
+
+/// Normalized RMSSD and entropy above which the rhythm is likely atrial
+/// fibrillation (Dash et al., 2009).
+const AF_NORMALIZED_RMSSD: f64 = 0.1;
+const AF_ENTROPY: f64 = 0.7;
+
+/// RR-interval irregularity, as a cross-check of an atrial-fibrillation
+/// determination.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Irregularity {
+    /// RMSSD over the mean RR interval.
+    pub normalized_rmssd: f64,
+    /// Poincaré plot spread across the identity line, in milliseconds:
+    /// beat-to-beat variability.
+    pub sd1_ms: f64,
+    /// Poincaré plot spread along the identity line, in milliseconds:
+    /// longer-term variability.
+    pub sd2_ms: f64,
+    /// Shannon entropy of the RR intervals in 16 equal bins between the
+    /// shortest and longest, from 0 (all in one) to 1 (evenly spread).
+    pub shannon_entropy: f64,
+    /// Whether both the normalized RMSSD and the entropy are high, as in
+    /// atrial fibrillation.
+    pub af_likely: bool,
+}
+
+/// Irregularity of RR intervals in milliseconds; needs at least 12.
+pub fn irregularity(rr: &[f64]) -> Option<Irregularity> {
+    if rr.len() < MIN_IRREGULARITY_INTERVALS {
+        return None;
+    }
+    let n = rr.len() as f64;
+    let mean_rr = rr.iter().sum::<f64>() / n;
+    let sdnn = (rr.iter().map(|r| (r - mean_rr).powi(2)).sum::<f64>() / (n - 1.0)).sqrt();
+    let diffs: Vec<f64> = rr.windows(2).map(|w| w[1] - w[0]).collect();
+    let rmssd = (diffs.iter().map(|d| d * d).sum::<f64>() / diffs.len() as f64).sqrt();
+    let mean_diff = diffs.iter().sum::<f64>() / diffs.len() as f64;
+    let sd_diff = (diffs.iter().map(|d| (d - mean_diff).powi(2)).sum::<f64>()
+        / (diffs.len() as f64 - 1.0))
+        .sqrt();
+    let sd1 = sd_diff / std::f64::consts::SQRT_2;
+    let sd2 = (2.0 * sdnn * sdnn - sd1 * sd1).max(0.0).sqrt();
+
+    let min = rr.iter().cloned().fold(f64::INFINITY, f64::min);
+    let max = rr.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    let mut bins = [0usize; ENTROPY_BINS];
+    for r in rr {
+        let bin = if max > min {
+            ((r - min) / (max - min) * ENTROPY_BINS as f64) as usize
+        } else {
+            0
+        };
+        bins[bin.min(ENTROPY_BINS - 1)] += 1;
+    }
+    let shannon_entropy = -bins
+        .iter()
+        .filter(|&&count| count > 0)
+        .map(|&count| {
+            let p = count as f64 / n;
+            p * p.ln()
+        })
+        .sum::<f64>()
+        / (ENTROPY_BINS as f64).ln();
+
+    let normalized_rmssd = rmssd / mean_rr;
+    Some(Irregularity {
+        normalized_rmssd,
+        sd1_ms: sd1,
+        sd2_ms: sd2,
+        shannon_entropy,
+        af_likely: normalized_rmssd > AF_NORMALIZED_RMSSD && shannon_entropy > AF_ENTROPY,
+    })
+}
+
+/// Compute the summary from R-peak sample indices.
+///
+/// Needs at least three beats (two RR intervals).
+pub fn summarize(r_peaks: &[usize], sample_rate: usize) -> Option<HrvSummary> {
+    if r_peaks.len() < 3 {
+        return None;
+    }
+
+    let rr: Vec<f64> = r_peaks
+        .windows(2)
+        .map(|w| (w[1] - w[0]) as f64 * 1000.0 / sample_rate as f64)
+        .collect();
+    let n = rr.len() as f64;
+
+    let mean_rr = rr.iter().sum::<f64>() / n;
+    let min_rr = rr.iter().cloned().fold(f64::INFINITY, f64::min);
+    let max_rr = rr.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    let sdnn = (rr.iter().map(|r| (r - mean_rr).powi(2)).sum::<f64>() / (n - 1.0)).sqrt();
+    let rmssd = (rr.windows(2).map(|w| (w[1] - w[0]).powi(2)).sum::<f64>() / (n - 1.0)).sqrt();
+
+    Some(HrvSummary {
+        beats: r_peaks.len(),
+        mean_hr_bpm: 60_000.0 / mean_rr,
+        min_hr_bpm: 60_000.0 / max_rr,
+        max_hr_bpm: 60_000.0 / min_rr,
+        sdnn_ms: sdnn,
+        rmssd_ms: rmssd,
+        irregularity: irregularity(&rr),
+        rr_intervals_ms: rr,
+    })
+}
+
+/// Sampling rate of the derived heart-rate channel, in Hz, the usual
+/// resampling rate for HRV analysis.
+pub const HEART_RATE_CHANNEL_RATE: f64 = 4.0;
+
+/// Beat-to-beat heart rate in beats per minute, sampled at `rate` over
+/// `duration` seconds from beat times in seconds: each sample holds the
+/// rate of the RR interval it falls in, and samples before the first beat
+/// or after the last hold the nearest interval's rate. `None` with fewer
+/// than two beats.
+pub fn heart_rate_series(beat_times: &[f64], duration: f64, rate: f64) -> Option<Vec<f64>> {
+    if beat_times.len() < 2 {
+        return None;
+    }
+    let n = (duration * rate).round() as usize;
+    let mut interval = 0;
+    let series = (0..n)
+        .map(|i| {
+            let t = i as f64 / rate;
+            while interval + 2 < beat_times.len() && t >= beat_times[interval + 1] {
+                interval += 1;
+            }
+            60.0 / (beat_times[interval + 1] - beat_times[interval])
+        })
+        .collect();
+    Some(series)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// R-peak sample indexes at 1000 Hz, so samples are milliseconds, for
+    /// RR intervals in milliseconds.
+    fn peaks(rr_ms: &[usize]) -> Vec<usize> {
+        std::iter::once(0)
+            .chain(rr_ms.iter().scan(0, |t, &rr| {
+                *t += rr;
+                Some(*t)
+            }))
+            .collect()
+    }
+
+    fn close(actual: f64, expected: f64) -> bool {
+        (actual - expected).abs() < 1e-6
+    }
+
+    #[test]
+    fn constant_rhythm_has_no_variability() {
+        let summary = summarize(&peaks(&[1000; 19]), 1000).unwrap();
+        assert_eq!(summary.beats, 20);
+        assert!(close(summary.mean_hr_bpm, 60.0));
+        assert!(close(summary.min_hr_bpm, 60.0) && close(summary.max_hr_bpm, 60.0));
+        assert!(close(summary.sdnn_ms, 0.0) && close(summary.rmssd_ms, 0.0));
+
+        let irregularity = summary.irregularity.unwrap();
+        assert!(close(irregularity.normalized_rmssd, 0.0));
+        assert!(close(irregularity.sd1_ms, 0.0) && close(irregularity.sd2_ms, 0.0));
+        assert!(close(irregularity.shannon_entropy, 0.0));
+        assert!(!irregularity.af_likely);
+    }
+
+    #[test]
+    fn alternating_rhythm_is_variable_but_not_af() {
+        let rr: Vec<usize> = (0..20)
+            .map(|i| if i % 2 == 0 { 800 } else { 1000 })
+            .collect();
+        let summary = summarize(&peaks(&rr), 1000).unwrap();
+        assert!(close(summary.mean_hr_bpm, 60_000.0 / 900.0));
+        assert!(close(summary.min_hr_bpm, 60.0) && close(summary.max_hr_bpm, 75.0));
+        assert!(close(summary.sdnn_ms, (200_000.0f64 / 19.0).sqrt()));
+        assert!(close(summary.rmssd_ms, 200.0));
+
+        // Every point of the Poincaré plot is on one line across the
+        // identity line, in two histogram bins
+        let irregularity = summary.irregularity.unwrap();
+        assert!(close(irregularity.normalized_rmssd, 200.0 / 900.0));
+        assert!((irregularity.sd1_ms - 145.095_250).abs() < 1e-5);
+        assert!(irregularity.sd2_ms < 1e-3);
+        assert!(close(irregularity.shannon_entropy, 0.25));
+        assert!(!irregularity.af_likely);
+    }
+
+    #[test]
+    fn irregular_spread_rhythm_is_likely_af() {
+        // Intervals from 600 to 1200 ms, two in each entropy bin, in a
+        // shuffled order
+        let rr: Vec<f64> = (0..32)
+            .map(|i| 600.0 + 40.0 * ((i * 7) % 16) as f64)
+            .collect();
+        let irregularity = irregularity(&rr).unwrap();
+        assert!(close(irregularity.shannon_entropy, 1.0));
+        assert!(irregularity.normalized_rmssd > AF_NORMALIZED_RMSSD);
+        assert!(irregularity.af_likely);
+    }
+
+    #[test]
+    fn needs_enough_beats() {
+        assert!(summarize(&[0, 1000], 1000).is_none());
+        let summary = summarize(&[0, 1000, 2000], 1000).unwrap();
+        assert!(summary.irregularity.is_none());
+        assert!(irregularity(&[1000.0; 11]).is_none());
+        assert!(irregularity(&[1000.0; 12]).is_some());
+    }
+
+    #[test]
+    fn heart_rate_holds_each_interval() {
+        let series = heart_rate_series(&[0.5, 1.5, 2.0], 3.0, 4.0).unwrap();
+        assert_eq!(series.len(), 12);
+        assert!(series[..6].iter().all(|&hr| close(hr, 60.0)));
+        assert!(series[6..].iter().all(|&hr| close(hr, 120.0)));
+        assert!(heart_rate_series(&[0.5], 3.0, 4.0).is_none());
+    }
+}
@@ -0,0 +1,127 @@
+use anyhow::{anyhow, Result};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+use crate::metrics::Metrics;
+
+/// Longest request line or header line accepted, in bytes.
+const MAX_LINE: u64 = 8 * 1024;
+
+/// Most header lines accepted in a request.
+const MAX_HEADERS: usize = 100;
+
+/// A request's method, path, and body length, with the body left unread.
+// Only the `serve` mode's uploads have a body to read
+#[cfg_attr(not(feature = "serve"), allow(dead_code))]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub content_length: usize,
+    reader: BufReader<TcpStream>,
+}
+
+impl Request {
+    /// Read a request's head from `stream`, first setting its read and
+    /// write timeouts to `timeout`, so a client that stalls mid-request or
+    /// stops reading the response fails the request instead of holding
+    /// its thread.
+    pub fn read(stream: &TcpStream, timeout: Duration) -> Result<Self> {
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let request_line = read_line(&mut reader)?;
+        let mut parts = request_line.split_whitespace();
+        let method = parts.next().unwrap_or_default().to_string();
+        let path = parts.next().unwrap_or_default().to_string();
+
+        let mut content_length = 0usize;
+        for _ in 0..MAX_HEADERS {
+            let line = read_line(&mut reader)?;
+            if line.trim().is_empty() {
+                return Ok(Self {
+                    method,
+                    path,
+                    content_length,
+                    reader,
+                });
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                if name.trim().eq_ignore_ascii_case("content-length") {
+                    content_length = value.trim().parse()?;
+                }
+            }
+        }
+        Err(anyhow!("More than {} request headers", MAX_HEADERS))
+    }
+
+    /// Read the body, growing the buffer as the bytes arrive rather than
+    /// allocating the declared length up front.
+    #[cfg(feature = "serve")]
+    pub fn read_body(&mut self) -> Result<Vec<u8>> {
+        let mut body = Vec::new();
+        (&mut self.reader)
+            .take(self.content_length as u64)
+            .read_to_end(&mut body)?;
+        if body.len() < self.content_length {
+            return Err(anyhow!(
+                "Request body ended after {} of {} bytes",
+                body.len(),
+                self.content_length
+            ));
+        }
+        Ok(body)
+    }
+}
+
+/// Read a line of at most [`MAX_LINE`] bytes; an empty string at the end
+/// of the stream.
+fn read_line(reader: &mut BufReader<TcpStream>) -> Result<String> {
+    let mut line = String::new();
+    let n = reader.by_ref().take(MAX_LINE).read_line(&mut line)?;
+    if n as u64 == MAX_LINE && !line.ends_with('\n') {
+        return Err(anyhow!("Request line longer than {} bytes", MAX_LINE));
+    }
+    Ok(line)
+}
+
+/// Answer `GET /healthz` and `GET /metrics`, returning whether `request`
+/// was one of them.
+pub fn respond_status(
+    stream: &mut TcpStream,
+    request: &Request,
+    metrics: &Metrics,
+) -> Result<bool> {
+    match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/healthz") => respond(stream, 200, "text/plain", b"ok\n")?,
+        ("GET", "/metrics") => respond(
+            stream,
+            200,
+            "text/plain; version=0.0.4",
+            metrics.render().as_bytes(),
+        )?,
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+/// Write a response with `body`, and close the connection.
+pub fn respond(stream: &mut TcpStream, status: u16, content_type: &str, body: &[u8]) -> Result<()> {
+    let reason = match status {
+        200 => "OK",
+        404 => "Not Found",
+        413 => "Payload Too Large",
+        503 => "Service Unavailable",
+        _ => "Unprocessable Entity",
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        reason,
+        content_type,
+        body.len()
+    )?;
+    stream.write_all(body)?;
+    Ok(())
+}
@@ -0,0 +1,309 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::fs::File;
+use std::io::{BufReader, Write};
+
+use crate::convert::{self, ConvertOptions};
+use crate::ecg_process;
+use crate::edf_read;
+use crate::extractor::ReportPdf;
+use crate::pdf_extract::{self, DrawingPath};
+use crate::profile::LayoutProfile;
+
+/// Print an EDF/EDF+/BDF+ file's header summary to `out` and, with
+/// `show_signals`, each signal's digital-to-physical scaling and the range
+/// of its samples.
+pub fn inspect_edf(out: &mut impl Write, path: &str, show_signals: bool) -> Result<()> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let header = edf_read::read_header(&mut reader)?;
+
+    writeln!(out, "File: {}", path)?;
+    if header.is_bdf() {
+        writeln!(out, "Format: BDF (24-bit)")?;
+    }
+    writeln!(out, "Patient: {}", header.patient)?;
+    writeln!(out, "Recording: {}", header.recording)?;
+    writeln!(out, "Start: {} {}", header.start_date, header.start_time)?;
+    writeln!(out, "Reserved: {}", header.reserved)?;
+    writeln!(
+        out,
+        "Data records: {} x {} s",
+        header.n_records, header.record_duration
+    )?;
+    writeln!(out, "Signals: {}", header.signals.len())?;
+
+    if !show_signals {
+        return Ok(());
+    }
+
+    for (i, sig) in header.signals.iter().enumerate() {
+        writeln!(out, "\nSignal {}: {}", i, sig.label)?;
+        if sig.is_annotations() {
+            writeln!(
+                out,
+                "  Annotation channel ({} samples/record)",
+                sig.samples_per_record
+            )?;
+            continue;
+        }
+
+        let gain = sig.gain();
+        let offset = sig.offset();
+        writeln!(
+            out,
+            "  Physical: [{}, {}] {}",
+            sig.phys_min, sig.phys_max, sig.phys_dim
+        )?;
+        writeln!(out, "  Digital:  [{}, {}]", sig.dig_min, sig.dig_max)?;
+        writeln!(
+            out,
+            "  Scaling:  physical = {:.9} * digital + {:.9} {}",
+            gain, offset, sig.phys_dim
+        )?;
+        match sig.phys_dim.as_str() {
+            "mV" => writeln!(out, "  Resolution: {:.4} uV per bit", gain * 1000.0)?,
+            "uV" => writeln!(out, "  Resolution: {:.4} uV per bit", gain)?,
+            _ => {}
+        }
+        writeln!(
+            out,
+            "  Sample rate: {} Hz",
+            sig.samples_per_record as f64 / header.record_duration
+        )?;
+
+        let samples = edf_read::read_digital_samples(&mut reader, &header, i)?;
+        if let (Some(&lo), Some(&hi)) = (samples.iter().min(), samples.iter().max()) {
+            writeln!(
+                out,
+                "  Samples: {} digital in [{}, {}] -> physical [{:.6}, {:.6}] {}",
+                samples.len(),
+                lo,
+                hi,
+                sig.to_physical(lo),
+                sig.to_physical(hi),
+                sig.phys_dim
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+/// A drawing path of a PDF page and what the extraction made of it.
+#[derive(Debug, Serialize)]
+pub struct PathReport {
+    /// Index of the path among the page's drawing paths.
+    pub index: usize,
+    /// Stroke (or, for filled traces, fill) color as RGB from 0 to 1.
+    pub color: [f64; 3],
+    /// Line width in points.
+    pub width: f64,
+    pub segments: usize,
+    /// Bounding box as [left, top, right, bottom], in points from the top-left.
+    pub bbox: [f64; 4],
+    /// "trace", "baseline", or "rejected".
+    pub role: &'static str,
+    /// Strip row of a trace path.
+    pub row: Option<usize>,
+    /// The filter that rejected the path, and why.
+    pub rejected_by: Option<String>,
+}
+
+/// A PDF page's drawing paths and baselines.
+#[derive(Debug, Serialize)]
+pub struct PageReport {
+    pub page: u32,
+    /// Baselines (PDF y-coordinates) found on the page.
+    pub baselines: Vec<f64>,
+    /// Why no baselines were found, if none were.
+    pub baseline_error: Option<String>,
+    pub paths: Vec<PathReport>,
+}
+
+/// Extract every drawing path of a report PDF's pages, with its color,
+/// width, segment count, and bounding box, and classify it as the
+/// extraction would with the layout profile: a trace path and its row, a
+/// baseline, or rejected, with the filter that rejected it.
+pub fn pdf_paths(path: &str, options: &ConvertOptions) -> Result<(LayoutProfile, Vec<PageReport>)> {
+    let pdf = ReportPdf::load_mem(&std::fs::read(path)?)?;
+    let (_, profile) = convert::choose_extractor(&pdf, options)?;
+    let mut pages = Vec::new();
+    for (&number, &page_id) in &pdf.doc.get_pages() {
+        let geometry = pdf_extract::get_page_geometry(&pdf.doc, page_id)?;
+        let paths = pdf_extract::extract_paths(&pdf.doc, page_id, &geometry, profile.filled_trace)?;
+        pages.push(page_report(number, &paths, &profile));
+    }
+    Ok((profile, pages))
+}
+
+fn page_report(page: u32, paths: &[DrawingPath], profile: &LayoutProfile) -> PageReport {
+    let (baselines, baseline_error) = match ecg_process::extract_baselines(paths, profile) {
+        Ok(baselines) => (baselines, None),
+        Err(e) => (Vec::new(), Some(e.to_string())),
+    };
+    let mut rows = vec![None; paths.len()];
+    if baseline_error.is_none() {
+        for trace in ecg_process::assign_traces(paths, &baselines, profile) {
+            rows[trace.path] = Some(trace.row);
+        }
+    }
+
+    let paths = paths
+        .iter()
+        .enumerate()
+        .map(|(index, path)| {
+            let (role, row, rejected_by) = match rows[index] {
+                Some(Some(row)) => ("trace", Some(row), None),
+                Some(None) => (
+                    "rejected",
+                    None,
+                    Some(format!(
+                        "row distance: centered farther than {} pt from every baseline",
+                        profile.max_row_distance
+                    )),
+                ),
+                None if profile.baseline.matches(path.color, path.width)
+                    && path
+                        .segments
+                        .iter()
+                        .any(|s| ecg_process::is_baseline_segment(s, profile)) =>
+                {
+                    ("baseline", None, None)
+                }
+                None => ("rejected", None, Some(trace_rejection(path, profile))),
+            };
+            PathReport {
+                index,
+                color: [path.color.0, path.color.1, path.color.2],
+                width: path.width,
+                segments: path.segments.len(),
+                bbox: bounding_box(path),
+                role,
+                row,
+                rejected_by,
+            }
+        })
+        .collect();
+    PageReport {
+        page,
+        baselines,
+        baseline_error,
+        paths,
+    }
+}
+
+/// The first trace filter a path that isn't a trace fails.
+fn trace_rejection(path: &DrawingPath, profile: &LayoutProfile) -> String {
+    let style = &profile.trace;
+    let (r, g, b) = path.color;
+    if [r, g, b]
+        .iter()
+        .zip(&style.color)
+        .any(|(c, want)| (c - want).abs() > style.color_tolerance)
+    {
+        format!(
+            "color: ({:.3}, {:.3}, {:.3}) is not the trace color ({}, {}, {}) ± {}",
+            r, g, b, style.color[0], style.color[1], style.color[2], style.color_tolerance
+        )
+    } else if !(style.width_min < path.width && path.width < style.width_max) {
+        format!(
+            "width: {:.3} is outside the trace widths ({}, {})",
+            path.width, style.width_min, style.width_max
+        )
+    } else if path.segments.len() < style.min_segments {
+        format!(
+            "segments: {} is fewer than the trace's {}",
+            path.segments.len(),
+            style.min_segments
+        )
+    } else {
+        "baselines: no baselines on the page to assign a row".to_string()
+    }
+}
+
+fn bounding_box(path: &DrawingPath) -> [f64; 4] {
+    let mut bbox = [
+        f64::INFINITY,
+        f64::INFINITY,
+        f64::NEG_INFINITY,
+        f64::NEG_INFINITY,
+    ];
+    for p in path.segments.iter().flat_map(|&(a, b)| [a, b]) {
+        bbox = [
+            bbox[0].min(p.x),
+            bbox[1].min(p.y),
+            bbox[2].max(p.x),
+            bbox[3].max(p.y),
+        ];
+    }
+    bbox
+}
+
+/// Print a report PDF's drawing paths and what the extraction made of each
+/// (see [`pdf_paths`]) to `out`, as text or as JSON.
+pub fn inspect_pdf(
+    out: &mut impl Write,
+    path: &str,
+    options: &ConvertOptions,
+    json: bool,
+) -> Result<()> {
+    let (profile, pages) = pdf_paths(path, options)?;
+    if json {
+        let dump = serde_json::json!({
+            "file": path,
+            "profile": profile.name,
+            "pages": pages,
+        });
+        writeln!(out, "{}", serde_json::to_string_pretty(&dump)?)?;
+        return Ok(());
+    }
+
+    writeln!(out, "File: {}", path)?;
+    writeln!(out, "Layout profile: {}", profile.name)?;
+    for page in &pages {
+        let traces = page.paths.iter().filter(|p| p.role == "trace").count();
+        writeln!(
+            out,
+            "\nPage {}: {} paths, {} trace paths",
+            page.page,
+            page.paths.len(),
+            traces
+        )?;
+        match &page.baseline_error {
+            Some(e) => writeln!(out, "  Baselines: none ({})", e)?,
+            None => writeln!(
+                out,
+                "  Baselines: {}",
+                page.baselines
+                    .iter()
+                    .map(|b| format!("{:.1}", b))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )?,
+        }
+        for p in &page.paths {
+            let verdict = match (p.role, p.row, &p.rejected_by) {
+                ("trace", Some(row), _) => format!("trace, row {}", row),
+                (_, _, Some(reason)) => format!("rejected by {}", reason),
+                (role, _, _) => role.to_string(),
+            };
+            writeln!(
+                out,
+                "  #{:<4} color ({:.3}, {:.3}, {:.3})  width {:.3}  {:>5} segments  \
+                 [{:.1}, {:.1}, {:.1}, {:.1}]  {}",
+                p.index,
+                p.color[0],
+                p.color[1],
+                p.color[2],
+                p.width,
+                p.segments,
+                p.bbox[0],
+                p.bbox[1],
+                p.bbox[2],
+                p.bbox[3],
+                verdict
+            )?;
+        }
+    }
+    Ok(())
+}
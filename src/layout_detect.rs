@@ -0,0 +1,212 @@
+use anyhow::{anyhow, Result};
+use std::collections::BTreeMap;
+
+use crate::pdf_extract::DrawingPath;
+use crate::profile::{LayoutProfile, LineStyle};
+
+/// Paper speed assumed when estimating the sampling rate: 25 mm/s, in points.
+pub(crate) const PAPER_SPEED_PT_PER_S: f64 = 25.0 * 72.0 / 25.4;
+
+/// Fewest vertical direction changes for a stroke style to count as a trace.
+const MIN_REVERSALS: usize = 50;
+
+/// Grid lines this close to a row's median y, in points, are its baseline.
+const BASELINE_SNAP: f64 = 2.0;
+
+/// A strip layout found from path geometry alone, without knowing the
+/// vendor's stroke colors and widths.
+#[derive(Debug, Clone)]
+pub struct DetectedLayout {
+    /// Profile with the trace's stroke style, the row count and distance,
+    /// and the sampling rate estimated at 25 mm/s; other fields are kept
+    /// from the profile given to [`detect_layout`].
+    pub profile: LayoutProfile,
+    /// Baseline y-coordinate of each row, in time order.
+    pub baselines: Vec<f64>,
+    /// Indices of the trace's paths, in drawing order.
+    pub trace: Vec<usize>,
+    /// Vertical direction changes of the trace; higher is more ECG-like.
+    pub score: usize,
+}
+
+/// Stroke color and width, rounded, identifying a cluster of paths.
+type StyleKey = ([i64; 3], i64);
+
+fn style_key(path: &DrawingPath) -> StyleKey {
+    let q = |v: f64| (v * 1000.0).round() as i64;
+    let (r, g, b) = path.color;
+    ([q(r), q(g), q(b)], q(path.width))
+}
+
+/// Number of times a path changes vertical direction.
+fn reversals(path: &DrawingPath) -> usize {
+    let mut count = 0;
+    let mut last = 0.0;
+    for (p1, p2) in &path.segments {
+        let dy = p2.y - p1.y;
+        if dy.abs() < 1e-3 {
+            continue;
+        }
+        if last != 0.0 && dy.signum() != last {
+            count += 1;
+        }
+        last = dy.signum();
+    }
+    count
+}
+
+/// Whether a path runs left to right, as nearly all its segments do.
+fn runs_forward(path: &DrawingPath) -> bool {
+    let forward = path
+        .segments
+        .iter()
+        .filter(|(p1, p2)| p2.x >= p1.x - 1e-3)
+        .count();
+    forward * 10 >= path.segments.len() * 9
+}
+
+/// The paths of a style cluster that belong to a trace: those running left
+/// to right that zig-zag, or that continue the previous such path, as flat
+/// stretches between beats do; lone straight lines, such as grid lines, are
+/// left out.
+fn trace_paths(paths: &[DrawingPath], members: &[usize]) -> Vec<usize> {
+    let mut trace: Vec<usize> = Vec::new();
+    for &i in members {
+        let path = &paths[i];
+        if !runs_forward(path) {
+            continue;
+        }
+        let continues = trace.last().is_some_and(|&prev| {
+            let (_, end) = paths[prev].segments[paths[prev].segments.len() - 1];
+            let (start, _) = path.segments[0];
+            (end.x - start.x).abs() < 0.01 && (end.y - start.y).abs() < 0.01
+        });
+        if continues || reversals(path) > 0 {
+            trace.push(i);
+        }
+    }
+    trace
+}
+
+/// Median of the values, reordering them.
+fn median(values: &mut [f64]) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+    let mid = values.len() / 2;
+    Some(*values.select_nth_unstable_by(mid, f64::total_cmp).1)
+}
+
+/// Find the ECG trace, its rows, and their baselines on a page by geometry.
+///
+/// Stroked paths are clustered by color and width. The trace is the
+/// cluster whose left-to-right paths zig-zag vertically the most; straight
+/// lines in the same style that don't continue it, such as grid lines, are
+/// left out. In drawing order, a new row starts where the trace jumps back to the left. Each
+/// row's baseline is a long horizontal grid line within 2 pt of the
+/// row's median y, or else the median itself, as an ECG spends most of its
+/// time near the isoelectric line.
+pub fn detect_layout(paths: &[DrawingPath], base: &LayoutProfile) -> Result<DetectedLayout> {
+    let mut clusters: BTreeMap<StyleKey, Vec<usize>> = BTreeMap::new();
+    for (i, path) in paths.iter().enumerate() {
+        if !path.segments.is_empty() {
+            clusters.entry(style_key(path)).or_default().push(i);
+        }
+    }
+    let (score, trace) = clusters
+        .into_values()
+        .map(|members| {
+            let trace = trace_paths(paths, &members);
+            let score = trace.iter().map(|&i| reversals(&paths[i])).sum::<usize>();
+            (score, trace)
+        })
+        .max_by_key(|(score, _)| *score)
+        .filter(|(score, _)| *score >= MIN_REVERSALS)
+        .ok_or_else(|| anyhow!("No waveform found: no stroked paths zig-zag like an ECG trace"))?;
+
+    let xs = trace
+        .iter()
+        .flat_map(|&i| &paths[i].segments)
+        .flat_map(|(p1, p2)| [p1.x, p2.x]);
+    let (min_x, max_x) = xs.fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), x| {
+        (lo.min(x), hi.max(x))
+    });
+    let span = max_x - min_x;
+
+    // Split the trace into rows where it jumps back to the left
+    let mut rows: Vec<Vec<usize>> = Vec::new();
+    let mut last_x = f64::NEG_INFINITY;
+    for &i in &trace {
+        let segments = &paths[i].segments;
+        if rows.is_empty() || segments[0].0.x < last_x - span / 4.0 {
+            rows.push(Vec::new());
+        }
+        rows.last_mut().unwrap().push(i);
+        last_x = segments[segments.len() - 1].1.x;
+    }
+
+    // Long horizontal lines in any style are grid lines
+    let grid: Vec<f64> = paths
+        .iter()
+        .flat_map(|p| &p.segments)
+        .filter(|(p1, p2)| (p1.y - p2.y).abs() < 0.01 && (p2.x - p1.x).abs() >= span / 2.0)
+        .map(|(p1, _)| p1.y)
+        .collect();
+    let baselines: Vec<f64> = rows
+        .iter()
+        .filter_map(|row| {
+            let mut ys: Vec<f64> = row
+                .iter()
+                .flat_map(|&i| paths[i].segments.iter().map(|(_, p)| p.y))
+                .collect();
+            let mid = median(&mut ys)?;
+            let snapped = grid
+                .iter()
+                .copied()
+                .filter(|y| (y - mid).abs() <= BASELINE_SNAP)
+                .min_by(|a, b| (a - mid).abs().total_cmp(&(b - mid).abs()));
+            Some(snapped.unwrap_or(mid))
+        })
+        .collect();
+
+    // Sampling rate from the median point spacing at 25 mm/s
+    let mut dxs: Vec<f64> = trace
+        .iter()
+        .flat_map(|&i| &paths[i].segments)
+        .map(|(p1, p2)| p2.x - p1.x)
+        .filter(|&dx| dx > 1e-3)
+        .collect();
+    let dx = median(&mut dxs).ok_or_else(|| anyhow!("No waveform found: trace has no width"))?;
+    let sample_rate = ((PAPER_SPEED_PT_PER_S / dx).round() as usize).max(1);
+
+    // Rows are assigned by nearest baseline, up to half the row spacing
+    let mut sorted = baselines.clone();
+    sorted.sort_by(f64::total_cmp);
+    let max_row_distance = sorted
+        .windows(2)
+        .map(|w| (w[1] - w[0]) / 2.0)
+        .fold(base.max_row_distance, f64::min);
+
+    let first = &paths[trace[0]];
+    let (r, g, b) = first.color;
+    let profile = LayoutProfile {
+        name: "generic".to_string(),
+        sample_rate,
+        rows: baselines.len(),
+        trace: LineStyle {
+            color: [r, g, b],
+            color_tolerance: 1e-3,
+            width_min: first.width - 0.005,
+            width_max: first.width + 0.005,
+            min_segments: 1,
+        },
+        max_row_distance,
+        ..base.clone()
+    };
+    Ok(DetectedLayout {
+        profile,
+        baselines,
+        trace,
+        score,
+    })
+}
@@ -0,0 +1,88 @@
+//! Convert a KardiaMobile 1L ECG from PDF into EDF+.
+//!
+//! The stable entry point is [`convert`] with a [`ConvertOptions`] builder,
+//! or [`convert_bytes`] to convert a PDF in memory.
+//! Pre-write [`Hook`]s can modify the [`EcgRecording`] before it is written.
+//! The processing modules are public for advanced use, but their
+//! signatures may change between minor releases.
+//!
+//! Cargo features: `cli` (the only default) builds the command-line tool,
+//! the `config` module for its `kardia2edf.toml` files, and the `progress`
+//! module for its progress bars; `plot` adds the `plot` module's SVG and
+//! PNG plots, `raster` the `raster` module's tracing of scanned reports,
+//! `serve` the HTTP conversion service, `watch` the drop-folder watcher,
+//! and `zstd` Zstandard-compressed output beside the built-in gzip.
+//! Build with `default-features = false` for just the conversion library.
+//!
+//! The library builds for `wasm32-unknown-unknown` with
+//! `default-features = false`, as the `wasm` package's JavaScript API
+//! does. There is no filesystem or threads there, so the `cache` and
+//! `work_queue` modules are left out, and functions taking paths fail. The
+//! C API is the `capi` package's shared library.
+
+pub mod audit;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod cache;
+pub mod calibration;
+pub mod capabilities;
+pub mod compress;
+#[cfg(feature = "cli")]
+pub mod config;
+pub mod conversion_report;
+pub mod delineate;
+pub mod demo;
+pub mod dicom_write;
+pub mod dsp;
+pub mod ecg_process;
+pub mod edf_read;
+pub mod edf_validate;
+pub mod edf_write;
+pub mod extractor;
+pub mod failure;
+pub mod gdf_write;
+pub mod hrv;
+pub mod inspect;
+pub mod layout_detect;
+pub mod merge;
+pub mod metrics;
+pub mod noise;
+pub mod pdf_extract;
+pub mod pdf_repair;
+pub mod pdf_text;
+#[cfg(feature = "plot")]
+pub mod plot;
+pub mod profile;
+#[cfg(feature = "cli")]
+pub mod progress;
+pub mod provenance;
+pub mod qc;
+#[cfg(feature = "raster")]
+pub mod raster;
+pub mod sample_export;
+pub mod sample_flags;
+#[cfg(feature = "serve")]
+pub mod serve;
+pub mod signal_writer;
+pub mod stats;
+pub mod tal;
+pub mod text_policy;
+pub mod time_shift;
+pub mod verify;
+#[cfg(feature = "watch")]
+pub mod watch;
+pub mod waveform_json;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod work_queue;
+
+mod convert;
+#[cfg(any(feature = "serve", feature = "watch"))]
+mod http;
+mod recording;
+
+pub use compress::Compression;
+pub use convert::{
+    convert, convert_bytes, read_recording, write_recording, ConvertOptions, Hook, Progress,
+    ProgressFn,
+};
+pub use profile::{ExtractionConfig, LayoutProfile};
+pub use recording::EcgRecording;
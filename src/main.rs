@@ -1,59 +1,1056 @@
-mod ecg_process;
-mod edf_write;
-mod pdf_extract;
-
-use anyhow::{anyhow, Result};
-
-fn main() -> Result<()> {
-    let pdf_path = "kardiamobile-1l-ecg.pdf";
-    let edf_path = "kardiamobile-1l-ecg.edf";
-
-    // Calibration: 1 mV = 28.346 PDF points (10mm at 2.8346 pt/mm)
-    let cal_pt_per_mv = 28.346_f64;
-    let sample_rate: usize = 300;
-
-    // Load PDF
-    let doc = lopdf::Document::load(pdf_path)?;
-    let pages = doc.get_pages();
-    let &page_id = pages.get(&2).ok_or_else(|| anyhow!("Page 2 not found"))?;
-
-    // Get page height for coordinate transformation
-    let page_height = pdf_extract::get_page_height(&doc, page_id)?;
-
-    // Extract drawing paths from page 2
-    let paths = pdf_extract::extract_paths(&doc, page_id, page_height)?;
-
-    // Find baselines
-    let baselines = ecg_process::extract_baselines(&paths)?;
-    println!(
-        "Baselines (PDF y-coordinates): {:?}",
-        baselines
-            .iter()
-            .map(|b| format!("{:.1}", b))
-            .collect::<Vec<_>>()
-    );
+use anyhow::Result;
+use clap::builder::PossibleValuesParser;
+use clap::{ArgAction, Args, Parser, Subcommand};
+
+use kardiamobile_1l_ecg_convert_pdf_to_edf::config::Config;
+use kardiamobile_1l_ecg_convert_pdf_to_edf::dicom_write::DicomStudy;
+use kardiamobile_1l_ecg_convert_pdf_to_edf::ecg_process::Detrend;
+use kardiamobile_1l_ecg_convert_pdf_to_edf::extractor::{self, TraceChoice};
+use kardiamobile_1l_ecg_convert_pdf_to_edf::failure::Failure;
+use kardiamobile_1l_ecg_convert_pdf_to_edf::pdf_text::DateOrder;
+#[cfg(feature = "plot")]
+use kardiamobile_1l_ecg_convert_pdf_to_edf::plot;
+use kardiamobile_1l_ecg_convert_pdf_to_edf::progress::{self, Suspended};
+#[cfg(feature = "serve")]
+use kardiamobile_1l_ecg_convert_pdf_to_edf::serve;
+use kardiamobile_1l_ecg_convert_pdf_to_edf::signal_writer::{OutputFormat, Quantization, Unit};
+use kardiamobile_1l_ecg_convert_pdf_to_edf::text_policy::TextPolicy;
+#[cfg(feature = "watch")]
+use kardiamobile_1l_ecg_convert_pdf_to_edf::watch;
+#[cfg(feature = "watch")]
+use kardiamobile_1l_ecg_convert_pdf_to_edf::work_queue::RetryPolicy;
+use kardiamobile_1l_ecg_convert_pdf_to_edf::{calibration, capabilities};
+use kardiamobile_1l_ecg_convert_pdf_to_edf::{
+    convert, demo, edf_validate, inspect, merge, stats, write_recording, Compression,
+    ConvertOptions, ExtractionConfig, LayoutProfile,
+};
+use std::io::IsTerminal;
+use std::process::ExitCode;
+#[cfg(any(feature = "serve", feature = "watch"))]
+use std::time::Duration;
+use tracing::{info, Level};
+use tracing_subscriber::fmt::writer::MakeWriterExt;
+
+/// Exit status for an invalid command line, as sysexits.h EX_USAGE, so it
+/// can't be mistaken for a failure class (see [`Failure::exit_code`]).
+const EXIT_USAGE: u8 = 64;
+
+/// Convert a KardiaMobile 1L ECG from PDF into EDF+.
+#[derive(Parser)]
+#[command(
+    version,
+    args_conflicts_with_subcommands = true,
+    after_help = "Examples:
+  kardiamobile-1l-ecg-convert-pdf-to-edf report.pdf report.edf
+  kardiamobile-1l-ecg-convert-pdf-to-edf convert report.pdf report.bdf --bdf --csv report.csv
+  kardiamobile-1l-ecg-convert-pdf-to-edf convert watch.pdf watch.edf --profile withings
+  kardiamobile-1l-ecg-convert-pdf-to-edf convert report.pdf session.edf --append
+  kardiamobile-1l-ecg-convert-pdf-to-edf inspect report.edf
+  kardiamobile-1l-ecg-convert-pdf-to-edf --list-formats
+
+Exit status:
+  0   success
+  1   other failure
+  2   input not found or unreadable
+  3   no ECG waveform found in the PDF
+  4   quality score below --qc-fail-below
+  5   output can't be written
+  6   input isn't a readable PDF
+  64  invalid command line"
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// List the file formats this build reads and writes, with what each
+    /// can hold, and exit
+    #[arg(long)]
+    list_formats: bool,
+
+    /// List the built-in extractors and their layout profiles, and exit
+    #[arg(long)]
+    list_profiles: bool,
+
+    /// Log more: -v adds the details of each step, such as the thresholds
+    /// used; -vv adds trace events
+    #[arg(short, long, action = ArgAction::Count, global = true)]
+    verbose: u8,
+
+    /// Log only warnings and errors
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Log as JSON lines on stderr, with a timestamp, level, and source
+    /// module, for batch log collection
+    #[arg(long, global = true)]
+    log_json: bool,
+
+    #[command(flatten)]
+    convert: ConvertArgs,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Convert a PDF into EDF+ (the default when no subcommand is given)
+    Convert(Box<ConvertArgs>),
+
+    /// Print the header of an EDF/EDF+ file, or list the drawing paths of
+    /// a report PDF and what the extraction makes of each
+    Inspect(InspectArgs),
+
+    /// Check an EDF/EDF+ or BDF/BDF+ file against the specification
+    Validate(ValidateArgs),
+
+    /// Print each signal's amplitude histogram, noise floor, SNR, and
+    /// clipping in an EDF/EDF+ or BDF/BDF+ file
+    Stats(StatsArgs),
+
+    /// Merge recordings (PDFs or EDF files) into one EDF+D session file,
+    /// in order of their start times
+    Merge(MergeArgs),
+
+    /// Write a synthetic 30-second lead I recording, needing no input PDF
+    Demo(DemoArgs),
+
+    /// Run an HTTP conversion service with /healthz and /metrics endpoints
+    #[cfg(feature = "serve")]
+    Serve(ServeArgs),
+
+    /// Watch a drop folder and convert each PDF that appears in it
+    #[cfg(feature = "watch")]
+    Watch(WatchArgs),
+}
+
+#[derive(Args)]
+struct ConvertArgs {
+    /// Input Kardia ECG report PDF
+    #[arg(default_value = "kardiamobile-1l-ecg.pdf")]
+    pdf: String,
+
+    /// Output EDF+ file
+    #[arg(default_value = "kardiamobile-1l-ecg.edf")]
+    edf: String,
+
+    /// Config file of conversion defaults, which the other options
+    /// override (default: kardia2edf.toml in the current directory, if any)
+    #[arg(long, value_name = "FILE")]
+    config: Option<String>,
+
+    /// Append to the output as a new segment of an EDF+D session file
+    /// (created if missing) instead of overwriting it
+    #[arg(long)]
+    append: bool,
+
+    /// EDF+ patient identification field, overriding the name, sex, and
+    /// birthdate read from the PDF (e.g. "X F 02-AUG-1951 Jane_Doe")
+    #[arg(long)]
+    patient: Option<String>,
+
+    /// Order of the day and month in the report's numeric dates; auto uses
+    /// the layout profile's, or month first if the report prints AM/PM
+    /// times, otherwise day first
+    #[arg(long, value_name = "ORDER", default_value = "auto",
+          value_parser = ["auto", "day-first", "month-first"])]
+    date_order: String,
+
+    /// EDF+ recording identification field, after the start date unless it
+    /// begins with "Startdate" (e.g. "X X Study_42"); GDF recording ID
+    #[arg(long, value_name = "TEXT")]
+    recording_id: Option<String>,
+
+    /// Transducer type of the ECG signal
+    #[arg(long, value_name = "TEXT")]
+    transducer: Option<String>,
+
+    /// Prefiltering of the ECG signal, instead of the filter printed on the report
+    #[arg(long, value_name = "TEXT")]
+    prefilter: Option<String>,
+
+    /// Label of the ECG signal (16 characters), instead of "EKG I"
+    #[arg(long, value_name = "TEXT")]
+    label: Option<String>,
+
+    /// Shift the start date/time and birthdate back by a random number of
+    /// days per patient, kept in this JSON mapping file (created if
+    /// missing), to temporally de-identify research data
+    #[arg(long, value_name = "MAPPING")]
+    time_shift: Option<String>,
+
+    /// Replace the patient's name, sex, and birthdate with "X" in the EDF+
+    /// header and every sidecar output; a --patient value is kept as a
+    /// pseudonym
+    #[arg(long)]
+    anonymize: bool,
+
+    /// Write the heart-rate/HRV summary (RR intervals, SDNN, RMSSD) to a JSON file
+    #[arg(long, value_name = "FILE")]
+    hrv_json: Option<String>,
+
+    /// Also write the waveform with its calibration and report metadata
+    /// (determination, heart rate, duration, device, firmware) to a JSON file
+    #[arg(long, value_name = "FILE")]
+    json: Option<String>,
+
+    /// Plot lead I on ECG paper (25 mm/s, 10 mm/mV) as an SVG file, or
+    /// PNG if FILE ends in .png, to check the extraction without an EDF viewer
+    #[cfg(feature = "plot")]
+    #[arg(long, value_name = "FILE")]
+    plot: Option<String>,
+
+    /// Resolution of a PNG plot
+    #[cfg(feature = "plot")]
+    #[arg(long, value_name = "DPI", default_value_t = plot::DEFAULT_DPI, requires = "plot")]
+    plot_dpi: f64,
+
+    /// Split the plot into this many rows instead of 10-second rows
+    #[cfg(feature = "plot")]
+    #[arg(long, value_name = "N", requires = "plot",
+          value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    plot_rows: Option<usize>,
+
+    /// Also write a DICOM General ECG waveform file, e.g. for PACS
+    #[arg(long, value_name = "FILE")]
+    dicom: Option<String>,
+
+    /// Also write the samples as CSV, with a time column
+    #[arg(long, value_name = "FILE")]
+    csv: Option<String>,
+
+    /// Also write the samples as a NumPy .npy array
+    #[arg(long, value_name = "FILE")]
+    npy: Option<String>,
+
+    /// Write the CSV and NumPy exports as one file per signal, e.g.
+    /// ecg_EKG_I.csv, for scripts that expect single-lead files
+    #[arg(long)]
+    split_channels: bool,
+
+    /// Write each strip row's trace points (x, y, source path) as CSV files
+    /// to this directory, to see which strokes landed in which row
+    #[arg(long, value_name = "DIR")]
+    debug_rows: Option<String>,
+
+    /// Draw the extracted trace paths, colored by row, and the final samples
+    /// on the same axes as an SVG file, to spot extraction errors
+    #[arg(long, value_name = "FILE")]
+    debug_overlay: Option<String>,
+
+    /// Physical range of the signal with this label, as LABEL=MIN:MAX,
+    /// e.g. "EKG I=-5:5"; repeat for each lead (default: each signal's
+    /// own sample range)
+    #[arg(long, value_name = "LABEL=MIN:MAX", value_parser = parse_phys_range)]
+    phys_range: Vec<(String, f64, f64)>,
+
+    /// Write the ECG with a fixed physical range of ± this many mV, so all
+    /// files share identical scaling (±5 mV is 0.1526 µV per bit in EDF+)
+    #[arg(long, value_name = "MV", conflicts_with = "lsb")]
+    fixed_range: Option<f64>,
+
+    /// Write the ECG with a fixed resolution of this many µV per bit, with
+    /// 0 mV at digital 0
+    #[arg(long, value_name = "UV")]
+    lsb: Option<f64>,
+
+    /// Skip this many seconds of the recording, e.g. a noisy start; the
+    /// start time in the header moves accordingly
+    #[arg(long, value_name = "SEC")]
+    start: Option<f64>,
+
+    /// Write only this many seconds of the recording, from --start
+    #[arg(long, value_name = "SEC")]
+    duration: Option<f64>,
+
+    /// Split the output into files of this many seconds, named with a
+    /// _segment_<n> suffix, for tools that cap file duration
+    #[arg(long, value_name = "SEC", conflicts_with = "append")]
+    segment: Option<f64>,
+
+    /// Fix every header field that varies between runs, for byte-identical
+    /// output: times come from SOURCE_DATE_EPOCH (or 0), DICOM UIDs from
+    /// the input's hash
+    #[arg(long)]
+    deterministic: bool,
+
+    /// Physical dimension of the ECG signals in every output: millivolts
+    /// (mV) or microvolts (uV)
+    #[arg(long, value_name = "UNIT", default_value = "mV",
+          value_parser = ["mV", "uV"])]
+    unit: String,
+
+    /// Add a "Sample flags" channel: 0 measured, 1 interpolated across a
+    /// gap in a row, 2 padding at the end of the last data record, 3
+    /// missing, 4 clipped, 5 lead-off, 6 despiked
+    #[arg(long)]
+    flags_channel: bool,
+
+    /// Add a "Heart rate" channel: the beat-to-beat heart rate in bpm from
+    /// the detected R-peaks, at 4 Hz, for sleep and HRV analysis tools
+    #[arg(long)]
+    hr_channel: bool,
+
+    /// Write each sample's flag (measured, interpolated, padded, missing,
+    /// clipped, lead_off, despiked) as CSV to this file
+    #[arg(long, value_name = "FILE")]
+    flags_csv: Option<String>,
+
+    /// DICOM patient ID (defaults to the EDF+ patient code)
+    #[arg(long, help_heading = "DICOM")]
+    patient_id: Option<String>,
+
+    /// DICOM accession number
+    #[arg(long, help_heading = "DICOM")]
+    accession_number: Option<String>,
+
+    /// DICOM study ID
+    #[arg(long, help_heading = "DICOM")]
+    study_id: Option<String>,
+
+    /// DICOM referring physician, as "Family^Given"
+    #[arg(long, help_heading = "DICOM")]
+    referring_physician: Option<String>,
+
+    /// DICOM study description
+    #[arg(long, help_heading = "DICOM")]
+    study_description: Option<String>,
+
+    /// Write 24-bit BDF+ instead of 16-bit EDF+, for finer quantization
+    #[arg(long)]
+    bdf: bool,
+
+    /// Write GDF v2 instead of EDF+, for BioSig and SigViewer
+    #[arg(long, conflicts_with_all = ["bdf", "append"])]
+    gdf: bool,
+
+    /// Write plain EDF instead of EDF+, for legacy software: the ECG
+    /// signal only, without the annotation signal
+    #[arg(long, conflicts_with_all = ["bdf", "gdf", "append"])]
+    edf_classic: bool,
+
+    /// Non-ASCII header text: transliterate to ASCII, keep as latin1
+    /// bytes, or reject (strict)
+    #[arg(long, value_name = "POLICY", default_value = "ascii",
+          value_parser = ["ascii", "latin1", "strict"])]
+    header_text: String,
+
+    /// Data record duration in seconds, e.g. 0.2; moved to the nearest
+    /// duration holding whole samples of every signal if needed
+    #[arg(long, value_name = "SECONDS")]
+    record_duration: Option<f64>,
+
+    /// Strip to read from reports with both: the enhanced-filter strip,
+    /// the original unfiltered one, or both as two signals
+    #[arg(long, value_name = "TRACE", default_value = "enhanced",
+          value_parser = ["enhanced", "original", "both"])]
+    trace: String,
+
+    /// Remove each strip row's offset before joining the rows: its median
+    /// (per-row) or mean (per-row-mean)
+    #[arg(long, value_name = "MODE", default_value = "none",
+          value_parser = ["none", "per-row", "per-row-mean"])]
+    detrend: String,
+
+    /// Join strip rows continuously: trim samples repeated from the end of
+    /// the previous row and remove the step at each row join
+    #[arg(long)]
+    continuous_rows: bool,
+
+    /// Interpolate gaps in strip rows up to this many seconds long; longer
+    /// gaps are left out of EDF+/BDF+ output as a discontinuous file
+    #[arg(long, value_name = "SECONDS")]
+    max_interpolated_gap: Option<f64>,
+
+    /// Write the strip rows that extract when others fail, leaving the
+    /// failed rows out as a gap, instead of failing the conversion
+    #[arg(long)]
+    allow_partial: bool,
+
+    /// Write the decision trail (profile, calibration source, thresholds,
+    /// discarded paths, warnings) to a .log named like the output
+    #[arg(long)]
+    audit_log: bool,
+
+    /// Write extraction statistics (paths, rows, gaps, calibration, voltage
+    /// range, heart rate) and warnings to a .report.json named like the
+    /// output
+    #[arg(long)]
+    report_json: bool,
+
+    /// Fail without writing when the quality score, from 0 (broken) to 1
+    /// (clean), is below this
+    #[arg(long, value_name = "SCORE")]
+    qc_fail_below: Option<f64>,
+
+    /// Flip lead I when most of its QRS complexes point down, as when the
+    /// device was held backwards
+    #[arg(long)]
+    fix_inversion: bool,
+
+    /// Annotate each beat's P onset and offset, QRS onset and offset, and
+    /// T end, and print the PR, QRS, QT, and QTc intervals
+    #[arg(long)]
+    delineate: bool,
+
+    /// Annotate pacemaker spikes, narrow high-slew jumps, as "Pacing"
+    #[arg(long)]
+    detect_pacing: bool,
+
+    /// Replace single-sample spikes at PDF subpath joins with the midpoint
+    /// of their neighbours
+    #[arg(long)]
+    despike: bool,
+
+    /// Remove baseline wander below this frequency (e.g. 0.5) with a
+    /// high-pass filter
+    #[arg(long, value_name = "HZ")]
+    highpass: Option<f64>,
+
+    /// Smooth the digitized trace above this frequency (e.g. 40) with a
+    /// low-pass filter
+    #[arg(long, value_name = "HZ")]
+    lowpass: Option<f64>,
 
-    // Extract waveform rows
-    let rows = ecg_process::extract_ecg_waveform_rows(&paths, &baselines);
+    /// Remove mains interference with a notch filter at 50 or 60 Hz
+    /// (default: off)
+    #[arg(long, value_name = "HZ",
+          value_parser = PossibleValuesParser::new(["50", "60", "off"]))]
+    notch: Option<String>,
 
-    // Concatenate all rows into a single voltage signal
-    let signal = ecg_process::concatenate_to_signal(&rows, &baselines, cal_pt_per_mv)?;
+    /// Re-read the written file and fail unless its samples match the
+    /// signal within one quantization step
+    #[arg(long)]
+    verify: bool,
 
-    let duration_sec = signal.len() as f64 / sample_rate as f64;
-    let min_v = signal.iter().cloned().fold(f64::INFINITY, f64::min);
-    let max_v = signal.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    /// Write the output's SHA-256 to a .sha256 sidecar named like it, for
+    /// sha256sum --check
+    #[arg(long)]
+    checksum: bool,
 
-    println!("\nTotal samples: {}", signal.len());
-    println!("Duration: {:.2} seconds", duration_sec);
-    println!("Sampling rate: {} Hz", sample_rate);
-    println!("Voltage range: [{:.3}, {:.3}] mV", min_v, max_v);
+    /// Compress the output and its CSV and JSON sidecars with gzip (gz) or
+    /// Zstandard (zst), adding the extension to their names
+    #[arg(long, value_name = "FORMAT", conflicts_with = "append",
+          value_parser = ["gz", "zst"])]
+    compress: Option<String>,
 
-    // Write EDF+ file
-    edf_write::write_edf(edf_path, &signal, sample_rate)?;
+    /// Calibration in millimeters per millivolt, overriding the scale
+    /// printed on or measured from the report
+    #[arg(long, value_name = "MM")]
+    mm_per_mv: Option<f64>,
+
+    /// JSON layout profile describing the strip page of an unusual PDF
+    /// (see profiles/kardiamobile-1l.json for the fields and defaults)
+    #[arg(long, value_name = "FILE")]
+    layout_profile: Option<String>,
+
+    /// Stroke color of the trace paths, overriding the layout profile's:
+    /// R,G,B components from 0 to 1 (e.g. 0,0,0) or hex (e.g. #000000)
+    #[arg(long, value_name = "COLOR", value_parser = parse_color)]
+    trace_color: Option<[f64; 3]>,
+
+    /// Stroke width range of the trace paths in points, overriding the
+    /// layout profile's, e.g. 0.35:0.45
+    #[arg(long, value_name = "MIN:MAX", value_parser = parse_width_range)]
+    trace_width: Option<(f64, f64)>,
+
+    /// Fewest line segments a trace path has, overriding the layout profile's
+    #[arg(long, value_name = "N")]
+    min_segments: Option<usize>,
+
+    /// Read the trace from thin filled shapes along their centerlines, for
+    /// PDFs that draw the waveform as a filled polygon instead of a line
+    #[arg(long)]
+    filled_trace: bool,
+
+    /// Extractor for the PDF's vendor, e.g. "kardiamobile-1l" (detected
+    /// from the PDF text if omitted)
+    #[arg(long, value_name = "NAME")]
+    extractor: Option<String>,
+
+    /// Built-in profile: a vendor's extractor and layout, or "generic" to
+    /// find the trace and rows by path geometry in any vector ECG PDF
+    #[arg(long, value_name = "NAME", conflicts_with_all = ["extractor", "layout_profile"],
+          value_parser = PossibleValuesParser::new(extractor::EXTRACTORS.iter().map(|e| e.name())))]
+    profile: Option<String>,
+}
+
+#[derive(Args)]
+struct ValidateArgs {
+    /// EDF/EDF+ file to check
+    file: String,
+}
+
+#[derive(Args)]
+struct StatsArgs {
+    /// Converted EDF/EDF+ file
+    file: String,
+}
+
+#[derive(Args)]
+struct InspectArgs {
+    /// EDF/EDF+ file to inspect, or a report PDF to list its drawing paths
+    file: String,
+
+    /// Show each signal's digital-to-physical gain and offset, and the
+    /// physical range of its samples
+    #[arg(long)]
+    signal: bool,
+
+    /// Print a PDF's drawing paths as JSON
+    #[arg(long)]
+    json: bool,
+
+    /// Built-in profile whose filters classify a PDF's paths (detected from
+    /// the PDF text if omitted)
+    #[arg(long, value_name = "NAME",
+          value_parser = PossibleValuesParser::new(extractor::EXTRACTORS.iter().map(|e| e.name())))]
+    profile: Option<String>,
+}
+
+#[derive(Args)]
+struct MergeArgs {
+    /// Kardia report PDFs and EDF/EDF+ files to merge
+    #[arg(required = true)]
+    inputs: Vec<String>,
+
+    /// Output EDF+D session file, replaced if it exists
+    #[arg(short, long)]
+    output: String,
+
+    /// Write a 24-bit BDF+D session file
+    #[arg(long)]
+    bdf: bool,
+
+    /// Compress the session file with gzip (gz) or Zstandard (zst), adding
+    /// the extension to its name
+    #[arg(long, value_name = "FORMAT", value_parser = ["gz", "zst"])]
+    compress: Option<String>,
+
+    /// Merge recordings whose patient fields differ, under the first
+    /// recording's patient, instead of refusing to
+    #[arg(long)]
+    mixed_patients: bool,
+}
+
+#[derive(Args)]
+struct DemoArgs {
+    /// Output EDF+ file
+    #[arg(short, long, default_value = "demo.edf")]
+    output: String,
+
+    /// Also write the recording as a Kardia-style report PDF, which
+    /// converts back to the same recording
+    #[arg(long, value_name = "FILE")]
+    pdf: Option<String>,
+}
+
+#[cfg(feature = "serve")]
+#[derive(Args)]
+struct ServeArgs {
+    /// Address to listen on
+    #[arg(long, default_value = "127.0.0.1:8080")]
+    listen: String,
+
+    /// Most conversions run at the same time; further uploads get 503
+    /// (default: the number of CPUs)
+    #[arg(long, value_name = "N",
+          value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    max_conversions: Option<usize>,
+
+    /// Most connections handled at the same time, conversions included;
+    /// further connections get 503
+    #[arg(long, value_name = "N", default_value_t = 64,
+          value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    max_connections: usize,
+
+    /// Seconds to wait for the next bytes of a request, or for the client
+    /// to read the response, before dropping the connection
+    #[arg(long, value_name = "SECONDS", default_value_t = 30,
+          value_parser = clap::builder::RangedU64ValueParser::<u64>::new().range(1..))]
+    timeout: u64,
+
+    /// Config file of conversion defaults (default: kardia2edf.toml in the
+    /// current directory, if any)
+    #[arg(long, value_name = "FILE")]
+    config: Option<String>,
+}
+
+#[cfg(feature = "watch")]
+#[derive(Args)]
+struct WatchArgs {
+    /// Directory to watch for PDFs
+    dir: String,
+
+    /// Config file of conversion defaults (default: kardia2edf.toml in the
+    /// current directory, if any)
+    #[arg(long, value_name = "FILE")]
+    config: Option<String>,
+
+    /// Directory for the EDF+ files (defaults to the watched directory)
+    #[arg(long, value_name = "DIR")]
+    out_dir: Option<String>,
+
+    /// Number of conversions run at the same time
+    #[arg(long, default_value_t = 2)]
+    concurrency: usize,
+
+    /// Number of PDFs waiting for a worker before scanning pauses
+    #[arg(long, default_value_t = 16)]
+    queue_size: usize,
+
+    /// Retries for transient I/O errors, with exponential backoff
+    #[arg(long, default_value_t = 3)]
+    retries: u32,
+
+    /// Delay before the first retry, in milliseconds; doubled for each retry
+    #[arg(long, value_name = "MS", default_value_t = 500)]
+    backoff: u64,
+
+    /// Longest time between directory scans, in milliseconds; changes in
+    /// the directory start one sooner
+    #[arg(long, value_name = "MS", default_value_t = 2000)]
+    poll_interval: u64,
+
+    /// Move each PDF converted into this subfolder of the watched directory
+    #[arg(long, value_name = "SUBDIR", num_args = 0..=1, default_missing_value = "archive")]
+    archive: Option<String>,
+
+    /// Convert PDFs already converted with the same content, too
+    #[arg(long)]
+    force: bool,
+
+    /// Serve /healthz and Prometheus /metrics for the conversions on this
+    /// address, e.g. 127.0.0.1:9090
+    #[arg(long, value_name = "ADDR")]
+    metrics_listen: Option<String>,
+}
+
+fn main() -> ExitCode {
+    let cli = match Cli::try_parse() {
+        Ok(cli) => cli,
+        Err(e) => {
+            let _ = e.print();
+            return if e.use_stderr() {
+                ExitCode::from(EXIT_USAGE)
+            } else {
+                ExitCode::SUCCESS
+            };
+        }
+    };
+    init_logging(cli.verbose, cli.quiet, cli.log_json);
+    match run(cli) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            progress::clear();
+            eprintln!("Error: {:?}", e);
+            ExitCode::from(Failure::of(&e).exit_code())
+        }
+    }
+}
+
+fn run(cli: Cli) -> Result<()> {
+    if cli.list_formats || cli.list_profiles {
+        if cli.list_formats {
+            capabilities::list_formats(&mut std::io::stdout().lock())?;
+        }
+        if cli.list_profiles {
+            capabilities::list_profiles(&mut std::io::stdout().lock())?;
+        }
+        return Ok(());
+    }
+
+    match cli.command {
+        Some(Command::Convert(args)) => run_convert(*args),
+        Some(Command::Inspect(args)) => run_inspect(args),
+        Some(Command::Validate(args)) => {
+            edf_validate::print_validation(&mut std::io::stdout().lock(), &args.file)
+        }
+        Some(Command::Stats(args)) => stats::print_stats(&mut std::io::stdout().lock(), &args.file),
+        Some(Command::Merge(args)) => run_merge(args),
+        Some(Command::Demo(args)) => run_demo(args),
+        #[cfg(feature = "serve")]
+        Some(Command::Serve(args)) => run_serve(args),
+        #[cfg(feature = "watch")]
+        Some(Command::Watch(args)) => run_watch(args),
+        None => run_convert(cli.convert),
+    }
+}
+
+fn run_inspect(args: InspectArgs) -> Result<()> {
+    let is_pdf = std::path::Path::new(&args.file)
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("pdf"));
+    if !is_pdf {
+        return inspect::inspect_edf(&mut std::io::stdout().lock(), &args.file, args.signal);
+    }
+    let mut options = ConvertOptions::new();
+    if let Some(name) = args.profile {
+        options = options.extractor(name);
+    }
+    inspect::inspect_pdf(
+        &mut std::io::stdout().lock(),
+        &args.file,
+        &options,
+        args.json,
+    )
+}
+
+/// Parse a `--phys-range` value, LABEL=MIN:MAX.
+fn parse_phys_range(value: &str) -> std::result::Result<(String, f64, f64), String> {
+    let (label, range) = value
+        .rsplit_once('=')
+        .ok_or("expected LABEL=MIN:MAX, e.g. \"EKG I=-5:5\"")?;
+    let (min, max) = range.split_once(':').ok_or("expected MIN:MAX after '='")?;
+    let parse = |v: &str| {
+        v.trim()
+            .parse::<f64>()
+            .map_err(|e| format!("{:?}: {}", v, e))
+    };
+    Ok((label.to_string(), parse(min)?, parse(max)?))
+}
+
+/// Parse an RGB color as "R,G,B" components from 0 to 1, or "#RRGGBB".
+fn parse_color(value: &str) -> std::result::Result<[f64; 3], String> {
+    let mut color = [0.0; 3];
+    if let Some(hex) = value.strip_prefix('#') {
+        if hex.len() != 6 || !hex.is_ascii() {
+            return Err(format!("expected #RRGGBB, got {:?}", value));
+        }
+        for (i, c) in color.iter_mut().enumerate() {
+            let byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16)
+                .map_err(|e| format!("{:?}: {}", value, e))?;
+            *c = byte as f64 / 255.0;
+        }
+        return Ok(color);
+    }
+    let parts: Vec<&str> = value.split(',').collect();
+    if parts.len() != 3 {
+        return Err("expected R,G,B from 0 to 1, e.g. 0,0,0, or #RRGGBB".to_string());
+    }
+    for (c, part) in color.iter_mut().zip(parts) {
+        *c = part
+            .trim()
+            .parse()
+            .map_err(|e| format!("{:?}: {}", part, e))?;
+    }
+    Ok(color)
+}
+
+/// Parse a stroke width range as "MIN:MAX".
+fn parse_width_range(value: &str) -> std::result::Result<(f64, f64), String> {
+    let (min, max) = value
+        .split_once(':')
+        .ok_or("expected MIN:MAX, e.g. 0.35:0.45")?;
+    let parse = |v: &str| {
+        v.trim()
+            .parse::<f64>()
+            .map_err(|e| format!("{:?}: {}", v, e))
+    };
+    Ok((parse(min)?, parse(max)?))
+}
+
+/// Log to the console at the level of the -v and -q flags: progress lines
+/// to stdout and warnings to stderr, as plain text, or every event to
+/// stderr as JSON lines. Progress bars are drawn only on a terminal, and
+/// not with -q or JSON logs.
+fn init_logging(verbose: u8, quiet: bool, json: bool) {
+    let level = match (quiet, verbose) {
+        (true, _) => Level::WARN,
+        (false, 0) => Level::INFO,
+        (false, 1) => Level::DEBUG,
+        (false, _) => Level::TRACE,
+    };
+    let logger = tracing_subscriber::fmt().with_max_level(level);
+    if json {
+        logger.json().with_writer(std::io::stderr).init();
+    } else {
+        logger
+            .without_time()
+            .with_level(false)
+            .with_target(false)
+            .with_writer(
+                (|| Suspended(std::io::stderr()))
+                    .with_max_level(Level::WARN)
+                    .or_else(|| Suspended(std::io::stdout())),
+            )
+            .init();
+    }
+    if quiet || json || !std::io::stdout().is_terminal() {
+        progress::hide();
+    }
+}
+
+/// Conversion options with the defaults from the config file, if any.
+fn config_options(path: Option<&str>) -> Result<ConvertOptions> {
+    match Config::find(path)? {
+        Some(config) => config.apply(ConvertOptions::new()),
+        None => Ok(ConvertOptions::new()),
+    }
+}
+
+fn run_convert(args: ConvertArgs) -> Result<()> {
+    let mut options = config_options(args.config.as_deref())?
+        .append(args.append)
+        .on_progress(progress::show);
+    if args.bdf {
+        options = options.format(OutputFormat::Bdf);
+    }
+    if args.gdf {
+        options = options.format(OutputFormat::Gdf);
+    }
+    if args.edf_classic {
+        options = options.format(OutputFormat::EdfClassic);
+    }
+    options = options.date_order(match args.date_order.as_str() {
+        "day-first" => DateOrder::DayFirst,
+        "month-first" => DateOrder::MonthFirst,
+        _ => DateOrder::Auto,
+    });
+    options = options.text_policy(match args.header_text.as_str() {
+        "latin1" => TextPolicy::Latin1,
+        "strict" => TextPolicy::Strict,
+        _ => TextPolicy::Ascii,
+    });
+    if let Some(seconds) = args.record_duration {
+        options = options.record_duration(seconds);
+    }
+    let mut header = options.header.clone();
+    if let Some(patient) = &args.patient {
+        header = header.patient(patient);
+    }
+    if let Some(id) = &args.recording_id {
+        header = header.recording_id(id);
+    }
+    if let Some(transducer) = &args.transducer {
+        header = header.transducer(transducer);
+    }
+    if let Some(prefilter) = &args.prefilter {
+        header = header.prefilter(prefilter);
+    }
+    if let Some(label) = &args.label {
+        header = header.label(label);
+    }
+    options = options.header(header);
+    if let Some(path) = args.time_shift {
+        options = options.time_shift(path);
+    }
+    options = options.anonymize(args.anonymize);
+    if let Some(path) = args.hrv_json {
+        options = options.hrv_json(path);
+    }
+    if let Some(path) = args.json {
+        options = options.json(path);
+    }
+    #[cfg(feature = "plot")]
+    if let Some(path) = args.plot {
+        options = options.plot(path).plot_dpi(args.plot_dpi);
+    }
+    #[cfg(feature = "plot")]
+    if let Some(rows) = args.plot_rows {
+        options = options.plot_rows(rows);
+    }
+    if let Some(path) = args.dicom {
+        let study = options.dicom_study.clone();
+        options = options.dicom(path).dicom_study(DicomStudy {
+            patient_id: args.patient_id.or(study.patient_id),
+            accession_number: args.accession_number.or(study.accession_number),
+            study_id: args.study_id.or(study.study_id),
+            referring_physician: args.referring_physician.or(study.referring_physician),
+            study_description: args.study_description.or(study.study_description),
+            uid_seed: None,
+        });
+    }
+    if let Some(path) = args.csv {
+        options = options.csv(path);
+    }
+    if let Some(path) = args.npy {
+        options = options.npy(path);
+    }
+    options = options.split_channels(args.split_channels);
+    if let Some(dir) = args.debug_rows {
+        options = options.debug_rows(dir);
+    }
+    if let Some(path) = args.debug_overlay {
+        options = options.debug_overlay(path);
+    }
+    options = options.trace(match args.trace.as_str() {
+        "original" => TraceChoice::Original,
+        "both" => TraceChoice::Both,
+        _ => TraceChoice::Enhanced,
+    });
+    options = options.detrend(match args.detrend.as_str() {
+        "per-row" => Detrend::RowMedian,
+        "per-row-mean" => Detrend::RowMean,
+        _ => Detrend::None,
+    });
+    options = options.continuous_rows(args.continuous_rows);
+    options = options.allow_partial(args.allow_partial);
+    options = options.audit_log(args.audit_log);
+    options = options.report_json(args.report_json);
+    if let Some(score) = args.qc_fail_below {
+        options = options.qc_fail_below(score);
+    }
+    options = options.fix_inversion(args.fix_inversion);
+    options = options.delineate(args.delineate);
+    options = options.detect_pacing(args.detect_pacing);
+    options = options.despike(args.despike);
+    if let Some(hz) = args.highpass {
+        options = options.highpass(hz);
+    }
+    if let Some(hz) = args.lowpass {
+        options = options.lowpass(hz);
+    }
+    if let Some(notch) = args.notch {
+        options.notch = notch.parse().ok();
+    }
+    options = options.verify(args.verify);
+    options = options.checksum(args.checksum);
+    if let Some(extension) = &args.compress {
+        options = options.compress(compression(extension));
+    }
+    if let Some(seconds) = args.max_interpolated_gap {
+        options = options.max_interpolated_gap(seconds);
+    }
+    options = options.flags_channel(args.flags_channel);
+    options = options.heart_rate_channel(args.hr_channel);
+    for (label, min, max) in args.phys_range {
+        options = options.phys_range(label, min, max);
+    }
+    if args.start.is_some() || args.duration.is_some() {
+        options = options.window(args.start.unwrap_or(0.0), args.duration);
+    }
+    if let Some(seconds) = args.segment {
+        options = options.segment(seconds);
+    }
+    options = options.deterministic(args.deterministic);
+    if args.unit == "uV" {
+        options = options.unit(Unit::Microvolt);
+    }
+    if let Some(mv) = args.fixed_range {
+        options = options.quantization(Quantization::Range(mv));
+    }
+    if let Some(uv) = args.lsb {
+        options = options.quantization(Quantization::Lsb(uv));
+    }
+    if let Some(path) = args.flags_csv {
+        options = options.flags_csv(path);
+    }
+    if let Some(name) = args.extractor.or(args.profile) {
+        options = options.extractor(name);
+    }
+    if let Some(path) = args.layout_profile {
+        options = options.profile(LayoutProfile::from_file(&path)?);
+    }
+    options = options.extraction(ExtractionConfig {
+        trace_color: args.trace_color,
+        trace_width: args.trace_width,
+        min_segments: args.min_segments,
+        filled_trace: args.filled_trace.then_some(true),
+    });
+    if let Some(mm) = args.mm_per_mv {
+        if !(mm.is_finite() && mm > 0.0) {
+            anyhow::bail!("--mm-per-mv must be positive, got {}", mm);
+        }
+        options = options.cal_pt_per_mv(mm * calibration::PT_PER_MM);
+    }
+
+    convert(&args.pdf, &args.edf, &options)?;
+    if options.segment.is_some() {
+        return Ok(());
+    }
+
+    let edf_path = options.output_path(&args.edf);
+    let file_size = std::fs::metadata(&edf_path)?.len();
+    let format = options
+        .format
+        .writer(options.write_settings())
+        .name()
+        .trim_end_matches('+');
+    info!("{} file written: {}", format, edf_path);
+    info!("File size: {} bytes", file_size);
+
+    Ok(())
+}
+
+#[cfg(feature = "serve")]
+fn run_serve(args: ServeArgs) -> Result<()> {
+    let defaults = serve::ServeOptions::default();
+    let options = serve::ServeOptions {
+        max_conversions: args.max_conversions.unwrap_or(defaults.max_conversions),
+        max_connections: args.max_connections,
+        timeout: Duration::from_secs(args.timeout),
+    };
+    serve::serve(
+        &args.listen,
+        config_options(args.config.as_deref())?,
+        options,
+    )
+}
+
+#[cfg(feature = "watch")]
+fn run_watch(args: WatchArgs) -> Result<()> {
+    let options = watch::WatchOptions {
+        out_dir: args.out_dir.map(Into::into),
+        concurrency: args.concurrency,
+        queue_size: args.queue_size,
+        retry: RetryPolicy {
+            max_retries: args.retries,
+            initial_backoff: Duration::from_millis(args.backoff),
+            ..RetryPolicy::default()
+        },
+        poll_interval: Duration::from_millis(args.poll_interval),
+        archive: args.archive.map(Into::into),
+        force: args.force,
+        metrics_listen: args.metrics_listen,
+    };
+    let convert_options = config_options(args.config.as_deref())?.on_progress(progress::show);
+    watch::watch(&args.dir, convert_options, options)
+}
+
+fn run_merge(args: MergeArgs) -> Result<()> {
+    let format = if args.bdf {
+        OutputFormat::Bdf
+    } else {
+        OutputFormat::Edf
+    };
+    let mut options = ConvertOptions::new()
+        .format(format)
+        .on_progress(progress::show);
+    if let Some(extension) = &args.compress {
+        options = options.compress(compression(extension));
+    }
+    let merge_options = merge::MergeOptions {
+        mixed_patients: args.mixed_patients,
+    };
+    merge::merge(&args.inputs, &args.output, &options, &merge_options)?;
+    info!(
+        "Session file written: {}",
+        options.output_path(&args.output)
+    );
+    Ok(())
+}
+
+/// The compression a `--compress` value names.
+fn compression(extension: &str) -> Compression {
+    match extension {
+        "zst" => Compression::Zstd,
+        _ => Compression::Gzip,
+    }
+}
+
+fn run_demo(args: DemoArgs) -> Result<()> {
+    let options = ConvertOptions::new();
+    let recording = demo::demo_recording(options.profile.sample_rate);
+    write_recording(&recording, &args.output, &options)?;
+    info!(
+        "Demo EDF file written: {} ({} s, {} beats)",
+        args.output,
+        recording.duration(),
+        recording.annotations.len()
+    );
 
-    let file_size = std::fs::metadata(edf_path)?.len();
-    println!("\nEDF file written: {}", edf_path);
-    println!("File size: {} bytes", file_size);
+    if let Some(pdf) = args.pdf {
+        demo::write_demo_pdf(&recording, &options.profile, &pdf)?;
+        info!("Demo PDF file written: {}", pdf);
+    }
 
     Ok(())
 }
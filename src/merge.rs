@@ -0,0 +1,441 @@
+use anyhow::{anyhow, Context, Result};
+use chrono::{Duration, NaiveDateTime};
+use std::collections::BTreeMap;
+use std::io::Cursor;
+use std::path::Path;
+
+use crate::audit::outln;
+use crate::compress::{self, Compression};
+use crate::convert::{self, ConvertOptions, Progress};
+use crate::edf_read::{self, Header};
+use crate::edf_write::{self, Annotation, EdfSignal, Format, Gap, WriteSettings};
+use crate::signal_writer::OutputFormat;
+
+/// One continuous recording to merge.
+struct Segment {
+    /// Input file the segment was read from.
+    source: String,
+    start: NaiveDateTime,
+    patient: String,
+    equipment: Option<String>,
+    signals: Vec<EdfSignal>,
+    annotations: Vec<Annotation>,
+    gaps: Vec<Gap>,
+}
+
+impl Segment {
+    fn duration(&self) -> f64 {
+        self.signals
+            .iter()
+            .map(|s| s.samples.len() as f64 / s.sample_rate)
+            .fold(0.0, f64::max)
+    }
+
+    /// The writer settings for this segment.
+    fn write_settings(&self, options: &ConvertOptions) -> WriteSettings {
+        WriteSettings {
+            gaps: self.gaps.clone(),
+            equipment: self.equipment.clone(),
+            ..options.write_settings()
+        }
+    }
+}
+
+/// Settings for [`merge`].
+#[derive(Debug, Clone, Default)]
+pub struct MergeOptions {
+    /// Merge recordings whose patient identification fields differ, under
+    /// the first recording's, instead of refusing to.
+    pub mixed_patients: bool,
+}
+
+/// Merge recordings into one EDF+D (or BDF+D) session file, in order of
+/// their start times.
+///
+/// Inputs ending in `.pdf` are converted with `options`; others are read
+/// as EDF/EDF+ or BDF/BDF+ files, decompressed if they end in `.gz` or
+/// `.zst`, with each continuous run of an EDF+D file's data records taken
+/// as its own recording. Each recording keeps
+/// its annotations and starts with a "Recording <file name>" annotation,
+/// and its data records get onsets from its start time, so the time
+/// between recordings is kept. The signals of all recordings must match in
+/// label and sampling rate, and their patient fields must be the same
+/// unless `merge` allows [`MergeOptions::mixed_patients`]; signals without
+/// a set physical range get one covering every recording's samples, and
+/// the annotation signal is sized for the recording with the most
+/// annotations. The session is merged in memory
+/// and replaces an existing output only once every recording is in it, so
+/// a failed merge leaves the output as it was. Outputs ending in `.gz` or
+/// `.zst`, or given that extension by [`ConvertOptions::compress`], are
+/// compressed.
+pub fn merge(
+    inputs: &[String],
+    output: &str,
+    options: &ConvertOptions,
+    merge: &MergeOptions,
+) -> Result<()> {
+    let output = &options.output_path(output);
+    let output_path = Path::new(output);
+    for input in inputs {
+        if output_path.exists() && same_file(input, output)? {
+            return Err(anyhow!("Output {} is also an input", output));
+        }
+    }
+
+    let mut segments = Vec::new();
+    for (i, input) in inputs.iter().enumerate() {
+        let read = if is_pdf(input) {
+            pdf_segment(input, options).map(|s| vec![s])
+        } else {
+            edf_segments(input)
+        };
+        segments.extend(read.with_context(|| format!("Failed to read {}", input))?);
+        options.progress(Progress::Files {
+            done: i + 1,
+            total: inputs.len(),
+        });
+    }
+    outln!(
+        "Merging {} recordings from {} files into {}",
+        segments.len(),
+        inputs.len(),
+        output
+    );
+    let session = merge_segments(segments, options, merge)?;
+
+    // Replace the output only once the whole session is written
+    let bytes = match Compression::of_path(output) {
+        Some(compression) => compress::encode(compression, &session)?,
+        None => session,
+    };
+    let temp = format!("{}.tmp", output);
+    std::fs::write(&temp, bytes)
+        .and_then(|()| std::fs::rename(&temp, output))
+        .inspect_err(|_| {
+            let _ = std::fs::remove_file(&temp);
+        })
+        .with_context(|| format!("Failed to write {}", output))
+}
+
+/// Write segments, in order of their start times, into a session file in
+/// memory.
+fn merge_segments(
+    mut segments: Vec<Segment>,
+    options: &ConvertOptions,
+    merge: &MergeOptions,
+) -> Result<Vec<u8>> {
+    if segments.is_empty() {
+        return Err(anyhow!("No recordings to merge"));
+    }
+    segments.sort_by_key(|s| s.start);
+    share_phys_ranges(&mut segments);
+
+    let first = &segments[0];
+    if !merge.mixed_patients {
+        if let Some(other) = segments
+            .iter()
+            .find(|s| s.patient.trim() != first.patient.trim())
+        {
+            return Err(anyhow!(
+                "{} has patient {:?} but {} has {:?}; not merging recordings of different patients",
+                first.source,
+                first.patient.trim(),
+                other.source,
+                other.patient.trim()
+            ));
+        }
+    }
+
+    // Size the annotation signal for every segment up front, as it can't
+    // grow once the first is written
+    let format = match options.format {
+        OutputFormat::Bdf => Format::Bdf,
+        _ => Format::Edf,
+    };
+    let mut annotation_samples = 0;
+    for segment in &segments {
+        let needed = edf_write::session_annotation_samples(
+            format,
+            &segment.signals,
+            segment.start,
+            first.start,
+            &segment.annotations,
+            &segment.write_settings(options),
+        )
+        .with_context(|| format!("Failed to merge {}", segment.source))?;
+        annotation_samples = annotation_samples.max(needed);
+    }
+
+    let mut session = Cursor::new(Vec::new());
+    for segment in &segments {
+        outln!(
+            "  {} at {}, {:.1} s",
+            segment.source,
+            segment.start.format("%Y-%m-%d %H:%M:%S%.3f"),
+            segment.duration()
+        );
+        let writer = options.format.writer(WriteSettings {
+            annotation_samples: Some(annotation_samples),
+            ..segment.write_settings(options)
+        });
+        writer
+            .append_to(
+                &mut session,
+                &segment.signals,
+                Some(segment.start),
+                &segment.patient,
+                &segment.annotations,
+            )
+            .with_context(|| format!("Failed to merge {}", segment.source))?;
+    }
+    Ok(session.into_inner())
+}
+
+fn is_pdf(path: &str) -> bool {
+    Path::new(path)
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("pdf"))
+}
+
+fn same_file(a: &str, b: &str) -> Result<bool> {
+    Ok(Path::new(a).exists() && std::fs::canonicalize(a)? == std::fs::canonicalize(b)?)
+}
+
+/// The "Recording <file name>" annotation starting each recording.
+fn source_annotation(path: &str) -> Annotation {
+    let name = Path::new(path)
+        .file_name()
+        .map_or(path.into(), |n| n.to_string_lossy());
+    Annotation::new(0.0, format!("Recording {}", name))
+}
+
+/// Convert a PDF into a segment, as [`convert::write_recording`] would
+/// write it.
+fn pdf_segment(path: &str, options: &ConvertOptions) -> Result<Segment> {
+    let mut recording = convert::read_recording(path, options)?;
+    convert::run_hooks(&mut recording, options)?;
+    let start = recording
+        .start
+        .ok_or_else(|| anyhow!("No recording start time in the report"))?;
+    let mut annotations = vec![source_annotation(path)];
+    annotations.extend(convert::recording_annotations(&recording));
+    Ok(Segment {
+        source: path.to_string(),
+        start,
+        patient: recording.patient.clone(),
+        equipment: recording.report.device.clone(),
+        signals: convert::recording_signals(&recording, options)?,
+        annotations,
+        gaps: convert::recording_gaps(&recording)?,
+    })
+}
+
+/// Read an EDF file's recordings: one per continuous run of data records.
+fn edf_segments(path: &str) -> Result<Vec<Segment>> {
+    let mut reader = Cursor::new(compress::read(path)?);
+    let header = edf_read::read_header(&mut reader)?;
+    let n_records =
+        usize::try_from(header.n_records).map_err(|_| anyhow!("Unknown number of data records"))?;
+    let plus = header.annotation_index().is_some();
+    if plus && header.recording.split(' ').nth(1) == Some("X") {
+        return Err(anyhow!("No recording start time in the header"));
+    }
+    let file_start = header.start_datetime()?;
+
+    // Record onsets and annotations, from the TALs in EDF+
+    let mut onsets = Vec::with_capacity(n_records);
+    let mut annotations = Vec::new();
+    for record in 0..n_records {
+        if !plus {
+            onsets.push(record as f64 * header.record_duration);
+            continue;
+        }
+        let tals = edf_read::read_record_tals(&mut reader, &header, record)?;
+        let keeping = tals
+            .first()
+            .ok_or_else(|| anyhow!("Record {}: missing time-keeping TAL", record))?;
+        onsets.push(keeping.onset);
+        for (i, tal) in tals.iter().enumerate() {
+            // The time-keeping TAL's first text is empty
+            for text in tal.texts.iter().skip(usize::from(i == 0)) {
+                annotations.push(Annotation {
+                    onset: tal.onset,
+                    duration: tal.duration,
+                    text: text.clone(),
+                });
+            }
+        }
+    }
+
+    // Split where a record doesn't follow on from the one before
+    let mut runs: Vec<(usize, usize)> = Vec::new();
+    for (record, &onset) in onsets.iter().enumerate() {
+        match runs.last_mut() {
+            Some((first, end))
+                if (onset - onsets[*first] - (*end - *first) as f64 * header.record_duration)
+                    .abs()
+                    < 1e-3 =>
+            {
+                *end = record + 1
+            }
+            _ => runs.push((record, record + 1)),
+        }
+    }
+
+    let data: Vec<usize> = (0..header.signals.len())
+        .filter(|&i| !header.signals[i].is_annotations())
+        .collect();
+    let mut samples = Vec::with_capacity(data.len());
+    for &i in &data {
+        let h = &header.signals[i];
+        let digital = edf_read::read_digital_samples(&mut reader, &header, i)?;
+        samples.push(
+            digital
+                .iter()
+                .map(|&d| h.to_physical(d))
+                .collect::<Vec<f64>>(),
+        );
+    }
+
+    let mut segments = Vec::with_capacity(runs.len());
+    for (n, &(first, end)) in runs.iter().enumerate() {
+        // Each run has the annotations from its onset to the next run's;
+        // the first run also those before it
+        let onset = onsets[first];
+        let from = if n == 0 { f64::NEG_INFINITY } else { onset };
+        let until = runs.get(n + 1).map_or(f64::INFINITY, |r| onsets[r.0]);
+        let mut segment_annotations: Vec<Annotation> = annotations
+            .iter()
+            .filter(|a| a.onset >= from && a.onset < until)
+            .map(|a| Annotation {
+                onset: (a.onset - onset).max(0.0),
+                ..a.clone()
+            })
+            .collect();
+        // A recording from an earlier merge keeps its own file name
+        if !segment_annotations
+            .iter()
+            .any(|a| a.onset == 0.0 && a.text.starts_with("Recording "))
+        {
+            segment_annotations.insert(0, source_annotation(path));
+        }
+        segments.push(Segment {
+            source: path.to_string(),
+            start: file_start + Duration::milliseconds((onset * 1000.0).round() as i64),
+            patient: header.patient.clone(),
+            equipment: None,
+            signals: data
+                .iter()
+                .zip(&samples)
+                .map(|(&i, samples)| edf_signal(&header, i, samples, first, end))
+                .collect(),
+            annotations: segment_annotations,
+            gaps: Vec::new(),
+        });
+    }
+    Ok(segments)
+}
+
+/// A signal's samples in data records `first..end`, with its header fields.
+fn edf_signal(
+    header: &Header,
+    index: usize,
+    samples: &[f64],
+    first: usize,
+    end: usize,
+) -> EdfSignal {
+    let h = &header.signals[index];
+    let n = h.samples_per_record;
+    EdfSignal {
+        transducer: h.transducer.clone(),
+        prefilter: h.prefilter.clone(),
+        ..EdfSignal::new(
+            &h.label,
+            &h.phys_dim,
+            n as f64 / header.record_duration,
+            samples[first * n..end * n].to_vec(),
+        )
+    }
+}
+
+/// Give each signal without a set physical range the range covering that
+/// signal's samples in every segment, so later segments aren't clipped to
+/// the first one's.
+fn share_phys_ranges(segments: &mut [Segment]) {
+    let mut ranges: BTreeMap<String, (f64, f64)> = BTreeMap::new();
+    for signal in segments.iter().flat_map(|s| &s.signals) {
+        let (min, max) = signal.physical_range();
+        let range = ranges
+            .entry(signal.label.clone())
+            .or_insert((f64::INFINITY, f64::NEG_INFINITY));
+        *range = (range.0.min(min), range.1.max(max));
+    }
+    for signal in segments.iter_mut().flat_map(|s| &mut s.signals) {
+        if signal.phys_range.is_none() {
+            signal.phys_range = ranges.get(&signal.label).copied();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn segment(source: &str, minute: u32, patient: &str, notes: usize) -> Segment {
+        let start = NaiveDate::from_ymd_opt(2024, 5, 4)
+            .and_then(|d| d.and_hms_opt(10, minute, 0))
+            .unwrap();
+        let mut annotations = vec![source_annotation(source)];
+        annotations.extend(
+            (0..notes).map(|i| {
+                Annotation::new(0.5, format!("Long annotation number {} on this record", i))
+            }),
+        );
+        Segment {
+            source: source.to_string(),
+            start,
+            patient: patient.to_string(),
+            equipment: None,
+            signals: vec![EdfSignal::ecg(vec![0.5; 3000], 300)],
+            annotations,
+            gaps: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn sizes_annotations_for_every_segment() {
+        let segments = vec![
+            segment("a.edf", 0, "X X X Jane", 0),
+            segment("b.pdf", 5, "X X X Jane", 8),
+        ];
+        let session =
+            merge_segments(segments, &ConvertOptions::new(), &MergeOptions::default()).unwrap();
+
+        let mut reader = Cursor::new(&session);
+        let header = edf_read::read_header(&mut reader).unwrap();
+        assert!(header.reserved.starts_with("EDF+D"));
+        assert_eq!(header.n_records, 20);
+        let tals = edf_read::read_record_tals(&mut reader, &header, 10).unwrap();
+        let texts = tals.iter().flat_map(|t| &t.texts).count();
+        assert_eq!(texts, 1 + 1 + 8);
+    }
+
+    #[test]
+    fn refuses_mixed_patients() {
+        let segments = || {
+            vec![
+                segment("a.edf", 0, "X X X Jane", 0),
+                segment("b.edf", 5, "X X X John", 0),
+            ]
+        };
+        let options = ConvertOptions::new();
+        let refused = merge_segments(segments(), &options, &MergeOptions::default());
+        assert!(refused.is_err());
+
+        let merge = MergeOptions {
+            mixed_patients: true,
+        };
+        assert!(merge_segments(segments(), &options, &merge).is_ok());
+    }
+}
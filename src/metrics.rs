@@ -0,0 +1,103 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::failure::Failure;
+
+/// Upper bounds of the conversion latency histogram buckets, in seconds.
+const LATENCY_BUCKETS: [f64; 8] = [0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// Conversion counters and latency histogram for service modes,
+/// rendered in the Prometheus text exposition format.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    conversions: AtomicU64,
+    failures: Mutex<BTreeMap<&'static str, u64>>,
+    /// Cumulative counts per bucket; the +Inf bucket is the conversion count.
+    latency_buckets: [AtomicU64; LATENCY_BUCKETS.len()],
+    /// Sum of latencies in microseconds.
+    latency_sum_us: AtomicU64,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a finished conversion, successful or not.
+    pub fn record<T>(&self, elapsed: Duration, result: &anyhow::Result<T>) {
+        self.conversions.fetch_add(1, Ordering::Relaxed);
+        if let Err(e) = result {
+            *self
+                .failures
+                .lock()
+                .unwrap()
+                .entry(error_code(e))
+                .or_insert(0) += 1;
+        }
+
+        let seconds = elapsed.as_secs_f64();
+        for (bucket, &bound) in self.latency_buckets.iter().zip(&LATENCY_BUCKETS) {
+            if seconds <= bound {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.latency_sum_us
+            .fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// Render all metrics in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let count = self.conversions.load(Ordering::Relaxed);
+
+        let _ = writeln!(
+            out,
+            "# HELP kardia2edf_conversions_total Conversions attempted."
+        );
+        let _ = writeln!(out, "# TYPE kardia2edf_conversions_total counter");
+        let _ = writeln!(out, "kardia2edf_conversions_total {}", count);
+
+        let _ = writeln!(
+            out,
+            "# HELP kardia2edf_conversion_failures_total Failed conversions by error code."
+        );
+        let _ = writeln!(out, "# TYPE kardia2edf_conversion_failures_total counter");
+        for (code, n) in self.failures.lock().unwrap().iter() {
+            let _ = writeln!(
+                out,
+                "kardia2edf_conversion_failures_total{{code=\"{}\"}} {}",
+                code, n
+            );
+        }
+
+        let name = "kardia2edf_conversion_duration_seconds";
+        let _ = writeln!(out, "# HELP {} Conversion latency.", name);
+        let _ = writeln!(out, "# TYPE {} histogram", name);
+        for (bucket, bound) in self.latency_buckets.iter().zip(&LATENCY_BUCKETS) {
+            let n = bucket.load(Ordering::Relaxed);
+            let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, n);
+        }
+        let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, count);
+        let sum = self.latency_sum_us.load(Ordering::Relaxed) as f64 / 1e6;
+        let _ = writeln!(out, "{}_sum {}", name, sum);
+        let _ = writeln!(out, "{}_count {}", name, count);
+
+        out
+    }
+}
+
+/// Classify a conversion error for metrics labels, by its [`Failure`].
+pub fn error_code(error: &anyhow::Error) -> &'static str {
+    match Failure::of(error) {
+        Failure::InputNotFound => "input_not_found",
+        Failure::NoWaveform => "no_waveform",
+        Failure::QcFailed => "qc_failed",
+        Failure::WriteFailed => "write_failed",
+        Failure::InvalidPdf => "invalid_pdf",
+        Failure::Other if error.chain().any(|cause| cause.is::<std::io::Error>()) => "io_error",
+        Failure::Other => "conversion_failed",
+    }
+}
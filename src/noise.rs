@@ -0,0 +1,57 @@
+/// Noise-floor and signal-to-noise estimate for a voltage series.
+#[derive(Debug, Clone, Copy)]
+pub struct NoiseEstimate {
+    /// Estimated RMS of the noise floor in mV.
+    pub noise_rms: f64,
+    /// RMS of the signal around its median in mV, with the noise power removed.
+    pub signal_rms: f64,
+    /// Signal-to-noise ratio in dB (infinite if no noise is detected).
+    pub snr_db: f64,
+}
+
+fn median(values: &mut [f64]) -> f64 {
+    values.sort_by(|a, b| a.total_cmp(b));
+    let n = values.len();
+    if n.is_multiple_of(2) {
+        (values[n / 2 - 1] + values[n / 2]) / 2.0
+    } else {
+        values[n / 2]
+    }
+}
+
+/// Estimate the noise floor from the high-frequency residual.
+///
+/// The sample-to-sample differences of white noise have standard deviation
+/// sqrt(2) * sigma. Their median absolute deviation ignores the few large
+/// differences in QRS complexes, so it measures the noise between beats
+/// (mostly the TP segments) rather than the waveform itself.
+pub fn estimate_noise(voltages: &[f64]) -> Option<NoiseEstimate> {
+    if voltages.len() < 3 {
+        return None;
+    }
+
+    let mut diffs: Vec<f64> = voltages.windows(2).map(|w| w[1] - w[0]).collect();
+    let center = median(&mut diffs);
+    let mut deviations: Vec<f64> = diffs.iter().map(|d| (d - center).abs()).collect();
+    // MAD / 0.6745 estimates the standard deviation of a normal distribution
+    let noise_rms = median(&mut deviations) / 0.6745 / std::f64::consts::SQRT_2;
+
+    let mut sorted = voltages.to_vec();
+    let level = median(&mut sorted);
+    let total_power =
+        voltages.iter().map(|v| (v - level).powi(2)).sum::<f64>() / voltages.len() as f64;
+    let noise_power = noise_rms * noise_rms;
+    let signal_power = (total_power - noise_power).max(0.0);
+
+    let snr_db = if noise_power > 0.0 {
+        10.0 * (signal_power / noise_power).log10()
+    } else {
+        f64::INFINITY
+    };
+
+    Some(NoiseEstimate {
+        noise_rms,
+        signal_rms: signal_power.sqrt(),
+        snr_db,
+    })
+}
@@ -1,6 +1,7 @@
 use anyhow::{anyhow, Result};
 use lopdf::content::Content;
-use lopdf::{Document, Object, ObjectId};
+use lopdf::{Dictionary, Document, Object, ObjectId};
+use std::rc::Rc;
 
 /// A 2D point in top-left-origin coordinates (matching pymupdf convention).
 #[derive(Debug, Clone, Copy)]
@@ -16,7 +17,7 @@ pub struct DrawingPath {
     pub segments: Vec<(Point, Point)>,
     /// Stroke color as (r, g, b). Black = (0, 0, 0).
     pub color: (f64, f64, f64),
-    /// Line width in PDF user units.
+    /// Line width in points (PDF user units times the page's /UserUnit).
     pub width: f64,
 }
 
@@ -27,8 +28,15 @@ struct GraphicsState {
     ctm: [f64; 6],
     /// Stroke color as RGB.
     stroke_color: (f64, f64, f64),
+    /// Fill color as RGB; for a pattern, its shading's starting color.
+    fill_color: (f64, f64, f64),
     /// Line width.
     line_width: f64,
+    /// The last clipping path set, painted by a shading (`sh`).
+    clip: Option<Rc<Vec<(Point, Point)>>>,
+    /// Every clipping path set since the state was saved; what is painted
+    /// shows only inside all of them.
+    clip_regions: Vec<Rc<ClipRegion>>,
 }
 
 impl Default for GraphicsState {
@@ -36,18 +44,90 @@ impl Default for GraphicsState {
         Self {
             ctm: [1.0, 0.0, 0.0, 1.0, 0.0, 0.0],
             stroke_color: (0.0, 0.0, 0.0),
+            fill_color: (0.0, 0.0, 0.0),
             line_width: 1.0,
+            clip: None,
+            clip_regions: Vec::new(),
         }
     }
 }
 
+/// Distance within which a point on a clipping path's edge counts as
+/// inside it, in PDF points, as traces often run right along the edge of
+/// the strip's clipping rectangle.
+const CLIP_TOLERANCE: f64 = 0.5;
+
+/// A clipping path, set with `W` (nonzero winding rule) or `W*` (even-odd).
+struct ClipRegion {
+    /// Edges of the path, with each open subpath closed.
+    edges: Vec<(Point, Point)>,
+    even_odd: bool,
+}
+
+impl ClipRegion {
+    fn new(segments: &[(Point, Point)], even_odd: bool) -> Self {
+        let mut edges = Vec::with_capacity(segments.len() + 1);
+        let mut start = 0;
+        for end in 1..=segments.len() {
+            let joined = end < segments.len()
+                && (segments[end].0.x - segments[end - 1].1.x).abs() <= SAME_POINT
+                && (segments[end].0.y - segments[end - 1].1.y).abs() <= SAME_POINT;
+            if !joined {
+                edges.extend_from_slice(&segments[start..end]);
+                push_segment(&mut edges, segments[end - 1].1, segments[start].0);
+                start = end;
+            }
+        }
+        Self { edges, even_odd }
+    }
+
+    /// Whether a point is inside the region or within [`CLIP_TOLERANCE`]
+    /// of its edge.
+    fn contains(&self, p: Point) -> bool {
+        let mut winding = 0i32;
+        let mut crossings = 0u32;
+        for &(a, b) in &self.edges {
+            if distance_to_segment(p, a, b) <= CLIP_TOLERANCE {
+                return true;
+            }
+            // Count the edges crossing a ray from the point to the right,
+            // upward ones winding +1 and downward ones -1
+            if (a.y <= p.y) != (b.y <= p.y) {
+                let side = (b.x - a.x) * (p.y - a.y) - (p.x - a.x) * (b.y - a.y);
+                let upward = b.y > a.y;
+                if (upward && side > 0.0) || (!upward && side < 0.0) {
+                    crossings += 1;
+                    winding += if upward { 1 } else { -1 };
+                }
+            }
+        }
+        if self.even_odd {
+            crossings % 2 == 1
+        } else {
+            winding != 0
+        }
+    }
+}
+
+/// Distance from a point to a line segment.
+fn distance_to_segment(p: Point, a: Point, b: Point) -> f64 {
+    let (dx, dy) = (b.x - a.x, b.y - a.y);
+    let length_sq = dx * dx + dy * dy;
+    let t = if length_sq > 0.0 {
+        (((p.x - a.x) * dx + (p.y - a.y) * dy) / length_sq).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    (p.x - a.x - t * dx).hypot(p.y - a.y - t * dy)
+}
+
 /// Multiply two 2D affine transformation matrices.
 /// Each matrix is [a, b, c, d, e, f] representing:
 ///   | a c e |
 ///   | b d f |
 ///   | 0 0 1 |
 /// Result = m1 * m2 (m2 applied first, then m1).
-fn multiply_ctm(m1: &[f64; 6], m2: &[f64; 6]) -> [f64; 6] {
+pub(crate) fn multiply_ctm(m1: &[f64; 6], m2: &[f64; 6]) -> [f64; 6] {
     [
         m1[0] * m2[0] + m1[2] * m2[1],
         m1[1] * m2[0] + m1[3] * m2[1],
@@ -58,18 +138,17 @@ fn multiply_ctm(m1: &[f64; 6], m2: &[f64; 6]) -> [f64; 6] {
     ]
 }
 
-/// Transform a raw PDF coordinate through the CTM, then flip y to top-left origin.
-fn transform_point(x: f64, y: f64, ctm: &[f64; 6], page_height: f64) -> Point {
-    let x_pdf = ctm[0] * x + ctm[2] * y + ctm[4];
-    let y_pdf = ctm[1] * x + ctm[3] * y + ctm[5];
+/// Transform a raw PDF coordinate through the CTM, which starts from the
+/// page's [`PageGeometry::matrix`], to top-left-origin page coordinates.
+fn transform_point(x: f64, y: f64, ctm: &[f64; 6]) -> Point {
     Point {
-        x: x_pdf,
-        y: page_height - y_pdf,
+        x: ctm[0] * x + ctm[2] * y + ctm[4],
+        y: ctm[1] * x + ctm[3] * y + ctm[5],
     }
 }
 
 /// Extract a numeric value from a lopdf Object.
-fn obj_f64(obj: &Object) -> Result<f64> {
+pub(crate) fn obj_f64(obj: &Object) -> Result<f64> {
     match obj {
         Object::Real(f) => Ok(*f as f64),
         Object::Integer(i) => Ok(*i as f64),
@@ -85,48 +164,113 @@ fn deref<'a>(doc: &'a Document, obj: &'a Object) -> Result<&'a Object> {
     }
 }
 
-/// Get the page height from the MediaBox (checking page dict, then parent).
-pub fn get_page_height(doc: &Document, page_id: ObjectId) -> Result<f64> {
-    get_page_height_inner(doc, page_id, 0)
+/// How a page's user space maps to the page as displayed: its MediaBox,
+/// /Rotate, and /UserUnit.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PageGeometry {
+    /// Matrix from PDF user space to points from the displayed page's
+    /// top-left corner, y down.
+    pub matrix: [f64; 6],
+    /// Points per user space unit.
+    pub user_unit: f64,
+    /// Displayed page width in points.
+    pub width: f64,
+    /// Displayed page height in points.
+    pub height: f64,
 }
 
-fn get_page_height_inner(doc: &Document, obj_id: ObjectId, depth: usize) -> Result<f64> {
-    if depth > 10 {
-        return Ok(792.0); // default US Letter
+impl PageGeometry {
+    /// Map a point in user space to displayed page coordinates.
+    pub fn transform(&self, x: f64, y: f64) -> Point {
+        transform_point(x, y, &self.matrix)
     }
-    let obj = doc.get_object(obj_id)?;
-    let dict = obj.as_dict().map_err(|e| anyhow!("{}", e))?;
-
-    if let Ok(mb) = dict.get(b"MediaBox") {
-        let mb = deref(doc, mb)?;
-        if let Object::Array(arr) = mb {
-            if arr.len() == 4 {
-                return obj_f64(&arr[3]);
+}
+
+/// Get the page's geometry. The MediaBox and /Rotate are inherited from
+/// parent page tree nodes; /UserUnit is set on the page itself.
+pub fn get_page_geometry(doc: &Document, page_id: ObjectId) -> Result<PageGeometry> {
+    let page = doc.get_dictionary(page_id)?;
+    // US Letter if no MediaBox is found
+    let media_box = inherited(doc, page, b"MediaBox")
+        .and_then(|mb| match mb {
+            Object::Array(arr) if arr.len() == 4 => {
+                let v: Vec<f64> = arr.iter().map(obj_f64).collect::<Result<_>>().ok()?;
+                Some([
+                    v[0].min(v[2]),
+                    v[1].min(v[3]),
+                    v[0].max(v[2]),
+                    v[1].max(v[3]),
+                ])
             }
-        }
-    }
+            _ => None,
+        })
+        .unwrap_or([0.0, 0.0, 612.0, 792.0]);
+    let rotate = inherited(doc, page, b"Rotate")
+        .and_then(|r| obj_f64(r).ok())
+        .map_or(0, |r| (r as i64).rem_euclid(360));
+    let user_unit = page
+        .get(b"UserUnit")
+        .ok()
+        .and_then(|u| obj_f64(deref(doc, u).ok()?).ok())
+        .filter(|&u| u > 0.0)
+        .unwrap_or(1.0);
+
+    // Unrotated, from the top-left corner, then turned clockwise
+    let [llx, lly, urx, ury] = media_box;
+    let (w, h) = (urx - llx, ury - lly);
+    let flip = [1.0, 0.0, 0.0, -1.0, -llx, ury];
+    let (turn, width, height) = match rotate {
+        90 => ([0.0, 1.0, -1.0, 0.0, h, 0.0], h, w),
+        180 => ([-1.0, 0.0, 0.0, -1.0, w, h], w, h),
+        270 => ([0.0, -1.0, 1.0, 0.0, 0.0, w], h, w),
+        _ => ([1.0, 0.0, 0.0, 1.0, 0.0, 0.0], w, h),
+    };
+    let scale = [user_unit, 0.0, 0.0, user_unit, 0.0, 0.0];
+    Ok(PageGeometry {
+        matrix: multiply_ctm(&scale, &multiply_ctm(&turn, &flip)),
+        user_unit,
+        width: width * user_unit,
+        height: height * user_unit,
+    })
+}
 
-    // Walk up to parent
-    if let Ok(parent_ref) = dict.get(b"Parent") {
-        if let Object::Reference(parent_id) = parent_ref {
-            return get_page_height_inner(doc, *parent_id, depth + 1);
+/// An inheritable page attribute, from the page or its nearest ancestor.
+fn inherited<'a>(doc: &'a Document, page: &'a Dictionary, key: &[u8]) -> Option<&'a Object> {
+    let mut dict = page;
+    for _ in 0..32 {
+        if let Ok(value) = dict.get(key) {
+            return deref(doc, value).ok();
         }
+        dict = dict
+            .get(b"Parent")
+            .and_then(Object::as_reference)
+            .and_then(|id| doc.get_dictionary(id))
+            .ok()?;
     }
-
-    Ok(792.0)
+    None
 }
 
 /// Extract all stroked drawing paths from a PDF page's content stream.
+///
+/// With `filled_trace`, thin filled shapes, including clipping paths
+/// painted with a shading, are read too: each as the path along its
+/// centerline, with its fill color and its thickness as the width (see
+/// [`fill_centerline`]).
 pub fn extract_paths(
     doc: &Document,
     page_id: ObjectId,
-    page_height: f64,
+    geometry: &PageGeometry,
+    filled_trace: bool,
 ) -> Result<Vec<DrawingPath>> {
     let content_bytes = doc.get_page_content(page_id)?;
     let content = Content::decode(&content_bytes).map_err(|e| anyhow!("{}", e))?;
 
     let mut paths = Vec::new();
-    let mut state = GraphicsState::default();
+    let mut state = GraphicsState {
+        ctm: geometry.matrix,
+        line_width: geometry.user_unit,
+        ..GraphicsState::default()
+    };
     let mut state_stack: Vec<GraphicsState> = Vec::new();
     let mut current_segments: Vec<(Point, Point)> = Vec::new();
     let mut current_pos = Point { x: 0.0, y: 0.0 };
@@ -145,140 +289,101 @@ pub fn extract_paths(
             }
 
             // Concat transformation matrix
-            "cm" => {
-                if op.operands.len() == 6 {
-                    let m = [
-                        obj_f64(&op.operands[0])?,
-                        obj_f64(&op.operands[1])?,
-                        obj_f64(&op.operands[2])?,
-                        obj_f64(&op.operands[3])?,
-                        obj_f64(&op.operands[4])?,
-                        obj_f64(&op.operands[5])?,
-                    ];
-                    state.ctm = multiply_ctm(&state.ctm, &m);
-                }
+            "cm" if op.operands.len() == 6 => {
+                let m = [
+                    obj_f64(&op.operands[0])?,
+                    obj_f64(&op.operands[1])?,
+                    obj_f64(&op.operands[2])?,
+                    obj_f64(&op.operands[3])?,
+                    obj_f64(&op.operands[4])?,
+                    obj_f64(&op.operands[5])?,
+                ];
+                state.ctm = multiply_ctm(&state.ctm, &m);
             }
 
             // Set line width
             "w" => {
                 if let Some(w) = op.operands.first() {
-                    state.line_width = obj_f64(w)?;
-                }
-            }
-
-            // Set stroke color (RGB)
-            "RG" => {
-                if op.operands.len() == 3 {
-                    state.stroke_color = (
-                        obj_f64(&op.operands[0])?,
-                        obj_f64(&op.operands[1])?,
-                        obj_f64(&op.operands[2])?,
-                    );
-                }
-            }
-
-            // Set stroke color (grayscale)
-            "G" => {
-                if let Some(g) = op.operands.first() {
-                    let v = obj_f64(g)?;
-                    state.stroke_color = (v, v, v);
+                    state.line_width = obj_f64(w)? * geometry.user_unit;
                 }
             }
 
-            // Set stroke color (CMYK)
-            "K" => {
-                if op.operands.len() == 4 {
-                    let c = obj_f64(&op.operands[0])?;
-                    let m = obj_f64(&op.operands[1])?;
-                    let y = obj_f64(&op.operands[2])?;
-                    let k = obj_f64(&op.operands[3])?;
-                    state.stroke_color = (
-                        (1.0 - c) * (1.0 - k),
-                        (1.0 - m) * (1.0 - k),
-                        (1.0 - y) * (1.0 - k),
-                    );
+            // Set stroke color (gray, RGB, CMYK, or by color space)
+            "G" | "RG" | "K" | "SC" | "SCN" => {
+                if let Some(color) = device_color(&op.operands) {
+                    state.stroke_color = color;
                 }
             }
 
-            // Set stroke color (generic, variable operands)
-            "SC" | "SCN" => {
-                match op.operands.len() {
-                    1 => {
-                        let v = obj_f64(&op.operands[0])?;
-                        state.stroke_color = (v, v, v);
-                    }
-                    3 => {
-                        state.stroke_color = (
-                            obj_f64(&op.operands[0])?,
-                            obj_f64(&op.operands[1])?,
-                            obj_f64(&op.operands[2])?,
-                        );
-                    }
-                    4 => {
-                        let c = obj_f64(&op.operands[0])?;
-                        let m = obj_f64(&op.operands[1])?;
-                        let y = obj_f64(&op.operands[2])?;
-                        let k = obj_f64(&op.operands[3])?;
-                        state.stroke_color = (
-                            (1.0 - c) * (1.0 - k),
-                            (1.0 - m) * (1.0 - k),
-                            (1.0 - y) * (1.0 - k),
-                        );
-                    }
-                    _ => {}
+            // Set fill color, or a pattern's by name
+            "g" | "rg" | "k" | "sc" | "scn" => {
+                if let Some(Object::Name(name)) = op.operands.last() {
+                    state.fill_color =
+                        shading_color(doc, page_id, b"Pattern", name).unwrap_or((0.0, 0.0, 0.0));
+                } else if let Some(color) = device_color(&op.operands) {
+                    state.fill_color = color;
                 }
             }
 
             // Moveto
-            "m" => {
-                if op.operands.len() == 2 {
-                    let x = obj_f64(&op.operands[0])?;
-                    let y = obj_f64(&op.operands[1])?;
-                    let p = transform_point(x, y, &state.ctm, page_height);
-                    current_pos = p;
-                    subpath_start = p;
-                }
+            "m" if op.operands.len() == 2 => {
+                let x = obj_f64(&op.operands[0])?;
+                let y = obj_f64(&op.operands[1])?;
+                let p = transform_point(x, y, &state.ctm);
+                current_pos = p;
+                subpath_start = p;
             }
 
             // Lineto
-            "l" => {
-                if op.operands.len() == 2 {
-                    let x = obj_f64(&op.operands[0])?;
-                    let y = obj_f64(&op.operands[1])?;
-                    let new_pos = transform_point(x, y, &state.ctm, page_height);
-                    current_segments.push((current_pos, new_pos));
-                    current_pos = new_pos;
-                }
+            "l" if op.operands.len() == 2 => {
+                let x = obj_f64(&op.operands[0])?;
+                let y = obj_f64(&op.operands[1])?;
+                let new_pos = transform_point(x, y, &state.ctm);
+                push_segment(&mut current_segments, current_pos, new_pos);
+                current_pos = new_pos;
+            }
+
+            // Curveto: both control points (c), the first at the current
+            // point (v), or the second at the end point (y)
+            "c" | "v" | "y" => {
+                let Some(coords) = curve_operands(&op.operator, &op.operands)? else {
+                    continue;
+                };
+                let point = |i: usize| transform_point(coords[i], coords[i + 1], &state.ctm);
+                let end = point(4);
+                let (c1, c2) = match op.operator.as_str() {
+                    "v" => (current_pos, point(2)),
+                    "y" => (point(0), end),
+                    _ => (point(0), point(2)),
+                };
+                flatten_curve(&mut current_segments, [current_pos, c1, c2, end]);
+                current_pos = end;
             }
 
             // Close subpath
-            "h" => {
-                if (current_pos.x - subpath_start.x).abs() > 0.001
-                    || (current_pos.y - subpath_start.y).abs() > 0.001
-                {
-                    current_segments.push((current_pos, subpath_start));
-                    current_pos = subpath_start;
-                }
+            "h" if (current_pos.x - subpath_start.x).abs() > 0.001
+                || (current_pos.y - subpath_start.y).abs() > 0.001 =>
+            {
+                current_segments.push((current_pos, subpath_start));
+                current_pos = subpath_start;
             }
 
             // Rectangle
-            "re" => {
-                if op.operands.len() == 4 {
-                    let rx = obj_f64(&op.operands[0])?;
-                    let ry = obj_f64(&op.operands[1])?;
-                    let rw = obj_f64(&op.operands[2])?;
-                    let rh = obj_f64(&op.operands[3])?;
-                    let p1 = transform_point(rx, ry, &state.ctm, page_height);
-                    let p2 = transform_point(rx + rw, ry, &state.ctm, page_height);
-                    let p3 = transform_point(rx + rw, ry + rh, &state.ctm, page_height);
-                    let p4 = transform_point(rx, ry + rh, &state.ctm, page_height);
-                    current_segments.push((p1, p2));
-                    current_segments.push((p2, p3));
-                    current_segments.push((p3, p4));
-                    current_segments.push((p4, p1));
-                    current_pos = p1;
-                    subpath_start = p1;
-                }
+            "re" if op.operands.len() == 4 => {
+                let rx = obj_f64(&op.operands[0])?;
+                let ry = obj_f64(&op.operands[1])?;
+                let rw = obj_f64(&op.operands[2])?;
+                let rh = obj_f64(&op.operands[3])?;
+                let p1 = transform_point(rx, ry, &state.ctm);
+                let p2 = transform_point(rx + rw, ry, &state.ctm);
+                let p3 = transform_point(rx + rw, ry + rh, &state.ctm);
+                let p4 = transform_point(rx, ry + rh, &state.ctm);
+                push_segment(&mut current_segments, p1, p2);
+                push_segment(&mut current_segments, p2, p3);
+                push_segment(&mut current_segments, p3, p4);
+                push_segment(&mut current_segments, p4, p1);
+                current_pos = p1;
+                subpath_start = p1;
             }
 
             // Stroke path
@@ -288,19 +393,38 @@ pub fn extract_paths(
 
             // Close and stroke
             "s" => {
-                if (current_pos.x - subpath_start.x).abs() > 0.001
-                    || (current_pos.y - subpath_start.y).abs() > 0.001
-                {
-                    current_segments.push((current_pos, subpath_start));
-                }
+                push_segment(&mut current_segments, current_pos, subpath_start);
                 emit_path(&mut paths, &mut current_segments, &state);
             }
 
-            // Fill operations — discard path
+            // Fill operations: a thin shape's centerline, or discard path
             "f" | "F" | "f*" => {
+                if filled_trace {
+                    let centerlines = fill_centerline(&current_segments, state.fill_color);
+                    paths.extend(centerlines.into_iter().filter_map(|p| clip_path(p, &state)));
+                }
                 current_segments.clear();
             }
 
+            // Set clipping path, applied by the next painting operator
+            "W" | "W*" => {
+                state.clip = Some(Rc::new(current_segments.clone()));
+                let region = ClipRegion::new(&current_segments, op.operator == "W*");
+                state.clip_regions.push(Rc::new(region));
+            }
+
+            // Paint a shading within the clipping path
+            "sh" => {
+                if let (true, Some(clip), Some(Object::Name(name))) =
+                    (filled_trace, &state.clip, op.operands.first())
+                {
+                    let color =
+                        shading_color(doc, page_id, b"Shading", name).unwrap_or((0.0, 0.0, 0.0));
+                    let centerlines = fill_centerline(clip, color);
+                    paths.extend(centerlines.into_iter().filter_map(|p| clip_path(p, &state)));
+                }
+            }
+
             // Fill and stroke
             "B" | "B*" | "b" | "b*" => {
                 emit_path(&mut paths, &mut current_segments, &state);
@@ -318,16 +442,337 @@ pub fn extract_paths(
     Ok(paths)
 }
 
+/// Distance under which two points are the same, in PDF points.
+const SAME_POINT: f64 = 0.001;
+
+/// Farthest a flattened curve may stray from the curve, in PDF points.
+const CURVE_FLATNESS: f64 = 0.05;
+
+/// Most line segments one curve is flattened into.
+const MAX_CURVE_STEPS: usize = 64;
+
+/// Add a line segment, dropping it if it has zero length, as some
+/// generators draw with a moveto followed by a lineto to the same point.
+fn push_segment(segments: &mut Vec<(Point, Point)>, from: Point, to: Point) {
+    if (to.x - from.x).abs() > SAME_POINT || (to.y - from.y).abs() > SAME_POINT {
+        segments.push((from, to));
+    }
+}
+
+/// The coordinates of a `c`, `v`, or `y` operator as the six of `c`, with
+/// the control point a shorthand leaves out as zeros; `None` if the operands
+/// are malformed.
+fn curve_operands(operator: &str, operands: &[Object]) -> Result<Option<[f64; 6]>> {
+    let expected = if operator == "c" { 6 } else { 4 };
+    if operands.len() != expected {
+        return Ok(None);
+    }
+    let mut coords = [0.0; 6];
+    // `v` gives the second control point and end point; `y` and `c` start
+    // with the first control point
+    let offset = if operator == "v" { 2 } else { 0 };
+    for (i, operand) in operands.iter().enumerate() {
+        let slot = if operator == "y" && i >= 2 {
+            i + 2
+        } else {
+            i + offset
+        };
+        coords[slot] = obj_f64(operand)?;
+    }
+    Ok(Some(coords))
+}
+
+/// Add a cubic Bezier curve, from its start point, two control points, and
+/// end point, as the fewest line segments within [`CURVE_FLATNESS`] of it,
+/// so a nearly straight curve between two trace samples stays one segment.
+fn flatten_curve(segments: &mut Vec<(Point, Point)>, [p0, p1, p2, p3]: [Point; 4]) {
+    // The curve lies within 3/4 of its control points' distance from the
+    // chord, so a curve with control points near the chord is one segment;
+    // otherwise the control polygon's bends bound the flattening error
+    let chord = (p3.x - p0.x).hypot(p3.y - p0.y);
+    let off_chord = |p: Point| {
+        if chord > SAME_POINT {
+            ((p3.x - p0.x) * (p0.y - p.y) - (p0.x - p.x) * (p3.y - p0.y)).abs() / chord
+        } else {
+            (p.x - p0.x).hypot(p.y - p0.y)
+        }
+    };
+    let steps = if 0.75 * off_chord(p1).max(off_chord(p2)) <= CURVE_FLATNESS {
+        1
+    } else {
+        let bend =
+            |a: Point, b: Point, c: Point| (a.x - 2.0 * b.x + c.x).hypot(a.y - 2.0 * b.y + c.y);
+        let bend = bend(p0, p1, p2).max(bend(p1, p2, p3));
+        ((0.75 * bend / CURVE_FLATNESS).sqrt().ceil() as usize).clamp(1, MAX_CURVE_STEPS)
+    };
+    let mut from = p0;
+    for i in 1..=steps {
+        let t = i as f64 / steps as f64;
+        let u = 1.0 - t;
+        let (b0, b1, b2, b3) = (u * u * u, 3.0 * u * u * t, 3.0 * u * t * t, t * t * t);
+        let to = Point {
+            x: b0 * p0.x + b1 * p1.x + b2 * p2.x + b3 * p3.x,
+            y: b0 * p0.y + b1 * p1.y + b2 * p2.y + b3 * p3.y,
+        };
+        push_segment(segments, from, to);
+        if (to.x - from.x).abs() > SAME_POINT || (to.y - from.y).abs() > SAME_POINT {
+            from = to;
+        }
+    }
+}
+
 fn emit_path(
     paths: &mut Vec<DrawingPath>,
     segments: &mut Vec<(Point, Point)>,
     state: &GraphicsState,
 ) {
     if !segments.is_empty() {
-        paths.push(DrawingPath {
-            segments: segments.drain(..).collect(),
+        let path = DrawingPath {
+            segments: std::mem::take(segments),
             color: state.stroke_color,
             width: state.line_width,
+        };
+        paths.extend(clip_path(path, state));
+    }
+}
+
+/// A path without the segments that fall outside the clipping paths in
+/// effect, judged by their midpoints, so strokes hidden outside the strip
+/// viewport aren't read as trace; `None` if no segment is visible.
+fn clip_path(mut path: DrawingPath, state: &GraphicsState) -> Option<DrawingPath> {
+    if !state.clip_regions.is_empty() {
+        path.segments.retain(|(a, b)| {
+            let mid = Point {
+                x: (a.x + b.x) / 2.0,
+                y: (a.y + b.y) / 2.0,
+            };
+            state.clip_regions.iter().all(|region| region.contains(mid))
+        });
+    }
+    (!path.segments.is_empty()).then_some(path)
+}
+
+/// RGB of gray (1), RGB (3), or CMYK (4) color operands; `None` for other
+/// counts, as for color spaces with other components, or for patterns.
+fn device_color(operands: &[Object]) -> Option<(f64, f64, f64)> {
+    let values = operands
+        .iter()
+        .map(obj_f64)
+        .collect::<Result<Vec<_>>>()
+        .ok()?;
+    match values[..] {
+        [v] => Some((v, v, v)),
+        [r, g, b] => Some((r, g, b)),
+        [c, m, y, k] => Some((
+            (1.0 - c) * (1.0 - k),
+            (1.0 - m) * (1.0 - k),
+            (1.0 - y) * (1.0 - k),
+        )),
+        _ => None,
+    }
+}
+
+/// Starting color (`/C0` of its function) of the shading named `name` in
+/// the page's `/Shading` resources, or of a shading pattern named `name` in
+/// its `/Pattern` resources.
+fn shading_color(
+    doc: &Document,
+    page_id: ObjectId,
+    category: &[u8],
+    name: &[u8],
+) -> Option<(f64, f64, f64)> {
+    let dict_of = |object: &Object| -> Option<Dictionary> {
+        match deref(doc, object).ok()? {
+            Object::Dictionary(dict) => Some(dict.clone()),
+            Object::Stream(stream) => Some(stream.dict.clone()),
+            _ => None,
+        }
+    };
+    let (inline, ids) = doc.get_page_resources(page_id).ok()?;
+    let resource = inline
+        .into_iter()
+        .chain(ids.into_iter().filter_map(|id| doc.get_dictionary(id).ok()))
+        .find_map(|resources| {
+            let named = dict_of(resources.get(category).ok()?)?;
+            dict_of(named.get(name).ok()?)
+        })?;
+    let shading = match resource.get(b"Shading") {
+        Ok(shading) => dict_of(shading)?,
+        Err(_) => resource,
+    };
+    let function = dict_of(shading.get(b"Function").ok()?)?;
+    let c0 = match deref(doc, function.get(b"C0").ok()?).ok()? {
+        Object::Array(values) => values.clone(),
+        _ => return None,
+    };
+    device_color(&c0)
+}
+
+/// Thickest filled shape, in points, read as a trace by [`fill_centerline`].
+const MAX_FILL_THICKNESS: f64 = 2.0;
+
+/// The centerlines of the thin closed shapes in a filled path, each as a
+/// path in the fill color with the shape's thickness as its width; shapes
+/// thicker than [`MAX_FILL_THICKNESS`] or no longer than thick, such as
+/// backgrounds and dots, are left out.
+///
+/// A thin shape's outline runs out along one side of its centerline and
+/// back along the other, meeting at its leftmost and rightmost points. Each
+/// vertex of the first side is paired with the nearest point of the other,
+/// and the centerline runs through their midpoints, from the middle of one
+/// end cap to the middle of the other.
+fn fill_centerline(segments: &[(Point, Point)], color: (f64, f64, f64)) -> Vec<DrawingPath> {
+    let mut paths = Vec::new();
+    let mut start = 0;
+    for end in 1..=segments.len() {
+        let connected = end < segments.len()
+            && (segments[end].0.x - segments[end - 1].1.x).abs() <= SAME_POINT
+            && (segments[end].0.y - segments[end - 1].1.y).abs() <= SAME_POINT;
+        if !connected {
+            let mut outline: Vec<Point> = segments[start..end].iter().map(|s| s.0).collect();
+            outline.push(segments[end - 1].1);
+            paths.extend(shape_centerline(outline, color));
+            start = end;
+        }
+    }
+    paths
+}
+
+fn shape_centerline(mut outline: Vec<Point>, color: (f64, f64, f64)) -> Option<DrawingPath> {
+    let same =
+        |a: Point, b: Point| (a.x - b.x).abs() <= SAME_POINT && (a.y - b.y).abs() <= SAME_POINT;
+    if outline.len() > 1 && same(outline[0], outline[outline.len() - 1]) {
+        outline.pop();
+    }
+    let n = outline.len();
+    if n < 4 {
+        return None;
+    }
+    let by_x = |a: &&Point, b: &&Point| a.x.total_cmp(&b.x).then(a.y.total_cmp(&b.y));
+    let left = (0..n).min_by(|&i, &j| by_x(&&outline[i], &&outline[j]))?;
+    let right = (0..n).max_by(|&i, &j| by_x(&&outline[i], &&outline[j]))?;
+    // The two sides, each from the leftmost to the rightmost point
+    let side = |step: usize| {
+        let mut points = vec![outline[left]];
+        let mut i = left;
+        while i != right {
+            i = (i + step) % n;
+            points.push(outline[i]);
+        }
+        points
+    };
+    let (one, other) = (side(1), side(n - 1));
+
+    // The other side without the end points, so its cap edges are not
+    // taken as nearest
+    let inner = &other[1..other.len() - 1];
+    let nearest = |p: Point| {
+        if let [only] = inner {
+            return Some(*only);
+        }
+        inner
+            .windows(2)
+            .map(|w| {
+                let (a, b) = (w[0], w[1]);
+                let (dx, dy) = (b.x - a.x, b.y - a.y);
+                let len2 = dx * dx + dy * dy;
+                let t = if len2 > 0.0 {
+                    (((p.x - a.x) * dx + (p.y - a.y) * dy) / len2).clamp(0.0, 1.0)
+                } else {
+                    0.0
+                };
+                Point {
+                    x: a.x + dx * t,
+                    y: a.y + dy * t,
+                }
+            })
+            .min_by(|q, r| {
+                let d = |q: &Point| (q.x - p.x).hypot(q.y - p.y);
+                d(q).total_cmp(&d(r))
+            })
+    };
+    // Thickness and length from the area and perimeter, as of a rectangle
+    let mut area = 0.0;
+    let mut perimeter = 0.0;
+    for (i, &a) in outline.iter().enumerate() {
+        let b = outline[(i + 1) % n];
+        area += a.x * b.y - b.x * a.y;
+        perimeter += (b.x - a.x).hypot(b.y - a.y);
+    }
+    let (area, half) = (area.abs() / 2.0, perimeter / 2.0);
+    let thickness = (half - (half * half - 4.0 * area).max(0.0).sqrt()) / 2.0;
+    if thickness > MAX_FILL_THICKNESS || half - thickness <= thickness {
+        return None;
+    }
+
+    let mut middles = Vec::with_capacity(one.len());
+    for &p in &one[1..one.len() - 1] {
+        let q = nearest(p)?;
+        middles.push(Point {
+            x: (p.x + q.x) / 2.0,
+            y: (p.y + q.y) / 2.0,
         });
     }
+
+    // End caps: the end point and whichever neighbor lies across the shape,
+    // rather than along it toward the centerline's far part. A neighbor on
+    // the first side is then paired by the cap, not as a middle.
+    let cap = |end: Point, a: Point, b: Point, toward: Point| {
+        let (dx, dy) = (toward.x - end.x, toward.y - end.y);
+        let along = |p: Point| {
+            let (px, py) = (p.x - end.x, p.y - end.y);
+            (px * dx + py * dy).abs() / (px.hypot(py) * dx.hypot(dy)).max(f64::MIN_POSITIVE)
+        };
+        let (across, on_first_side) = if along(a) <= along(b) {
+            (a, true)
+        } else {
+            (b, false)
+        };
+        if (across.x - end.x).hypot(across.y - end.y) <= 1.5 * thickness {
+            let middle = Point {
+                x: (end.x + across.x) / 2.0,
+                y: (end.y + across.y) / 2.0,
+            };
+            (middle, on_first_side)
+        } else {
+            (end, false)
+        }
+    };
+    let beyond = |end: Point, mut candidates: Box<dyn Iterator<Item = Point> + '_>, other_end| {
+        candidates
+            .find(|p: &Point| (p.x - end.x).hypot(p.y - end.y) > 2.0 * thickness)
+            .unwrap_or(other_end)
+    };
+    let (start, finish) = (one[0], one[one.len() - 1]);
+    let (first, skip_first) = cap(
+        start,
+        one[1],
+        other[1],
+        beyond(start, Box::new(middles.iter().copied()), finish),
+    );
+    let (last, skip_last) = cap(
+        finish,
+        one[one.len() - 2],
+        other[other.len() - 2],
+        beyond(finish, Box::new(middles.iter().rev().copied()), start),
+    );
+    if skip_last {
+        middles.pop();
+    }
+    if skip_first && !middles.is_empty() {
+        middles.remove(0);
+    }
+    let mut segments = Vec::with_capacity(middles.len() + 1);
+    let mut from = first;
+    for to in middles.into_iter().chain(std::iter::once(last)) {
+        push_segment(&mut segments, from, to);
+        if !same(from, to) {
+            from = to;
+        }
+    }
+    (!segments.is_empty()).then_some(DrawingPath {
+        segments,
+        color,
+        width: thickness,
+    })
 }
@@ -0,0 +1,181 @@
+use anyhow::{anyhow, Result};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// What [`rebuild_xref`] found in a damaged PDF.
+#[derive(Debug, Clone, Default)]
+pub struct RepairReport {
+    /// Objects whose definition is complete, by object number.
+    pub recovered: Vec<u32>,
+    /// Objects cut off before their `endobj`, left out.
+    pub truncated: Vec<u32>,
+}
+
+impl RepairReport {
+    /// Warning lines for the user.
+    pub fn warnings(&self) -> Vec<String> {
+        let mut lines = vec![format!(
+            "recovered {} objects by scanning the file for object definitions",
+            self.recovered.len()
+        )];
+        if !self.truncated.is_empty() {
+            lines.push(format!(
+                "left out {} truncated object(s): {}",
+                self.truncated.len(),
+                join_ids(&self.truncated)
+            ));
+        }
+        lines
+    }
+}
+
+fn join_ids(ids: &[u32]) -> String {
+    ids.iter()
+        .map(u32::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// An object definition found by scanning: number, generation, and offset.
+struct Found {
+    id: u32,
+    generation: u16,
+    offset: usize,
+}
+
+/// Rebuild the cross-reference table of a PDF whose table is damaged or
+/// missing, as in files truncated mid-transfer.
+///
+/// The file is scanned for `N G obj` definitions; each one followed by its
+/// `endobj` is kept, the last definition of a number winning as with
+/// incremental updates. A fresh table and trailer, pointing at the `/Root`
+/// (and `/Info`) named in the file or else at the object with
+/// `/Type /Catalog`, are appended to the original bytes.
+pub fn rebuild_xref(bytes: &[u8]) -> Result<(Vec<u8>, RepairReport)> {
+    let found = scan_objects(bytes);
+    let mut report = RepairReport::default();
+    let mut table: BTreeMap<u32, (u16, usize)> = BTreeMap::new();
+    for (i, object) in found.iter().enumerate() {
+        let end = found.get(i + 1).map_or(bytes.len(), |next| next.offset);
+        if find(&bytes[object.offset..end], b"endobj").is_some() {
+            table.insert(object.id, (object.generation, object.offset));
+        } else {
+            report.truncated.push(object.id);
+        }
+    }
+    report.truncated.retain(|id| !table.contains_key(id));
+    report.recovered = table.keys().copied().collect();
+    if table.is_empty() {
+        return Err(anyhow!("no complete objects found"));
+    }
+
+    let root = reference_after(bytes, b"/Root")
+        .or_else(|| catalog(bytes, &found))
+        .ok_or_else(|| anyhow!("no document catalog found"))?;
+    let info = reference_after(bytes, b"/Info");
+
+    let size = table.keys().last().map_or(1, |&id| id + 1);
+    let mut repaired = bytes.to_vec();
+    repaired.push(b'\n');
+    let xref_start = repaired.len();
+    let mut xref = format!("xref\n0 {}\n", size);
+    for id in 0..size {
+        match table.get(&id) {
+            Some(&(generation, offset)) => {
+                writeln!(xref, "{:010} {:05} n ", offset, generation)?;
+            }
+            None => writeln!(xref, "{:010} {:05} f ", 0, if id == 0 { 65535 } else { 0 })?,
+        }
+    }
+    write!(xref, "trailer\n<< /Size {} /Root {} 0 R", size, root)?;
+    if let Some(info) = info.filter(|id| table.contains_key(id)) {
+        write!(xref, " /Info {} 0 R", info)?;
+    }
+    write!(xref, " >>\nstartxref\n{}\n%%EOF\n", xref_start)?;
+    repaired.extend_from_slice(xref.as_bytes());
+    Ok((repaired, report))
+}
+
+/// Every `N G obj` definition, in file order.
+fn scan_objects(bytes: &[u8]) -> Vec<Found> {
+    let mut found = Vec::new();
+    let mut from = 0;
+    while let Some(at) = find(&bytes[from..], b"obj").map(|i| from + i) {
+        from = at + 3;
+        // Not the start of a longer keyword or name, e.g. "objstm"
+        if bytes.get(at + 3).is_some_and(|c| c.is_ascii_alphanumeric()) {
+            continue;
+        }
+        let mut i = at;
+        let Some(generation) = number_before(bytes, &mut i) else {
+            continue;
+        };
+        let Some(id) = number_before(bytes, &mut i) else {
+            continue;
+        };
+        if i > 0 && !bytes[i - 1].is_ascii_whitespace() {
+            continue;
+        }
+        if let (Ok(id), Ok(generation)) = (u32::try_from(id), u16::try_from(generation)) {
+            found.push(Found {
+                id,
+                generation,
+                offset: i,
+            });
+        }
+    }
+    found
+}
+
+/// The number ending just before `*i`, after skipping whitespace, moving
+/// `*i` to its first digit.
+fn number_before(bytes: &[u8], i: &mut usize) -> Option<u64> {
+    let mut end = *i;
+    while end > 0 && bytes[end - 1].is_ascii_whitespace() {
+        end -= 1;
+    }
+    if end == *i {
+        return None;
+    }
+    let mut start = end;
+    while start > 0 && bytes[start - 1].is_ascii_digit() {
+        start -= 1;
+    }
+    if start == end || end - start > 10 {
+        return None;
+    }
+    *i = start;
+    std::str::from_utf8(&bytes[start..end]).ok()?.parse().ok()
+}
+
+/// The object number of the last `key N G R` reference in the file.
+fn reference_after(bytes: &[u8], key: &[u8]) -> Option<u32> {
+    let mut last = None;
+    let mut from = 0;
+    while let Some(at) = find(&bytes[from..], key).map(|i| from + i) {
+        from = at + key.len();
+        let rest = &bytes[from..bytes.len().min(from + 32)];
+        let text = String::from_utf8_lossy(rest);
+        let mut parts = text.split_ascii_whitespace();
+        if let (Some(id), Some(_), Some(r)) = (parts.next(), parts.next(), parts.next()) {
+            if r.starts_with('R') {
+                last = id.parse().ok().or(last);
+            }
+        }
+    }
+    last
+}
+
+/// The object number of the last object with `/Type /Catalog`.
+fn catalog(bytes: &[u8], found: &[Found]) -> Option<u32> {
+    found.iter().enumerate().rev().find_map(|(i, object)| {
+        let end = found.get(i + 1).map_or(bytes.len(), |next| next.offset);
+        let body = &bytes[object.offset..end];
+        (find(body, b"/Type /Catalog").is_some() || find(body, b"/Type/Catalog").is_some())
+            .then_some(object.id)
+    })
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
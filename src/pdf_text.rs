@@ -0,0 +1,1266 @@
+use anyhow::{anyhow, Result};
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, NaiveTime, Timelike};
+use lopdf::content::Content;
+use lopdf::{Dictionary, Document, Encoding, Object, ObjectId};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::audit::errln;
+use crate::edf_write::EDF_YEARS;
+use crate::pdf_extract::{get_page_geometry, multiply_ctm, obj_f64};
+
+/// A run of text shown by a single text operator, positioned on the page as
+/// displayed.
+#[derive(Debug, Clone)]
+pub struct TextRun {
+    pub text: String,
+    /// Origin of the run, in points from the displayed page's bottom-left corner.
+    pub x: f64,
+    pub y: f64,
+}
+
+const IDENTITY: [f64; 6] = [1.0, 0.0, 0.0, 1.0, 0.0, 0.0];
+
+/// How a font's string bytes map to text: its ToUnicode CMap if it has one,
+/// else for simple fonts the base encoding with its Differences.
+#[derive(Debug, Clone)]
+struct FontEncoding {
+    /// Bytes per character code: 1 for simple fonts, usually 2 for
+    /// composite (Type0) fonts.
+    code_bytes: usize,
+    /// Text of each character code.
+    codes: HashMap<u32, String>,
+}
+
+impl FontEncoding {
+    fn new(doc: &Document, font: &Dictionary) -> Self {
+        let composite = font.get(b"Subtype").and_then(Object::as_name).ok() == Some(b"Type0");
+        let mut encoding = Self {
+            code_bytes: if composite { 2 } else { 1 },
+            codes: HashMap::new(),
+        };
+        if !composite {
+            encoding.add_simple_encoding(doc, font);
+        }
+        let to_unicode = font
+            .get(b"ToUnicode")
+            .and_then(|o| doc.dereference(o))
+            .and_then(|(_, o)| o.as_stream());
+        if let Ok(stream) = to_unicode {
+            let data = stream
+                .decompressed_content()
+                .unwrap_or_else(|_| stream.content.clone());
+            encoding.add_to_unicode(&data);
+        }
+        encoding
+    }
+
+    /// Map each byte through the font's base encoding and Differences.
+    fn add_simple_encoding(&mut self, doc: &Document, font: &Dictionary) {
+        let encoding = font.get(b"Encoding").and_then(|o| doc.dereference(o));
+        let (base, differences) = match encoding {
+            Ok((_, Object::Name(name))) => (name.as_slice(), None),
+            Ok((_, Object::Dictionary(dict))) => (
+                dict.get(b"BaseEncoding")
+                    .and_then(Object::as_name)
+                    .unwrap_or(b"WinAnsiEncoding"),
+                dict.get(b"Differences").and_then(Object::as_array).ok(),
+            ),
+            _ => (b"WinAnsiEncoding".as_slice(), None),
+        };
+        for (code, c) in base_encoding(doc, base).into_iter().enumerate() {
+            if let Some(c) = c {
+                self.codes.insert(code as u32, c.to_string());
+            }
+        }
+        // Differences: a starting code, then glyph names for consecutive codes
+        let mut code = 0;
+        for item in differences.into_iter().flatten() {
+            match item {
+                Object::Integer(start) => code = *start as u32,
+                Object::Name(name) => {
+                    if let Some(text) = glyph_text(&String::from_utf8_lossy(name)) {
+                        self.codes.insert(code, text);
+                    }
+                    code += 1;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Map codes through a ToUnicode CMap, over any other encoding.
+    fn add_to_unicode(&mut self, data: &[u8]) {
+        let tokens = cmap_tokens(data);
+        let mut i = 0;
+        let hex = |i: usize| match tokens.get(i) {
+            Some(CMapToken::Hex(bytes)) => Some(bytes.as_slice()),
+            _ => None,
+        };
+        while i < tokens.len() {
+            match &tokens[i] {
+                CMapToken::Word(w) if w == "begincodespacerange" => {
+                    if let Some(low) = hex(i + 1) {
+                        self.code_bytes = low.len().max(1);
+                    }
+                }
+                CMapToken::Word(w) if w == "beginbfchar" => {
+                    while let (Some(code), Some(text)) = (hex(i + 1), hex(i + 2)) {
+                        self.codes.insert(code_value(code), utf16_text(text));
+                        i += 2;
+                    }
+                }
+                CMapToken::Word(w) if w == "beginbfrange" => {
+                    while let (Some(low), Some(high)) = (hex(i + 1), hex(i + 2)) {
+                        let (low, high) = (code_value(low), code_value(high));
+                        i += 2;
+                        if let Some(first) = hex(i + 1) {
+                            // Consecutive codes map to consecutive last characters
+                            let mut units = utf16_units(first);
+                            for code in low..=high.min(low + 0xFFFF) {
+                                self.codes.insert(code, String::from_utf16_lossy(&units));
+                                if let Some(last) = units.last_mut() {
+                                    *last = last.wrapping_add(1);
+                                }
+                            }
+                            i += 1;
+                        } else if matches!(tokens.get(i + 1), Some(CMapToken::ArrayStart)) {
+                            i += 1;
+                            let mut code = low;
+                            while let Some(text) = hex(i + 1) {
+                                self.codes.insert(code, utf16_text(text));
+                                code += 1;
+                                i += 1;
+                            }
+                            i += 1;
+                        }
+                    }
+                }
+                _ => {}
+            }
+            i += 1;
+        }
+    }
+
+    /// Decode a string's bytes; codes without a mapping are dropped, except
+    /// in simple fonts, where they are read as Latin-1.
+    fn decode(&self, bytes: &[u8]) -> String {
+        let mut text = String::new();
+        for chunk in bytes.chunks(self.code_bytes) {
+            let code = code_value(chunk);
+            match self.codes.get(&code) {
+                Some(mapped) => text.push_str(mapped),
+                None if self.code_bytes == 1 => text.push(chunk[0] as char),
+                None => {}
+            }
+        }
+        expand_ligatures(&text)
+    }
+}
+
+/// The characters of a named base encoding, e.g. "MacRomanEncoding".
+fn base_encoding(doc: &Document, name: &[u8]) -> [Option<char>; 256] {
+    let mut font = Dictionary::new();
+    font.set("Type", Object::Name(b"Font".to_vec()));
+    font.set("Encoding", Object::Name(name.to_vec()));
+    let mut chars = [None; 256];
+    if let Ok(Encoding::OneByteEncoding(table)) = font.get_font_encoding(doc) {
+        for (c, &unit) in chars.iter_mut().zip(table.iter()) {
+            *c = unit.and_then(|u| char::from_u32(u as u32));
+        }
+    } else {
+        for (code, c) in chars.iter_mut().enumerate() {
+            *c = Some(code as u8 as char);
+        }
+    }
+    chars
+}
+
+/// Unicode text for a glyph name used in an encoding's Differences: the
+/// "uniXXXX" and "uXXXX[XX]" forms, single characters, and common names.
+fn glyph_text(name: &str) -> Option<String> {
+    let hex = name.strip_prefix("uni").or_else(|| name.strip_prefix('u'));
+    if let Some(hex) = hex.filter(|h| h.len() >= 4 && h.bytes().all(|b| b.is_ascii_hexdigit())) {
+        let units: Vec<u16> = if name.starts_with("uni") {
+            hex.as_bytes()
+                .chunks(4)
+                .map(|c| u16::from_str_radix(std::str::from_utf8(c).ok()?, 16).ok())
+                .collect::<Option<_>>()?
+        } else {
+            let c = char::from_u32(u32::from_str_radix(hex, 16).ok()?)?;
+            return Some(c.to_string());
+        };
+        return Some(String::from_utf16_lossy(&units));
+    }
+    if name.chars().count() == 1 {
+        return Some(name.to_string());
+    }
+    const NAMES: &[(&str, &str)] = &[
+        ("space", " "),
+        ("exclam", "!"),
+        ("numbersign", "#"),
+        ("percent", "%"),
+        ("ampersand", "&"),
+        ("quotesingle", "'"),
+        ("quoteright", "\u{2019}"),
+        ("quoteleft", "\u{2018}"),
+        ("quotedbl", "\""),
+        ("parenleft", "("),
+        ("parenright", ")"),
+        ("asterisk", "*"),
+        ("plus", "+"),
+        ("comma", ","),
+        ("hyphen", "-"),
+        ("minus", "\u{2212}"),
+        ("period", "."),
+        ("slash", "/"),
+        ("colon", ":"),
+        ("semicolon", ";"),
+        ("less", "<"),
+        ("equal", "="),
+        ("greater", ">"),
+        ("question", "?"),
+        ("at", "@"),
+        ("bracketleft", "["),
+        ("bracketright", "]"),
+        ("underscore", "_"),
+        ("zero", "0"),
+        ("one", "1"),
+        ("two", "2"),
+        ("three", "3"),
+        ("four", "4"),
+        ("five", "5"),
+        ("six", "6"),
+        ("seven", "7"),
+        ("eight", "8"),
+        ("nine", "9"),
+        ("endash", "\u{2013}"),
+        ("emdash", "\u{2014}"),
+        ("bullet", "\u{2022}"),
+        ("degree", "\u{b0}"),
+        ("mu", "\u{b5}"),
+        ("copyright", "\u{a9}"),
+        ("registered", "\u{ae}"),
+        ("nbspace", "\u{a0}"),
+        ("fi", "fi"),
+        ("fl", "fl"),
+        ("ff", "ff"),
+        ("ffi", "ffi"),
+        ("ffl", "ffl"),
+    ];
+    NAMES
+        .iter()
+        .find(|(glyph, _)| *glyph == name)
+        .map(|(_, text)| text.to_string())
+}
+
+/// Spell out typographic ligatures, so "ﬁ" reads as "fi".
+fn expand_ligatures(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\u{FB00}' => out.push_str("ff"),
+            '\u{FB01}' => out.push_str("fi"),
+            '\u{FB02}' => out.push_str("fl"),
+            '\u{FB03}' => out.push_str("ffi"),
+            '\u{FB04}' => out.push_str("ffl"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// A big-endian character code.
+fn code_value(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0, |code, &b| code << 8 | b as u32)
+}
+
+fn utf16_units(bytes: &[u8]) -> Vec<u16> {
+    bytes
+        .chunks(2)
+        .map(|c| (c[0] as u16) << 8 | c.get(1).copied().unwrap_or(0) as u16)
+        .collect()
+}
+
+fn utf16_text(bytes: &[u8]) -> String {
+    String::from_utf16_lossy(&utf16_units(bytes))
+}
+
+/// Tokens of a CMap program, as far as reading its mappings goes.
+#[derive(Debug, Clone, PartialEq)]
+enum CMapToken {
+    Hex(Vec<u8>),
+    ArrayStart,
+    ArrayEnd,
+    Word(String),
+}
+
+fn cmap_tokens(data: &[u8]) -> Vec<CMapToken> {
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < data.len() {
+        match data[i] {
+            b'%' => {
+                while i < data.len() && data[i] != b'\n' && data[i] != b'\r' {
+                    i += 1;
+                }
+            }
+            b'<' if data.get(i + 1) == Some(&b'<') => i += 1,
+            b'>' if data.get(i + 1) == Some(&b'>') => i += 1,
+            b'<' => {
+                let end = data[i..]
+                    .iter()
+                    .position(|&b| b == b'>')
+                    .map_or(data.len(), |p| i + p);
+                let digits: Vec<u8> = data[i + 1..end]
+                    .iter()
+                    .filter(|b| b.is_ascii_hexdigit())
+                    .copied()
+                    .collect();
+                let bytes = digits
+                    .chunks(2)
+                    .map(|pair| {
+                        let hex = std::str::from_utf8(pair).unwrap_or("0");
+                        let value = u8::from_str_radix(hex, 16).unwrap_or(0);
+                        if pair.len() == 1 {
+                            value << 4
+                        } else {
+                            value
+                        }
+                    })
+                    .collect();
+                tokens.push(CMapToken::Hex(bytes));
+                i = end;
+            }
+            b'[' => tokens.push(CMapToken::ArrayStart),
+            b']' => tokens.push(CMapToken::ArrayEnd),
+            b if b.is_ascii_whitespace() => {}
+            _ => {
+                let start = i;
+                while i < data.len() && !b"[]<>%/() \t\r\n".contains(&data[i]) {
+                    i += 1;
+                }
+                if i == start {
+                    i += 1;
+                    continue;
+                }
+                tokens.push(CMapToken::Word(
+                    String::from_utf8_lossy(&data[start..i]).into_owned(),
+                ));
+                continue;
+            }
+        }
+        i += 1;
+    }
+    tokens
+}
+
+/// The text encodings of a page's fonts, by resource name.
+fn page_fonts(doc: &Document, page_id: ObjectId) -> HashMap<Vec<u8>, FontEncoding> {
+    doc.get_page_fonts(page_id)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(name, font)| (name, FontEncoding::new(doc, font)))
+        .collect()
+}
+
+/// Decode a PDF string operand in the current font, or byte by byte as
+/// Latin-1 when no font has been selected.
+fn decode_string(obj: &Object, font: Option<&FontEncoding>) -> Option<String> {
+    match obj {
+        Object::String(bytes, _) => Some(match font {
+            Some(font) => font.decode(bytes),
+            None => bytes.iter().map(|&b| b as char).collect(),
+        }),
+        _ => None,
+    }
+}
+
+/// Decode a TJ array, inserting a space where the kerning adjustment
+/// is large enough to be a word gap.
+fn decode_array(items: &[Object], font: Option<&FontEncoding>) -> String {
+    let mut text = String::new();
+    for item in items {
+        match item {
+            Object::Integer(_) | Object::Real(_) => {
+                // Adjustments are in thousandths of text space; negative moves right
+                if obj_f64(item).unwrap_or(0.0) < -200.0 {
+                    text.push(' ');
+                }
+            }
+            _ => {
+                if let Some(s) = decode_string(item, font) {
+                    text.push_str(&s);
+                }
+            }
+        }
+    }
+    text
+}
+
+/// Extract all text runs (Tj, TJ, ', ") from a PDF page's content stream.
+pub fn extract_text_runs(doc: &Document, page_id: ObjectId) -> Result<Vec<TextRun>> {
+    let content_bytes = doc.get_page_content(page_id)?;
+    let content = Content::decode(&content_bytes).map_err(|e| anyhow!("{}", e))?;
+
+    let fonts = page_fonts(doc, page_id);
+    let geometry = get_page_geometry(doc, page_id)?;
+
+    let mut runs = Vec::new();
+    // Starts from the displayed page's top-left-origin coordinates
+    let mut ctm = geometry.matrix;
+    let mut font: Option<&FontEncoding> = None;
+    let mut stack: Vec<([f64; 6], Option<&FontEncoding>)> = Vec::new();
+    // Text matrix and text line matrix
+    let mut tm = IDENTITY;
+    let mut tlm = IDENTITY;
+    let mut leading = 0.0;
+
+    for op in &content.operations {
+        let text = match op.operator.as_str() {
+            "q" => {
+                stack.push((ctm, font));
+                None
+            }
+            "Q" => {
+                if let Some((m, f)) = stack.pop() {
+                    ctm = m;
+                    font = f;
+                }
+                None
+            }
+            "cm" if op.operands.len() == 6 => {
+                let mut m = [0.0; 6];
+                for (v, o) in m.iter_mut().zip(&op.operands) {
+                    *v = obj_f64(o)?;
+                }
+                ctm = multiply_ctm(&ctm, &m);
+                None
+            }
+
+            // Begin text object
+            "BT" => {
+                tm = IDENTITY;
+                tlm = IDENTITY;
+                None
+            }
+
+            // Text font; its size only scales glyphs, not positions
+            "Tf" => {
+                font = op
+                    .operands
+                    .first()
+                    .and_then(|name| name.as_name().ok())
+                    .and_then(|name| fonts.get(name));
+                None
+            }
+
+            // Text positioning
+            "Tm" if op.operands.len() == 6 => {
+                for (v, o) in tm.iter_mut().zip(&op.operands) {
+                    *v = obj_f64(o)?;
+                }
+                tlm = tm;
+                None
+            }
+            "Td" | "TD" if op.operands.len() == 2 => {
+                let tx = obj_f64(&op.operands[0])?;
+                let ty = obj_f64(&op.operands[1])?;
+                if op.operator == "TD" {
+                    leading = -ty;
+                }
+                tlm = multiply_ctm(&tlm, &[1.0, 0.0, 0.0, 1.0, tx, ty]);
+                tm = tlm;
+                None
+            }
+            "TL" if op.operands.len() == 1 => {
+                leading = obj_f64(&op.operands[0])?;
+                None
+            }
+            "T*" => {
+                tlm = multiply_ctm(&tlm, &[1.0, 0.0, 0.0, 1.0, 0.0, -leading]);
+                tm = tlm;
+                None
+            }
+
+            // Text showing
+            "Tj" => op.operands.first().and_then(|o| decode_string(o, font)),
+            "TJ" => match op.operands.first() {
+                Some(Object::Array(items)) => Some(decode_array(items, font)),
+                _ => None,
+            },
+            "'" | "\"" => {
+                tlm = multiply_ctm(&tlm, &[1.0, 0.0, 0.0, 1.0, 0.0, -leading]);
+                tm = tlm;
+                op.operands.last().and_then(|o| decode_string(o, font))
+            }
+
+            _ => None,
+        };
+
+        if let Some(text) = text {
+            let m = multiply_ctm(&ctm, &tm);
+            runs.push(TextRun {
+                text,
+                x: m[4],
+                y: geometry.height - m[5],
+            });
+        }
+    }
+
+    Ok(runs)
+}
+
+/// Group a page's text runs into lines, top to bottom, each joined left to right.
+pub fn group_lines(runs: &[TextRun]) -> Vec<String> {
+    let mut runs = runs.to_vec();
+    runs.sort_by(|a, b| b.y.total_cmp(&a.y));
+
+    let mut lines: Vec<Vec<TextRun>> = Vec::new();
+    for run in runs {
+        match lines.last_mut() {
+            // Runs within 2 units vertically share a line
+            Some(line) if (line[0].y - run.y).abs() < 2.0 => line.push(run),
+            _ => lines.push(vec![run]),
+        }
+    }
+
+    lines
+        .into_iter()
+        .map(|mut line| {
+            line.sort_by(|a, b| a.x.total_cmp(&b.x));
+            let text: Vec<&str> = line.iter().map(|r| r.text.as_str()).collect();
+            text.join(" ")
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+        .filter(|line| !line.is_empty())
+        .collect()
+}
+
+/// Find the text value printed for a label.
+///
+/// Labels ending in ':' are followed by their value on the same line
+/// ("Patient: Jane Doe"); other labels are column headings with the value
+/// directly below. Returns the nearest run in that position.
+pub fn value_after_label(runs: &[TextRun], label: &str) -> Option<String> {
+    let label_run = runs.iter().find(|r| r.text.trim() == label)?;
+    let right = || {
+        runs.iter()
+            .filter(|r| (r.y - label_run.y).abs() < 2.0 && r.x > label_run.x + 0.1)
+            .min_by(|a, b| a.x.total_cmp(&b.x))
+    };
+    let below = || {
+        runs.iter()
+            .filter(|r| (r.x - label_run.x).abs() < 2.0 && r.y < label_run.y - 2.0)
+            .max_by(|a, b| a.y.total_cmp(&b.y))
+    };
+    let value = if label.ends_with(':') {
+        right().or_else(below)
+    } else {
+        below()
+    };
+    value
+        .map(|r| r.text.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|text| !text.is_empty())
+}
+
+/// Patient details printed on the report.
+#[derive(Debug, Clone, Default)]
+pub struct Patient {
+    pub name: Option<String>,
+    /// 'M' or 'F'.
+    pub sex: Option<char>,
+    pub birthdate: Option<NaiveDate>,
+}
+
+/// Find the patient name, sex, and date of birth in the report text.
+///
+/// Kardia reports print "Prepared for", "Sex", and "Date of Birth" labels above
+/// their values on page 1, and "Patient:" beside the name on the strip pages.
+/// Each field is taken from the first page that has it. Numeric birthdates
+/// are read in `order` (see [`DateOrder::resolve`]), and two-digit birth
+/// years are resolved so the birthdate is not after the recording.
+pub fn find_patient(
+    pages: &[Vec<TextRun>],
+    recorded: Option<NaiveDateTime>,
+    order: DateOrder,
+) -> Patient {
+    let mut patient = Patient::default();
+    for runs in pages {
+        if patient.name.is_none() {
+            patient.name = value_after_label(runs, "Prepared for")
+                .or_else(|| value_after_label(runs, "Patient:"));
+        }
+        if patient.sex.is_none() {
+            patient.sex = value_after_label(runs, "Sex").and_then(|s| {
+                match s.to_ascii_lowercase().as_str() {
+                    "male" | "m" => Some('M'),
+                    "female" | "f" => Some('F'),
+                    _ => None,
+                }
+            });
+        }
+        if patient.birthdate.is_none() {
+            patient.birthdate = value_after_label(runs, "Date of Birth").and_then(|s| {
+                let (date, other) =
+                    parse_numeric_date(s.trim(), order, recorded.map(|t| t.date()))?;
+                if let Some(other) = other {
+                    warn_ambiguous(s.trim(), date, other);
+                }
+                Some(date)
+            });
+        }
+    }
+    patient
+}
+
+/// Order of the day and month in numeric dates such as "03/02/2026".
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DateOrder {
+    /// Month first if the report prints 12-hour times with AM/PM, as US
+    /// reports do, otherwise day first. Dates that read either way are
+    /// logged.
+    #[default]
+    Auto,
+    /// Day first, as in "13/02/2026".
+    DayFirst,
+    /// Month first, as in "02/13/2026".
+    MonthFirst,
+}
+
+impl DateOrder {
+    /// This order, or `fallback` if it is [`DateOrder::Auto`].
+    pub fn or(self, fallback: DateOrder) -> DateOrder {
+        match self {
+            DateOrder::Auto => fallback,
+            order => order,
+        }
+    }
+
+    /// This order, or for [`DateOrder::Auto`] month first if a line of the
+    /// report has a time with AM/PM. Without one it stays `Auto`, read day
+    /// first.
+    pub fn resolve(self, lines: &[String]) -> DateOrder {
+        match self {
+            DateOrder::Auto if lines.iter().any(|line| has_meridiem(line)) => DateOrder::MonthFirst,
+            order => order,
+        }
+    }
+}
+
+/// Whether a line has a time with an AM/PM marker, e.g. "10:42:00 PM".
+fn has_meridiem(line: &str) -> bool {
+    let tokens = date_tokens(line);
+    tokens
+        .iter()
+        .enumerate()
+        .any(|(i, token)| match parse_time(token) {
+            Some((_, Some(_))) => true,
+            Some((_, None)) => tokens
+                .get(i + 1)
+                .is_some_and(|m| parse_meridiem(m).is_some()),
+            None => false,
+        })
+}
+
+/// Warn of a numeric date read day first that also reads month first.
+fn warn_ambiguous(text: &str, date: NaiveDate, other: NaiveDate) {
+    errln!(
+        "Warning: date {} is ambiguous; read day first as {} rather than {} (use --date-order to choose)",
+        text,
+        date,
+        other
+    );
+}
+
+/// Find the recording start date/time in the report text, with numeric
+/// dates read in `order` (see [`DateOrder::resolve`]).
+///
+/// Kardia reports print it as e.g. "Recorded on: Friday, February 13, 2026 at 10:42:00 PM",
+/// with the date order and month names depending on the locale.
+pub fn find_recording_start(lines: &[String], order: DateOrder) -> Option<NaiveDateTime> {
+    lines.iter().find_map(|line| parse_date_time(line, order))
+}
+
+/// Month names by month, in English, German, French, and Spanish.
+const MONTH_NAMES: [&[&str]; 12] = [
+    &["january", "januar", "janvier", "enero"],
+    &["february", "februar", "février", "febrero"],
+    &["march", "märz", "mars", "marzo"],
+    &["april", "avril", "abril"],
+    &["may", "mai", "mayo"],
+    &["june", "juni", "juin", "junio"],
+    &["july", "juli", "juillet", "julio"],
+    &["august", "août", "agosto"],
+    &["september", "septembre", "septiembre"],
+    &["october", "oktober", "octobre", "octubre"],
+    &["november", "novembre", "noviembre"],
+    &["december", "dezember", "décembre", "diciembre"],
+];
+
+/// Parse a month name or an abbreviation of at least three letters
+/// ("Feb", "févr.", "Sept"), if it names a single month.
+fn parse_month_name(token: &str) -> Option<u32> {
+    let token = token.trim_end_matches('.').to_lowercase();
+    if token.chars().count() < 3 {
+        return None;
+    }
+    let mut months = (1..=12u32).filter(|&m| {
+        MONTH_NAMES[m as usize - 1]
+            .iter()
+            .any(|name| name.starts_with(&token))
+    });
+    let month = months.next()?;
+    months.next().is_none().then_some(month)
+}
+
+/// Parse a day of the month, allowing a trailing "." or English ordinal suffix.
+fn parse_day(token: &str) -> Option<u32> {
+    let token = token.trim_end_matches('.');
+    let digits = token.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let day: u32 = digits.parse().ok()?;
+    (1..=31).contains(&day).then_some(day)
+}
+
+/// Parse a four-digit year.
+fn parse_year(token: &str) -> Option<i32> {
+    (token.len() == 4).then(|| token.parse().ok()).flatten()
+}
+
+/// Parse a numeric date: "2026-02-13", or day and month in `order` as in
+/// "13/02/2026", "13.02.2026", and "13-02-26". The other order is used
+/// when `order`'s reading is impossible. Two-digit years are resolved as
+/// [`expand_year`] does.
+///
+/// Returns the date, and for [`DateOrder::Auto`] the month-first reading
+/// too if it is another valid date.
+fn parse_numeric_date(
+    token: &str,
+    order: DateOrder,
+    not_after: Option<NaiveDate>,
+) -> Option<(NaiveDate, Option<NaiveDate>)> {
+    let parts: Vec<&str> = token.split(['/', '.', '-']).collect();
+    let [a, b, c] = parts[..] else {
+        return None;
+    };
+    let num = |p: &str| -> Option<u32> {
+        (!p.is_empty() && p.len() <= 4 && p.bytes().all(|b| b.is_ascii_digit()))
+            .then(|| p.parse().ok())
+            .flatten()
+    };
+    let (a, b, c) = (num(a)?, num(b)?, num(c)?);
+    if parts[0].len() == 4 {
+        return NaiveDate::from_ymd_opt(a as i32, b, c).map(|date| (date, None));
+    }
+    let year = match parts[2].len() {
+        4 => c as i32,
+        2 => expand_year(c, not_after),
+        _ => return None,
+    };
+    let day_first = NaiveDate::from_ymd_opt(year, b, a);
+    let month_first = NaiveDate::from_ymd_opt(year, a, b);
+    let (date, other) = match order {
+        DateOrder::MonthFirst => (month_first, day_first),
+        DateOrder::Auto | DateOrder::DayFirst => (day_first, month_first),
+    };
+    match date {
+        Some(date) => {
+            let ambiguous = other.filter(|&other| order == DateOrder::Auto && other != date);
+            Some((date, ambiguous))
+        }
+        None => other.map(|date| (date, None)),
+    }
+}
+
+/// The year of a two-digit year: the latest not after `not_after`, or
+/// without it by the EDF rule, 85-99 as 1985-1999 and 00-84 as 2000-2084.
+fn expand_year(year: u32, not_after: Option<NaiveDate>) -> i32 {
+    let latest = not_after.map_or(*EDF_YEARS.end(), |d| d.year());
+    let year = 2000 + year as i32;
+    if year > latest {
+        year - 100
+    } else {
+        year
+    }
+}
+
+/// Parse a date starting at `tokens[0]`, returning it, the month-first
+/// reading of an ambiguous numeric date (see [`parse_numeric_date`]), and
+/// the tokens used.
+///
+/// Accepts "February 13 2026", "13 Feb 2026", and numeric dates.
+fn parse_date(tokens: &[&str], order: DateOrder) -> Option<(NaiveDate, Option<NaiveDate>, usize)> {
+    if let Some((date, other)) = tokens
+        .first()
+        .and_then(|t| parse_numeric_date(t, order, None))
+    {
+        return Some((date, other, 1));
+    }
+    let [t0, t1, t2, ..] = tokens else {
+        return None;
+    };
+    let (month, day) = match (parse_month_name(t0), parse_month_name(t1)) {
+        (Some(month), _) => (month, parse_day(t1)?),
+        (None, Some(month)) => (month, parse_day(t0)?),
+        _ => return None,
+    };
+    NaiveDate::from_ymd_opt(parse_year(t2)?, month, day).map(|date| (date, None, 3))
+}
+
+/// Parse a "h:mm[:ss]" time, optionally with an attached "AM"/"PM".
+fn parse_time(token: &str) -> Option<(NaiveTime, Option<bool>)> {
+    let lower = token.to_ascii_lowercase();
+    let (clock, pm) = match lower.strip_suffix("pm").or(lower.strip_suffix("p.m.")) {
+        Some(clock) => (clock, Some(true)),
+        None => match lower.strip_suffix("am").or(lower.strip_suffix("a.m.")) {
+            Some(clock) => (clock, Some(false)),
+            None => (lower.as_str(), None),
+        },
+    };
+    let time = NaiveTime::parse_from_str(clock, "%H:%M:%S")
+        .or_else(|_| NaiveTime::parse_from_str(clock, "%H:%M"))
+        .ok()?;
+    Some((time, pm))
+}
+
+/// Parse an AM/PM marker token; true for PM.
+fn parse_meridiem(token: &str) -> Option<bool> {
+    match token.to_ascii_lowercase().as_str() {
+        "pm" | "p.m." => Some(true),
+        "am" | "a.m." => Some(false),
+        _ => None,
+    }
+}
+
+/// A line's words for reading dates and times, split at whitespace and
+/// commas, without the Spanish "de" ("13 de febrero de 2026").
+fn date_tokens(line: &str) -> Vec<&str> {
+    line.split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|t| !t.is_empty() && *t != "de")
+        .collect()
+}
+
+/// Parse a date followed closely by a time anywhere in a line, e.g.
+/// "Friday, February 13, 2026 at 10:42:00 PM" or "13/02/2026 22:42".
+fn parse_date_time(line: &str, order: DateOrder) -> Option<NaiveDateTime> {
+    let tokens = date_tokens(line);
+
+    for i in 0..tokens.len() {
+        let Some((date, other, used)) = parse_date(&tokens[i..], order) else {
+            continue;
+        };
+
+        // The time follows the date within a few tokens ("at", "um", "à"),
+        // optionally with an AM/PM marker after it
+        let rest = &tokens[i + used..];
+        let Some((t, (time, attached))) = rest
+            .iter()
+            .take(3)
+            .enumerate()
+            .find_map(|(t, token)| parse_time(token).map(|parsed| (t, parsed)))
+        else {
+            continue;
+        };
+        let pm = attached.or_else(|| rest[t + 1..].iter().take(2).find_map(|m| parse_meridiem(m)));
+        let time = match pm {
+            Some(true) if time.hour() < 12 => time + Duration::hours(12),
+            Some(false) if time.hour() == 12 => time - Duration::hours(12),
+            _ => time,
+        };
+        if let Some(other) = other {
+            warn_ambiguous(tokens[i], date, other);
+        }
+        return Some(date.and_time(time));
+    }
+    None
+}
+
+/// Find the heart rate printed on the report ("Heart Rate: 76 BPM"), in BPM.
+pub fn find_heart_rate(pages: &[Vec<TextRun>]) -> Option<f64> {
+    pages.iter().find_map(|runs| {
+        value_after_label(runs, "Heart Rate:")?
+            .split_whitespace()
+            .next()?
+            .parse()
+            .ok()
+    })
+}
+
+/// Find the rhythm determination printed on the report, e.g. "Normal Sinus Rhythm".
+pub fn find_determination(pages: &[Vec<TextRun>]) -> Option<String> {
+    pages
+        .iter()
+        .find_map(|runs| value_after_label(runs, "Kardia Determination:"))
+}
+
+/// Filtering of a printed strip, from the footer of its page.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TraceFilter {
+    /// Kardia's Enhanced Filter, removing mains hum and baseline wander.
+    #[default]
+    Enhanced,
+    /// The original, unfiltered trace.
+    Original,
+}
+
+impl TraceFilter {
+    /// Identifier for messages and signal labels, e.g. "original".
+    pub fn name(self) -> &'static str {
+        match self {
+            TraceFilter::Enhanced => "enhanced",
+            TraceFilter::Original => "original",
+        }
+    }
+
+    /// EDF prefiltering header text for a strip with this filtering, with
+    /// the mains frequency the Enhanced Filter removes, if known.
+    pub fn prefilter(self, mains_hz: Option<f64>) -> String {
+        match (self, mains_hz) {
+            (TraceFilter::Enhanced, Some(hz)) => format!("Enhanced Filter, {}Hz mains", hz),
+            (TraceFilter::Enhanced, None) => "Enhanced Filter".to_string(),
+            (TraceFilter::Original, _) => "None (original unfiltered)".to_string(),
+        }
+    }
+}
+
+/// Find the filtering named in a strip page's footer, e.g. "Enhanced
+/// Filter, Mains Frequency: 50Hz" or "Original (unfiltered)".
+pub fn find_trace_filter(runs: &[TextRun]) -> Option<TraceFilter> {
+    runs.iter().find_map(|run| {
+        if run.text.contains("Enhanced Filter") {
+            Some(TraceFilter::Enhanced)
+        } else if run.text.contains("Original") || run.text.contains("Unfiltered") {
+            Some(TraceFilter::Original)
+        } else {
+            None
+        }
+    })
+}
+
+/// Find a labeled value, trying each label in turn: as its own run (see
+/// [`value_after_label`]) or at the start of a run that also holds the
+/// value ("Firmware: 1.4.2").
+fn labeled_value(runs: &[TextRun], labels: &[&str]) -> Option<String> {
+    labels.iter().find_map(|label| {
+        value_after_label(runs, label).or_else(|| {
+            runs.iter().find_map(|run| {
+                let text = run.text.trim();
+                let head = text.get(..label.len())?;
+                let value = text[label.len()..].trim_start_matches(':').trim();
+                (head.eq_ignore_ascii_case(label) && !value.is_empty())
+                    .then(|| value.split_whitespace().collect::<Vec<_>>().join(" "))
+            })
+        })
+    })
+}
+
+/// Find the mains frequency printed in a strip footer ("Mains Frequency:
+/// 50Hz"), in Hz.
+pub fn find_mains_frequency(pages: &[Vec<TextRun>]) -> Option<f64> {
+    const LABEL: &str = "mains frequency";
+    pages.iter().flatten().find_map(|run| {
+        let text = run.text.to_lowercase();
+        let rest = text[text.find(LABEL)? + LABEL.len()..].trim_start_matches([':', ' ']);
+        let end = rest
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(rest.len());
+        rest[..end].parse().ok().filter(|&hz: &f64| hz > 0.0)
+    })
+}
+
+/// Find the recording duration printed on the report ("Duration: 30s"), in seconds.
+pub fn find_duration(pages: &[Vec<TextRun>]) -> Option<f64> {
+    pages.iter().find_map(|runs| {
+        labeled_value(runs, &["Duration:", "Recording duration:", "Duration"])
+            .and_then(|text| parse_duration(&text))
+    })
+}
+
+/// Parse a duration such as "30s", "30 sec", "1 min 30 s", "0:30", or
+/// "00:00:30", in seconds.
+fn parse_duration(text: &str) -> Option<f64> {
+    let text = text.trim().trim_end_matches('.').to_lowercase();
+    if text.contains(':') {
+        return text.split(':').try_fold(0.0, |total, part| {
+            Some(total * 60.0 + part.trim().parse::<f64>().ok()?)
+        });
+    }
+    // Number and unit pairs
+    let mut total = None;
+    let mut rest = text.trim();
+    while !rest.is_empty() {
+        let end = rest
+            .find(|c: char| !(c.is_ascii_digit() || c == '.' || c == ','))
+            .unwrap_or(rest.len());
+        let number: f64 = rest[..end].replace(',', ".").parse().ok()?;
+        let after = rest[end..].trim_start();
+        let end = after
+            .find(|c: char| !c.is_alphabetic())
+            .unwrap_or(after.len());
+        let scale = match &after[..end] {
+            "s" | "sec" | "secs" | "second" | "seconds" | "sek" | "seg" => 1.0,
+            "m" | "min" | "mins" | "minute" | "minutes" => 60.0,
+            "h" | "hr" | "hrs" | "hour" | "hours" => 3600.0,
+            _ => return None,
+        };
+        total = Some(total.unwrap_or(0.0) + number * scale);
+        rest = after[end..].trim_start_matches([',', '.']).trim_start();
+    }
+    total
+}
+
+/// Find the recording device named on the report: a "Device:" or similar
+/// field, or for Kardia reports the "1L Recording"/"6L Recording" heading.
+pub fn find_device(pages: &[Vec<TextRun>]) -> Option<String> {
+    let labels = ["Device:", "Device model:", "Recorded with:"];
+    pages
+        .iter()
+        .find_map(|runs| labeled_value(runs, &labels))
+        .or_else(|| {
+            pages.iter().flatten().find_map(|run| {
+                match run.text.trim().strip_suffix(" Recording")? {
+                    leads @ ("1L" | "6L") => Some(format!("KardiaMobile {}", leads)),
+                    _ => None,
+                }
+            })
+        })
+}
+
+/// Find the device firmware version printed on the report.
+pub fn find_firmware(pages: &[Vec<TextRun>]) -> Option<String> {
+    let labels = [
+        "Firmware version:",
+        "Firmware Version:",
+        "Firmware:",
+        "FW version:",
+        "Firmware",
+    ];
+    pages
+        .iter()
+        .find_map(|runs| labeled_value(runs, &labels))
+        .map(|v| v.trim_start_matches(['v', 'V']).to_string())
+        .filter(|v| !v.is_empty())
+}
+
+/// Findings and device details printed on the report, for metadata outputs.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ReportInfo {
+    /// Heart rate in BPM, as printed.
+    pub heart_rate_bpm: Option<f64>,
+    /// Rhythm determination, e.g. "Normal Sinus Rhythm".
+    pub determination: Option<String>,
+    /// Recording duration in seconds, as printed.
+    pub duration_seconds: Option<f64>,
+    /// Recording device, e.g. "KardiaMobile 1L".
+    pub device: Option<String>,
+    /// Firmware version of the recording device, e.g. "1.4.2".
+    pub firmware: Option<String>,
+    /// Mains frequency in Hz, as printed in the strip footer.
+    pub mains_hz: Option<f64>,
+}
+
+impl ReportInfo {
+    /// The printed findings and device details as one line, e.g. "76 BPM,
+    /// Normal Sinus Rhythm, 30 s, KardiaMobile 1L"; empty if none were found.
+    pub fn summary(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(bpm) = self.heart_rate_bpm {
+            parts.push(format!("{} BPM", bpm));
+        }
+        parts.extend(self.determination.clone());
+        if let Some(seconds) = self.duration_seconds {
+            parts.push(format!("{} s", seconds));
+        }
+        parts.extend(self.device.clone());
+        if let Some(firmware) = &self.firmware {
+            parts.push(format!("firmware {}", firmware));
+        }
+        parts.join(", ")
+    }
+}
+
+/// Find the findings and device details printed on the report.
+pub fn find_report_info(pages: &[Vec<TextRun>]) -> ReportInfo {
+    ReportInfo {
+        heart_rate_bpm: find_heart_rate(pages),
+        determination: find_determination(pages),
+        duration_seconds: find_duration(pages),
+        device: find_device(pages),
+        firmware: find_firmware(pages),
+        mains_hz: find_mains_frequency(pages),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date_time(line: &str, order: DateOrder) -> Option<String> {
+        parse_date_time(line, order).map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+    }
+
+    fn date(token: &str, order: DateOrder) -> Option<String> {
+        parse_numeric_date(token, order, None).map(|(d, _)| d.to_string())
+    }
+
+    #[test]
+    fn month_names() {
+        let expected = Some("2026-02-13 22:42:00".to_string());
+        for line in [
+            "Recorded on: Friday, February 13, 2026 at 10:42:00 PM",
+            "Aufgenommen am: Freitag, 13. Februar 2026 um 22:42:00",
+            "Enregistré le : vendredi 13 février 2026 à 22:42:00",
+            "Grabado el: viernes, 13 de febrero de 2026 22:42:00",
+        ] {
+            assert_eq!(date_time(line, DateOrder::Auto), expected, "{}", line);
+        }
+        assert_eq!(parse_month_name("Sept"), Some(9));
+        assert_eq!(parse_month_name("févr."), Some(2));
+        assert_eq!(parse_month_name("Mär"), Some(3));
+        // Shorter abbreviations could name several months
+        assert_eq!(parse_month_name("ma"), None);
+    }
+
+    #[test]
+    fn abbreviated_month_either_side_of_the_day() {
+        for line in ["13 Feb 2026 10:42 PM", "Feb 13, 2026 10:42 PM"] {
+            assert_eq!(
+                date_time(line, DateOrder::Auto),
+                Some("2026-02-13 22:42:00".to_string()),
+                "{}",
+                line
+            );
+        }
+    }
+
+    #[test]
+    fn numeric_dates() {
+        assert_eq!(
+            date("2026-02-13", DateOrder::MonthFirst).as_deref(),
+            Some("2026-02-13")
+        );
+        assert_eq!(
+            date("13/02/2026", DateOrder::Auto).as_deref(),
+            Some("2026-02-13")
+        );
+        assert_eq!(
+            date("13.02.26", DateOrder::Auto).as_deref(),
+            Some("2026-02-13")
+        );
+        // Impossible day-first readings are read month first
+        assert_eq!(
+            date("02/13/2026", DateOrder::DayFirst).as_deref(),
+            Some("2026-02-13")
+        );
+        assert_eq!(
+            date("03/02/2026", DateOrder::DayFirst).as_deref(),
+            Some("2026-02-03")
+        );
+        assert_eq!(
+            date("03/02/2026", DateOrder::MonthFirst).as_deref(),
+            Some("2026-03-02")
+        );
+        assert_eq!(date("1.2.3", DateOrder::Auto), None);
+        assert_eq!(date("13/13/2026", DateOrder::Auto), None);
+    }
+
+    #[test]
+    fn ambiguous_dates_are_reported_only_without_evidence() {
+        let day = NaiveDate::from_ymd_opt(2026, 2, 3);
+        let month = NaiveDate::from_ymd_opt(2026, 3, 2);
+        assert_eq!(
+            parse_numeric_date("03/02/2026", DateOrder::Auto, None),
+            day.zip(Some(month))
+        );
+        assert_eq!(
+            parse_numeric_date("03/02/2026", DateOrder::DayFirst, None),
+            day.zip(Some(None))
+        );
+        assert_eq!(
+            parse_numeric_date("13/02/2026", DateOrder::Auto, None)
+                .unwrap()
+                .1,
+            None
+        );
+        assert_eq!(
+            parse_numeric_date("02/02/2026", DateOrder::Auto, None)
+                .unwrap()
+                .1,
+            None
+        );
+    }
+
+    #[test]
+    fn date_order_from_evidence() {
+        let lines = |line: &str| vec!["Heart Rate: 76 BPM".to_string(), line.to_string()];
+        let us = lines("Recorded 03/02/2026 at 10:42 PM");
+        let order = DateOrder::Auto.resolve(&us);
+        assert_eq!(order, DateOrder::MonthFirst);
+        assert_eq!(
+            date_time(&us[1], order),
+            Some("2026-03-02 22:42:00".to_string())
+        );
+
+        let eu = lines("Recorded 03/02/2026 22:42");
+        let order = DateOrder::Auto.resolve(&eu);
+        assert_eq!(order, DateOrder::Auto);
+        assert_eq!(
+            date_time(&eu[1], order),
+            Some("2026-02-03 22:42:00".to_string())
+        );
+
+        // An explicit order or the profile's wins over the report's AM/PM times
+        let order = DateOrder::DayFirst.or(DateOrder::MonthFirst).resolve(&us);
+        assert_eq!(order, DateOrder::DayFirst);
+        assert_eq!(
+            DateOrder::Auto.or(DateOrder::DayFirst).resolve(&us),
+            DateOrder::DayFirst
+        );
+    }
+
+    #[test]
+    fn twelve_am_and_pm() {
+        let at = |time: &str| date_time(&format!("February 13, 2026 {}", time), DateOrder::Auto);
+        assert_eq!(at("12:05 AM"), Some("2026-02-13 00:05:00".to_string()));
+        assert_eq!(at("12:05 PM"), Some("2026-02-13 12:05:00".to_string()));
+        assert_eq!(at("12:05:30am"), Some("2026-02-13 00:05:30".to_string()));
+        assert_eq!(at("11:59 p.m."), Some("2026-02-13 23:59:00".to_string()));
+        assert_eq!(at("00:05"), Some("2026-02-13 00:05:00".to_string()));
+    }
+
+    #[test]
+    fn two_digit_years_stay_in_the_edf_range() {
+        assert_eq!(
+            date("01/02/84", DateOrder::DayFirst).as_deref(),
+            Some("2084-02-01")
+        );
+        assert_eq!(
+            date("01/02/85", DateOrder::DayFirst).as_deref(),
+            Some("1985-02-01")
+        );
+        for token in ["01/02/00", "01/02/50", "01/02/99"] {
+            let (date, _) = parse_numeric_date(token, DateOrder::DayFirst, None).unwrap();
+            assert!(EDF_YEARS.contains(&date.year()), "{}", token);
+        }
+        // Four-digit years outside 1985-2084 are read as printed, for the
+        // caller to write as unknown
+        let date = parse_numeric_date("01/02/1984", DateOrder::DayFirst, None)
+            .unwrap()
+            .0;
+        assert!(!EDF_YEARS.contains(&date.year()));
+    }
+
+    #[test]
+    fn birth_years_are_not_after_the_recording() {
+        let recorded = NaiveDate::from_ymd_opt(2026, 2, 13);
+        let birth =
+            |token| parse_numeric_date(token, DateOrder::MonthFirst, recorded).map(|(d, _)| d);
+        assert_eq!(birth("5/4/70"), NaiveDate::from_ymd_opt(1970, 5, 4));
+        assert_eq!(birth("1/4/26"), NaiveDate::from_ymd_opt(2026, 1, 4));
+        assert_eq!(birth("5/4/27"), NaiveDate::from_ymd_opt(1927, 5, 4));
+    }
+}
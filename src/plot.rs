@@ -0,0 +1,456 @@
+use anyhow::{anyhow, Result};
+use std::fmt::Write as _;
+use std::io::Write as _;
+use std::path::Path;
+
+use crate::audit::outln;
+use crate::recording::EcgRecording;
+use crate::sample_flags::SampleFlag;
+
+/// Paper speed: millimeters per second.
+pub const MM_PER_SECOND: f64 = 25.0;
+
+/// Gain: millimeters per millivolt.
+pub const MM_PER_MV: f64 = 10.0;
+
+/// Seconds of signal per row of the plot, unless a number of rows is set.
+const ROW_SECONDS: f64 = 10.0;
+
+/// Resolution of PNG plots unless set otherwise, in dots per inch.
+pub const DEFAULT_DPI: f64 = 150.0;
+
+/// Widest or tallest PNG plot, in pixels.
+const MAX_PNG_SIDE: f64 = 20000.0;
+
+/// Width left of each row for its 1 mV calibration pulse, in mm.
+const PULSE_MARGIN: f64 = 10.0;
+
+/// Height of the title band above the grid, in mm.
+const TITLE_HEIGHT: f64 = 10.0;
+
+/// Smallest row height, in mm: 3 mV.
+const MIN_ROW_HEIGHT: f64 = 30.0;
+
+/// How a plot is laid out and rasterized.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PlotSettings {
+    /// Resolution of PNG plots, in dots per inch.
+    pub dpi: f64,
+    /// Number of rows to split the strip into, each a whole number of
+    /// seconds long; `None` for 10-second rows.
+    pub rows: Option<usize>,
+}
+
+impl Default for PlotSettings {
+    fn default() -> Self {
+        Self {
+            dpi: DEFAULT_DPI,
+            rows: None,
+        }
+    }
+}
+
+/// A recording laid out on ECG paper, in millimeters from the top left.
+pub(crate) struct Plot {
+    pub width: f64,
+    pub height: f64,
+    /// Top of the grid, below the title.
+    pub grid_top: f64,
+    /// One line of text describing the recording and the scales.
+    pub title: String,
+    /// Baseline (0 mV) of each row.
+    pub baselines: Vec<f64>,
+    /// The trace as polylines, broken at row ends and missing samples.
+    pub trace: Vec<Vec<(f64, f64)>>,
+}
+
+impl Plot {
+    /// Lay out lead I of a recording in rows at [`MM_PER_SECOND`] and
+    /// [`MM_PER_MV`], all as tall as the signal's range needs.
+    pub(crate) fn new(recording: &EcgRecording, settings: &PlotSettings) -> Self {
+        let rate = recording.sample_rate as f64;
+        let row_seconds = match settings.rows {
+            Some(rows) => (recording.duration() / rows.max(1) as f64).ceil().max(1.0),
+            None => ROW_SECONDS,
+        };
+        let per_row = (row_seconds * rate).round() as usize;
+        let n_rows = recording.signal.len().div_ceil(per_row).max(1);
+        let missing = |i: usize| recording.flags.get(i) == Some(&SampleFlag::Missing);
+
+        // Room above and below the baseline for the signal's extremes
+        let (mut low, mut high) = (-0.5f64, 0.5f64);
+        for (i, &v) in recording.signal.iter().enumerate() {
+            if !missing(i) {
+                low = low.min(v);
+                high = high.max(v);
+            }
+        }
+        let room = |mv: f64| ((mv * MM_PER_MV + 5.0).max(MIN_ROW_HEIGHT / 2.0) / 5.0).ceil() * 5.0;
+        let (above, below) = (room(high), room(-low));
+        let row_height = above + below;
+
+        let baselines: Vec<f64> = (0..n_rows)
+            .map(|row| TITLE_HEIGHT + row as f64 * row_height + above)
+            .collect();
+        let mut trace = Vec::new();
+        for (row, &baseline) in baselines.iter().enumerate() {
+            let mut line: Vec<(f64, f64)> = Vec::new();
+            let first = row * per_row;
+            let last = ((row + 1) * per_row).min(recording.signal.len());
+            for i in first..last {
+                if missing(i) {
+                    if line.len() > 1 {
+                        trace.push(std::mem::take(&mut line));
+                    }
+                    line.clear();
+                    continue;
+                }
+                let x = PULSE_MARGIN + (i - first) as f64 / rate * MM_PER_SECOND;
+                line.push((x, baseline - recording.signal[i] * MM_PER_MV));
+            }
+            if line.len() > 1 {
+                trace.push(line);
+            }
+        }
+
+        let mut title = String::from("Lead I");
+        if let Some(start) = recording.start {
+            write!(title, ", {}", start.format("%Y-%m-%d %H:%M:%S")).unwrap();
+        }
+        write!(
+            title,
+            ", {:.1} s at {} Hz; {} mm/s, {} mm/mV",
+            recording.duration(),
+            recording.sample_rate,
+            MM_PER_SECOND,
+            MM_PER_MV
+        )
+        .unwrap();
+
+        Self {
+            width: PULSE_MARGIN + row_seconds * MM_PER_SECOND + 5.0,
+            height: TITLE_HEIGHT + n_rows as f64 * row_height,
+            grid_top: TITLE_HEIGHT,
+            title,
+            baselines,
+            trace,
+        }
+    }
+
+    /// The 1 mV, 0.2 s calibration pulse left of the row at `baseline`.
+    pub(crate) fn pulse(baseline: f64) -> [(f64, f64); 6] {
+        let top = baseline - MM_PER_MV;
+        let (start, end) = (3.0, 3.0 + 0.2 * MM_PER_SECOND);
+        [
+            (1.0, baseline),
+            (start, baseline),
+            (start, top),
+            (end, top),
+            (end, baseline),
+            (PULSE_MARGIN - 1.0, baseline),
+        ]
+    }
+}
+
+/// Append an SVG path of a polyline, with coordinates to 0.01 mm.
+fn push_polyline(d: &mut String, points: &[(f64, f64)]) {
+    for (i, (x, y)) in points.iter().enumerate() {
+        let command = if i == 0 { 'M' } else { 'L' };
+        write!(d, "{}{:.2} {:.2}", command, x, y).unwrap();
+    }
+}
+
+/// Color of the 1 mm grid lines.
+const MINOR_GRID: [u8; 3] = [0xf7, 0xc6, 0xc6];
+
+/// Color of the 5 mm grid lines.
+const MAJOR_GRID: [u8; 3] = [0xe5, 0x8f, 0x8f];
+
+fn hex([r, g, b]: [u8; 3]) -> String {
+    format!("#{:02x}{:02x}{:02x}", r, g, b)
+}
+
+/// Write the plot as SVG, or as PNG if `path` ends in `.png`.
+pub fn write_plot(path: &str, recording: &EcgRecording, settings: &PlotSettings) -> Result<()> {
+    let png = Path::new(path)
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("png"));
+    if png {
+        write_png(path, recording, settings)
+    } else {
+        write_svg(path, recording, settings)
+    }
+}
+
+/// Write lead I of the recording as an SVG image of ECG paper: a 1 mm grid
+/// with 5 mm major lines, rows at 25 mm/s and 10 mm/mV, each starting with
+/// a 1 mV calibration pulse, and the recording's start time, duration, and
+/// sampling rate above. The image is sized in millimeters, so it prints at
+/// scale. Missing samples are left blank.
+pub fn write_svg(path: &str, recording: &EcgRecording, settings: &PlotSettings) -> Result<()> {
+    let plot = Plot::new(recording, settings);
+    let (width, height) = (plot.width, plot.height);
+    let mut svg = String::new();
+    writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}mm" height="{h}mm" viewBox="0 0 {w} {h}">"#,
+        w = width,
+        h = height
+    )?;
+    writeln!(
+        svg,
+        r#"<rect width="{}" height="{}" fill="white"/>"#,
+        width, height
+    )?;
+
+    // Grid: minor lines every 1 mm, then major lines every 5 mm over them
+    for (step, color, stroke) in [(1, hex(MINOR_GRID), 0.1), (5, hex(MAJOR_GRID), 0.2)] {
+        let mut d = String::new();
+        let columns = width.floor() as usize;
+        for x in (0..=columns).step_by(step) {
+            write!(d, "M{} {}V{}", x, plot.grid_top, height)?;
+        }
+        let rows = (height - plot.grid_top).floor() as usize;
+        for y in (0..=rows).step_by(step) {
+            write!(d, "M0 {}H{}", plot.grid_top + y as f64, width)?;
+        }
+        writeln!(
+            svg,
+            r#"<path d="{}" stroke="{}" stroke-width="{}" fill="none"/>"#,
+            d, color, stroke
+        )?;
+    }
+
+    let mut d = String::new();
+    for &baseline in &plot.baselines {
+        push_polyline(&mut d, &Plot::pulse(baseline));
+    }
+    for line in &plot.trace {
+        push_polyline(&mut d, line);
+    }
+    writeln!(
+        svg,
+        r#"<path d="{}" stroke="black" stroke-width="0.3" stroke-linejoin="round" fill="none"/>"#,
+        d
+    )?;
+    writeln!(
+        svg,
+        r#"<text x="{}" y="{}" font-family="sans-serif" font-size="4">{}</text>"#,
+        PULSE_MARGIN,
+        plot.grid_top - 3.0,
+        plot.title
+    )?;
+    svg.push_str("</svg>\n");
+
+    std::fs::write(path, svg)?;
+    outln!("Plot written: {}", path);
+    Ok(())
+}
+
+/// Write lead I of the recording as a PNG image of ECG paper, drawn like
+/// [`write_svg`] at `settings.dpi`, for previews in reports and emails.
+/// Lines are antialiased; lines narrower than a pixel are drawn one pixel
+/// wide, and the 1 mm grid is left out below 3 pixels per millimeter.
+pub fn write_png(path: &str, recording: &EcgRecording, settings: &PlotSettings) -> Result<()> {
+    let plot = Plot::new(recording, settings);
+    let scale = settings.dpi / 25.4;
+    let (width, height) = (plot.width * scale, plot.height * scale);
+    if settings.dpi.is_nan() || settings.dpi <= 0.0 || width > MAX_PNG_SIDE || height > MAX_PNG_SIDE
+    {
+        return Err(anyhow!(
+            "Plot of {:.0} x {:.0} mm at {} dpi is too large for PNG; lower the dpi or use more rows",
+            plot.width,
+            plot.height,
+            settings.dpi
+        ));
+    }
+    let mut canvas = Canvas::new(width.round() as usize, height.round() as usize);
+
+    // Grid: minor lines every 1 mm, then major lines every 5 mm over them
+    for (step, color, stroke) in [(1, MINOR_GRID, 0.1), (5, MAJOR_GRID, 0.2)] {
+        if step == 1 && scale < 3.0 {
+            continue;
+        }
+        let stroke = (stroke * scale).max(1.0);
+        let top = plot.grid_top * scale;
+        for x in (0..=plot.width.floor() as usize).step_by(step) {
+            let x = x as f64 * scale;
+            canvas.line((x, top), (x, height), stroke, color);
+        }
+        let rows = (plot.height - plot.grid_top).floor() as usize;
+        for y in (0..=rows).step_by(step) {
+            let y = top + y as f64 * scale;
+            canvas.line((0.0, y), (width, y), stroke, color);
+        }
+    }
+
+    let stroke = (0.3 * scale).max(1.0);
+    let pulses = plot.baselines.iter().map(|&b| Plot::pulse(b).to_vec());
+    for line in pulses.chain(plot.trace.iter().cloned()) {
+        for pair in line.windows(2) {
+            let (a, b) = (pair[0], pair[1]);
+            canvas.line(
+                (a.0 * scale, a.1 * scale),
+                (b.0 * scale, b.1 * scale),
+                stroke,
+                [0, 0, 0],
+            );
+        }
+    }
+    // Text about 3 mm tall, baseline 3 mm above the grid
+    let dot = ((3.0 * scale / 7.0).round() as usize).max(1);
+    let text_top = ((plot.grid_top - 3.0) * scale) as usize - 7 * dot;
+    canvas.text((PULSE_MARGIN * scale) as usize, text_top, dot, &plot.title);
+
+    std::fs::write(path, canvas.encode_png()?)?;
+    outln!(
+        "Plot written: {} ({} x {} px at {} dpi)",
+        path,
+        canvas.width,
+        canvas.height,
+        settings.dpi
+    );
+    Ok(())
+}
+
+/// An RGB image drawn on white.
+struct Canvas {
+    width: usize,
+    height: usize,
+    pixels: Vec<[u8; 3]>,
+}
+
+impl Canvas {
+    fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![[255; 3]; width * height],
+        }
+    }
+
+    /// Paint a pixel with `color` at opacity `alpha`.
+    fn blend(&mut self, x: usize, y: usize, color: [u8; 3], alpha: f64) {
+        if x >= self.width || y >= self.height || alpha <= 0.0 {
+            return;
+        }
+        let pixel = &mut self.pixels[y * self.width + x];
+        for (p, c) in pixel.iter_mut().zip(color) {
+            *p = (*p as f64 * (1.0 - alpha) + c as f64 * alpha).round() as u8;
+        }
+    }
+
+    /// Draw an antialiased line `width` pixels wide between two points, in
+    /// pixel coordinates.
+    fn line(&mut self, a: (f64, f64), b: (f64, f64), width: f64, color: [u8; 3]) {
+        let half = width / 2.0;
+        let reach = half + 1.0;
+        let x0 = (a.0.min(b.0) - reach).max(0.0) as usize;
+        let y0 = (a.1.min(b.1) - reach).max(0.0) as usize;
+        let x1 = ((a.0.max(b.0) + reach).max(0.0) as usize).min(self.width);
+        let y1 = ((a.1.max(b.1) + reach).max(0.0) as usize).min(self.height);
+        let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+        let length2 = dx * dx + dy * dy;
+        for y in y0..y1 {
+            for x in x0..x1 {
+                // Distance from the pixel's center to the segment
+                let (px, py) = (x as f64 + 0.5, y as f64 + 0.5);
+                let t = if length2 > 0.0 {
+                    (((px - a.0) * dx + (py - a.1) * dy) / length2).clamp(0.0, 1.0)
+                } else {
+                    0.0
+                };
+                let distance = (px - a.0 - t * dx).hypot(py - a.1 - t * dy);
+                self.blend(x, y, color, (half + 0.5 - distance).min(1.0));
+            }
+        }
+    }
+
+    /// Draw text in the built-in 5 x 7 font, each font dot `dot` pixels
+    /// square, from the top left corner at `(x, y)`.
+    fn text(&mut self, x: usize, y: usize, dot: usize, text: &str) {
+        for (i, c) in text.chars().enumerate() {
+            let left = x + i * 6 * dot;
+            for (row, bits) in glyph(c).iter().enumerate() {
+                for col in 0..5 {
+                    if bits & (0x10 >> col) == 0 {
+                        continue;
+                    }
+                    for py in 0..dot {
+                        for px in 0..dot {
+                            self.blend(left + col * dot + px, y + row * dot + py, [0; 3], 1.0);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Encode as an 8-bit RGB PNG.
+    fn encode_png(&self) -> Result<Vec<u8>> {
+        let mut data = Vec::with_capacity(self.height * (1 + self.width * 3));
+        for row in self.pixels.chunks(self.width) {
+            data.push(0); // filter: none
+            data.extend(row.iter().flatten());
+        }
+        let mut zlib = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        zlib.write_all(&data)?;
+
+        let mut header = Vec::with_capacity(13);
+        header.extend_from_slice(&(self.width as u32).to_be_bytes());
+        header.extend_from_slice(&(self.height as u32).to_be_bytes());
+        header.extend_from_slice(&[8, 2, 0, 0, 0]); // 8-bit RGB, no interlace
+
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        png_chunk(&mut png, b"IHDR", &header);
+        png_chunk(&mut png, b"IDAT", &zlib.finish()?);
+        png_chunk(&mut png, b"IEND", &[]);
+        Ok(png)
+    }
+}
+
+/// Append a PNG chunk: its length, type, data, and CRC of type and data.
+fn png_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let mut crc = crc32fast::Hasher::new();
+    crc.update(kind);
+    crc.update(data);
+    png.extend_from_slice(&crc.finalize().to_be_bytes());
+}
+
+/// Rows of a character in the 5 x 7 font, high bit on the left; blank for
+/// characters plot titles don't use.
+fn glyph(c: char) -> [u8; 7] {
+    match c {
+        '0' => [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E],
+        '1' => [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        '2' => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F],
+        '3' => [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E],
+        '4' => [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02],
+        '5' => [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E],
+        '6' => [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E],
+        '7' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+        '8' => [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E],
+        '9' => [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C],
+        'H' => [0x11, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'I' => [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        'L' => [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1F],
+        'V' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x0A, 0x04],
+        'a' => [0x00, 0x00, 0x0E, 0x01, 0x0F, 0x11, 0x0F],
+        'd' => [0x01, 0x01, 0x0D, 0x13, 0x11, 0x11, 0x0F],
+        'e' => [0x00, 0x00, 0x0E, 0x11, 0x1F, 0x10, 0x0E],
+        'm' => [0x00, 0x00, 0x1A, 0x15, 0x15, 0x11, 0x11],
+        's' => [0x00, 0x00, 0x0E, 0x10, 0x0E, 0x01, 0x1E],
+        't' => [0x08, 0x08, 0x1C, 0x08, 0x08, 0x09, 0x06],
+        'z' => [0x00, 0x00, 0x1F, 0x02, 0x04, 0x08, 0x1F],
+        '/' => [0x00, 0x01, 0x02, 0x04, 0x08, 0x10, 0x00],
+        ',' => [0x00, 0x00, 0x00, 0x00, 0x0C, 0x04, 0x08],
+        ';' => [0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x04, 0x08],
+        ':' => [0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x0C, 0x00],
+        '-' => [0x00, 0x00, 0x00, 0x1F, 0x00, 0x00, 0x00],
+        '.' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C],
+        _ => [0; 7],
+    }
+}
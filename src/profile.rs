@@ -0,0 +1,336 @@
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use validator::{Validate, ValidationError, ValidationErrors, ValidationErrorsKind};
+
+use crate::pdf_text::DateOrder;
+
+/// Where and how the ECG strip is drawn in a report PDF.
+///
+/// The built-in default matches KardiaMobile 1L reports. Profiles for other
+/// layouts are JSON files with the same fields; any field left out takes its
+/// default, and unknown fields are rejected.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Validate)]
+#[serde(default, deny_unknown_fields)]
+pub struct LayoutProfile {
+    /// Profile name, for messages.
+    #[validate(length(min = 1, max = 64))]
+    pub name: String,
+    /// Page number (1-based) holding the ECG strip.
+    #[validate(range(min = 1))]
+    pub page: u32,
+    /// Calibration in PDF points per millivolt.
+    #[validate(range(exclusive_min = 0.0, max = 1000.0))]
+    pub cal_pt_per_mv: f64,
+    /// Sampling rate of the printed trace's points, in Hz.
+    #[validate(range(min = 1, max = 10000))]
+    pub sample_rate: usize,
+    /// Fewest strip rows on the page. Every visible baseline is read as a
+    /// row, so strips with more rows need no change.
+    #[validate(range(min = 1, max = 32))]
+    pub rows: usize,
+    /// The horizontal baseline grid lines, one per row.
+    #[validate(nested)]
+    pub baseline: LineStyle,
+    /// The ECG trace paths.
+    #[validate(nested)]
+    pub trace: LineStyle,
+    /// Shortest horizontal line, in points, counted as a baseline.
+    #[validate(range(exclusive_min = 0.0))]
+    pub min_baseline_length: f64,
+    /// Baselines at or above this y-coordinate (top-left origin) are ignored.
+    #[validate(range(exclusive_min = 0.0))]
+    pub max_baseline_y: f64,
+    /// Trace paths centered further than this from every baseline are ignored.
+    #[validate(range(exclusive_min = 0.0))]
+    pub max_row_distance: f64,
+    /// Chain trace paths that continue one another and assign each chain to
+    /// a row as a whole, so tall waves reaching into a neighboring row stay
+    /// with their own row.
+    pub reattach_overshoot: bool,
+    /// The trace is drawn as thin filled shapes rather than stroked lines,
+    /// and is read along their centerlines.
+    pub filled_trace: bool,
+    /// Order of the day and month in the report's numeric dates: "auto",
+    /// "day-first", or "month-first".
+    pub date_order: DateOrder,
+}
+
+/// Stroke color, width, and minimum segment count identifying a set of paths.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Validate)]
+#[serde(default, deny_unknown_fields)]
+#[validate(schema(function = "validate_width_range"))]
+pub struct LineStyle {
+    /// RGB stroke color, each component from 0 to 1.
+    #[validate(custom(function = "validate_color"))]
+    pub color: [f64; 3],
+    /// Largest difference in any color component still matching `color`.
+    #[validate(range(min = 0.0, max = 1.0))]
+    pub color_tolerance: f64,
+    /// Stroke width range in points (exclusive).
+    #[validate(range(min = 0.0, max = 10.0))]
+    pub width_min: f64,
+    #[validate(range(min = 0.0, max = 10.0))]
+    pub width_max: f64,
+    /// Fewest line segments a matching path has.
+    #[validate(range(min = 1))]
+    pub min_segments: usize,
+}
+
+impl Default for LayoutProfile {
+    fn default() -> Self {
+        Self {
+            name: "kardiamobile-1l".to_string(),
+            page: 2,
+            // 1 mV = 28.346 PDF points (10mm at 2.8346 pt/mm)
+            cal_pt_per_mv: 28.346,
+            sample_rate: 300,
+            rows: 4,
+            baseline: LineStyle {
+                min_segments: 4,
+                ..LineStyle::default()
+            },
+            trace: LineStyle::default(),
+            min_baseline_length: 500.0,
+            max_baseline_y: 760.0,
+            max_row_distance: 80.0,
+            reattach_overshoot: true,
+            filled_trace: false,
+            date_order: DateOrder::Auto,
+        }
+    }
+}
+
+impl LayoutProfile {
+    /// Layout of Withings ScanWatch and Move ECG reports from the Health Mate
+    /// app: one page with three 10-second rows of a red trace over a gray
+    /// grid, at 25 mm/s and 10 mm/mV.
+    pub fn withings() -> Self {
+        Self {
+            name: "withings".to_string(),
+            page: 1,
+            sample_rate: 300,
+            rows: 3,
+            baseline: LineStyle {
+                color: [0.6, 0.6, 0.6],
+                color_tolerance: 0.05,
+                width_min: 0.2,
+                width_max: 0.8,
+                min_segments: 3,
+            },
+            trace: LineStyle {
+                color: [0.898, 0.224, 0.208],
+                color_tolerance: 0.05,
+                width_min: 0.5,
+                width_max: 1.5,
+                min_segments: 40,
+            },
+            min_baseline_length: 700.0,
+            max_baseline_y: 560.0,
+            max_row_distance: 70.0,
+            ..Self::default()
+        }
+    }
+
+    /// Layout of Samsung Health Monitor ECG reports from Galaxy Watch: one
+    /// landscape page with three 10-second rows of a black trace sampled at
+    /// 500 Hz, at 25 mm/s and 10 mm/mV.
+    pub fn samsung() -> Self {
+        Self {
+            name: "samsung".to_string(),
+            page: 1,
+            sample_rate: 500,
+            rows: 3,
+            baseline: LineStyle {
+                color: [0.5, 0.5, 0.5],
+                color_tolerance: 0.05,
+                width_min: 0.2,
+                width_max: 0.8,
+                min_segments: 3,
+            },
+            trace: LineStyle {
+                color: [0.0, 0.0, 0.0],
+                color_tolerance: 0.05,
+                width_min: 0.5,
+                width_max: 1.2,
+                min_segments: 40,
+            },
+            min_baseline_length: 450.0,
+            max_baseline_y: 560.0,
+            max_row_distance: 70.0,
+            ..Self::default()
+        }
+    }
+}
+
+impl Default for LineStyle {
+    fn default() -> Self {
+        Self {
+            color: [0.0, 0.0, 0.0],
+            color_tolerance: 0.05,
+            width_min: 0.35,
+            width_max: 0.45,
+            min_segments: 40,
+        }
+    }
+}
+
+impl LineStyle {
+    /// Whether a path's stroke color and width match this style.
+    pub fn matches(&self, color: (f64, f64, f64), width: f64) -> bool {
+        let (r, g, b) = color;
+        [r, g, b]
+            .iter()
+            .zip(&self.color)
+            .all(|(c, want)| (c - want).abs() <= self.color_tolerance)
+            && self.width_min < width
+            && width < self.width_max
+    }
+}
+
+/// Overrides of a layout profile's trace filter, for reports whose trace is
+/// drawn in another color or width than the profile's, e.g. after a vendor
+/// app update.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ExtractionConfig {
+    /// RGB stroke color of the trace, each component from 0 to 1.
+    pub trace_color: Option<[f64; 3]>,
+    /// Stroke width range of the trace in points (exclusive).
+    pub trace_width: Option<(f64, f64)>,
+    /// Fewest line segments a trace path has.
+    pub min_segments: Option<usize>,
+    /// Whether the trace is drawn as thin filled shapes.
+    pub filled_trace: Option<bool>,
+}
+
+impl ExtractionConfig {
+    /// Whether nothing is overridden.
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Override the trace filter of `profile`, and validate the result.
+    pub fn apply(&self, profile: &mut LayoutProfile) -> Result<()> {
+        if let Some(color) = self.trace_color {
+            profile.trace.color = color;
+        }
+        if let Some((min, max)) = self.trace_width {
+            profile.trace.width_min = min;
+            profile.trace.width_max = max;
+        }
+        if let Some(min_segments) = self.min_segments {
+            profile.trace.min_segments = min_segments;
+        }
+        if let Some(filled_trace) = self.filled_trace {
+            profile.filled_trace = filled_trace;
+        }
+        profile
+            .validate()
+            .map_err(|e| anyhow!("Invalid trace filter: {}", format_errors(&e).join("; ")))
+    }
+}
+
+fn validate_color(color: &[f64; 3]) -> Result<(), ValidationError> {
+    if color.iter().all(|c| (0.0..=1.0).contains(c)) {
+        Ok(())
+    } else {
+        Err(ValidationError::new("color")
+            .with_message(format!("components must be between 0 and 1, got {:?}", color).into()))
+    }
+}
+
+fn validate_width_range(style: &LineStyle) -> Result<(), ValidationError> {
+    if style.width_min < style.width_max {
+        Ok(())
+    } else {
+        Err(ValidationError::new("width_range").with_message(
+            format!(
+                "width_min ({}) must be less than width_max ({})",
+                style.width_min, style.width_max
+            )
+            .into(),
+        ))
+    }
+}
+
+impl LayoutProfile {
+    /// Read and validate a JSON layout profile.
+    ///
+    /// Syntax errors and unknown or mistyped fields are reported with their
+    /// line and column; out-of-range values are reported one per line with
+    /// their field path, e.g. `trace.width_max`.
+    pub fn from_file(path: &str) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Cannot read layout profile {}", path))?;
+        Self::from_json(&text).with_context(|| format!("Invalid layout profile {}", path))
+    }
+
+    /// Parse and validate a JSON layout profile.
+    pub fn from_json(text: &str) -> Result<Self> {
+        let profile: Self = serde_json::from_str(text)?;
+        profile
+            .validate()
+            .map_err(|e| anyhow!(format_errors(&e).join("\n")))?;
+        Ok(profile)
+    }
+}
+
+/// Flatten validation errors into "field.path: message" lines.
+fn format_errors(errors: &ValidationErrors) -> Vec<String> {
+    let mut lines = Vec::new();
+    collect_errors(errors, "", &mut lines);
+    lines.sort();
+    lines
+}
+
+fn collect_errors(errors: &ValidationErrors, prefix: &str, lines: &mut Vec<String>) {
+    for (field, kind) in errors.errors() {
+        // Struct-level (schema) errors are keyed "__all__"
+        let path = match (prefix, field.as_ref()) {
+            (p, "__all__") => p.to_string(),
+            ("", f) => f.to_string(),
+            (p, f) => format!("{}.{}", p, f),
+        };
+        match kind {
+            ValidationErrorsKind::Field(errs) => {
+                for e in errs {
+                    lines.push(format!("{}: {}", path, describe(e)));
+                }
+            }
+            ValidationErrorsKind::Struct(inner) => collect_errors(inner, &path, lines),
+            ValidationErrorsKind::List(items) => {
+                for (i, inner) in items {
+                    collect_errors(inner, &format!("{}[{}]", path, i), lines);
+                }
+            }
+        }
+    }
+}
+
+/// Describe one validation error, using its message or its range bounds.
+fn describe(error: &ValidationError) -> String {
+    if let Some(message) = &error.message {
+        return message.to_string();
+    }
+    let param = |name: &str| error.params.get(name).map(|v| v.to_string());
+    let value = param("value").unwrap_or_default();
+    match error.code.as_ref() {
+        "range" => {
+            let bound = match (param("min"), param("exclusive_min"), param("max")) {
+                (Some(min), _, Some(max)) => format!("between {} and {}", min, max),
+                (_, Some(min), Some(max)) => format!("above {} and at most {}", min, max),
+                (Some(min), _, None) => format!("at least {}", min),
+                (_, Some(min), None) => format!("greater than {}", min),
+                (None, None, Some(max)) => format!("at most {}", max),
+                (None, None, None) => "in range".to_string(),
+            };
+            format!("must be {}, got {}", bound, value)
+        }
+        "length" => match (param("min"), param("max")) {
+            (Some(min), Some(max)) => {
+                format!("length must be between {} and {}, got {}", min, max, value)
+            }
+            _ => format!("invalid length, got {}", value),
+        },
+        code => format!("invalid ({}), got {}", code, value),
+    }
+}
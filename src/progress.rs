@@ -0,0 +1,87 @@
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use std::io::{self, Write};
+use std::sync::{Mutex, OnceLock};
+
+use crate::convert::Progress;
+
+/// Layout of each bar, after its "Files" or "Pages" prefix.
+const TEMPLATE: &str = "{prefix:>5} [{bar:30}] {pos}/{len} {msg}";
+
+/// The bars drawn, once anything is shown.
+static BARS: OnceLock<Bars> = OnceLock::new();
+
+/// A bar for the files of a batch above one for the pages of the file
+/// being converted, each there only while it has steps left.
+struct Bars {
+    multi: MultiProgress,
+    files: Mutex<Option<ProgressBar>>,
+    pages: Mutex<Option<ProgressBar>>,
+}
+
+fn bars() -> &'static Bars {
+    BARS.get_or_init(|| Bars {
+        multi: MultiProgress::with_draw_target(ProgressDrawTarget::stdout()),
+        files: Mutex::new(None),
+        pages: Mutex::new(None),
+    })
+}
+
+/// Draw a conversion's [`Progress`] on standard output, for
+/// [`ConvertOptions::on_progress`](crate::ConvertOptions::on_progress).
+/// Nothing is drawn when standard output isn't a terminal.
+pub fn show(progress: Progress) {
+    let bars = bars();
+    let (slot, prefix, done, total, message) = match progress {
+        Progress::Files { done, total } => (&bars.files, "Files", done, total, String::new()),
+        Progress::Pages { done, total, rows } => {
+            (&bars.pages, "Pages", done, total, format!("{} rows", rows))
+        }
+    };
+    let mut slot = slot.lock().unwrap();
+    let bar = slot.get_or_insert_with(|| {
+        let bar = ProgressBar::new(0)
+            .with_style(ProgressStyle::with_template(TEMPLATE).expect("valid template"))
+            .with_prefix(prefix);
+        match progress {
+            Progress::Files { .. } => bars.multi.insert(0, bar),
+            Progress::Pages { .. } => bars.multi.add(bar),
+        }
+    });
+    bar.set_length(total as u64);
+    bar.set_position(done as u64);
+    bar.set_message(message);
+    if done >= total {
+        bar.finish_and_clear();
+        bars.multi.remove(bar);
+        *slot = None;
+    }
+}
+
+/// Draw no bars from now on, as for `--quiet` or JSON logs.
+pub fn hide() {
+    bars().multi.set_draw_target(ProgressDrawTarget::hidden());
+}
+
+/// Clear any bars left drawn, as when a conversion fails partway.
+pub fn clear() {
+    if let Some(bars) = BARS.get() {
+        let _ = bars.multi.clear();
+    }
+}
+
+/// A log writer that lifts the bars off the terminal while it writes, so
+/// log lines don't run into them.
+pub struct Suspended<W>(pub W);
+
+impl<W: Write> Write for Suspended<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match BARS.get() {
+            Some(bars) => bars.multi.suspend(|| self.0.write(buf)),
+            None => self.0.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
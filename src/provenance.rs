@@ -0,0 +1,155 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::path::Path;
+
+use crate::audit::errln;
+use crate::calibration::CalibrationSource;
+use crate::convert::ConvertOptions;
+
+/// Name of this converter, as recorded in provenance.
+pub const TOOL_NAME: &str = "kardia2edf";
+
+/// How an output file was produced, written into every output format so
+/// each file can be traced back to its input and settings on its own.
+#[derive(Debug, Clone, Serialize)]
+pub struct Provenance {
+    pub tool: String,
+    pub tool_version: String,
+    /// SHA-256 of the input PDF, or `None` for generated recordings.
+    pub input_sha256: Option<String>,
+    /// SHA-256 of the settings that affect the output (layout profile,
+    /// format, patient override).
+    pub options_sha256: String,
+    /// UTC time of the conversion, as ISO 8601.
+    pub converted_at: String,
+    /// Calibration used to scale the printed trace to millivolts.
+    pub calibration: ProvenanceCalibration,
+    /// Quality score of the recording, if one was computed.
+    pub qc_score: Option<f64>,
+}
+
+/// Calibration part of [`Provenance`].
+#[derive(Debug, Clone, Serialize)]
+pub struct ProvenanceCalibration {
+    /// Layout profile name.
+    pub profile: String,
+    pub points_per_mv: f64,
+    /// Where the calibration came from, e.g. "footer_text" (see
+    /// [`crate::calibration::CalibrationSource`]).
+    pub source: String,
+    /// Sampling rate of the printed trace's points, in Hz.
+    pub sample_rate: usize,
+}
+
+impl Provenance {
+    /// Provenance of a conversion of `input` (the PDF bytes) with `options`,
+    /// timestamped now.
+    pub fn new(input: Option<&[u8]>, options: &ConvertOptions) -> Self {
+        let settings = serde_json::json!({
+            "profile": options.profile,
+            "calibration": options.cal_pt_per_mv,
+            "format": format!("{:?}", options.format),
+            "header": options.header,
+        });
+        let converted_at = conversion_time(options.deterministic);
+        Self {
+            tool: TOOL_NAME.to_string(),
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+            input_sha256: input.map(sha256_hex),
+            options_sha256: sha256_hex(settings.to_string().as_bytes()),
+            converted_at: converted_at.format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+            calibration: ProvenanceCalibration {
+                profile: options.profile.name.clone(),
+                points_per_mv: options
+                    .cal_pt_per_mv
+                    .unwrap_or(options.profile.cal_pt_per_mv),
+                source: if options.cal_pt_per_mv.is_some() {
+                    CalibrationSource::Override
+                } else {
+                    CalibrationSource::Default
+                }
+                .name()
+                .to_string(),
+                sample_rate: options.profile.sample_rate,
+            },
+            qc_score: None,
+        }
+    }
+
+    /// One-line summary for text fields, such as an EDF+ annotation, a
+    /// DICOM description, or a CSV comment. Hashes are shortened to 16
+    /// hex digits to fit EDF annotation channels.
+    pub fn summary(&self) -> String {
+        let mut text = format!(
+            "{} {} at {} input {} options {} cal {} pt/mV ({}) {} Hz",
+            self.tool,
+            self.tool_version,
+            self.converted_at,
+            self.input_sha256.as_deref().map_or("none", |h| &h[..16]),
+            &self.options_sha256[..16],
+            self.calibration.points_per_mv,
+            self.calibration.source,
+            self.calibration.sample_rate
+        );
+        if let Some(score) = self.qc_score {
+            text.push_str(&format!(" qc {:.2}", score));
+        }
+        text
+    }
+}
+
+/// The time to record a conversion at: now, or, for deterministic output,
+/// the time in the `SOURCE_DATE_EPOCH` environment variable (seconds since
+/// 1970), or 1970-01-01T00:00:00Z if it isn't set.
+pub fn conversion_time(deterministic: bool) -> DateTime<Utc> {
+    if !deterministic {
+        return Utc::now();
+    }
+    let epoch = std::env::var("SOURCE_DATE_EPOCH").ok().map(|value| {
+        value
+            .trim()
+            .parse::<i64>()
+            .ok()
+            .and_then(|seconds| DateTime::from_timestamp(seconds, 0))
+            .ok_or(value)
+    });
+    match epoch {
+        Some(Ok(time)) => time,
+        Some(Err(value)) => {
+            errln!(
+                "Warning: SOURCE_DATE_EPOCH {:?} isn't a number of seconds; using 0",
+                value
+            );
+            DateTime::UNIX_EPOCH
+        }
+        None => DateTime::UNIX_EPOCH,
+    }
+}
+
+/// Lowercase hex SHA-256 digest.
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Checksum sidecar path for an output file, named like it with `.sha256`
+/// added, e.g. `ecg.edf.sha256`.
+pub fn checksum_path(output: &str) -> String {
+    format!("{}.sha256", output)
+}
+
+/// Write the SHA-256 of `output` to its checksum sidecar, in the format
+/// `sha256sum --check` reads, and return the sidecar's path.
+pub fn write_checksum(output: &str) -> Result<String> {
+    let digest = sha256_hex(&std::fs::read(output)?);
+    let name = Path::new(output)
+        .file_name()
+        .map_or(output.into(), |name| name.to_string_lossy());
+    let path = checksum_path(output);
+    std::fs::write(&path, format!("{}  {}\n", digest, name))?;
+    Ok(path)
+}
@@ -0,0 +1,110 @@
+use serde::Serialize;
+
+use crate::noise;
+use crate::recording::EcgRecording;
+use crate::sample_flags::SampleFlag;
+
+/// Shortest run of unchanging samples, in seconds, counted as flat line.
+const FLAT_RUN_SECONDS: f64 = 0.5;
+
+/// Largest sample-to-sample change, in millivolts, within a flat run.
+const FLAT_STEP_MV: f64 = 1e-3;
+
+/// Clipped fraction of the samples that scores zero.
+const CLIPPING_ZERO: f64 = 0.1;
+
+/// Noise floors, in millivolts RMS, scoring one (and below) and zero (and
+/// above): a clean printed trace's noise is a few microvolts.
+const NOISE_CLEAN_MV: f64 = 0.02;
+const NOISE_ZERO_MV: f64 = 0.2;
+
+/// Quality score of a conversion, from 0 (broken) to 1 (clean), with the
+/// measures and component scores it's made from.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct QcScore {
+    /// Product of the component scores.
+    pub score: f64,
+    /// Duration read against the duration printed on the report: their
+    /// ratio, smaller over larger; 1 if the report gives none.
+    pub duration: f64,
+    /// One minus the fraction of samples in flat-line runs.
+    pub flat_line: f64,
+    /// One at no clipping, falling to zero at a tenth of the samples clipped.
+    pub clipping: f64,
+    /// One at a clean noise floor, falling to zero at a noisy one.
+    pub noise: f64,
+    /// Seconds of trace read.
+    pub duration_seconds: f64,
+    /// Seconds printed on the report, if given.
+    pub expected_seconds: Option<f64>,
+    /// Fraction of samples in runs of at least half a second that don't
+    /// change.
+    pub flat_fraction: f64,
+    /// Fraction of samples flagged as clipped.
+    pub clipped_fraction: f64,
+    /// Noise floor in millivolts RMS, if the signal is long enough to tell.
+    pub noise_rms_mv: Option<f64>,
+    /// Number of single-sample spikes replaced by `--despike`.
+    pub despiked_samples: usize,
+}
+
+/// Score a recording: the samples read against the printed duration, the
+/// flat-line and clipping fractions, and the baseline noise floor. Each is
+/// scored from 0 to 1, and the score is their product, so one broken
+/// measure sinks it.
+pub fn score(recording: &EcgRecording) -> QcScore {
+    let n = recording.signal.len();
+    let duration_seconds = recording.duration();
+    let expected_seconds = recording.report.duration_seconds.filter(|&s| s > 0.0);
+    let duration = match expected_seconds {
+        Some(expected) => duration_seconds.min(expected) / duration_seconds.max(expected),
+        None => 1.0,
+    };
+
+    let flat_run = (FLAT_RUN_SECONDS * recording.sample_rate as f64).ceil() as usize;
+    let mut flat = 0;
+    let mut run = 1;
+    for i in 1..=n {
+        if i < n && (recording.signal[i] - recording.signal[i - 1]).abs() <= FLAT_STEP_MV {
+            run += 1;
+            continue;
+        }
+        if run >= flat_run {
+            flat += run;
+        }
+        run = 1;
+    }
+    let fraction = |count: usize| if n == 0 { 1.0 } else { count as f64 / n as f64 };
+    let flat_fraction = fraction(flat);
+    let clipped = recording
+        .flags
+        .iter()
+        .filter(|&&f| f == SampleFlag::Clipped)
+        .count();
+    let clipped_fraction = fraction(clipped);
+    let despiked_samples = recording
+        .flags
+        .iter()
+        .filter(|&&f| f == SampleFlag::Despiked)
+        .count();
+
+    let noise_rms_mv = noise::estimate_noise(&recording.signal).map(|n| n.noise_rms);
+    let noise = noise_rms_mv.map_or(0.0, |rms| {
+        ((NOISE_ZERO_MV - rms) / (NOISE_ZERO_MV - NOISE_CLEAN_MV)).clamp(0.0, 1.0)
+    });
+    let flat_line = 1.0 - flat_fraction;
+    let clipping = (1.0 - clipped_fraction / CLIPPING_ZERO).max(0.0);
+    QcScore {
+        score: duration * flat_line * clipping * noise,
+        duration,
+        flat_line,
+        clipping,
+        noise,
+        duration_seconds,
+        expected_seconds,
+        flat_fraction,
+        clipped_fraction,
+        noise_rms_mv,
+        despiked_samples,
+    }
+}
@@ -0,0 +1,622 @@
+use anyhow::{anyhow, Result};
+use lopdf::content::Content;
+use lopdf::{Document, Object, ObjectId, Stream};
+use std::collections::HashMap;
+
+use crate::audit::errln;
+use crate::ecg_process::median;
+use crate::layout_detect::PAPER_SPEED_PT_PER_S;
+use crate::pdf_extract::{multiply_ctm, obj_f64, PageGeometry, Point};
+
+/// Gray level below which a pixel is ink.
+const DARK_THRESHOLD: u8 = 128;
+
+/// Color pixels whose channels differ by more than this are grid, as on red
+/// or pink ECG paper, and are read as white.
+const GRID_CHROMA: u8 = 60;
+
+/// Horizontal ink runs longer than this fraction of the inked width are grid
+/// lines; a trace's baseline is broken up by its waves.
+const GRID_RUN_WIDTH: f64 = 0.5;
+
+/// Vertical ink runs longer than this fraction of the inked height are grid
+/// lines; no QRS complex is that tall.
+const GRID_RUN_HEIGHT: f64 = 0.25;
+
+/// Gray levels by which the trace is darker than a grid line it crosses.
+const TRACE_CONTRAST: f64 = 40.0;
+
+/// Pixel rows without ink that a trace row's band may bridge.
+const MAX_BAND_GAP: usize = 2;
+
+/// A band of ink is a trace row if its inked columns are at least this
+/// fraction of the widest band's.
+const MIN_ROW_COVERAGE: f64 = 0.5;
+
+/// Fewest millimeters a trace row's band spans vertically, leaving out
+/// lines of text.
+const MIN_ROW_HEIGHT_MM: f64 = 4.0;
+
+/// Images narrower than this many pixels, such as logos, are not searched.
+const MIN_IMAGE_WIDTH: usize = 200;
+
+/// An image's gray levels, 0 for black to 255 for white, row by row from
+/// the top.
+#[derive(Debug, Clone)]
+pub struct GrayImage {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
+
+/// An image drawn on a page, with the top-left-origin positions of its
+/// top-left and bottom-right corners.
+#[derive(Debug, Clone)]
+pub struct PageImage {
+    pub image: GrayImage,
+    pub top_left: Point,
+    pub bottom_right: Point,
+}
+
+impl PageImage {
+    /// Horizontal resolution in pixels per inch.
+    pub fn dpi(&self) -> f64 {
+        self.image.width as f64 / (self.bottom_right.x - self.top_left.x).abs() * 72.0
+    }
+
+    /// Page position of the center of pixel (`column`, `row`), which may be
+    /// fractional.
+    fn point(&self, column: f64, row: f64) -> Point {
+        let (w, h) = (self.image.width as f64, self.image.height as f64);
+        Point {
+            x: self.top_left.x + (column + 0.5) / w * (self.bottom_right.x - self.top_left.x),
+            y: self.top_left.y + (row + 0.5) / h * (self.bottom_right.y - self.top_left.y),
+        }
+    }
+}
+
+/// Trace rows read from an image: each row's points in page coordinates at
+/// the sampling rate, and its baseline.
+#[derive(Debug, Clone)]
+pub struct RasterStrip {
+    pub baselines: Vec<f64>,
+    pub rows: HashMap<usize, Vec<Point>>,
+}
+
+/// The images drawn on a page, decodable and at least [`MIN_IMAGE_WIDTH`]
+/// pixels wide, each turned upright on the displayed page. Images that
+/// can't be decoded are reported and skipped, and skewed ones left out.
+pub fn page_images(
+    doc: &Document,
+    page_id: ObjectId,
+    geometry: &PageGeometry,
+) -> Result<Vec<PageImage>> {
+    let content_bytes = doc.get_page_content(page_id)?;
+    let content = Content::decode(&content_bytes).map_err(|e| anyhow!("{}", e))?;
+
+    let mut images = Vec::new();
+    let mut ctm = geometry.matrix;
+    let mut stack = Vec::new();
+    for op in &content.operations {
+        match op.operator.as_str() {
+            "q" => stack.push(ctm),
+            "Q" => ctm = stack.pop().unwrap_or(ctm),
+            "cm" if op.operands.len() == 6 => {
+                let mut m = [0.0; 6];
+                for (v, o) in m.iter_mut().zip(&op.operands) {
+                    *v = obj_f64(o)?;
+                }
+                ctm = multiply_ctm(&ctm, &m);
+            }
+            "Do" => {
+                let Some(name) = op.operands.first().and_then(|o| o.as_name().ok()) else {
+                    continue;
+                };
+                let Some(stream) = xobject(doc, page_id, name) else {
+                    continue;
+                };
+                if stream.dict.get(b"Subtype").and_then(Object::as_name).ok() != Some(b"Image") {
+                    continue;
+                }
+                match decode_image(doc, stream) {
+                    Ok(image) => {
+                        // Skewed placements aren't traced
+                        let placed = orient(image, &ctm);
+                        if let Some(placed) = placed.filter(|p| p.image.width >= MIN_IMAGE_WIDTH) {
+                            images.push(placed);
+                        }
+                    }
+                    Err(e) => errln!(
+                        "Warning: image {} not read: {}",
+                        String::from_utf8_lossy(name),
+                        e
+                    ),
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(images)
+}
+
+/// Place an image drawn with the matrix `m` (mapping the unit square to the
+/// displayed page) upright: its pixels turned or mirrored so columns run
+/// left to right and rows top to bottom. `None` unless the image's edges
+/// are parallel to the page's.
+fn orient(image: GrayImage, m: &[f64; 6]) -> Option<PageImage> {
+    const AXIS: f64 = 1e-6;
+    let transposed = if m[1].abs() < AXIS && m[2].abs() < AXIS {
+        false
+    } else if m[0].abs() < AXIS && m[3].abs() < AXIS {
+        true
+    } else {
+        return None;
+    };
+    let corner = |u: f64, v: f64| Point {
+        x: m[0] * u + m[2] * v + m[4],
+        y: m[1] * u + m[3] * v + m[5],
+    };
+    let corners = [
+        corner(0.0, 0.0),
+        corner(1.0, 0.0),
+        corner(0.0, 1.0),
+        corner(1.0, 1.0),
+    ];
+    let top_left = Point {
+        x: corners.iter().map(|p| p.x).fold(f64::INFINITY, f64::min),
+        y: corners.iter().map(|p| p.y).fold(f64::INFINITY, f64::min),
+    };
+    let bottom_right = Point {
+        x: corners
+            .iter()
+            .map(|p| p.x)
+            .fold(f64::NEG_INFINITY, f64::max),
+        y: corners
+            .iter()
+            .map(|p| p.y)
+            .fold(f64::NEG_INFINITY, f64::max),
+    };
+    let (span_x, span_y) = (bottom_right.x - top_left.x, bottom_right.y - top_left.y);
+    if span_x <= 0.0 || span_y <= 0.0 {
+        return None;
+    }
+
+    // Upright already: the unit square's (0, 1) corner at the top left
+    let (w, h) = (image.width, image.height);
+    let upright = corner(0.0, 1.0);
+    if !transposed
+        && (upright.x - top_left.x).abs() < AXIS * span_x.max(1.0)
+        && (upright.y - top_left.y).abs() < AXIS * span_y.max(1.0)
+    {
+        return Some(PageImage {
+            image,
+            top_left,
+            bottom_right,
+        });
+    }
+
+    // Move each pixel to where its center is displayed; image rows run
+    // from v = 1 at the top of the image down to v = 0
+    let (width, height) = if transposed { (h, w) } else { (w, h) };
+    let mut pixels = vec![255; width * height];
+    for row in 0..h {
+        for column in 0..w {
+            let p = corner(
+                (column as f64 + 0.5) / w as f64,
+                1.0 - (row as f64 + 0.5) / h as f64,
+            );
+            let x = ((p.x - top_left.x) / span_x * width as f64) as usize;
+            let y = ((p.y - top_left.y) / span_y * height as f64) as usize;
+            pixels[y.min(height - 1) * width + x.min(width - 1)] = image.pixels[row * w + column];
+        }
+    }
+    Some(PageImage {
+        image: GrayImage {
+            width,
+            height,
+            pixels,
+        },
+        top_left,
+        bottom_right,
+    })
+}
+
+/// The XObject named `name` in the page's resources.
+fn xobject<'a>(doc: &'a Document, page_id: ObjectId, name: &[u8]) -> Option<&'a Stream> {
+    let (inline, ids) = doc.get_page_resources(page_id).ok()?;
+    let resources = inline
+        .into_iter()
+        .chain(ids.into_iter().filter_map(|id| doc.get_dictionary(id).ok()));
+    for dict in resources {
+        let Ok(xobjects) = doc.get_dict_in_dict(dict, b"XObject") else {
+            continue;
+        };
+        let object = match xobjects.get(name) {
+            Ok(Object::Reference(id)) => doc.get_object(*id).ok()?,
+            Ok(object) => object,
+            Err(_) => continue,
+        };
+        return object.as_stream().ok();
+    }
+    None
+}
+
+/// Decode an image XObject to gray levels, with colored pixels as white.
+/// Supports uncompressed, Flate, LZW, and JPEG (DCT) images in gray, RGB,
+/// or CMYK at 1 to 16 bits per component.
+pub fn decode_image(doc: &Document, stream: &Stream) -> Result<GrayImage> {
+    let dict = &stream.dict;
+    let int = |key: &[u8]| dict.get(key).and_then(Object::as_i64).ok();
+    if dict
+        .get(b"ImageMask")
+        .and_then(Object::as_bool)
+        .unwrap_or(false)
+    {
+        return Err(anyhow!("image masks are not traced"));
+    }
+    let width = int(b"Width").ok_or_else(|| anyhow!("no /Width"))? as usize;
+    let height = int(b"Height").ok_or_else(|| anyhow!("no /Height"))? as usize;
+
+    let filters = stream.filters().unwrap_or_default();
+    let (data, components, bits) = if filters.last().is_some_and(|f| f == "DCTDecode") {
+        if filters.len() > 1 {
+            return Err(anyhow!("filters {} are not supported", filters.join(", ")));
+        }
+        let mut decoder = jpeg_decoder::Decoder::new(stream.content.as_slice());
+        let data = decoder.decode().map_err(|e| anyhow!("JPEG: {}", e))?;
+        let info = decoder
+            .info()
+            .ok_or_else(|| anyhow!("JPEG: no image info"))?;
+        let (components, bits) = match info.pixel_format {
+            jpeg_decoder::PixelFormat::L8 => (1, 8),
+            jpeg_decoder::PixelFormat::L16 => (1, 16),
+            jpeg_decoder::PixelFormat::RGB24 => (3, 8),
+            jpeg_decoder::PixelFormat::CMYK32 => (4, 8),
+        };
+        if (info.width as usize, info.height as usize) != (width, height) {
+            return Err(anyhow!("JPEG size differs from /Width and /Height"));
+        }
+        (data, components, bits)
+    } else {
+        if let Some(f) = filters
+            .iter()
+            .find(|f| !matches!(f.as_str(), "FlateDecode" | "LZWDecode" | "ASCII85Decode"))
+        {
+            return Err(anyhow!("{} images are not supported", f));
+        }
+        let data = if filters.is_empty() {
+            stream.content.clone()
+        } else {
+            // lopdf declines to decompress image streams itself
+            let mut plain = stream.clone();
+            plain.dict.remove(b"Subtype");
+            plain.decompressed_content()?
+        };
+        let components = color_components(doc, dict.get(b"ColorSpace").ok())?;
+        (
+            data,
+            components,
+            int(b"BitsPerComponent").unwrap_or(8) as usize,
+        )
+    };
+    if !matches!(bits, 1 | 2 | 4 | 8 | 16) {
+        return Err(anyhow!("{} bits per component is not supported", bits));
+    }
+    let inverted = dict
+        .get(b"Decode")
+        .and_then(Object::as_array)
+        .ok()
+        .and_then(|d| Some(obj_f64(d.first()?).ok()? > obj_f64(d.get(1)?).ok()?))
+        .unwrap_or(false);
+
+    let stride = (width * components * bits).div_ceil(8);
+    if data.len() < stride * height {
+        return Err(anyhow!("image data is truncated"));
+    }
+    let max = ((1u32 << bits) - 1) as f64;
+    let sample = |row: &[u8], i: usize| -> u8 {
+        let value = match bits {
+            8 => row[i] as f64,
+            16 => row[2 * i] as f64 * 257.0,
+            _ => {
+                let bit = i * bits;
+                ((row[bit / 8] >> (8 - bits - bit % 8)) & ((1 << bits) - 1) as u8) as f64
+            }
+        };
+        let level = (value / max * 255.0).round() as u8;
+        if inverted {
+            255 - level
+        } else {
+            level
+        }
+    };
+    let mut pixels = Vec::with_capacity(width * height);
+    for row in data.chunks(stride).take(height) {
+        for x in 0..width {
+            let c: Vec<u8> = (0..components)
+                .map(|k| sample(row, x * components + k))
+                .collect();
+            pixels.push(gray_level(&c));
+        }
+    }
+    Ok(GrayImage {
+        width,
+        height,
+        pixels,
+    })
+}
+
+/// Components per pixel of an image color space.
+fn color_components(doc: &Document, space: Option<&Object>) -> Result<usize> {
+    let space = match space {
+        Some(Object::Reference(id)) => doc.get_object(*id)?,
+        Some(space) => space,
+        None => return Err(anyhow!("no /ColorSpace")),
+    };
+    let (name, params) = match space {
+        Object::Name(name) => (name.as_slice(), None),
+        Object::Array(array) => (
+            array
+                .first()
+                .and_then(|n| n.as_name().ok())
+                .unwrap_or_default(),
+            array.get(1),
+        ),
+        _ => return Err(anyhow!("invalid /ColorSpace")),
+    };
+    match name {
+        b"DeviceGray" | b"CalGray" => Ok(1),
+        b"DeviceRGB" | b"CalRGB" | b"Lab" => Ok(3),
+        b"DeviceCMYK" => Ok(4),
+        b"ICCBased" => {
+            let profile = match params {
+                Some(Object::Reference(id)) => doc.get_object(*id)?.as_stream()?,
+                _ => return Err(anyhow!("invalid ICCBased color space")),
+            };
+            Ok(profile.dict.get(b"N").and_then(Object::as_i64)? as usize)
+        }
+        other => Err(anyhow!(
+            "{} color space is not supported",
+            String::from_utf8_lossy(other)
+        )),
+    }
+}
+
+/// Gray level of a gray, RGB, or CMYK pixel; strongly colored pixels are
+/// grid and read as white.
+fn gray_level(c: &[u8]) -> u8 {
+    let [r, g, b] = match *c {
+        [gray] => return gray,
+        [r, g, b] => [r, g, b],
+        [cyan, magenta, yellow, black] => {
+            [cyan, magenta, yellow].map(|v| 255 - v.saturating_add(black))
+        }
+        _ => return c[0],
+    };
+    if r.max(g).max(b) - r.min(g).min(b) > GRID_CHROMA {
+        return 255;
+    }
+    ((299 * r as u32 + 587 * g as u32 + 114 * b as u32) / 1000) as u8
+}
+
+/// Trace the ECG rows drawn in an image, at `sample_rate` Hz at 25 mm/s.
+///
+/// Dark pixels are ink, after grid lines (long straight runs) are removed.
+/// Rows are horizontal bands of ink spanning most of the strip's width;
+/// each row's trace is followed column by column, taking in each column
+/// the ink nearest the previous column's, at its darkest pixel or, for the
+/// vertical strokes of a QRS complex, at the far end of the stroke. The
+/// baseline is the row's median level.
+pub fn trace_strip(image: &PageImage, sample_rate: usize) -> Option<RasterStrip> {
+    let GrayImage {
+        width,
+        height,
+        ref pixels,
+    } = image.image;
+    let mut ink: Vec<bool> = pixels.iter().map(|&p| p < DARK_THRESHOLD).collect();
+    remove_grid_lines(&mut ink, pixels, width, height);
+
+    let pt_per_row = (image.bottom_right.y - image.top_left.y).abs() / height as f64;
+    let min_height = (MIN_ROW_HEIGHT_MM * 72.0 / 25.4 / pt_per_row) as usize;
+    let bands = trace_bands(&ink, width, height, min_height);
+
+    let pt_per_sample = PAPER_SPEED_PT_PER_S / sample_rate as f64;
+    let mut strip = RasterStrip {
+        baselines: Vec::new(),
+        rows: HashMap::new(),
+    };
+    for (top, bottom) in bands {
+        let levels = follow_trace(&ink, pixels, width, top, bottom);
+        let Some(baseline) = median(levels.iter().flatten().copied().collect()) else {
+            continue;
+        };
+        let points: Vec<Point> = levels
+            .iter()
+            .enumerate()
+            .filter_map(|(x, y)| Some(image.point(x as f64, (*y)?)))
+            .collect();
+        let points = resample(points, pt_per_sample, image);
+        if points.len() < 2 {
+            continue;
+        }
+        strip.rows.insert(strip.baselines.len(), points);
+        strip.baselines.push(image.point(0.0, baseline).y);
+    }
+    (!strip.baselines.is_empty()).then_some(strip)
+}
+
+/// Clear horizontal and vertical ink runs long enough to be grid lines
+/// (see [`GRID_RUN_WIDTH`] and [`GRID_RUN_HEIGHT`]), measured against the
+/// bounding box of the ink. Pixels of a run clearly darker than the run's
+/// median are where the trace crosses the line, and are kept.
+fn remove_grid_lines(ink: &mut [bool], pixels: &[u8], width: usize, height: usize) {
+    let rows_inked: Vec<usize> = (0..height)
+        .filter(|&y| ink[y * width..(y + 1) * width].contains(&true))
+        .collect();
+    let columns_inked: Vec<usize> = (0..width)
+        .filter(|&x| (0..height).any(|y| ink[y * width + x]))
+        .collect();
+    let (Some(&top), Some(&bottom), Some(&left), Some(&right)) = (
+        rows_inked.first(),
+        rows_inked.last(),
+        columns_inked.first(),
+        columns_inked.last(),
+    ) else {
+        return;
+    };
+    let min_run_x = ((right - left + 1) as f64 * GRID_RUN_WIDTH) as usize;
+    let min_run_y = ((bottom - top + 1) as f64 * GRID_RUN_HEIGHT) as usize;
+
+    let mut grid = vec![false; ink.len()];
+    let mut mark_runs = |indices: &mut dyn Iterator<Item = usize>, min_run: usize| {
+        let mut run = Vec::new();
+        for i in indices.chain(std::iter::once(usize::MAX)) {
+            if i != usize::MAX && ink[i] {
+                run.push(i);
+                continue;
+            }
+            if run.len() > min_run {
+                let levels = run.iter().map(|&j| pixels[j] as f64).collect();
+                let line = median(levels).unwrap_or_default();
+                for &j in &run {
+                    grid[j] |= pixels[j] as f64 > line - TRACE_CONTRAST;
+                }
+            }
+            run.clear();
+        }
+    };
+    for y in top..=bottom {
+        mark_runs(&mut (y * width..(y + 1) * width), min_run_x);
+    }
+    for x in left..=right {
+        mark_runs(&mut (0..height).map(|y| y * width + x), min_run_y);
+    }
+    for (i, g) in grid.into_iter().enumerate() {
+        ink[i] &= !g;
+    }
+}
+
+/// Top and bottom pixel rows of the bands of ink that are trace rows (see
+/// [`MIN_ROW_COVERAGE`]), from the top.
+fn trace_bands(
+    ink: &[bool],
+    width: usize,
+    height: usize,
+    min_height: usize,
+) -> Vec<(usize, usize)> {
+    let inked = |y: usize| ink[y * width..(y + 1) * width].contains(&true);
+    let mut bands: Vec<(usize, usize)> = Vec::new();
+    for y in (0..height).filter(|&y| inked(y)) {
+        match bands.last_mut() {
+            Some((_, bottom)) if y - *bottom <= MAX_BAND_GAP + 1 => *bottom = y,
+            _ => bands.push((y, y)),
+        }
+    }
+    let coverage = |&(top, bottom): &(usize, usize)| {
+        (0..width)
+            .filter(|&x| (top..=bottom).any(|y| ink[y * width + x]))
+            .count()
+    };
+    let covered: Vec<usize> = bands.iter().map(coverage).collect();
+    let widest = covered.iter().copied().max().unwrap_or(0);
+    bands
+        .into_iter()
+        .zip(covered)
+        .filter(|&((top, bottom), covered)| {
+            bottom - top >= min_height && covered as f64 >= widest as f64 * MIN_ROW_COVERAGE
+        })
+        .map(|(band, _)| band)
+        .collect()
+}
+
+/// The trace's level, as a fractional pixel row, in each column of the band
+/// from `top` to `bottom`, or `None` where the column has no ink.
+fn follow_trace(
+    ink: &[bool],
+    pixels: &[u8],
+    width: usize,
+    top: usize,
+    bottom: usize,
+) -> Vec<Option<f64>> {
+    let runs_in = |x: usize| {
+        let mut runs: Vec<(usize, usize)> = Vec::new();
+        for y in (top..=bottom).filter(|&y| ink[y * width + x]) {
+            match runs.last_mut() {
+                Some((_, end)) if *end + 1 == y => *end = y,
+                _ => runs.push((y, y)),
+            }
+        }
+        runs
+    };
+    // The stroke's thickness: the median run length
+    let lengths = (0..width)
+        .flat_map(runs_in)
+        .map(|(a, b)| (b - a + 1) as f64)
+        .collect();
+    let stroke = median(lengths).unwrap_or(1.0);
+
+    let mut previous: Option<f64> = None;
+    (0..width)
+        .map(|x| {
+            let runs = runs_in(x);
+            let distance = |&&(a, b): &&(usize, usize)| match previous {
+                Some(p) => (a as f64 - p).max(p - b as f64).max(0.0),
+                None => -((b - a) as f64),
+            };
+            let &(a, b) = runs
+                .iter()
+                .min_by(|r, s| distance(r).total_cmp(&distance(s)))?;
+            let level = if (b - a + 1) as f64 > 2.0 * stroke && previous.is_some() {
+                // A vertical stroke: the trace moved to its far end
+                let p = previous.unwrap_or_default();
+                if (a as f64 - p).abs() > (b as f64 - p).abs() {
+                    a as f64 + (stroke - 1.0) / 2.0
+                } else {
+                    b as f64 - (stroke - 1.0) / 2.0
+                }
+            } else {
+                // The middle of the run's darkest pixels
+                let darkest = (a..=b).map(|y| pixels[y * width + x]).min().unwrap_or(0);
+                let rows: Vec<usize> = (a..=b)
+                    .filter(|&y| pixels[y * width + x] == darkest)
+                    .collect();
+                rows.iter().sum::<usize>() as f64 / rows.len() as f64
+            };
+            previous = Some(level);
+            Some(level)
+        })
+        .collect()
+}
+
+/// Points at each `spacing` along x, interpolated between the traced
+/// points of neighboring columns; columns without ink are left as gaps.
+fn resample(mut points: Vec<Point>, spacing: f64, image: &PageImage) -> Vec<Point> {
+    points.sort_by(|a, b| a.x.total_cmp(&b.x));
+    let column = (image.bottom_right.x - image.top_left.x).abs() / image.image.width as f64;
+    let (Some(first), Some(last)) = (points.first(), points.last()) else {
+        return points;
+    };
+    let n = ((last.x - first.x) / spacing).floor() as usize + 1;
+    let mut resampled = Vec::with_capacity(n);
+    let mut k = 0;
+    for i in 0..n {
+        let x = first.x + i as f64 * spacing;
+        while k + 2 < points.len() && points[k + 1].x < x {
+            k += 1;
+        }
+        let (p, q) = (points[k], points[(k + 1).min(points.len() - 1)]);
+        if q.x - p.x > 1.5 * column {
+            continue;
+        }
+        let t = if q.x > p.x {
+            (x - p.x) / (q.x - p.x)
+        } else {
+            0.0
+        };
+        resampled.push(Point {
+            x,
+            y: p.y + (q.y - p.y) * t.clamp(0.0, 1.0),
+        });
+    }
+    resampled
+}
@@ -0,0 +1,213 @@
+use anyhow::{anyhow, Result};
+use chrono::{Duration, NaiveDateTime};
+use std::collections::BTreeMap;
+
+use crate::dsp::Filter;
+use crate::ecg_process::{PathCounts, RowSeries};
+use crate::edf_write::{Annotation, EdfSignal};
+use crate::pdf_text::{ReportInfo, TraceFilter};
+use crate::provenance::Provenance;
+use crate::sample_flags::{self, SampleFlag};
+
+/// A reconstructed ECG recording, ready to be written.
+///
+/// Pre-write hooks receive this mutably, so they can filter the signal or
+/// add annotations before the output file is written.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct EcgRecording {
+    /// Lead I voltage samples in millivolts.
+    pub signal: Vec<f64>,
+    /// Sampling rate in Hz.
+    pub sample_rate: usize,
+    /// Whether each sample of `signal` was measured, interpolated, missing,
+    /// or clipped; hooks that change the signal's length must keep one flag
+    /// per sample.
+    pub flags: Vec<SampleFlag>,
+    /// Filtering of the printed strip `signal` was read from.
+    pub trace_filter: TraceFilter,
+    /// Recording start date/time, if known.
+    pub start: Option<NaiveDateTime>,
+    /// EDF+ patient identification field.
+    pub patient: String,
+    /// EDF+ annotations, with onsets relative to the recording start.
+    pub annotations: Vec<Annotation>,
+    /// Additional signals written after lead I, each at its own sampling rate.
+    pub extra_signals: Vec<EdfSignal>,
+    /// Findings and device details printed on the report.
+    pub report: ReportInfo,
+    /// The strip's rows before they were joined into `signal`, by page
+    /// number, for stitching them another way; empty if not read from a
+    /// strip.
+    pub rows: BTreeMap<u32, Vec<RowSeries>>,
+    /// How many of each strip page's drawing paths were read as the trace,
+    /// by page number; empty if not read from a vector strip.
+    pub paths: BTreeMap<u32, PathCounts>,
+    /// Filters applied to lead I and the other millivolt signals after
+    /// extraction, in order, recorded in their prefiltering fields.
+    pub filters: Vec<Filter>,
+    /// Input, settings, and calibration this recording was made from,
+    /// written into every output.
+    pub provenance: Provenance,
+}
+
+impl EcgRecording {
+    /// Duration of the signal in seconds.
+    pub fn duration(&self) -> f64 {
+        self.signal.len() as f64 / self.sample_rate as f64
+    }
+
+    /// Keep only the `duration` seconds (or the rest of the recording, if
+    /// `None`) from `start` seconds in, to the nearest sample: every signal
+    /// and the flags are cut, annotations outside the window are dropped
+    /// and the rest made relative to it, notes on the whole recording (at
+    /// onset 0, without a duration) are kept at the window's start, such
+    /// as the polarity correction, and the start time is moved to
+    /// the window's start. The duration printed on the report is narrowed
+    /// to the window, so the quality score compares like with like.
+    pub fn trim(&mut self, start: f64, duration: Option<f64>) -> Result<()> {
+        let total = self.duration();
+        if !(start.is_finite() && start >= 0.0 && start < total) {
+            return Err(anyhow!(
+                "Window start must be from 0 to below the recording's {:.2} s, got {}",
+                total,
+                start
+            ));
+        }
+        if let Some(d) = duration.filter(|d| !(d.is_finite() && *d > 0.0)) {
+            return Err(anyhow!("Window duration must be positive, got {}", d));
+        }
+        sample_flags::check_len(&self.flags, self.signal.len())?;
+        let fs = self.sample_rate as f64;
+        let first = (start * fs).round() as usize;
+        let end = duration.map_or(self.signal.len(), |d| {
+            (first + (d * fs).round() as usize).min(self.signal.len())
+        });
+        let (from, to) = (first as f64 / fs, end as f64 / fs);
+
+        self.signal = self.signal[first..end].to_vec();
+        self.flags = self.flags[first..end].to_vec();
+        for extra in &mut self.extra_signals {
+            let at = |t: f64| ((t * extra.sample_rate).round() as usize).min(extra.samples.len());
+            let (a, b) = (at(from), at(to));
+            extra.samples = extra.samples[a..b.max(a)].to_vec();
+        }
+        self.annotations.retain_mut(|a| {
+            let end = a.onset + a.duration.unwrap_or(0.0);
+            let whole = a.onset == 0.0 && a.duration.is_none();
+            let inside = whole || (a.onset < to && (end > from || a.onset >= from));
+            if inside {
+                let onset = a.onset.max(from);
+                a.duration = a.duration.map(|_| end.min(to) - onset);
+                a.onset = onset - from;
+            }
+            inside
+        });
+        self.start = self
+            .start
+            .map(|t| t + Duration::microseconds((from * 1e6).round() as i64));
+        self.report.duration_seconds = self
+            .report
+            .duration_seconds
+            .map(|printed| (printed - from).clamp(0.0, to - from));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::demo::demo_recording;
+
+    fn note(onset: f64, duration: Option<f64>, text: &str) -> Annotation {
+        Annotation {
+            onset,
+            duration,
+            text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn trims_samples_and_moves_start() {
+        let mut recording = demo_recording(300);
+        let full = recording.clone();
+        recording
+            .extra_signals
+            .push(EdfSignal::new("HR", "bpm", 1.0, vec![72.0; 30]));
+        recording.trim(10.0, Some(5.0)).unwrap();
+
+        assert_eq!(recording.signal, full.signal[3000..4500]);
+        assert_eq!(recording.flags.len(), 1500);
+        assert_eq!(recording.extra_signals[0].samples.len(), 5);
+        let start = full.start.unwrap() + Duration::seconds(10);
+        assert_eq!(recording.start, Some(start));
+        assert!((recording.duration() - 5.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn window_ends_at_the_recording_end() {
+        let mut recording = demo_recording(300);
+        recording.trim(29.0, Some(5.0)).unwrap();
+        assert_eq!(recording.signal.len(), 300);
+
+        let mut recording = demo_recording(300);
+        recording.trim(0.5 + 1.0 / 600.0, None).unwrap();
+        assert_eq!(recording.signal.len(), 9000 - 151);
+    }
+
+    #[test]
+    fn relativizes_and_clips_annotations() {
+        let mut recording = demo_recording(300);
+        let beats_in_window = recording
+            .annotations
+            .iter()
+            .filter(|a| a.onset >= 10.0 && a.onset < 15.0)
+            .count();
+        recording.annotations.extend([
+            note(8.0, Some(4.0), "Noise before"),
+            note(14.0, Some(6.0), "Noise after"),
+            note(16.0, None, "Outside"),
+        ]);
+        recording.trim(10.0, Some(5.0)).unwrap();
+
+        let beats: Vec<&Annotation> = recording
+            .annotations
+            .iter()
+            .filter(|a| a.text == "R")
+            .collect();
+        assert_eq!(beats.len(), beats_in_window);
+        assert!(beats.iter().all(|a| (0.0..5.0).contains(&a.onset)));
+        let find = |text: &str| recording.annotations.iter().find(|a| a.text == text);
+        let before = find("Noise before").unwrap();
+        assert_eq!((before.onset, before.duration), (0.0, Some(2.0)));
+        let after = find("Noise after").unwrap();
+        assert_eq!((after.onset, after.duration), (4.0, Some(1.0)));
+        assert!(find("Outside").is_none());
+    }
+
+    #[test]
+    fn keeps_whole_recording_notes() {
+        let mut recording = demo_recording(300);
+        recording
+            .annotations
+            .push(note(0.0, None, "Polarity inverted, corrected"));
+        recording.trim(20.0, None).unwrap();
+
+        let kept = recording
+            .annotations
+            .iter()
+            .find(|a| a.text == "Polarity inverted, corrected");
+        assert_eq!(kept.map(|a| a.onset), Some(0.0));
+    }
+
+    #[test]
+    fn rejects_windows_outside_the_recording() {
+        let mut recording = demo_recording(300);
+        assert!(recording.trim(30.0, None).is_err());
+        assert!(recording.trim(-1.0, None).is_err());
+        assert!(recording.trim(f64::NAN, None).is_err());
+        assert!(recording.trim(1.0, Some(0.0)).is_err());
+        assert!(recording.trim(1.0, Some(f64::INFINITY)).is_err());
+        assert_eq!(recording.signal.len(), 9000);
+    }
+}
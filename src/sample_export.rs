@@ -0,0 +1,161 @@
+use anyhow::{anyhow, Result};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::audit::{errln, outln};
+use crate::compress;
+use crate::edf_write::EdfSignal;
+use crate::recording::EcgRecording;
+
+/// Path of one channel's file when splitting: the label is appended to the
+/// file stem, e.g. "ecg.csv" and "EKG I" give "ecg_EKG_I.csv", and
+/// "ecg.csv.gz" gives "ecg_EKG_I.csv.gz".
+pub fn channel_path(path: &str, label: &str) -> String {
+    let (path, compression) = compress::split_extension(path);
+    let label: String = label
+        .trim()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    let p = Path::new(path);
+    let stem = p.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match p.extension() {
+        Some(ext) => format!("{}_{}.{}", stem, label, ext.to_string_lossy()),
+        None => format!("{}_{}", stem, label),
+    };
+    let path = p.with_file_name(name).to_string_lossy().into_owned();
+    match compression {
+        Some(compression) => compression.add_extension(&path),
+        None => path,
+    }
+}
+
+/// The signals written to one combined file: those sampled at the lead's
+/// rate. Others are skipped with a warning, as they need their own files.
+fn same_rate_signals(signals: &[EdfSignal]) -> Vec<&EdfSignal> {
+    let rate = signals.first().map_or(0.0, |s| s.sample_rate);
+    signals
+        .iter()
+        .filter(|s| {
+            let same = s.sample_rate == rate;
+            if !same {
+                errln!(
+                    "Warning: {} at {} Hz is left out of the combined file; \
+                     use --split-channels to write it on its own",
+                    s.label.trim(),
+                    s.sample_rate
+                );
+            }
+            same
+        })
+        .collect()
+}
+
+/// Write the signals as CSV: provenance, start, and report findings as "#"
+/// comments, a header row, then a time column in seconds and one column per
+/// signal.
+///
+/// With `split`, each signal goes to its own file (see [`channel_path`]),
+/// at its own sampling rate.
+pub fn write_csv(
+    path: &str,
+    recording: &EcgRecording,
+    signals: &[EdfSignal],
+    split: bool,
+) -> Result<()> {
+    if split {
+        for s in signals {
+            let channel = channel_path(path, &s.label);
+            write_csv_file(&channel, recording, &[s])?;
+            outln!("CSV written: {}", channel);
+        }
+    } else {
+        write_csv_file(path, recording, &same_rate_signals(signals))?;
+        outln!("CSV written: {}", path);
+    }
+    Ok(())
+}
+
+fn write_csv_file(path: &str, recording: &EcgRecording, signals: &[&EdfSignal]) -> Result<()> {
+    let rate = signals
+        .first()
+        .ok_or_else(|| anyhow!("No signals to write to {}", path))?
+        .sample_rate;
+    let mut file = compress::create(path)?;
+
+    writeln!(file, "# {}", recording.provenance.summary())?;
+    if let Some(start) = recording.start {
+        writeln!(file, "# start {}", start.format("%Y-%m-%dT%H:%M:%S"))?;
+    }
+    let report = recording.report.summary();
+    if !report.is_empty() {
+        writeln!(file, "# report {}", report)?;
+    }
+    write!(file, "time_s")?;
+    for s in signals {
+        write!(file, ",{} ({})", s.label.trim(), s.phys_dim.trim())?;
+    }
+    writeln!(file)?;
+
+    let n = signals.iter().map(|s| s.samples.len()).max().unwrap_or(0);
+    for i in 0..n {
+        write!(file, "{:.4}", i as f64 / rate)?;
+        for s in signals {
+            match s.samples.get(i) {
+                Some(v) => write!(file, ",{:.6}", v)?,
+                None => write!(file, ",")?,
+            }
+        }
+        writeln!(file)?;
+    }
+    file.finish()?;
+    Ok(())
+}
+
+/// Write the signals as a NumPy .npy array of little-endian float64: shape
+/// (samples, signals), or (samples,) per file with `split`.
+pub fn write_npy(path: &str, signals: &[EdfSignal], split: bool) -> Result<()> {
+    if split {
+        for s in signals {
+            let channel = channel_path(path, &s.label);
+            write_npy_file(&channel, &[s])?;
+            outln!("NPY written: {}", channel);
+        }
+    } else {
+        write_npy_file(path, &same_rate_signals(signals))?;
+        outln!("NPY written: {}", path);
+    }
+    Ok(())
+}
+
+fn write_npy_file(path: &str, signals: &[&EdfSignal]) -> Result<()> {
+    let n = signals.iter().map(|s| s.samples.len()).max().unwrap_or(0);
+    let shape = match signals {
+        [_] => format!("({},)", n),
+        _ => format!("({}, {})", n, signals.len()),
+    };
+
+    // Format version 1.0: magic, version, header length, then the header
+    // dict padded with spaces to a multiple of 64 bytes, ending in a newline
+    let mut header = format!(
+        "{{'descr': '<f8', 'fortran_order': False, 'shape': {}, }}",
+        shape
+    );
+    let unpadded = 10 + header.len() + 1;
+    header.push_str(&" ".repeat(unpadded.next_multiple_of(64) - unpadded));
+    header.push('\n');
+
+    let mut file = BufWriter::new(File::create(path)?);
+    file.write_all(b"\x93NUMPY\x01\x00")?;
+    file.write_all(&(header.len() as u16).to_le_bytes())?;
+    file.write_all(header.as_bytes())?;
+    for i in 0..n {
+        for s in signals {
+            let v = s.samples.get(i).copied().unwrap_or(f64::NAN);
+            file.write_all(&v.to_le_bytes())?;
+        }
+    }
+    file.flush()?;
+    Ok(())
+}
@@ -0,0 +1,133 @@
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use std::io::Write;
+
+use crate::compress;
+use crate::edf_write::{EdfSignal, Gap};
+
+/// Label of the flags channel added with `--flags-channel`.
+pub const FLAGS_LABEL: &str = "Sample flags";
+
+/// Where a sample's value came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SampleFlag {
+    /// Read from a trace point in the PDF.
+    Measured,
+    /// Filled in between the points on either side of a gap in a row.
+    Interpolated,
+    /// Written past the end of the signal to fill the last data record.
+    Padded,
+    /// In a gap too long to interpolate; interpolated in the signal, but
+    /// data records wholly inside the gap are left out of EDF+/BDF+ files.
+    Missing,
+    /// Read from the trace where it runs flat along the row's highest or
+    /// lowest level, as when the device clipped the display; the true
+    /// value may lie beyond it.
+    Clipped,
+    /// In a flat line at the baseline, as when the electrodes were off;
+    /// not a measurement of the heart.
+    LeadOff,
+    /// A single-sample spike, as at the joins of PDF subpaths, replaced by
+    /// the midpoint of its neighbours.
+    Despiked,
+}
+
+impl SampleFlag {
+    /// Value of the flag in the flags channel: 0 to 6.
+    pub fn code(self) -> u8 {
+        match self {
+            SampleFlag::Measured => 0,
+            SampleFlag::Interpolated => 1,
+            SampleFlag::Padded => 2,
+            SampleFlag::Missing => 3,
+            SampleFlag::Clipped => 4,
+            SampleFlag::LeadOff => 5,
+            SampleFlag::Despiked => 6,
+        }
+    }
+
+    /// Identifier for the flags CSV, e.g. "interpolated".
+    pub fn name(self) -> &'static str {
+        match self {
+            SampleFlag::Measured => "measured",
+            SampleFlag::Interpolated => "interpolated",
+            SampleFlag::Padded => "padded",
+            SampleFlag::Missing => "missing",
+            SampleFlag::Clipped => "clipped",
+            SampleFlag::LeadOff => "lead_off",
+            SampleFlag::Despiked => "despiked",
+        }
+    }
+}
+
+/// Error unless there is one flag per sample, as hooks that change the
+/// signal's length must keep.
+pub fn check_len(flags: &[SampleFlag], samples: usize) -> Result<()> {
+    if flags.len() == samples {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "Sample flags cover {} samples but the signal has {}; \
+             hooks that change the signal's length must update the flags",
+            flags.len(),
+            samples
+        ))
+    }
+}
+
+/// The flags as a signal of their codes, padded with [`SampleFlag::Padded`]
+/// to fill the last data record.
+pub fn flags_signal(flags: &[SampleFlag], sample_rate: usize) -> EdfSignal {
+    let codes = flags.iter().map(|f| f.code() as f64).collect();
+    EdfSignal {
+        phys_range: Some((0.0, 6.0)),
+        pad_value: SampleFlag::Padded.code() as f64,
+        ..EdfSignal::new(FLAGS_LABEL, "", sample_rate as f64, codes)
+    }
+}
+
+/// The runs of [`SampleFlag::Missing`] samples, in seconds.
+pub fn gaps(flags: &[SampleFlag], sample_rate: usize) -> Vec<Gap> {
+    spans(flags, SampleFlag::Missing, sample_rate)
+}
+
+/// The runs of samples with flag `of`, in seconds.
+pub fn spans(flags: &[SampleFlag], of: SampleFlag, sample_rate: usize) -> Vec<Gap> {
+    let fs = sample_rate as f64;
+    let mut gaps = Vec::new();
+    let mut start = None;
+    let matches = flags.iter().map(|&flag| flag == of).chain([false]);
+    for (i, is_of) in matches.enumerate() {
+        match (is_of, start) {
+            (true, None) => start = Some(i),
+            (false, Some(s)) => {
+                gaps.push(Gap {
+                    onset: s as f64 / fs,
+                    duration: (i - s) as f64 / fs,
+                });
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    gaps
+}
+
+/// Write the flags as CSV: a header row, then each sample's index, time in
+/// seconds, and flag name.
+pub fn write_flags_csv(path: &str, flags: &[SampleFlag], sample_rate: usize) -> Result<()> {
+    let mut file = compress::create(path)?;
+    writeln!(file, "sample,time,flag")?;
+    for (i, flag) in flags.iter().enumerate() {
+        writeln!(
+            file,
+            "{},{:.6},{}",
+            i,
+            i as f64 / sample_rate as f64,
+            flag.name()
+        )?;
+    }
+    file.finish()?;
+    Ok(())
+}
@@ -0,0 +1,165 @@
+use anyhow::Result;
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::audit::{errln, outln};
+use crate::convert::{self, convert_bytes, ConvertOptions};
+use crate::http::{self, Request};
+use crate::metrics::Metrics;
+
+/// Largest accepted PDF upload, in bytes.
+const MAX_BODY: usize = 64 * 1024 * 1024;
+
+/// Longest wait to send a refused connection its 503.
+const REFUSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Settings for [`serve`].
+#[derive(Debug, Clone)]
+pub struct ServeOptions {
+    /// Most conversions run at the same time; further uploads are refused
+    /// with 503 until one finishes.
+    pub max_conversions: usize,
+    /// Most connections handled at the same time, conversions included;
+    /// further connections are refused with 503 and closed.
+    pub max_connections: usize,
+    /// Longest wait for the next bytes of a request, or for the client to
+    /// take the next bytes of the response.
+    pub timeout: Duration,
+}
+
+impl Default for ServeOptions {
+    fn default() -> Self {
+        Self {
+            max_conversions: std::thread::available_parallelism().map_or(2, |n| n.get()),
+            max_connections: 64,
+            timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Run the HTTP conversion service until the process is stopped. Options
+/// that write files are refused, as uploads are converted in memory.
+///
+/// Endpoints:
+/// - `POST /convert` with a PDF body responds with the EDF+ file, converted
+///   in memory by [`convert_bytes`].
+/// - `GET /healthz` responds "ok" while the service is accepting requests.
+/// - `GET /metrics` responds with conversion counters and latency in the
+///   Prometheus text format.
+///
+/// Each connection is handled on its own thread, so health checks and
+/// metrics scrapes are answered during long conversions. Connections
+/// beyond `max_connections` handled at once, and uploads beyond
+/// `max_conversions` running at once, are refused with 503, the latter
+/// before their body is read; connections that stall for longer than
+/// `timeout` are dropped.
+pub fn serve(addr: &str, options: ConvertOptions, serve: ServeOptions) -> Result<()> {
+    convert::check_in_memory(&options)?;
+    let listener = TcpListener::bind(addr)?;
+    outln!(
+        "Listening on http://{} (up to {} conversion(s) at once)",
+        listener.local_addr()?,
+        serve.max_conversions
+    );
+
+    let metrics = Arc::new(Metrics::new());
+    let options = Arc::new(options);
+    let running = Arc::new(AtomicUsize::new(0));
+    let connections = Arc::new(AtomicUsize::new(0));
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                errln!("Warning: connection failed: {}", e);
+                continue;
+            }
+        };
+        let Some(connection) = Slot::take(&connections, serve.max_connections) else {
+            // Refused without reading the request, and without waiting
+            // long on a client that doesn't read the answer
+            let _ = stream
+                .set_write_timeout(Some(REFUSE_TIMEOUT))
+                .map_err(anyhow::Error::from)
+                .and_then(|()| {
+                    http::respond(&mut stream, 503, "text/plain", b"Too many connections\n")
+                });
+            continue;
+        };
+        let metrics = Arc::clone(&metrics);
+        let options = Arc::clone(&options);
+        let running = Arc::clone(&running);
+        let serve = serve.clone();
+        std::thread::spawn(move || {
+            let _connection = connection;
+            if let Err(e) = handle(stream, &metrics, &options, &serve, &running) {
+                errln!("Warning: request failed: {}", e);
+            }
+        });
+    }
+    Ok(())
+}
+
+/// A connection or conversion counted as running until dropped.
+struct Slot(Arc<AtomicUsize>);
+
+impl Slot {
+    /// Count one more as running, unless `max` already are.
+    fn take(running: &Arc<AtomicUsize>, max: usize) -> Option<Self> {
+        running
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| {
+                (n < max).then_some(n + 1)
+            })
+            .ok()
+            .map(|_| Slot(Arc::clone(running)))
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Read one request and write its response.
+fn handle(
+    mut stream: TcpStream,
+    metrics: &Metrics,
+    options: &ConvertOptions,
+    serve: &ServeOptions,
+    running: &Arc<AtomicUsize>,
+) -> Result<()> {
+    let mut request = Request::read(&stream, serve.timeout)?;
+    if http::respond_status(&mut stream, &request, metrics)? {
+        return Ok(());
+    }
+    match (request.method.as_str(), request.path.as_str()) {
+        ("POST", "/convert") => {
+            if request.content_length > MAX_BODY {
+                return http::respond(&mut stream, 413, "text/plain", b"PDF too large\n");
+            }
+            let Some(_slot) = Slot::take(running, serve.max_conversions) else {
+                return http::respond(
+                    &mut stream,
+                    503,
+                    "text/plain",
+                    b"Too many conversions running\n",
+                );
+            };
+            let pdf = request.read_body()?;
+
+            let started = Instant::now();
+            let result = convert_bytes(&pdf, options);
+            metrics.record(started.elapsed(), &result);
+            match result {
+                Ok(edf) => http::respond(&mut stream, 200, "application/octet-stream", &edf),
+                Err(e) => {
+                    let message = format!("{:#}\n", e);
+                    http::respond(&mut stream, 422, "text/plain", message.as_bytes())
+                }
+            }
+        }
+        _ => http::respond(&mut stream, 404, "text/plain", b"not found\n"),
+    }
+}
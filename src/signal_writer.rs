@@ -0,0 +1,359 @@
+use anyhow::{anyhow, Result};
+use chrono::NaiveDateTime;
+use serde::Deserialize;
+use std::io::{Read, Seek, Write};
+
+use crate::edf_write::{self, Annotation, EdfSignal, Format, WriteSettings};
+use crate::gdf_write;
+
+/// A session file read and written in place, such as a buffer in memory.
+pub trait Session: Read + Write + Seek {}
+
+impl<T: Read + Write + Seek> Session for T {}
+
+/// A file format backend that writes signals and annotations.
+pub trait SignalWriter {
+    /// Short format name for messages, e.g. "EDF+".
+    fn name(&self) -> &'static str;
+
+    /// Write a new file, overwriting any existing one.
+    fn write(
+        &self,
+        path: &str,
+        signals: &[EdfSignal],
+        start: Option<NaiveDateTime>,
+        patient: &str,
+        annotations: &[Annotation],
+    ) -> Result<()>;
+
+    /// Write a new file's content to `out`, such as a buffer in memory.
+    fn write_to(
+        &self,
+        out: &mut dyn Write,
+        signals: &[EdfSignal],
+        start: Option<NaiveDateTime>,
+        patient: &str,
+        annotations: &[Annotation],
+    ) -> Result<()>;
+
+    /// Append to a session file as a new segment, creating it if missing.
+    /// Formats without discontinuous files don't support this.
+    fn append(
+        &self,
+        path: &str,
+        _signals: &[EdfSignal],
+        _start: Option<NaiveDateTime>,
+        _patient: &str,
+        _annotations: &[Annotation],
+    ) -> Result<()> {
+        Err(anyhow!(
+            "Cannot append to {}: {} files have no segments",
+            path,
+            self.name()
+        ))
+    }
+
+    /// Append to a session in `session`, such as a buffer in memory, as
+    /// [`SignalWriter::append`] does to a file.
+    fn append_to(
+        &self,
+        _session: &mut dyn Session,
+        _signals: &[EdfSignal],
+        _start: Option<NaiveDateTime>,
+        _patient: &str,
+        _annotations: &[Annotation],
+    ) -> Result<()> {
+        Err(anyhow!(
+            "Cannot append to the session: {} files have no segments",
+            self.name()
+        ))
+    }
+}
+
+/// EDF+ or BDF+ backend, see [`edf_write::write_edf`] and [`edf_write::append_edf`].
+#[derive(Debug, Clone)]
+pub struct EdfWriter {
+    pub format: Format,
+    pub settings: WriteSettings,
+}
+
+impl SignalWriter for EdfWriter {
+    fn name(&self) -> &'static str {
+        match self.format {
+            Format::Edf => "EDF+",
+            Format::Bdf => "BDF+",
+        }
+    }
+
+    fn write(
+        &self,
+        path: &str,
+        signals: &[EdfSignal],
+        start: Option<NaiveDateTime>,
+        patient: &str,
+        annotations: &[Annotation],
+    ) -> Result<()> {
+        let write = match self.format {
+            Format::Edf => edf_write::write_edf,
+            Format::Bdf => edf_write::write_bdf,
+        };
+        write(
+            path,
+            signals,
+            start,
+            patient,
+            annotations,
+            self.settings.clone(),
+        )
+    }
+
+    fn write_to(
+        &self,
+        out: &mut dyn Write,
+        signals: &[EdfSignal],
+        start: Option<NaiveDateTime>,
+        patient: &str,
+        annotations: &[Annotation],
+    ) -> Result<()> {
+        let write_to = match self.format {
+            Format::Edf => edf_write::write_edf_to,
+            Format::Bdf => edf_write::write_bdf_to,
+        };
+        write_to(
+            out,
+            signals,
+            start,
+            patient,
+            annotations,
+            self.settings.clone(),
+        )
+    }
+
+    fn append(
+        &self,
+        path: &str,
+        signals: &[EdfSignal],
+        start: Option<NaiveDateTime>,
+        patient: &str,
+        annotations: &[Annotation],
+    ) -> Result<()> {
+        let append = match self.format {
+            Format::Edf => edf_write::append_edf,
+            Format::Bdf => edf_write::append_bdf,
+        };
+        append(
+            path,
+            signals,
+            start,
+            patient,
+            annotations,
+            self.settings.clone(),
+        )
+    }
+
+    fn append_to(
+        &self,
+        mut session: &mut dyn Session,
+        signals: &[EdfSignal],
+        start: Option<NaiveDateTime>,
+        patient: &str,
+        annotations: &[Annotation],
+    ) -> Result<()> {
+        let append = match self.format {
+            Format::Edf => edf_write::append_edf_to,
+            Format::Bdf => edf_write::append_bdf_to,
+        };
+        append(
+            &mut session,
+            signals,
+            start,
+            patient,
+            annotations,
+            self.settings.clone(),
+        )
+    }
+}
+
+/// Plain EDF backend, see [`edf_write::write_edf_classic`]. Annotations
+/// are left out, as plain EDF has no annotation signal.
+#[derive(Debug, Clone)]
+pub struct EdfClassicWriter {
+    pub settings: WriteSettings,
+}
+
+impl SignalWriter for EdfClassicWriter {
+    fn name(&self) -> &'static str {
+        "EDF"
+    }
+
+    fn write(
+        &self,
+        path: &str,
+        signals: &[EdfSignal],
+        start: Option<NaiveDateTime>,
+        patient: &str,
+        _annotations: &[Annotation],
+    ) -> Result<()> {
+        edf_write::write_edf_classic(path, signals, start, patient, self.settings.clone())
+    }
+
+    fn write_to(
+        &self,
+        out: &mut dyn Write,
+        signals: &[EdfSignal],
+        start: Option<NaiveDateTime>,
+        patient: &str,
+        _annotations: &[Annotation],
+    ) -> Result<()> {
+        edf_write::write_edf_classic_to(out, signals, start, patient, self.settings.clone())
+    }
+}
+
+/// GDF v2 backend, see [`gdf_write::write_gdf`].
+#[derive(Debug, Clone)]
+pub struct GdfWriter {
+    pub settings: WriteSettings,
+}
+
+impl SignalWriter for GdfWriter {
+    fn name(&self) -> &'static str {
+        "GDF"
+    }
+
+    fn write(
+        &self,
+        path: &str,
+        signals: &[EdfSignal],
+        start: Option<NaiveDateTime>,
+        patient: &str,
+        annotations: &[Annotation],
+    ) -> Result<()> {
+        gdf_write::write_gdf(
+            path,
+            signals,
+            start,
+            patient,
+            annotations,
+            self.settings.clone(),
+        )
+    }
+
+    fn write_to(
+        &self,
+        out: &mut dyn Write,
+        signals: &[EdfSignal],
+        start: Option<NaiveDateTime>,
+        patient: &str,
+        annotations: &[Annotation],
+    ) -> Result<()> {
+        gdf_write::write_gdf_to(
+            out,
+            signals,
+            start,
+            patient,
+            annotations,
+            self.settings.clone(),
+        )
+    }
+}
+
+/// Output file format of a conversion.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputFormat {
+    /// EDF+ with 16-bit samples.
+    #[default]
+    Edf,
+    /// BDF+ (BioSemi) with 24-bit samples, for finer quantization.
+    Bdf,
+    /// GDF v2 with 32-bit float samples, for BioSig and SigViewer.
+    Gdf,
+    /// Plain EDF with 16-bit samples and no annotation signal, for legacy
+    /// software that doesn't read EDF+.
+    EdfClassic,
+}
+
+impl OutputFormat {
+    /// Digital minimum and maximum sample values; `None` for GDF, whose
+    /// samples are floats.
+    pub fn digital_range(self) -> Option<(i32, i32)> {
+        match self {
+            OutputFormat::Edf | OutputFormat::EdfClassic => Some(Format::Edf.digital_range()),
+            OutputFormat::Bdf => Some(Format::Bdf.digital_range()),
+            OutputFormat::Gdf => None,
+        }
+    }
+
+    /// The backend writing this format with the given settings.
+    pub fn writer(self, settings: WriteSettings) -> Box<dyn SignalWriter> {
+        match self {
+            OutputFormat::Edf => Box::new(EdfWriter {
+                format: Format::Edf,
+                settings,
+            }),
+            OutputFormat::Bdf => Box::new(EdfWriter {
+                format: Format::Bdf,
+                settings,
+            }),
+            OutputFormat::Gdf => Box::new(GdfWriter { settings }),
+            OutputFormat::EdfClassic => Box::new(EdfClassicWriter { settings }),
+        }
+    }
+}
+
+/// Physical dimension the ECG signals are written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Unit {
+    /// Millivolts, as read from the strip.
+    #[default]
+    Millivolt,
+    /// Microvolts, as some analysis pipelines expect.
+    Microvolt,
+}
+
+impl Unit {
+    /// The physical dimension field, "mV" or "uV".
+    pub fn phys_dim(self) -> &'static str {
+        match self {
+            Unit::Millivolt => "mV",
+            Unit::Microvolt => "uV",
+        }
+    }
+
+    /// Value of one millivolt in this unit.
+    pub fn per_mv(self) -> f64 {
+        match self {
+            Unit::Millivolt => 1.0,
+            Unit::Microvolt => 1000.0,
+        }
+    }
+}
+
+/// A fixed resolution for the ECG signals, so every converted file has the
+/// same scaling and amplitudes compare across files, instead of a physical
+/// range fitted to each recording's samples.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Quantization {
+    /// Physical range of ± this many millivolts; in 16-bit EDF+, ±5 mV is
+    /// 0.1526 µV per bit.
+    Range(f64),
+    /// This many microvolts per bit, with 0 mV at digital 0; the physical
+    /// range is as wide as the format's digital range allows.
+    Lsb(f64),
+}
+
+impl Quantization {
+    /// Physical (min, max) in millivolts for the digital range
+    /// `(dig_min, dig_max)`.
+    pub fn phys_range(self, (dig_min, dig_max): (i32, i32)) -> Result<(f64, f64)> {
+        match self {
+            Quantization::Range(mv) if mv.is_finite() && mv > 0.0 => Ok((-mv, mv)),
+            Quantization::Lsb(uv) if uv.is_finite() && uv > 0.0 => {
+                let lsb = uv / 1000.0;
+                Ok((lsb * dig_min as f64, lsb * dig_max as f64))
+            }
+            Quantization::Range(mv) => Err(anyhow!("Fixed range must be positive, got {} mV", mv)),
+            Quantization::Lsb(uv) => Err(anyhow!("LSB must be positive, got {} µV", uv)),
+        }
+    }
+}
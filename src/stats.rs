@@ -0,0 +1,120 @@
+use anyhow::Result;
+use std::fs::File;
+use std::io::{BufReader, Write};
+
+use crate::ecg_process;
+use crate::edf_read;
+use crate::noise::{self, NoiseEstimate};
+use crate::sample_flags::SampleFlag;
+
+/// Bins of the amplitude histogram.
+const HISTOGRAM_BINS: usize = 16;
+
+/// Width, in characters, of the histogram's longest bar.
+const HISTOGRAM_WIDTH: usize = 40;
+
+/// Amplitude and noise statistics of one signal of a converted file.
+#[derive(Debug, Clone)]
+pub struct SignalStats {
+    pub label: String,
+    pub phys_dim: String,
+    pub samples: usize,
+    /// Lowest and highest physical value.
+    pub range: (f64, f64),
+    /// Sample counts in [`HISTOGRAM_BINS`] equal bins across `range`.
+    pub histogram: Vec<usize>,
+    /// Noise floor from the high-frequency residual, and the SNR.
+    pub noise: Option<NoiseEstimate>,
+    /// Fraction of samples in flat runs at the highest or lowest value, as
+    /// where the printed strip was clipped.
+    pub clipped_fraction: f64,
+}
+
+/// Statistics of each signal of an EDF/EDF+ or BDF/BDF+ file, leaving out
+/// the annotation signal.
+pub fn edf_stats(path: &str) -> Result<Vec<SignalStats>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let header = edf_read::read_header(&mut reader)?;
+    let mut stats = Vec::new();
+    for (i, sig) in header.signals.iter().enumerate() {
+        if sig.is_annotations() {
+            continue;
+        }
+        let values: Vec<f64> = edf_read::read_digital_samples(&mut reader, &header, i)?
+            .into_iter()
+            .map(|d| sig.to_physical(d))
+            .collect();
+        let range = values
+            .iter()
+            .fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), &v| {
+                (lo.min(v), hi.max(v))
+            });
+        let mut histogram = vec![0; HISTOGRAM_BINS];
+        let width = (range.1 - range.0) / HISTOGRAM_BINS as f64;
+        for v in &values {
+            let bin = if width > 0.0 {
+                ((v - range.0) / width) as usize
+            } else {
+                0
+            };
+            histogram[bin.min(HISTOGRAM_BINS - 1)] += 1;
+        }
+        let mut flags = vec![SampleFlag::Measured; values.len()];
+        ecg_process::flag_clipping(&values, &mut flags);
+        let clipped = flags.iter().filter(|&&f| f == SampleFlag::Clipped).count();
+        stats.push(SignalStats {
+            label: sig.label.clone(),
+            phys_dim: sig.phys_dim.clone(),
+            samples: values.len(),
+            range,
+            histogram,
+            noise: noise::estimate_noise(&values),
+            clipped_fraction: clipped as f64 / values.len().max(1) as f64,
+        });
+    }
+    Ok(stats)
+}
+
+/// Print each signal's amplitude histogram, noise floor, SNR, and clipping
+/// to `out`, to compare traces such as a report's Enhanced and Original
+/// filter strips.
+pub fn print_stats(out: &mut impl Write, path: &str) -> Result<()> {
+    writeln!(out, "File: {}", path)?;
+    for stats in edf_stats(path)? {
+        let dim = &stats.phys_dim;
+        writeln!(out, "\nSignal: {}", stats.label)?;
+        writeln!(out, "  Samples: {}", stats.samples)?;
+        if stats.samples == 0 {
+            continue;
+        }
+        writeln!(
+            out,
+            "  Range: [{:.4}, {:.4}] {}",
+            stats.range.0, stats.range.1, dim
+        )?;
+        match stats.noise {
+            Some(n) => writeln!(
+                out,
+                "  Noise floor: {:.4} {} RMS, signal {:.4} {} RMS, SNR {:.1} dB",
+                n.noise_rms, dim, n.signal_rms, dim, n.snr_db
+            )?,
+            None => writeln!(out, "  Noise floor: too few samples")?,
+        }
+        writeln!(out, "  Clipped: {:.2}%", stats.clipped_fraction * 100.0)?;
+        writeln!(out, "  Amplitude histogram:")?;
+        let width = (stats.range.1 - stats.range.0) / HISTOGRAM_BINS as f64;
+        let most = stats.histogram.iter().copied().max().unwrap_or(0).max(1);
+        for (bin, &count) in stats.histogram.iter().enumerate() {
+            let low = stats.range.0 + bin as f64 * width;
+            writeln!(
+                out,
+                "  {:>9.4} .. {:>9.4} {:>7} {}",
+                low,
+                low + width,
+                count,
+                "#".repeat((count * HISTOGRAM_WIDTH).div_ceil(most))
+            )?;
+        }
+    }
+    Ok(())
+}
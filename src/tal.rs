@@ -0,0 +1,216 @@
+use anyhow::{anyhow, Result};
+
+/// Separates a TAL's onset from its duration.
+const DURATION_MARK: u8 = 0x15;
+
+/// Ends a TAL's timestamp and each of its annotation texts.
+const TEXT_END: u8 = 0x14;
+
+/// Ends a TAL.
+const TAL_END: u8 = 0x00;
+
+/// An EDF+ Time-stamped Annotation List: annotations sharing an onset and
+/// duration, as stored in an "EDF Annotations" signal.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tal {
+    /// Onset in seconds from the start of the file; may be negative.
+    pub onset: f64,
+    /// Duration in seconds, if any.
+    pub duration: Option<f64>,
+    /// Annotation texts, UTF-8. A data record's first TAL, keeping its
+    /// time, has one empty text.
+    pub texts: Vec<String>,
+}
+
+impl Tal {
+    /// The time-keeping TAL starting a data record at `onset`.
+    pub fn time_keeping(onset: f64) -> Self {
+        Self {
+            onset,
+            duration: None,
+            texts: vec![String::new()],
+        }
+    }
+
+    /// A TAL of one annotation.
+    pub fn new(onset: f64, duration: Option<f64>, text: impl Into<String>) -> Self {
+        Self {
+            onset,
+            duration,
+            texts: vec![text.into()],
+        }
+    }
+}
+
+/// Format seconds for a TAL or header, without trailing zeros ("12.5", not
+/// "12.5000").
+pub fn format_seconds(seconds: f64) -> String {
+    let s = format!("{:.4}", seconds);
+    s.trim_end_matches('0').trim_end_matches('.').to_string()
+}
+
+/// Encode TALs, in order, without padding. Onsets are signed, durations are
+/// not; bytes that would end a text or TAL early (0x00, 0x14, 0x15) are
+/// replaced by spaces.
+pub fn encode(tals: &[Tal]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for tal in tals {
+        let sign = if tal.onset < 0.0 { '-' } else { '+' };
+        bytes.push(sign as u8);
+        bytes.extend_from_slice(format_seconds(tal.onset.abs()).as_bytes());
+        if let Some(duration) = tal.duration {
+            bytes.push(DURATION_MARK);
+            bytes.extend_from_slice(format_seconds(duration).as_bytes());
+        }
+        bytes.push(TEXT_END);
+        for text in &tal.texts {
+            bytes.extend(text.bytes().map(|b| match b {
+                TAL_END | TEXT_END | DURATION_MARK => b' ',
+                b => b,
+            }));
+            bytes.push(TEXT_END);
+        }
+        bytes.push(TAL_END);
+    }
+    bytes
+}
+
+/// Decode the TALs of an annotation signal's bytes, e.g. one data record's,
+/// ignoring the null padding after the last.
+pub fn decode(bytes: &[u8]) -> Result<Vec<Tal>> {
+    bytes
+        .split(|&b| b == TAL_END)
+        .filter(|tal| !tal.is_empty())
+        .map(decode_tal)
+        .collect()
+}
+
+fn decode_tal(bytes: &[u8]) -> Result<Tal> {
+    let mut parts = bytes.split(|&b| b == TEXT_END);
+    let timestamp = parts.next().unwrap_or_default();
+    let (onset, duration) = match timestamp.iter().position(|&b| b == DURATION_MARK) {
+        Some(i) => (&timestamp[..i], Some(&timestamp[i + 1..])),
+        None => (timestamp, None),
+    };
+    let number = |field: &[u8], what: &str| -> Result<f64> {
+        std::str::from_utf8(field)
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .ok_or_else(|| anyhow!("Invalid TAL {} {:?}", what, String::from_utf8_lossy(field)))
+    };
+    if !matches!(onset.first(), Some(b'+' | b'-')) {
+        return Err(anyhow!(
+            "TAL onset {:?} has no sign",
+            String::from_utf8_lossy(onset)
+        ));
+    }
+    let onset = number(onset, "onset")?;
+    let duration = duration.map(|d| number(d, "duration")).transpose()?;
+
+    let mut texts: Vec<&[u8]> = parts.collect();
+    // Each text ends with 0x14, leaving an empty part after the last
+    if texts.pop().is_none_or(|rest| !rest.is_empty()) {
+        return Err(anyhow!(
+            "TAL at {} is not terminated",
+            format_seconds(onset)
+        ));
+    }
+    let texts = texts
+        .into_iter()
+        .map(|t| {
+            String::from_utf8(t.to_vec()).map_err(|_| {
+                anyhow!(
+                    "TAL at {} has text that is not UTF-8",
+                    format_seconds(onset)
+                )
+            })
+        })
+        .collect::<Result<_>>()?;
+    Ok(Tal {
+        onset,
+        duration,
+        texts,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn time_keeping_tal() {
+        assert_eq!(encode(&[Tal::time_keeping(0.0)]), b"+0\x14\x14\x00");
+        assert_eq!(encode(&[Tal::time_keeping(12.0)]), b"+12\x14\x14\x00");
+    }
+
+    #[test]
+    fn onset_and_duration_formats() {
+        let tals = [
+            Tal::new(12.5, None, "R"),
+            Tal::new(0.25, Some(3.0), "Gap"),
+            Tal::new(-0.125, Some(0.5), "Before start"),
+            Tal::new(1.23456, None, "Rounded"),
+        ];
+        assert_eq!(
+            encode(&tals),
+            b"+12.5\x14R\x14\x00\
+              +0.25\x153\x14Gap\x14\x00\
+              -0.125\x150.5\x14Before start\x14\x00\
+              +1.2346\x14Rounded\x14\x00"
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let tals = vec![
+            Tal::time_keeping(30.0),
+            Tal::new(30.5, None, "R"),
+            Tal::new(31.0, Some(7.5), "Gap"),
+            Tal::new(-2.0, Some(0.25), "Before start"),
+        ];
+        assert_eq!(decode(&encode(&tals)).unwrap(), tals);
+    }
+
+    #[test]
+    fn multiple_annotations_per_tal() {
+        let tal = Tal {
+            onset: 4.0,
+            duration: Some(1.5),
+            texts: vec!["Lead off".to_string(), "Amplitude clipped".to_string()],
+        };
+        let bytes = encode(std::slice::from_ref(&tal));
+        assert_eq!(bytes, b"+4\x151.5\x14Lead off\x14Amplitude clipped\x14\x00");
+        assert_eq!(decode(&bytes).unwrap(), vec![tal]);
+    }
+
+    #[test]
+    fn utf8_texts() {
+        let tals = vec![
+            Tal::new(1.0, None, "Vorhofflimmern – ÄÖÜ"),
+            Tal::new(2.0, None, "心房細動"),
+        ];
+        assert_eq!(decode(&encode(&tals)).unwrap(), tals);
+    }
+
+    #[test]
+    fn separators_in_texts_are_replaced() {
+        let bytes = encode(&[Tal::new(1.0, None, "a\x14b\x15c\x00d")]);
+        assert_eq!(bytes, b"+1\x14a b c d\x14\x00");
+    }
+
+    #[test]
+    fn padding_is_ignored() {
+        let mut bytes = encode(&[Tal::time_keeping(0.0), Tal::new(0.5, None, "R")]);
+        bytes.resize(bytes.len() + 10, 0);
+        assert_eq!(decode(&bytes).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn invalid_tals_are_errors() {
+        assert!(decode(b"12\x14\x14\x00").is_err(), "no sign");
+        assert!(decode(b"+1x\x14\x14\x00").is_err(), "bad onset");
+        assert!(decode(b"+1\x15y\x14\x14\x00").is_err(), "bad duration");
+        assert!(decode(b"+1\x14R\x00").is_err(), "unterminated text");
+        assert!(decode(b"+1\x14\xff\x14\x00").is_err(), "not UTF-8");
+    }
+}
@@ -0,0 +1,79 @@
+use anyhow::{anyhow, Result};
+
+/// How non-ASCII characters in header text fields (patient, recording,
+/// labels, transducer, units, prefiltering) are written.
+///
+/// The EDF specification allows only printable ASCII in the header; some
+/// consumers also accept latin1 bytes, others reject anything else.
+/// Annotation texts are not affected, as EDF+ TALs are UTF-8.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TextPolicy {
+    /// Transliterate to ASCII ("José Müller" becomes "Jose Muller"), with
+    /// '?' for characters that have no ASCII form.
+    #[default]
+    Ascii,
+    /// Keep latin1 characters as single ISO 8859-1 bytes, and transliterate
+    /// the rest to ASCII.
+    Latin1,
+    /// Reject fields with characters outside printable ASCII.
+    Strict,
+}
+
+impl TextPolicy {
+    /// Encode a header field's value as bytes, or fail under
+    /// [`TextPolicy::Strict`]; `field` names it in the error.
+    pub fn encode(self, field: &str, value: &str) -> Result<Vec<u8>> {
+        let mut bytes = Vec::with_capacity(value.len());
+        for c in value.chars() {
+            match c {
+                ' '..='~' => bytes.push(c as u8),
+                _ if self == TextPolicy::Strict => {
+                    return Err(anyhow!(
+                        "{} {:?} has a character outside printable ASCII: {:?}",
+                        field,
+                        value,
+                        c
+                    ));
+                }
+                '\u{A0}'..='\u{FF}' if self == TextPolicy::Latin1 => bytes.push(c as u8),
+                _ => bytes.extend_from_slice(transliterate(c).as_bytes()),
+            }
+        }
+        Ok(bytes)
+    }
+}
+
+/// ASCII letters for latin1 characters U+00C0 to U+00FF; '?' where there is
+/// none or several (handled in [`transliterate`]).
+const LATIN1_LETTERS: &str = "AAAAAA?CEEEEIIIIDNOOOOOxOUUUUY??aaaaaa?ceeeeiiiidnooooo?ouuuuy?y";
+
+/// ASCII letters for Latin Extended-A characters U+0100 to U+017F.
+const LATIN_EXTENDED_A_LETTERS: &str = "AaAaAaCcCcCcCcDdDdEeEeEeEeEeGgGgGgGgHhHhIiIiIiIiIi??\
+JjKkkLlLlLlLlLlNnNnNnnNnOoOoOo??RrRrRrSsSsSsSsTtTtTtUuUuUuUuUuUuWwYyYZzZzZzs";
+
+/// ASCII transliteration of a non-ASCII character, or "?" if it has none.
+fn transliterate(c: char) -> &'static str {
+    match c {
+        'Æ' => "AE",
+        'æ' => "ae",
+        'Þ' => "TH",
+        'þ' => "th",
+        'ß' => "ss",
+        'Ĳ' => "IJ",
+        'ĳ' => "ij",
+        'Œ' => "OE",
+        'œ' => "oe",
+        '\u{A0}' => " ",
+        '‘' | '’' | '´' => "'",
+        '“' | '”' => "\"",
+        '–' | '—' => "-",
+        'µ' => "u",
+        '\u{C0}'..='\u{FF}' => letter(LATIN1_LETTERS, c as usize - 0xC0),
+        '\u{100}'..='\u{17F}' => letter(LATIN_EXTENDED_A_LETTERS, c as usize - 0x100),
+        _ => "?",
+    }
+}
+
+fn letter(table: &'static str, index: usize) -> &'static str {
+    &table[index..index + 1]
+}
@@ -0,0 +1,115 @@
+use anyhow::{anyhow, Context, Result};
+use chrono::{Duration, NaiveDate};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::audit::{errln, outln};
+use crate::recording::EcgRecording;
+
+/// Fewest days a patient's timestamps are shifted back.
+pub const MIN_SHIFT_DAYS: i64 = 365;
+
+/// Most days a patient's timestamps are shifted back.
+pub const MAX_SHIFT_DAYS: i64 = 3650;
+
+/// Serializes reading and updating mapping files between conversions run
+/// on worker threads, so two recordings of a new patient get one offset.
+static MAPPING_LOCK: Mutex<()> = Mutex::new(());
+
+/// Per-patient time offsets for temporally de-identifying recordings, kept
+/// in a JSON file mapping each EDF+ patient identification field to a
+/// number of days.
+///
+/// The file is the key to re-identifying dates, so it is created readable
+/// only by its owner and should be stored apart from the shifted data.
+#[derive(Debug, Clone, Default)]
+pub struct TimeShiftMap {
+    path: PathBuf,
+    days: BTreeMap<String, i64>,
+}
+
+impl TimeShiftMap {
+    /// Read a mapping file, or start an empty mapping if it doesn't exist.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let days = match std::fs::read_to_string(&path) {
+            Ok(text) => serde_json::from_str(&text)
+                .with_context(|| format!("Invalid time-shift mapping {}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self { path, days })
+    }
+
+    /// Days to shift this patient's timestamps by, drawing a new random
+    /// offset and saving the mapping if the patient is new.
+    pub fn offset_days(&mut self, patient: &str) -> Result<i64> {
+        if let Some(&days) = self.days.get(patient) {
+            return Ok(days);
+        }
+        let days = -random_days()?;
+        self.days.insert(patient.to_string(), days);
+        self.save()?;
+        Ok(days)
+    }
+
+    fn save(&self) -> Result<()> {
+        let text = serde_json::to_string_pretty(&self.days)?;
+        let mut file = owner_only(&self.path)?;
+        std::io::Write::write_all(&mut file, text.as_bytes())?;
+        Ok(())
+    }
+}
+
+/// Create or truncate a file readable only by its owner, where supported.
+fn owner_only(path: &Path) -> Result<std::fs::File> {
+    let mut open = std::fs::OpenOptions::new();
+    open.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut open, 0o600);
+    open.open(path)
+        .with_context(|| format!("Can't write time-shift mapping {}", path.display()))
+}
+
+/// A uniformly random whole number of days in
+/// [`MIN_SHIFT_DAYS`, `MAX_SHIFT_DAYS`].
+fn random_days() -> Result<i64> {
+    let mut bytes = [0u8; 8];
+    getrandom::getrandom(&mut bytes).map_err(|e| anyhow!("No random source: {}", e))?;
+    let span = (MAX_SHIFT_DAYS - MIN_SHIFT_DAYS + 1) as u64;
+    Ok(MIN_SHIFT_DAYS + (u64::from_le_bytes(bytes) % span) as i64)
+}
+
+/// Shift the recording's timestamps by its patient's offset in the mapping
+/// file at `path`: the start date/time and the birthdate in the patient
+/// field move together, so intervals between a patient's recordings and
+/// their age at each one are kept. Annotation onsets are relative to the
+/// start and are unchanged.
+pub fn apply(recording: &mut EcgRecording, path: &str) -> Result<()> {
+    let _lock = MAPPING_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    if recording.patient.split_whitespace().all(|p| p == "X") {
+        errln!(
+            "Warning: no patient identification to key the time shift; \
+             all such recordings share one offset"
+        );
+    }
+    let days = TimeShiftMap::load(path)?.offset_days(&recording.patient)?;
+    let shift = Duration::days(days);
+    recording.start = recording.start.map(|start| start + shift);
+    recording.patient = shift_birthdate(&recording.patient, shift);
+    outln!("Timestamps shifted per the mapping in {}", path);
+    Ok(())
+}
+
+/// The EDF+ patient identification field with its birthdate subfield
+/// ("dd-MMM-yyyy") shifted; other fields are kept as they are.
+fn shift_birthdate(patient: &str, shift: Duration) -> String {
+    let mut parts: Vec<String> = patient.split(' ').map(str::to_string).collect();
+    if let Some(field) = parts.get_mut(2) {
+        if let Ok(date) = NaiveDate::parse_from_str(field, "%d-%b-%Y") {
+            *field = (date + shift).format("%d-%b-%Y").to_string().to_uppercase();
+        }
+    }
+    parts.join(" ")
+}
@@ -0,0 +1,142 @@
+use anyhow::{anyhow, Result};
+use chrono::NaiveDateTime;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek};
+
+use crate::audit::outln;
+use crate::edf_read;
+use crate::edf_write::{format_edf_num, EdfSignal};
+
+/// Re-read a freshly written EDF/EDF+ or BDF/BDF+ file and check that its
+/// samples, scaled back with the header's physical and digital ranges,
+/// match the signals written, each within one quantization step plus the
+/// precision of the 8-character physical limits in the header.
+///
+/// Samples outside a signal's physical range are compared clipped, and
+/// padding past the end of a signal against its `pad_value`. In EDF+ files
+/// each data record is matched by its onset, so records left out in gaps
+/// and the earlier recordings of an appended session are skipped; `start`
+/// is the recording's start, as passed to the writer.
+pub fn verify_written(
+    path: &str,
+    signals: &[EdfSignal],
+    start: Option<NaiveDateTime>,
+) -> Result<()> {
+    verify_read(&mut BufReader::new(File::open(path)?), path, signals, start)
+}
+
+/// Check a written file read from `reader`, such as a buffer in memory,
+/// like [`verify_written`]; `name` stands for it in messages.
+pub fn verify_read<R: Read + Seek>(
+    reader: &mut R,
+    name: &str,
+    signals: &[EdfSignal],
+    start: Option<NaiveDateTime>,
+) -> Result<()> {
+    let header = edf_read::read_header(reader)?;
+    let data: Vec<usize> = (0..header.signals.len())
+        .filter(|&i| !header.signals[i].is_annotations())
+        .collect();
+    if data.len() != signals.len() {
+        return Err(anyhow!(
+            "Verification failed: {} has {} data signals, {} were written",
+            name,
+            data.len(),
+            signals.len()
+        ));
+    }
+    let n_records = usize::try_from(header.n_records)
+        .map_err(|_| anyhow!("Verification failed: {} has no record count", name))?;
+
+    // Onset of the recording's first data record within the file: the
+    // subsecond start, or where an appended recording begins
+    let base = match (header.annotation_index(), start) {
+        (Some(_), Some(t)) => (t - header.start_datetime()?).num_milliseconds() as f64 / 1000.0,
+        _ => 0.0,
+    };
+    let mut records = Vec::with_capacity(n_records);
+    for r in 0..n_records {
+        let rec = match header.annotation_index() {
+            Some(_) => {
+                let onset = edf_read::read_record_onset(reader, &header, r)?;
+                ((onset - base) / header.record_duration).round()
+            }
+            None => r as f64,
+        };
+        if rec >= 0.0 {
+            records.push((r, rec as usize));
+        }
+    }
+
+    let mut checked = 0;
+    for (&i, signal) in data.iter().zip(signals) {
+        let h = &header.signals[i];
+        let expected = signal.sample_rate * header.record_duration;
+        if h.label != signal.label || (h.samples_per_record as f64 - expected).abs() > 1e-6 {
+            return Err(anyhow!(
+                "Verification failed: signal {} reads back as {:?} with {} samples per record, \
+                 written as {:?} with {}",
+                i,
+                h.label,
+                h.samples_per_record,
+                signal.label,
+                expected
+            ));
+        }
+        let n = h.samples_per_record;
+        // Writers scale with the exact limits, readers with the header's
+        let tolerance =
+            h.gain().abs() + field_precision(h.phys_min).max(field_precision(h.phys_max));
+        let (low, high) = (h.phys_min.min(h.phys_max), h.phys_min.max(h.phys_max));
+        let digital = edf_read::read_digital_samples(reader, &header, i)?;
+        for &(r, rec) in &records {
+            if rec * n >= signal.samples.len() {
+                continue;
+            }
+            for k in 0..n {
+                let idx = rec * n + k;
+                let written = signal
+                    .samples
+                    .get(idx)
+                    .copied()
+                    .unwrap_or(signal.pad_value)
+                    .clamp(low, high);
+                let read = h.to_physical(digital[r * n + k]);
+                if (read - written).abs() > tolerance {
+                    return Err(anyhow!(
+                        "Verification failed: {} sample {} reads back as {} {}, \
+                         written as {} {} (tolerance {})",
+                        signal.label,
+                        idx,
+                        read,
+                        signal.phys_dim,
+                        written,
+                        signal.phys_dim,
+                        tolerance
+                    ));
+                }
+                checked += 1;
+            }
+        }
+    }
+    if checked == 0 {
+        return Err(anyhow!(
+            "Verification failed: no data record of the recording found in {}",
+            name
+        ));
+    }
+    outln!(
+        "Verified: {} samples of {} signals read back as written",
+        checked,
+        signals.len()
+    );
+    Ok(())
+}
+
+/// Half the last decimal place of a header number as written, e.g. 0.005
+/// for "-1234.56".
+fn field_precision(value: f64) -> f64 {
+    let written = format_edf_num(value);
+    let decimals = written.split('.').nth(1).map_or(0, str::len);
+    0.5 * 10f64.powi(-(decimals as i32))
+}
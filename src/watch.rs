@@ -0,0 +1,276 @@
+use anyhow::Result;
+use notify::{RecursiveMode, Watcher};
+use std::collections::HashMap;
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::audit::{errln, outln};
+use crate::cache::{file_sha256, ConversionCache};
+use crate::convert::{convert, ConvertOptions, Progress};
+use crate::http;
+use crate::metrics::Metrics;
+use crate::work_queue::{RetryPolicy, WorkQueue};
+
+/// Time the watched directory must be quiet after a change before it is
+/// scanned, and so the least time between two scans.
+const SETTLE: Duration = Duration::from_millis(500);
+
+/// Longest wait for the next bytes of a status request, or for a client to
+/// take the response.
+const STATUS_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Settings for [`watch`].
+#[derive(Debug, Clone)]
+pub struct WatchOptions {
+    /// Directory for the EDF+ files; defaults to the watched directory.
+    pub out_dir: Option<PathBuf>,
+    /// Number of conversions run at the same time.
+    pub concurrency: usize,
+    /// Number of PDFs waiting for a worker before scanning pauses.
+    pub queue_size: usize,
+    /// Retries for transient I/O errors, e.g. a PDF still being synced.
+    pub retry: RetryPolicy,
+    /// Longest time between directory scans; changes reported by the file
+    /// system start a scan sooner.
+    pub poll_interval: Duration,
+    /// Subfolder of the watched directory, e.g. "archive", that PDFs are
+    /// moved into once converted; they are left in place if `None`.
+    pub archive: Option<PathBuf>,
+    /// Convert PDFs the cache says are unchanged, too.
+    pub force: bool,
+    /// Address to serve `GET /healthz` and `GET /metrics` on, as the
+    /// `serve` mode does, e.g. "127.0.0.1:9090"; not served if `None`.
+    pub metrics_listen: Option<String>,
+}
+
+impl Default for WatchOptions {
+    fn default() -> Self {
+        Self {
+            out_dir: None,
+            concurrency: 2,
+            queue_size: 16,
+            retry: RetryPolicy::default(),
+            poll_interval: Duration::from_secs(2),
+            archive: None,
+            force: false,
+            metrics_listen: None,
+        }
+    }
+}
+
+/// Watch a drop folder and convert each PDF that appears in it, until the
+/// process is stopped.
+///
+/// The directory is scanned half a second after the file system last
+/// reports a change in it, and at least every `poll_interval`, for
+/// directories such as network shares where changes aren't reported. A
+/// PDF is queued once its size and modification time are unchanged between
+/// two scans, and again whenever it is replaced. Conversions run on a
+/// bounded [`WorkQueue`], so a burst of files is worked through
+/// `concurrency` at a time while scanning waits for room in the queue.
+///
+/// PDFs whose content was already converted into the output directory, by
+/// its [`ConversionCache`], are skipped unless `force` is set, so a
+/// restarted watcher doesn't convert the whole folder again. With an
+/// archive subfolder, each PDF converted or skipped is moved into it,
+/// replacing any of the same name; failed ones stay put.
+///
+/// The options' progress callback gets the files queued and done since
+/// the watch started, rather than the pages of each conversion. With
+/// `metrics_listen`, each conversion's result and latency, retries
+/// included, are recorded in the same [`Metrics`] the `serve` mode has.
+pub fn watch(dir: &str, mut options: ConvertOptions, watch: WatchOptions) -> Result<()> {
+    let dir = Path::new(dir);
+    let out_dir = watch.out_dir.clone().unwrap_or_else(|| dir.to_path_buf());
+    std::fs::create_dir_all(&out_dir)?;
+    let archive = watch.archive.as_ref().map(|sub| dir.join(sub));
+    if let Some(archive) = &archive {
+        std::fs::create_dir_all(archive)?;
+    }
+    outln!(
+        "Watching {} ({} worker(s), queue of {})",
+        dir.display(),
+        watch.concurrency,
+        watch.queue_size
+    );
+
+    // Files are reported as queued and done; the pages of conversions
+    // running side by side aren't
+    let on_progress = options.on_progress.take();
+    let report = move |done: &AtomicUsize, queued: &AtomicUsize| {
+        if let Some(callback) = on_progress {
+            callback(Progress::Files {
+                done: done.load(Ordering::Relaxed),
+                total: queued.load(Ordering::Relaxed),
+            });
+        }
+    };
+    let files_done = Arc::new(AtomicUsize::new(0));
+    let files_queued = Arc::new(AtomicUsize::new(0));
+
+    let metrics = Arc::new(Metrics::new());
+    if let Some(addr) = &watch.metrics_listen {
+        serve_status(addr, Arc::clone(&metrics))?;
+    }
+
+    let options = Arc::new(options);
+    let cache = Arc::new(Mutex::new(ConversionCache::load(&out_dir)));
+    let (retry, force) = (watch.retry, watch.force);
+    let work = move |pdf: PathBuf| {
+        let edf = out_dir
+            .join(pdf.file_stem().unwrap_or_default())
+            .with_extension("edf");
+        let edf = PathBuf::from(options.output_path(&edf.to_string_lossy()));
+        let hash = match file_sha256(&pdf) {
+            Ok(hash) => hash,
+            Err(e) => {
+                errln!("Warning: can't read {}: {}", pdf.display(), e);
+                return;
+            }
+        };
+        let cached = cache.lock().unwrap().is_converted(&pdf, &hash, &options);
+        if cached && !force && edf.exists() {
+            outln!("Skipped {} (unchanged since converted)", pdf.display());
+        } else {
+            let started = Instant::now();
+            let result =
+                retry.run(|| convert(&pdf.to_string_lossy(), &edf.to_string_lossy(), &options));
+            metrics.record(started.elapsed(), &result);
+            match result {
+                Ok(()) => outln!("Converted {} -> {}", pdf.display(), edf.display()),
+                Err(e) => {
+                    errln!("Warning: {} failed: {:#}", pdf.display(), e);
+                    return;
+                }
+            }
+            if let Err(e) = cache.lock().unwrap().record(&pdf, &hash, &options) {
+                errln!("Warning: {:#}", e);
+            }
+        }
+        if let Some(archive) = &archive {
+            let to = archive.join(pdf.file_name().unwrap_or_default());
+            if let Err(e) = std::fs::rename(&pdf, &to) {
+                errln!("Warning: can't archive {}: {}", pdf.display(), e);
+            }
+        }
+    };
+    let (done, queued) = (files_done.clone(), files_queued.clone());
+    let queue = WorkQueue::new(watch.queue_size, watch.concurrency, move |pdf: PathBuf| {
+        work(pdf);
+        done.fetch_add(1, Ordering::Relaxed);
+        report(&done, &queued);
+    });
+
+    // Wake early on changes in the directory; without a watcher, just poll
+    let (changed, changes) = mpsc::channel();
+    let watcher = notify::recommended_watcher(move |event| {
+        let _ = changed.send(event);
+    })
+    .and_then(|mut watcher| {
+        watcher.watch(dir, RecursiveMode::NonRecursive)?;
+        Ok(watcher)
+    });
+    let watcher = match watcher {
+        Ok(watcher) => Some(watcher),
+        Err(e) => {
+            errln!(
+                "Warning: can't watch {} for changes ({}); polling",
+                dir.display(),
+                e
+            );
+            None
+        }
+    };
+
+    // Last seen (size, modified) per PDF, and whether that version was queued
+    let mut seen: HashMap<PathBuf, ((u64, Option<SystemTime>), bool)> = HashMap::new();
+    loop {
+        // Forget PDFs that are gone, e.g. archived, so one dropped again
+        // under the same name is converted again
+        let pdfs = list_pdfs(dir)?;
+        seen.retain(|pdf, _| pdfs.contains(pdf));
+        for pdf in pdfs {
+            let Ok(metadata) = std::fs::metadata(&pdf) else {
+                continue;
+            };
+            let version = (metadata.len(), metadata.modified().ok());
+            match seen.get_mut(&pdf) {
+                Some((last, queued)) if *last == version => {
+                    if !*queued {
+                        *queued = true;
+                        files_queued.fetch_add(1, Ordering::Relaxed);
+                        report(&files_done, &files_queued);
+                        queue.submit(pdf);
+                    }
+                }
+                Some(entry) => *entry = (version, false),
+                None => {
+                    seen.insert(pdf, (version, false));
+                }
+            }
+        }
+        // Scan again after the next change or the poll interval, once the
+        // directory has been quiet for the settle time. PDFs waiting to be
+        // seen unchanged are scanned again after the settle time.
+        let waiting = seen.values().any(|(_, queued)| !queued);
+        let timeout = if waiting && watcher.is_some() {
+            SETTLE.min(watch.poll_interval)
+        } else {
+            watch.poll_interval
+        };
+        if changes.recv_timeout(timeout).is_ok() {
+            while changes.recv_timeout(SETTLE).is_ok() {}
+        }
+    }
+}
+
+/// Serve `GET /healthz` and `GET /metrics` for `metrics` on `addr`, as the
+/// `serve` mode does, on a thread of its own. Fails if `addr` can't be
+/// listened on.
+fn serve_status(addr: &str, metrics: Arc<Metrics>) -> Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    outln!("Metrics on http://{}/metrics", listener.local_addr()?);
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    errln!("Warning: connection failed: {}", e);
+                    continue;
+                }
+            };
+            let metrics = Arc::clone(&metrics);
+            std::thread::spawn(move || {
+                let result = http::Request::read(&stream, STATUS_TIMEOUT).and_then(|request| {
+                    if !http::respond_status(&mut stream, &request, &metrics)? {
+                        http::respond(&mut stream, 404, "text/plain", b"not found\n")?;
+                    }
+                    Ok(())
+                });
+                if let Err(e) = result {
+                    errln!("Warning: metrics request failed: {}", e);
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+/// The PDF files directly in a directory.
+fn list_pdfs(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut pdfs = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let is_pdf = path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("pdf"));
+        if is_pdf && path.is_file() {
+            pdfs.push(path);
+        }
+    }
+    pdfs.sort();
+    Ok(pdfs)
+}
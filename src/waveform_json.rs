@@ -0,0 +1,110 @@
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::audit::outln;
+use crate::compress;
+use crate::edf_write::EdfSignal;
+use crate::pdf_text::ReportInfo;
+use crate::provenance::Provenance;
+use crate::recording::EcgRecording;
+
+/// Version of the JSON layout below; bumped on incompatible changes.
+const FORMAT_VERSION: u32 = 1;
+
+/// Top-level JSON document.
+#[derive(Serialize)]
+struct WaveformJson<'a> {
+    format_version: u32,
+    /// ISO 8601 local date/time, if known.
+    recording_start: Option<String>,
+    duration_seconds: f64,
+    /// EDF+ patient identification field.
+    patient: &'a str,
+    calibration: Calibration<'a>,
+    signals: Vec<SignalJson<'a>>,
+    annotations: Vec<AnnotationJson<'a>>,
+    report: &'a ReportInfo,
+    provenance: &'a Provenance,
+}
+
+/// How the printed trace was scaled back to millivolts.
+#[derive(Serialize)]
+struct Calibration<'a> {
+    points_per_mv: f64,
+    /// Where the calibration came from, e.g. "footer_text".
+    source: &'a str,
+    /// Sampling rate of the printed trace's points, in Hz.
+    trace_sample_rate: usize,
+}
+
+#[derive(Serialize)]
+struct SignalJson<'a> {
+    label: &'a str,
+    unit: &'a str,
+    sample_rate: f64,
+    transducer: &'a str,
+    prefilter: &'a str,
+    samples: Vec<f64>,
+}
+
+#[derive(Serialize)]
+struct AnnotationJson<'a> {
+    onset: f64,
+    duration: Option<f64>,
+    text: &'a str,
+}
+
+/// Round to 6 decimal places, well below the trace's print resolution,
+/// to keep the file compact.
+fn round6(v: f64) -> f64 {
+    (v * 1e6).round() / 1e6
+}
+
+/// Write the signals, calibration, annotations, and report metadata as JSON,
+/// for web apps and other consumers that can't parse EDF.
+pub fn write_waveform_json(
+    path: &str,
+    recording: &EcgRecording,
+    signals: &[EdfSignal],
+) -> Result<()> {
+    let calibration = &recording.provenance.calibration;
+    let json = WaveformJson {
+        format_version: FORMAT_VERSION,
+        recording_start: recording
+            .start
+            .map(|t| t.format("%Y-%m-%dT%H:%M:%S").to_string()),
+        duration_seconds: recording.duration(),
+        patient: &recording.patient,
+        calibration: Calibration {
+            points_per_mv: calibration.points_per_mv,
+            source: &calibration.source,
+            trace_sample_rate: calibration.sample_rate,
+        },
+        signals: signals
+            .iter()
+            .map(|s| SignalJson {
+                label: &s.label,
+                unit: &s.phys_dim,
+                sample_rate: s.sample_rate,
+                transducer: &s.transducer,
+                prefilter: &s.prefilter,
+                samples: s.samples.iter().map(|&v| round6(v)).collect(),
+            })
+            .collect(),
+        annotations: recording
+            .annotations
+            .iter()
+            .map(|a| AnnotationJson {
+                onset: a.onset,
+                duration: a.duration,
+                text: &a.text,
+            })
+            .collect(),
+        report: &recording.report,
+        provenance: &recording.provenance,
+    };
+
+    compress::write(path, serde_json::to_string_pretty(&json)?)?;
+    outln!("Waveform JSON written: {}", path);
+    Ok(())
+}
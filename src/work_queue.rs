@@ -0,0 +1,127 @@
+use anyhow::Result;
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use crate::audit::errln;
+
+/// A bounded queue of jobs processed by a fixed number of worker threads.
+///
+/// [`submit`](WorkQueue::submit) blocks while the queue is full, so a burst
+/// of work slows down the producer instead of piling up unbounded.
+pub struct WorkQueue<T> {
+    sender: Option<SyncSender<T>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl<T: Send + 'static> WorkQueue<T> {
+    /// Start `concurrency` workers (at least one) running `handler` on jobs
+    /// from a queue holding up to `capacity` waiting jobs.
+    pub fn new<F>(capacity: usize, concurrency: usize, handler: F) -> Self
+    where
+        F: Fn(T) + Send + Sync + 'static,
+    {
+        let (sender, receiver) = sync_channel(capacity);
+        let receiver: Arc<Mutex<Receiver<T>>> = Arc::new(Mutex::new(receiver));
+        let handler = Arc::new(handler);
+        let workers = (0..concurrency.max(1))
+            .map(|_| {
+                let receiver = Arc::clone(&receiver);
+                let handler = Arc::clone(&handler);
+                std::thread::spawn(move || loop {
+                    // Hold the lock only while taking a job
+                    let job = receiver.lock().unwrap().recv();
+                    match job {
+                        Ok(job) => handler(job),
+                        Err(_) => break, // queue closed
+                    }
+                })
+            })
+            .collect();
+        Self {
+            sender: Some(sender),
+            workers,
+        }
+    }
+
+    /// Add a job, waiting for room if the queue is full.
+    pub fn submit(&self, job: T) {
+        if let Some(sender) = &self.sender {
+            // Sending only fails once every worker has exited
+            let _ = sender.send(job);
+        }
+    }
+
+    /// Close the queue and wait for the workers to finish the queued jobs.
+    pub fn finish(mut self) {
+        self.sender = None;
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// How many times, and how long apart, to retry a job after a transient error.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    /// Delay before the first retry; doubled for each retry after it.
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Run `f`, retrying with exponential backoff while it fails with a
+    /// transient error; returns the last result.
+    pub fn run<T>(&self, mut f: impl FnMut() -> Result<T>) -> Result<T> {
+        let mut backoff = self.initial_backoff;
+        let mut attempt = 0;
+        loop {
+            match f() {
+                Err(e) if attempt < self.max_retries && is_transient(&e) => {
+                    attempt += 1;
+                    errln!(
+                        "Warning: {:#}; retry {} of {} in {:?}",
+                        e,
+                        attempt,
+                        self.max_retries,
+                        backoff
+                    );
+                    std::thread::sleep(backoff);
+                    backoff = (backoff * 2).min(self.max_backoff);
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// Whether an error is likely to clear up on its own, such as a file that
+/// is still being copied or synced, or locked by another process.
+pub fn is_transient(error: &anyhow::Error) -> bool {
+    use std::io::ErrorKind::*;
+    error.chain().any(|cause| {
+        if let Some(e) = cause.downcast_ref::<std::io::Error>() {
+            return matches!(
+                e.kind(),
+                Interrupted | WouldBlock | TimedOut | UnexpectedEof | PermissionDenied
+            );
+        }
+        // A PDF cut short while still being written fails to parse
+        matches!(
+            cause.downcast_ref::<lopdf::Error>(),
+            Some(lopdf::Error::IO(_) | lopdf::Error::Trailer | lopdf::Error::Xref(_))
+        )
+    })
+}
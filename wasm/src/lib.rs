@@ -0,0 +1,22 @@
+use wasm_bindgen::prelude::*;
+
+use kardiamobile_1l_ecg_convert_pdf_to_edf::{convert_bytes, ConvertOptions};
+
+/// Convert a Kardia ECG report PDF into an EDF+ file in the browser, as
+/// `convertPdfToEdf(pdf: Uint8Array): Uint8Array` in JavaScript.
+///
+/// The PDF is converted in memory by [`convert_bytes`] with the default
+/// options, so the recording never leaves the user's machine. A failed
+/// conversion throws an `Error` with the message and its causes.
+///
+/// ```js
+/// import init, { convertPdfToEdf } from "./kardiamobile_1l_ecg_convert_pdf_to_edf_wasm.js";
+///
+/// await init();
+/// const pdf = new Uint8Array(await file.arrayBuffer());
+/// const edf = convertPdfToEdf(pdf);
+/// ```
+#[wasm_bindgen(js_name = convertPdfToEdf)]
+pub fn convert_pdf_to_edf(pdf: &[u8]) -> Result<Vec<u8>, JsError> {
+    convert_bytes(pdf, &ConvertOptions::new()).map_err(|e| JsError::new(&format!("{:#}", e)))
+}